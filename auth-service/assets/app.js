@@ -32,11 +32,18 @@ signupLoginLink.addEventListener("click", (e) => {
 
 // -----------------------------------------------------
 
+// Sent back in the X-CSRF-Token header; required for login, signup and once the auth cookie is set
+const csrfToken = fetch(`${window.location.origin}/auth/csrf-token`)
+  .then((response) => response.json())
+  .then((data) => data.csrfToken);
+
+// -----------------------------------------------------
+
 const loginForm = document.getElementById("login-form");
 const loginButton = document.getElementById("login-form-submit");
 const loginErrAlter = document.getElementById("login-err-alert");
 
-loginButton.addEventListener("click", (e) => {
+loginButton.addEventListener("click", async (e) => {
   e.preventDefault();
 
   const email = loginForm.email.value;
@@ -46,11 +53,11 @@ loginButton.addEventListener("click", (e) => {
     method: "POST",
     headers: {
       "Content-Type": "application/json",
+      "X-CSRF-Token": await csrfToken,
     },
     body: JSON.stringify({ email, password }),
   }).then((response) => {
     if (response.status === 206) {
-      TwoFAForm.email.value = email;
       response.json().then((data) => {
         TwoFAForm.login_attempt_id.value = data.loginAttemptId;
       });
@@ -85,7 +92,7 @@ const signupForm = document.getElementById("signup-form");
 const signupButton = document.getElementById("signup-form-submit");
 const signupErrAlter = document.getElementById("signup-err-alert");
 
-signupButton.addEventListener("click", (e) => {
+signupButton.addEventListener("click", async (e) => {
   e.preventDefault();
 
   const email = signupForm.email.value;
@@ -96,6 +103,7 @@ signupButton.addEventListener("click", (e) => {
     method: "POST",
     headers: {
       "Content-Type": "application/json",
+      "X-CSRF-Token": await csrfToken,
     },
     body: JSON.stringify({ email, password, requires2FA }),
   }).then((response) => {
@@ -126,10 +134,9 @@ const TwoFAForm = document.getElementById("2fa-form");
 const TwoFAButton = document.getElementById("2fa-form-submit");
 const TwoFAErrAlter = document.getElementById("2fa-err-alert");
 
-TwoFAButton.addEventListener("click", (e) => {
+TwoFAButton.addEventListener("click", async (e) => {
   e.preventDefault();
 
-  const email = TwoFAForm.email.value;
   const loginAttemptId = TwoFAForm.login_attempt_id.value;
   const TwoFACode = TwoFAForm.email_code.value;
 
@@ -137,11 +144,11 @@ TwoFAButton.addEventListener("click", (e) => {
     method: "POST",
     headers: {
       "Content-Type": "application/json",
+      "X-CSRF-Token": await csrfToken,
     },
-    body: JSON.stringify({ email, loginAttemptId, "2FACode": TwoFACode }),
+    body: JSON.stringify({ loginAttemptId, "2FACode": TwoFACode }),
   }).then((response) => {
     if (response.ok) {
-      TwoFAForm.email.value = "";
       TwoFAForm.email_code.value = "";
       TwoFAForm.login_attempt_id.value = "";
       TwoFAErrAlter.style.display = "none";
@@ -162,3 +169,31 @@ TwoFAButton.addEventListener("click", (e) => {
     }
   });
 });
+
+const TwoFAResendLink = document.getElementById("2fa-resend-link");
+
+TwoFAResendLink.addEventListener("click", async (e) => {
+  e.preventDefault();
+
+  const loginAttemptId = TwoFAForm.login_attempt_id.value;
+
+  fetch(`${window.location.origin}/auth/resend-2fa`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      "X-CSRF-Token": await csrfToken,
+    },
+    body: JSON.stringify({ loginAttemptId }),
+  }).then((response) => {
+    response.json().then((data) => {
+      let msg = response.ok ? data.message : data.error;
+      if (msg !== undefined && msg !== null && msg !== "") {
+        let label = response.ok ? "Sent" : "Error";
+        TwoFAErrAlter.innerHTML = `<span><strong>${label}: </strong>${msg}</span>`;
+        TwoFAErrAlter.style.display = "block";
+      } else {
+        TwoFAErrAlter.style.display = "none";
+      }
+    });
+  });
+});
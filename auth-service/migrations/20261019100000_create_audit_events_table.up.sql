@@ -0,0 +1,15 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS audit_events (
+    id UUID NOT NULL PRIMARY KEY,
+    occurred_at TIMESTAMPTZ NOT NULL,
+    kind TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    actor TEXT,
+    ip TEXT,
+    user_agent TEXT,
+    request_id TEXT,
+    reason TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_actor_occurred_at_idx ON audit_events (actor, occurred_at DESC);
+CREATE INDEX IF NOT EXISTS audit_events_occurred_at_idx ON audit_events (occurred_at DESC);
@@ -0,0 +1,24 @@
+-- Add up migration script here
+-- Used instead of Redis when `stores.banned_tokens` / `stores.two_fa_codes` is "postgres".
+-- Tokens, login attempt ids and 2FA codes are stored as keyed hashes (see `stores.hmac_secret`).
+-- Reads ignore expired rows; a background task deletes them.
+CREATE TABLE IF NOT EXISTS banned_tokens (
+    token_hash TEXT NOT NULL PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS banned_tokens_expires_at_idx ON banned_tokens (expires_at);
+
+-- Codes are keyed by login attempt, so a user can have several pending logins
+CREATE TABLE IF NOT EXISTS two_fa_codes (
+    login_attempt_id_hash TEXT NOT NULL PRIMARY KEY,
+    email TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    -- how often the code was resent and wrongly entered, for their limits
+    resends INTEGER NOT NULL DEFAULT 0,
+    failed_attempts INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS two_fa_codes_expires_at_idx ON two_fa_codes (expires_at);
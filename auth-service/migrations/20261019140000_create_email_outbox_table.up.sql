@@ -0,0 +1,20 @@
+-- Add up migration script here
+-- Emails waiting for the dispatcher; the message is cleared once it is sent or dead
+CREATE TABLE IF NOT EXISTS email_outbox (
+    id UUID NOT NULL PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    subject TEXT,
+    text_body TEXT,
+    html_body TEXT,
+    -- pending, sent or dead
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT,
+    -- the provider that accepted the email
+    provider TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    sent_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS email_outbox_due_idx ON email_outbox (next_attempt_at) WHERE status = 'pending';
@@ -0,0 +1,8 @@
+-- Add down migration script here
+ALTER TABLE users
+    DROP COLUMN IF EXISTS phone_number,
+    DROP COLUMN IF EXISTS two_fa_channel,
+    DROP COLUMN IF EXISTS pending_phone_number,
+    DROP COLUMN IF EXISTS phone_verification_code,
+    DROP COLUMN IF EXISTS phone_verification_expires_at,
+    DROP COLUMN IF EXISTS phone_verification_attempts;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- `phone_number` is only set once verified; the pending one waits for its code
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS phone_number TEXT,
+    ADD COLUMN IF NOT EXISTS two_fa_channel TEXT NOT NULL DEFAULT 'email',
+    ADD COLUMN IF NOT EXISTS pending_phone_number TEXT,
+    ADD COLUMN IF NOT EXISTS phone_verification_code TEXT,
+    ADD COLUMN IF NOT EXISTS phone_verification_expires_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS phone_verification_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE webhook_outbox DROP COLUMN IF EXISTS claim_token;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+-- Set when a dispatcher claims a delivery; its outcome is only recorded while the token still matches
+ALTER TABLE webhook_outbox ADD COLUMN IF NOT EXISTS claim_token UUID;
+
+-- Secrets are now stored encrypted and plaintext ones can't be read; subscribe again after upgrading
+DELETE FROM webhook_subscriptions WHERE secret NOT LIKE 'v1:%';
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- the email templates' locale picked at signup; NULL uses the request's Accept-Language
+ALTER TABLE users ADD COLUMN IF NOT EXISTS locale TEXT;
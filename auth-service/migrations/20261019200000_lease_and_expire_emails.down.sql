@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE email_outbox
+    DROP COLUMN IF EXISTS claim_token,
+    DROP COLUMN IF EXISTS expires_at;
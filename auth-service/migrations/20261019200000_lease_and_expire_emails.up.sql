@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- Set when a dispatcher claims an email; its outcome is only recorded while the token still matches
+ALTER TABLE email_outbox ADD COLUMN IF NOT EXISTS claim_token UUID;
+-- Emails that are useless afterwards, e.g. 2FA codes, are marked dead instead of sent late
+ALTER TABLE email_outbox ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
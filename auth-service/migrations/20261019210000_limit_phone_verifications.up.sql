@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- when the pending verification's code was sent, for the resend cooldown
+ALTER TABLE users ADD COLUMN IF NOT EXISTS phone_verification_sent_at TIMESTAMPTZ;
+UPDATE users SET phone_verification_sent_at = now()
+WHERE pending_phone_number IS NOT NULL AND phone_verification_sent_at IS NULL;
+
+-- Verification codes sent in the last day, for the daily limits per user and per number
+CREATE TABLE IF NOT EXISTS phone_verification_sends (
+    id BIGSERIAL PRIMARY KEY,
+    email TEXT NOT NULL,
+    phone_number TEXT NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS phone_verification_sends_email_idx ON phone_verification_sends (email, sent_at);
+CREATE INDEX IF NOT EXISTS phone_verification_sends_phone_number_idx ON phone_verification_sends (phone_number, sent_at);
@@ -0,0 +1,7 @@
+-- Add down migration script here
+UPDATE users
+SET pending_phone_number = NULL, phone_verification_code_hash = NULL,
+    phone_verification_expires_at = NULL, phone_verification_attempts = 0,
+    phone_verification_sent_at = NULL
+WHERE pending_phone_number IS NOT NULL;
+ALTER TABLE users RENAME COLUMN phone_verification_code_hash TO phone_verification_code;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Set by every accepted login, together with its `user.logged_in` webhook event
+ALTER TABLE users ADD COLUMN last_login_at TIMESTAMPTZ;
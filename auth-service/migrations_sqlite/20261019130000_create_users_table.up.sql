@@ -0,0 +1,7 @@
+-- Add up migration script here
+-- Same schema as the PostgreSQL `users` table
+CREATE TABLE IF NOT EXISTS users (
+    email TEXT NOT NULL PRIMARY KEY,
+    password_hash TEXT NOT NULL,
+    requires_2fa BOOLEAN NOT NULL DEFAULT FALSE
+);
@@ -0,0 +1,7 @@
+-- Add down migration script here
+ALTER TABLE users DROP COLUMN phone_number;
+ALTER TABLE users DROP COLUMN two_fa_channel;
+ALTER TABLE users DROP COLUMN pending_phone_number;
+ALTER TABLE users DROP COLUMN phone_verification_code;
+ALTER TABLE users DROP COLUMN phone_verification_expires_at;
+ALTER TABLE users DROP COLUMN phone_verification_attempts;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- Same columns as the PostgreSQL migration; timestamps are stored as RFC 3339 text
+ALTER TABLE users ADD COLUMN phone_number TEXT;
+ALTER TABLE users ADD COLUMN two_fa_channel TEXT NOT NULL DEFAULT 'email';
+ALTER TABLE users ADD COLUMN pending_phone_number TEXT;
+ALTER TABLE users ADD COLUMN phone_verification_code TEXT;
+ALTER TABLE users ADD COLUMN phone_verification_expires_at TEXT;
+ALTER TABLE users ADD COLUMN phone_verification_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Same column as the PostgreSQL migration
+ALTER TABLE users ADD COLUMN locale TEXT;
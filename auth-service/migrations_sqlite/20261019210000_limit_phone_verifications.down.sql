@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS phone_verification_sends;
+ALTER TABLE users DROP COLUMN phone_verification_sent_at;
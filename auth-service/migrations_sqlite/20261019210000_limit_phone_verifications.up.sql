@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- Same schema as the PostgreSQL migration; timestamps are stored as RFC 3339 text
+ALTER TABLE users ADD COLUMN phone_verification_sent_at TEXT;
+UPDATE users SET phone_verification_sent_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+WHERE pending_phone_number IS NOT NULL AND phone_verification_sent_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS phone_verification_sends (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email TEXT NOT NULL,
+    phone_number TEXT NOT NULL,
+    sent_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS phone_verification_sends_email_idx ON phone_verification_sends (email, sent_at);
+CREATE INDEX IF NOT EXISTS phone_verification_sends_phone_number_idx ON phone_verification_sends (phone_number, sent_at);
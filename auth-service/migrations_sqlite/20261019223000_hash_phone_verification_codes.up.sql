@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- Same as the PostgreSQL migration: pending verifications are dropped, as their codes can't be
+-- hashed here
+UPDATE users
+SET pending_phone_number = NULL, phone_verification_code = NULL,
+    phone_verification_expires_at = NULL, phone_verification_attempts = 0,
+    phone_verification_sent_at = NULL
+WHERE pending_phone_number IS NOT NULL;
+ALTER TABLE users RENAME COLUMN phone_verification_code TO phone_verification_code_hash;
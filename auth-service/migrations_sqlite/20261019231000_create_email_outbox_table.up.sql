@@ -0,0 +1,22 @@
+-- Add up migration script here
+-- Same schema as the PostgreSQL `email_outbox` table; timestamps are stored as RFC 3339 text
+CREATE TABLE IF NOT EXISTS email_outbox (
+    id BLOB NOT NULL PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    subject TEXT,
+    text_body TEXT,
+    html_body TEXT,
+    -- pending, sent or dead
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TEXT NOT NULL,
+    last_error TEXT,
+    -- the provider that accepted the email
+    provider TEXT,
+    created_at TEXT NOT NULL,
+    sent_at TEXT,
+    claim_token BLOB,
+    expires_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS email_outbox_due_idx ON email_outbox (next_attempt_at) WHERE status = 'pending';
@@ -1,8 +1,15 @@
 use crate::domain::{
+    audit::{AuditEvent, AuditSink},
     data_stores::{BannedTokenStore, TwoFACodeStore, UserStore},
-    EmailClient,
+    email_outbox::EmailOutbox,
+    health::HealthCheck,
+    EmailClient, SmsClient,
+};
+use crate::services::{email_delivery::EmailQueue, email_templates::EmailTemplates};
+use crate::utils::{
+    configuration::Settings, keyed_hash::KeyedHasher, request_context::TrustedProxies,
+    shutdown::ShutdownHandle,
 };
-use crate::utils::configuration::Settings;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -10,6 +17,11 @@ pub type UserStoreType = Arc<RwLock<dyn UserStore + Send + Sync>>;
 pub type TokenStoreType = Arc<RwLock<dyn BannedTokenStore + Send + Sync>>;
 pub type TwoFACodeStoreType = Arc<RwLock<dyn TwoFACodeStore + Send + Sync>>;
 pub type EmailClientType = Arc<dyn EmailClient + Send + Sync>;
+pub type EmailOutboxType = Arc<dyn EmailOutbox + Send + Sync>;
+pub type SmsClientType = Arc<dyn SmsClient + Send + Sync>;
+pub type AuditSinkType = Arc<dyn AuditSink + Send + Sync>;
+pub type HealthCheckType = Arc<dyn HealthCheck + Send + Sync>;
+pub type HealthChecksType = Arc<Vec<HealthCheckType>>;
 pub type SettingsType = Settings;
 
 #[derive(Clone)]
@@ -17,8 +29,18 @@ pub struct AppState {
     pub user_store: UserStoreType,
     pub token_store: TokenStoreType,
     pub two_fa_code_store: TwoFACodeStoreType,
-    pub email_client: EmailClientType,
+    pub email_queue: EmailQueue,
+    pub email_templates: Arc<EmailTemplates>,
+    // only set when `sms.enabled`; codes are emailed without it
+    pub sms_client: Option<SmsClientType>,
+    pub audit_sink: AuditSinkType,
+    pub health_checks: HealthChecksType,
+    // parsed once from `settings.trusted_proxies`
+    pub trusted_proxies: TrustedProxies,
+    // the stores' key; hashes the phone verification codes kept with users
+    pub hasher: KeyedHasher,
     pub settings: SettingsType,
+    pub shutdown: ShutdownHandle,
 }
 
 impl AppState {
@@ -26,15 +48,43 @@ impl AppState {
         user_store: UserStoreType,
         token_store: TokenStoreType,
         two_fa_code_store: TwoFACodeStoreType,
-        email_client: EmailClientType,
+        email_queue: EmailQueue,
+        audit_sink: AuditSinkType,
+        health_checks: HealthChecksType,
         settings: Settings,
     ) -> Self {
         Self {
             user_store,
             token_store,
             two_fa_code_store,
-            email_client,
+            email_queue,
+            email_templates: Arc::new(EmailTemplates::built_in()),
+            sms_client: None,
+            audit_sink,
+            health_checks,
+            trusted_proxies: TrustedProxies::from_settings(&settings),
+            hasher: KeyedHasher::from_settings(&settings),
             settings,
+            shutdown: ShutdownHandle::new(),
+        }
+    }
+
+    // Replaces the built-in templates, e.g. with ones loaded from `email.templates.dir`
+    pub fn with_email_templates(mut self, email_templates: EmailTemplates) -> Self {
+        self.email_templates = Arc::new(email_templates);
+        self
+    }
+
+    // Enables phone numbers and the SMS and voice 2FA channels
+    pub fn with_sms_client(mut self, sms_client: SmsClientType) -> Self {
+        self.sms_client = Some(sms_client);
+        self
+    }
+
+    // Audit failures are logged but never fail the request being audited
+    pub async fn record_audit_event(&self, event: AuditEvent) {
+        if let Err(e) = self.audit_sink.record(event).await {
+            tracing::error!(error = ?e, "Failed to record audit event");
         }
     }
 }
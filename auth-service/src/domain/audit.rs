@@ -0,0 +1,200 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Report, Result};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::utils::request_context::RequestContext;
+
+use super::Email;
+
+#[async_trait::async_trait]
+pub trait AuditSink {
+    async fn record(&self, event: AuditEvent) -> Result<(), AuditSinkError>;
+    // Newest events first
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, AuditSinkError>;
+}
+
+#[derive(Debug, Error)]
+pub enum AuditSinkError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    Signup,
+    Login,
+    Verify2FA,
+    #[serde(rename = "resend_2fa")]
+    Resend2FA,
+    Logout,
+    VerifyPhone,
+    #[serde(rename = "set_2fa_channel")]
+    Set2FAChannel,
+    AdminAction,
+}
+
+impl AuditEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Signup => "signup",
+            Self::Login => "login",
+            Self::Verify2FA => "verify_2fa",
+            Self::Resend2FA => "resend_2fa",
+            Self::Logout => "logout",
+            Self::VerifyPhone => "verify_phone",
+            Self::Set2FAChannel => "set_2fa_channel",
+            Self::AdminAction => "admin_action",
+        }
+    }
+}
+
+impl FromStr for AuditEventKind {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "signup" => Ok(Self::Signup),
+            "login" => Ok(Self::Login),
+            "verify_2fa" => Ok(Self::Verify2FA),
+            "resend_2fa" => Ok(Self::Resend2FA),
+            "logout" => Ok(Self::Logout),
+            "verify_phone" => Ok(Self::VerifyPhone),
+            "set_2fa_channel" => Ok(Self::Set2FAChannel),
+            "admin_action" => Ok(Self::AdminAction),
+            _ => Err(eyre!("unknown audit event kind: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+        }
+    }
+}
+
+impl FromStr for AuditOutcome {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "success" => Ok(Self::Success),
+            "failure" => Ok(Self::Failure),
+            _ => Err(eyre!("unknown audit outcome: {}", s)),
+        }
+    }
+}
+
+// A single security-relevant event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub kind: AuditEventKind,
+    pub outcome: AuditOutcome,
+    // email of the user acting, as submitted for failed attempts
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(kind: AuditEventKind, outcome: AuditOutcome) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            occurred_at: Utc::now(),
+            kind,
+            outcome,
+            actor: None,
+            ip: None,
+            user_agent: None,
+            request_id: None,
+            reason: None,
+        }
+    }
+
+    // Success for `Ok`, failure with the error message as reason for `Err`
+    pub fn from_result<T, E: Display>(kind: AuditEventKind, result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Self::new(kind, AuditOutcome::Success),
+            Err(e) => Self::new(kind, AuditOutcome::Failure).with_reason(e.to_string()),
+        }
+    }
+
+    pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    pub fn with_actor_email(self, email: &Email) -> Self {
+        self.with_actor(email.as_ref().expose_secret().to_owned())
+    }
+
+    pub fn with_context(mut self, context: &RequestContext) -> Self {
+        self.ip = context.ip.clone();
+        self.user_agent = context.user_agent.clone();
+        self.request_id = context.request_id.clone();
+        self
+    }
+
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+}
+
+// Filters for investigations. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub kind: Option<AuditEventKind>,
+    pub outcome: Option<AuditOutcome>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+pub const DEFAULT_AUDIT_QUERY_LIMIT: usize = 100;
+
+impl AuditQuery {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        self.actor
+            .as_ref()
+            .is_none_or(|actor| event.actor.as_ref() == Some(actor))
+            && self.kind.is_none_or(|kind| event.kind == kind)
+            && self.outcome.is_none_or(|outcome| event.outcome == outcome)
+            && self
+                .ip
+                .as_ref()
+                .is_none_or(|ip| event.ip.as_ref() == Some(ip))
+            && self
+                .request_id
+                .as_ref()
+                .is_none_or(|id| event.request_id.as_ref() == Some(id))
+            && self.since.is_none_or(|since| event.occurred_at >= since)
+            && self.until.is_none_or(|until| event.occurred_at < until)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_AUDIT_QUERY_LIMIT)
+    }
+}
@@ -1,9 +1,15 @@
+use std::{
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::{eyre, Context, Report, Result};
 use rand::Rng;
 use secrecy::{ExposeSecret, Secret};
 use thiserror::Error;
 
-use super::{email::Email, password::Password, User};
+use super::{email::Email, password::Password, PhoneNumber, PhoneVerification, TwoFAChannel, User};
 
 #[async_trait::async_trait]
 pub trait UserStore {
@@ -11,6 +17,52 @@ pub trait UserStore {
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError>;
     async fn validate_user(&self, email: &Email, password: &Password)
         -> Result<(), UserStoreError>;
+    // Replaces the user's pending phone verification, or clears it with `None`
+    async fn set_phone_verification(
+        &mut self,
+        email: &Email,
+        verification: Option<PhoneVerification>,
+    ) -> Result<(), UserStoreError>;
+    // Stores a verified phone number and clears the pending verification
+    async fn set_phone_number(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+    ) -> Result<(), UserStoreError>;
+    async fn set_two_fa_channel(
+        &mut self,
+        email: &Email,
+        channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError>;
+    // Called once a login is accepted, after the 2FA code if the user needs one
+    async fn record_login(
+        &mut self,
+        email: &Email,
+        logged_in_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError>;
+    // Counts a verification code sent to `phone_number` for the user. Sends older than
+    // a day are no longer counted and may be forgotten.
+    async fn record_phone_verification_sent(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError>;
+    async fn phone_verifications_sent_since(
+        &self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        since: DateTime<Utc>,
+    ) -> Result<PhoneVerificationSends, UserStoreError>;
+}
+
+// Verification codes sent within a period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhoneVerificationSends {
+    // for the user, to any number
+    pub by_user: u32,
+    // to the number, for any user
+    pub to_number: u32,
 }
 
 #[derive(Debug, Error)]
@@ -58,26 +110,80 @@ impl PartialEq for BannedTokenStoreError {
     }
 }
 
+// Attempts are forgotten at their `expires_at`
 #[async_trait::async_trait]
 pub trait TwoFACodeStore {
+    // Adding an existing id replaces the attempt
     async fn add_code(
         &mut self,
-        email: Email,
         login_attempt_id: LoginAttemptId,
+        attempt: LoginAttempt,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError>;
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError>;
+    // Replaces the code of a pending attempt and counts the resend; it still expires
+    // when the first code would have
+    async fn resend_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+        code: TwoFACode,
+        sent_at: DateTime<Utc>,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError>;
+    async fn remove_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError>;
 
-    async fn get_code(
+    async fn get_attempt(
         &self,
-        email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError>;
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError>;
+    // Removes and returns the attempt if `code` is its code, in one step, so a code can
+    // only be used once. A wrong code is counted, and the attempt removed once
+    // `max_failed_attempts` are. Codes can't be read back, as stores may only keep a hash.
+    async fn consume_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+        code: &TwoFACode,
+        max_failed_attempts: u32,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError>;
+}
+
+// A login waiting for its 2FA code. The email is bound to it, so verifying only needs the id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginAttempt {
+    pub email: Email,
+    // when the code was last sent, for the resend cooldown
+    pub sent_at: DateTime<Utc>,
+    // set at login; resending a code doesn't extend it
+    pub expires_at: DateTime<Utc>,
+    // codes sent after the first one
+    pub resends: u32,
+    // wrong codes entered so far
+    pub failed_attempts: u32,
+}
+
+impl LoginAttempt {
+    pub fn new(email: Email, ttl: Duration) -> Self {
+        let sent_at = Utc::now();
+        Self {
+            email,
+            sent_at,
+            expires_at: chrono::Duration::from_std(ttl)
+                .ok()
+                .and_then(|ttl| sent_at.checked_add_signed(ttl))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            resends: 0,
+            failed_attempts: 0,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum TwoFACodeStoreError {
     #[error("Login Attempt ID not found")]
     LoginAttemptIdNotFound,
+    #[error("Incorrect 2FA code")]
+    IncorrectCode,
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
@@ -87,6 +193,7 @@ impl PartialEq for TwoFACodeStoreError {
         matches!(
             (self, other),
             (Self::LoginAttemptIdNotFound, Self::LoginAttemptIdNotFound)
+                | (Self::IncorrectCode, Self::IncorrectCode)
                 | (Self::UnexpectedError(_), Self::UnexpectedError(_))
         )
     }
@@ -101,6 +208,14 @@ impl PartialEq for LoginAttemptId {
     }
 }
 
+impl Hash for LoginAttemptId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.expose_secret().hash(state);
+    }
+}
+
+impl Eq for LoginAttemptId {}
+
 impl LoginAttemptId {
     pub fn parse(id: Secret<String>) -> Result<Self> {
         match uuid::Uuid::parse_str(&id.expose_secret()).wrap_err("Invalid login attempt id") {
@@ -1,7 +1,28 @@
 use super::Email;
 use color_eyre::eyre::Result;
+use lettre::message::Mailbox;
 
 #[async_trait::async_trait]
 pub trait EmailClient {
-    async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()>;
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()>;
+
+    // Cheap authenticated call used by the readiness check; clients without one are always healthy
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// From and Reply-To of every email, whichever provider sends it
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailSender {
+    pub from: Mailbox,
+    pub reply_to: Option<Mailbox>,
+}
+
+// A rendered email; providers send both bodies as multipart/alternative
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailMessage {
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: String,
 }
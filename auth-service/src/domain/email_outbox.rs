@@ -0,0 +1,134 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Report, Result};
+use serde::Serialize;
+use thiserror::Error;
+use uuid::Uuid;
+
+use super::{Email, EmailMessage};
+
+// Emails waiting to be sent by the dispatcher, so a slow or failing provider never fails
+// the request that produced the email
+#[async_trait::async_trait]
+pub trait EmailOutbox {
+    // An email with `expires_at` is useless afterwards (e.g. a 2FA code) and is never sent late
+    async fn enqueue(
+        &self,
+        recipient: &Email,
+        message: &EmailMessage,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Uuid, EmailOutboxError>;
+
+    // The oldest pending email due by `due_by`, counted as one more attempt. It is not handed
+    // out again for `lease`, so a worker that dies mid-send only delays it. Claiming one at a
+    // time keeps the lease from running out on emails still waiting behind a slow send.
+    async fn claim_next(
+        &self,
+        due_by: DateTime<Utc>,
+        lease: Duration,
+    ) -> Result<Option<QueuedEmail>, EmailOutboxError>;
+
+    // `ClaimExpired` if the email was claimed again since, e.g. after its lease ran out
+    async fn mark_sent(
+        &self,
+        id: Uuid,
+        claim_token: Uuid,
+        provider: &str,
+    ) -> Result<(), EmailOutboxError>;
+
+    // Retried at `retry_at`, or dead when it is `None`
+    async fn mark_failed(
+        &self,
+        id: Uuid,
+        claim_token: Uuid,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxError>;
+
+    // Marks pending emails past their `expires_at` as dead, returning how many
+    async fn expire_overdue(&self) -> Result<u64, EmailOutboxError>;
+
+    async fn delivery(&self, id: Uuid) -> Result<Option<EmailDelivery>, EmailOutboxError>;
+
+    async fn stats(&self) -> Result<EmailOutboxStats, EmailOutboxError>;
+
+    // Deletes sent emails older than `before`, returning how many were deleted; dead ones are kept
+    async fn purge_sent(&self, before: DateTime<Utc>) -> Result<u64, EmailOutboxError>;
+}
+
+// `last_error` of emails that expired before they could be sent
+pub const EXPIRED_ERROR: &str = "expired before it could be sent";
+
+#[derive(Debug, Error)]
+pub enum EmailOutboxError {
+    #[error("Claim expired")]
+    ClaimExpired,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailDeliveryStatus {
+    Pending,
+    Sent,
+    // retries exhausted on every provider, or expired before it could be sent;
+    // kept for inspection
+    Dead,
+}
+
+impl EmailDeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Sent => "sent",
+            Self::Dead => "dead",
+        }
+    }
+}
+
+impl FromStr for EmailDeliveryStatus {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "sent" => Ok(Self::Sent),
+            "dead" => Ok(Self::Dead),
+            _ => Err(eyre!("unknown email delivery status: {}", s)),
+        }
+    }
+}
+
+// A claimed email; `attempts` includes the one about to be made
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedEmail {
+    pub id: Uuid,
+    // identifies this claim; its outcome is only recorded while it is the latest one
+    pub claim_token: Uuid,
+    pub recipient: Email,
+    pub message: EmailMessage,
+    pub attempts: u32,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// Delivery status of one email, without its content
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EmailDelivery {
+    pub id: Uuid,
+    pub status: EmailDeliveryStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub provider: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EmailOutboxStats {
+    pub pending: u64,
+    pub dead: u64,
+    pub oldest_pending_at: Option<DateTime<Utc>>,
+}
@@ -13,6 +13,16 @@ pub enum AuthAPIError {
     MissingToken,
     #[error("Invalid token")]
     InvalidToken,
+    #[error("Invalid CSRF token")]
+    InvalidCsrfToken,
+    #[error("Invalid input")]
+    InvalidInput,
+    #[error("Invalid phone number")]
+    InvalidPhoneNumber,
+    #[error("Phone number not verified")]
+    PhoneNumberNotVerified,
+    #[error("Too many requests")]
+    TooManyRequests,
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
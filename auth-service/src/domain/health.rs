@@ -0,0 +1,10 @@
+use color_eyre::eyre::Result;
+
+// A dependency probed by `/health/ready`, e.g. a database or the email provider
+#[async_trait::async_trait]
+pub trait HealthCheck {
+    // key of this dependency in the readiness response
+    fn name(&self) -> &'static str;
+
+    async fn check(&self) -> Result<()>;
+}
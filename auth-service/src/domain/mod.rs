@@ -1,11 +1,19 @@
+pub mod audit;
 pub mod data_stores;
 pub mod email;
 pub mod email_client;
+pub mod email_outbox;
 pub mod error;
+pub mod health;
 pub mod password;
+pub mod phone_number;
+pub mod sms_client;
 pub mod user;
+pub mod webhook;
 
 pub use email::Email;
 pub use email_client::*;
 pub use error::AuthAPIError;
-pub use user::User;
+pub use phone_number::PhoneNumber;
+pub use sms_client::*;
+pub use user::{PhoneVerification, TwoFAChannel, User};
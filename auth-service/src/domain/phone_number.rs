@@ -0,0 +1,102 @@
+use std::hash::Hash;
+
+use color_eyre::eyre::{eyre, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use secrecy::{ExposeSecret, Secret};
+
+lazy_static! {
+    // "+" and the country code, then at most 15 digits in total
+    static ref E164_PATTERN: Regex = Regex::new(r"^\+[1-9]\d{1,14}$").unwrap();
+}
+
+// A phone number in E.164 form, e.g. "+14155550100"
+#[derive(Debug, Clone)]
+pub struct PhoneNumber(Secret<String>);
+
+impl PartialEq for PhoneNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl Hash for PhoneNumber {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.expose_secret().hash(state)
+    }
+}
+impl Eq for PhoneNumber {}
+
+impl PhoneNumber {
+    // Spaces, dashes, dots and parentheses are dropped, so "+1 (415) 555-0100" is accepted
+    pub fn parse(s: Secret<String>) -> Result<PhoneNumber> {
+        let normalized: String = s
+            .expose_secret()
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+            .collect();
+
+        match E164_PATTERN.is_match(&normalized) {
+            true => Ok(Self(Secret::new(normalized))),
+            false => Err(eyre!("not a valid E.164 phone number")),
+        }
+    }
+
+    // For messages to the user, e.g. "+*******0100"
+    pub fn masked(&self) -> String {
+        let digits = &self.0.expose_secret()[1..];
+        let hidden = digits.len().saturating_sub(4);
+        format!("+{}{}", "*".repeat(hidden), &digits[hidden..])
+    }
+}
+
+impl AsRef<Secret<String>> for PhoneNumber {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<PhoneNumber> {
+        PhoneNumber::parse(Secret::new(s.to_owned()))
+    }
+
+    #[test]
+    fn test_e164_numbers_are_accepted() {
+        for number in ["+14155550100", "+442071838750", "+4915123456789", "+12"] {
+            assert_eq!(parse(number).unwrap().as_ref().expose_secret(), number);
+        }
+    }
+
+    #[test]
+    fn test_formatting_is_removed() {
+        let number = parse("+1 (415) 555-0100").unwrap();
+        assert_eq!(number.as_ref().expose_secret(), "+14155550100");
+        assert_eq!(number, parse("+1.415.555.0100").unwrap());
+    }
+
+    #[test]
+    fn test_invalid_numbers_are_rejected() {
+        for number in [
+            "",
+            "+",
+            "14155550100",
+            "+04155550100",
+            "+1415555010012345",
+            "+1415555O100",
+            "00441234567890",
+            "+1 415 555 0100 ext 2",
+        ] {
+            assert!(parse(number).is_err(), "{:?} was accepted", number);
+        }
+    }
+
+    #[test]
+    fn test_masked() {
+        assert_eq!(parse("+14155550100").unwrap().masked(), "+*******0100");
+        assert_eq!(parse("+12").unwrap().masked(), "+12");
+    }
+}
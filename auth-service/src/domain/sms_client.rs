@@ -0,0 +1,15 @@
+use super::PhoneNumber;
+use color_eyre::eyre::Result;
+
+#[async_trait::async_trait]
+pub trait SmsClient {
+    async fn send_sms(&self, recipient: &PhoneNumber, text: &str) -> Result<()>;
+    // Calls the recipient and reads `speech` out in `language` (e.g. "de"), for numbers
+    // that cannot receive texts
+    async fn call(&self, recipient: &PhoneNumber, speech: &str, language: &str) -> Result<()>;
+
+    // Cheap authenticated call used by the readiness check; clients without one are always healthy
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -1,10 +1,24 @@
-use super::{email::Email, password::Password};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Report, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{email::Email, password::Password, PhoneNumber};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct User {
     pub email: Email,
     pub password: Password,
     pub requires_2fa: bool,
+    // only set once the user has entered a code sent to it
+    pub phone_number: Option<PhoneNumber>,
+    pub two_fa_channel: TwoFAChannel,
+    pub phone_verification: Option<PhoneVerification>,
+    // the email templates' locale matching the user's language at signup; emails fall back
+    // to the request's Accept-Language without it
+    pub locale: Option<String>,
+    pub last_login_at: Option<DateTime<Utc>>,
 }
 
 impl User {
@@ -13,6 +27,74 @@ impl User {
             email,
             password,
             requires_2fa,
+            phone_number: None,
+            two_fa_channel: TwoFAChannel::Email,
+            phone_verification: None,
+            locale: None,
+            last_login_at: None,
+        }
+    }
+
+    pub fn with_locale(mut self, locale: Option<String>) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    // The languages to render the user's emails in, most preferred first
+    pub fn preferred_locales(&self, request_languages: &[String]) -> Vec<String> {
+        match &self.locale {
+            Some(locale) => vec![locale.to_owned()],
+            None => request_languages.to_vec(),
         }
     }
 }
+
+// Where login sends 2FA codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TwoFAChannel {
+    #[default]
+    Email,
+    Sms,
+    // the code is read out in a phone call
+    Voice,
+}
+
+impl TwoFAChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Sms => "sms",
+            Self::Voice => "voice",
+        }
+    }
+
+    pub fn uses_phone(&self) -> bool {
+        matches!(self, Self::Sms | Self::Voice)
+    }
+}
+
+impl FromStr for TwoFAChannel {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "email" => Ok(Self::Email),
+            "sms" => Ok(Self::Sms),
+            "voice" => Ok(Self::Voice),
+            _ => Err(eyre!("unknown 2FA channel: {}", s)),
+        }
+    }
+}
+
+// A phone number waiting for the user to enter the code sent to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhoneVerification {
+    pub phone_number: PhoneNumber,
+    // keyed hash of the code sent (see `KeyedHasher`), so stored users can't complete it
+    pub code_hash: String,
+    pub expires_at: DateTime<Utc>,
+    // wrong codes entered so far
+    pub attempts: u32,
+    pub sent_at: DateTime<Utc>,
+}
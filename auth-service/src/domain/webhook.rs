@@ -0,0 +1,132 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Report, Result};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Email, TwoFAChannel};
+
+// Account lifecycle events that subscribers can receive.
+// Each event must be enqueued in the same transaction as the change it describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookEventType {
+    #[serde(rename = "user.signed_up")]
+    UserSignedUp,
+    // password login, or the 2FA code for one, accepted
+    #[serde(rename = "user.logged_in")]
+    UserLoggedIn,
+    #[serde(rename = "user.phone_number_verified")]
+    UserPhoneNumberVerified,
+    #[serde(rename = "user.two_fa_channel_changed")]
+    UserTwoFAChannelChanged,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UserSignedUp => "user.signed_up",
+            Self::UserLoggedIn => "user.logged_in",
+            Self::UserPhoneNumberVerified => "user.phone_number_verified",
+            Self::UserTwoFAChannelChanged => "user.two_fa_channel_changed",
+        }
+    }
+}
+
+impl FromStr for WebhookEventType {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "user.signed_up" => Ok(Self::UserSignedUp),
+            "user.logged_in" => Ok(Self::UserLoggedIn),
+            "user.phone_number_verified" => Ok(Self::UserPhoneNumberVerified),
+            "user.two_fa_channel_changed" => Ok(Self::UserTwoFAChannelChanged),
+            _ => Err(eyre!("unknown webhook event type: {}", s)),
+        }
+    }
+}
+
+// JSON body POSTed to subscribers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookEvent {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: WebhookEventType,
+    pub occurred_at: DateTime<Utc>,
+    pub data: WebhookEventData,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookEventData {
+    pub email: String,
+    // the new channel, for `user.two_fa_channel_changed`
+    #[serde(
+        rename = "twoFAChannel",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub two_fa_channel: Option<TwoFAChannel>,
+}
+
+impl WebhookEvent {
+    pub fn new(event_type: WebhookEventType, email: &Email) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            event_type,
+            occurred_at: Utc::now(),
+            data: WebhookEventData {
+                email: email.as_ref().expose_secret().to_owned(),
+                two_fa_channel: None,
+            },
+        }
+    }
+
+    pub fn with_two_fa_channel(mut self, channel: TwoFAChannel) -> Self {
+        self.data.two_fa_channel = Some(channel);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookSubscription {
+    pub id: Uuid,
+    pub url: String,
+    // shared with the subscriber to verify the HMAC signature; stored encrypted
+    pub secret: Secret<String>,
+    pub event_types: Vec<WebhookEventType>,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    // retries exhausted, kept for inspection and manual replay
+    Dead,
+}
+
+impl WebhookDeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Dead => "dead",
+        }
+    }
+}
+
+impl FromStr for WebhookDeliveryStatus {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "delivered" => Ok(Self::Delivered),
+            "dead" => Ok(Self::Dead),
+            _ => Err(eyre!("unknown webhook delivery status: {}", s)),
+        }
+    }
+}
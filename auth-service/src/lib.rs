@@ -1,8 +1,10 @@
 use app_state::AppState;
 use axum::{
+    extract::connect_info::{ConnectInfo, IntoMakeServiceWithConnectInfo},
     http::StatusCode,
+    middleware::{self, AddExtension},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{get, post},
     serve::Serve,
     Json, Router,
 };
@@ -11,18 +13,37 @@ use redis::{Client, RedisResult};
 
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::error::Error;
+use sqlx::{
+    postgres::PgPoolOptions,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    PgPool, SqlitePool,
+};
+use std::{error::Error, future::IntoFuture, net::SocketAddr, str::FromStr, time::Duration};
 use utils::{
-    constants::env::DROPLET_IP,
-    tracing::{make_span_with_request_id, on_request, on_response},
+    configuration::ShutdownSettings,
+    cors::cors_layer,
+    csrf::CsrfLayer,
+    metrics::{metrics_handler, prometheus_handle, track_http_metrics},
+    shutdown::ShutdownHandle,
+    tracing::{make_span_with_request_id, on_request, on_response, propagate_trace_context},
+};
+
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+    trace::TraceLayer,
 };
 
-use tower_http::{cors::CorsLayer, services::ServeDir, trace::TraceLayer};
+type AppServer = Serve<
+    IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    AddExtension<Router, ConnectInfo<SocketAddr>>,
+>;
 
 pub struct Application {
-    server: Serve<Router, Router>,
+    server: AppServer,
     pub address: String,
+    shutdown: ShutdownHandle,
+    shutdown_settings: ShutdownSettings,
 }
 
 pub mod app_state;
@@ -31,49 +52,125 @@ pub mod services;
 pub mod utils;
 
 pub mod routes;
-use routes::{login, logout, signup, verify_2fa, verify_token};
+use routes::{
+    audit_events, csrf_token, dev_mailbox, email_delivery, health_live, health_ready, login,
+    logout, resend_2fa, set_2fa_channel, signup, start_phone_verification, verify_2fa,
+    verify_phone_number, verify_token,
+};
 
 impl Application {
     pub async fn build(app_state: AppState, address: &str) -> Result<Self, Box<dyn Error>> {
-        let droplet_ip = DROPLET_IP;
+        let shutdown = app_state.shutdown.clone();
+        let shutdown_settings = app_state.settings.shutdown.clone();
 
-        let allowed_origins: [axum::http::HeaderValue; 4] = [
-            "http://localhost:3000".parse()?,
-            "http://localhost:8080".parse()?,
-            "http://172.17.0.1".parse()?,
-            format!("https://{}:8000", droplet_ip).parse()?,
-        ];
+        // install the recorder before any request is handled
+        prometheus_handle();
 
-        let cors = CorsLayer::new()
-            .allow_credentials(true)
-            .allow_origin(allowed_origins);
+        let cors = cors_layer(&app_state.settings.cors);
+        let csrf = CsrfLayer::new(
+            app_state.settings.csrf.clone(),
+            app_state.settings.auth.cookie.cookie_name(),
+        );
 
-        let router = Router::new()
+        let mut routes = Router::new()
             .nest_service("/", ServeDir::new("assets"))
             .route("/signup", post(signup))
             .route("/login", post(login))
             .route("/logout", post(logout))
             .route("/verify-2fa", post(verify_2fa))
+            .route("/resend-2fa", post(resend_2fa))
             .route("/verify-token", post(verify_token))
+            .route("/csrf-token", get(csrf_token))
+            .route("/health/live", get(health_live))
+            .route("/health/ready", get(health_ready));
+        if app_state.settings.serves_dev_mailbox() {
+            routes = routes.route("/dev/mailbox", get(dev_mailbox));
+        }
+        if app_state.settings.telemetry.metrics_token.is_some() {
+            routes = routes.route("/metrics", get(metrics_handler));
+        }
+        if app_state.settings.admin.token.is_some() {
+            routes = routes
+                .route("/admin/audit-events", get(audit_events))
+                .route("/admin/emails/:id", get(email_delivery));
+        }
+        if app_state.sms_client.is_some() {
+            routes = routes
+                .route("/phone-number", post(start_phone_verification))
+                .route("/phone-number/verify", post(verify_phone_number))
+                .route("/2fa-channel", post(set_2fa_channel));
+        }
+
+        let router = routes
             .with_state(app_state)
+            .layer(csrf)
+            .layer(middleware::from_fn(track_http_metrics))
             .layer(cors)
+            .layer(middleware::from_fn(propagate_trace_context))
             .layer(
                 TraceLayer::new_for_http()
                     .make_span_with(make_span_with_request_id)
                     .on_request(on_request)
                     .on_response(on_response),
-            );
+            )
+            // echo the id back to the caller
+            .layer(PropagateRequestIdLayer::x_request_id())
+            // outermost, so the id exists before the trace span is created
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
 
         let listener = tokio::net::TcpListener::bind(address).await?;
         let address = listener.local_addr()?.to_string();
-        let server = axum::serve(listener, router);
+        let server = axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        );
 
-        Ok(Application { server, address })
+        Ok(Application {
+            server,
+            address,
+            shutdown,
+            shutdown_settings,
+        })
     }
 
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    // Serves until the shutdown handle is triggered. Readiness then reports unhealthy for
+    // `unready_delay_ms` while requests are still served, so load balancers see enough failed
+    // checks to stop routing here.
+    // After that no new connections are accepted and in-flight requests get `drain_timeout_secs`.
     pub async fn run(self) -> Result<(), std::io::Error> {
         tracing::info!("listening on {}", &self.address);
-        self.server.await
+
+        let unready_delay = Duration::from_millis(self.shutdown_settings.unready_delay_ms);
+        let drain_timeout = Duration::from_secs(self.shutdown_settings.drain_timeout_secs);
+
+        let shutdown = self.shutdown.clone();
+        let stop_accepting = async move {
+            shutdown.wait().await;
+            tokio::time::sleep(unready_delay).await;
+            tracing::info!("draining in-flight requests");
+        };
+        let server = self
+            .server
+            .with_graceful_shutdown(stop_accepting)
+            .into_future();
+
+        let shutdown = self.shutdown;
+        let drain_deadline = async move {
+            shutdown.wait().await;
+            tokio::time::sleep(unready_delay + drain_timeout).await;
+        };
+
+        tokio::select! {
+            result = server => result,
+            _ = drain_deadline => {
+                tracing::warn!("drain timeout elapsed, dropping remaining connections");
+                Ok(())
+            }
+        }
     }
 }
 
@@ -93,6 +190,13 @@ impl IntoResponse for AuthAPIError {
             }
             AuthAPIError::MissingToken => (StatusCode::BAD_REQUEST, "Missing token"),
             AuthAPIError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
+            AuthAPIError::InvalidCsrfToken => (StatusCode::FORBIDDEN, "Invalid CSRF token"),
+            AuthAPIError::InvalidInput => (StatusCode::BAD_REQUEST, "Invalid input"),
+            AuthAPIError::InvalidPhoneNumber => (StatusCode::BAD_REQUEST, "Invalid phone number"),
+            AuthAPIError::PhoneNumberNotVerified => {
+                (StatusCode::CONFLICT, "Phone number not verified")
+            }
+            AuthAPIError::TooManyRequests => (StatusCode::TOO_MANY_REQUESTS, "Too many requests"),
             AuthAPIError::UnexpectedError(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Unexpected error")
             }
@@ -121,13 +225,29 @@ fn log_error_chain(e: &(dyn Error + 'static)) {
     tracing::error!("{}", report);
 }
 
-pub async fn get_postgres_pool(url: &Secret<String>) -> Result<PgPool, sqlx::Error> {
+pub async fn get_postgres_pool(
+    url: &Secret<String>,
+    max_connections: u32,
+) -> Result<PgPool, sqlx::Error> {
     PgPoolOptions::new()
-        .max_connections(5)
+        .max_connections(max_connections)
         .connect(url.expose_secret())
         .await
 }
 
+// Creates the database file if it does not exist yet
+pub async fn get_sqlite_pool(
+    url: &Secret<String>,
+    max_connections: u32,
+) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(url.expose_secret())?.create_if_missing(true);
+
+    SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .connect_with(options)
+        .await
+}
+
 pub fn get_redis_client(
     redis_hostname: String,
     redis_password: String,
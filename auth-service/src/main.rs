@@ -1,68 +1,274 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use auth_service::app_state::AppState;
+use auth_service::app_state::{
+    AppState, AuditSinkType, EmailClientType, EmailOutboxType, HealthCheckType, SmsClientType,
+    TokenStoreType, TwoFACodeStoreType, UserStoreType,
+};
 
+use auth_service::domain::EmailSender;
 use auth_service::services::aws_email_client::AWSEmailClient;
+use auth_service::services::capturing_email_client::CapturingEmailClient;
+use auth_service::services::capturing_sms_client::CapturingSmsClient;
+use auth_service::services::data_stores::expired_rows_purger::ExpiredRowsPurger;
+use auth_service::services::data_stores::hashmap_two_fa_code_store::HashmapTwoFACodeStore;
+use auth_service::services::data_stores::hashmap_user_store::HashmapUserStore;
+use auth_service::services::data_stores::hashset_banned_token_store::HashsetBannedTokenStore;
+use auth_service::services::data_stores::in_memory_audit_sink::InMemoryAuditSink;
+use auth_service::services::data_stores::in_memory_email_outbox::InMemoryEmailOutbox;
+use auth_service::services::data_stores::json_lines_audit_sink::JsonLinesAuditSink;
+use auth_service::services::data_stores::postgres_audit_sink::PostgresAuditSink;
+use auth_service::services::data_stores::postgres_banned_token_store::PostgresBannedTokenStore;
+use auth_service::services::data_stores::postgres_email_outbox::PostgresEmailOutbox;
+use auth_service::services::data_stores::postgres_two_fa_code_store::PostgresTwoFACodeStore;
 use auth_service::services::data_stores::postgres_user_store::PostgresUserStore;
 use auth_service::services::data_stores::redis_banned_token_store::RedisBannedTokenStore;
 use auth_service::services::data_stores::redis_two_fa_code_store::RedisTwoFACodeStore;
+use auth_service::services::data_stores::sqlite_email_outbox::SqliteEmailOutbox;
+use auth_service::services::data_stores::sqlite_user_store::SqliteUserStore;
+use auth_service::services::email_delivery::dispatcher::EmailDispatcher;
+use auth_service::services::email_delivery::failover::FailoverEmailClient;
+use auth_service::services::email_delivery::EmailQueue;
+use auth_service::services::email_templates::EmailTemplates;
+use auth_service::services::file_outbox_email_client::FileOutboxEmailClient;
+use auth_service::services::health::{
+    EmailHealthCheck, PostgresHealthCheck, RedisHealthCheck, SmsHealthCheck, SqliteHealthCheck,
+};
+use auth_service::services::postmark_email_client::PostmarkEmailClient;
+use auth_service::services::smtp_email_client::SmtpEmailClient;
+use auth_service::services::twilio_sms_client::TwilioSmsClient;
+use auth_service::services::webhooks::dispatcher::WebhookDispatcher;
 
-use auth_service::utils::configuration::{PostgresSettings, RedisSettings, CONFIG};
+use auth_service::utils::configuration::{
+    AuditSettings, AuditSinkKind, AwsSesSettings, EmailOutboxBackend, EmailProvider,
+    PostgresSettings, RedisSettings, Settings, SmsProvider, SmsSettings, SqliteSettings,
+    StoreBackend, UserStoreBackend, WebhookSettings,
+};
 
-use auth_service::utils::tracing::init_tracing;
-use auth_service::{get_postgres_pool, get_redis_client, Application};
+use auth_service::utils::keyed_hash::KeyedHasher;
+use auth_service::utils::secret_cipher::SecretCipher;
+use auth_service::utils::shutdown::trigger_on_signal;
+use auth_service::utils::tracing::{init_tracing, shutdown_tracing};
+use auth_service::{get_postgres_pool, get_redis_client, get_sqlite_pool, Application};
 
 use aws_config::meta::region::RegionProviderChain;
 use aws_config::Region;
+use aws_sdk_sesv2::config::Credentials;
 use aws_sdk_sesv2::Client as AWSClient;
+use secrecy::ExposeSecret;
 
-use sqlx::PgPool;
+use sqlx::{PgPool, SqlitePool};
 use tokio::sync::RwLock;
 
 #[tokio::main]
 async fn main() {
     color_eyre::install().expect("Failed to install color_eyre");
-    init_tracing().expect("Failed to initialize tracing");
+    let configuration = &Settings::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    init_tracing(&configuration.telemetry).expect("Failed to initialize tracing");
 
-    let configuration = &*CONFIG;
+    // only connected when a backend uses it; `Settings::validate` ensures it is configured then
+    let pg_pool = if configuration.uses_postgres() {
+        Some(
+            configure_postgresql(&configuration.postgres)
+                .await
+                .expect("Failed to configure PostgreSQL"),
+        )
+    } else {
+        None
+    };
+    let postgres = || {
+        pg_pool
+            .clone()
+            .expect("Postgres is used by a backend but was not connected")
+    };
 
-    let postgres_settings = &configuration.postgres;
-    let redis_settings = &configuration.redis;
-    let pg_pool: sqlx::Pool<sqlx::Postgres> = configure_postgresql(&postgres_settings)
-        .await
-        .expect("Failed to configure PostgreSQL");
-    let redis_config = configure_redis(&redis_settings);
-    let redis_code_config = configure_redis(&redis_settings);
-
-    let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
-    let token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(Arc::new(
-        RwLock::new(redis_config),
-    ))));
-    let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(Arc::new(
-        RwLock::new(redis_code_config),
-    ))));
-
-    // let email_client = Arc::new(configure_postmark_email_client());
-    let email_client = Arc::new(configure_aws_ses_client(&configuration.region).await);
-
-    let app_state: AppState = AppState::new(
+    let audit_sink = configure_audit_sink(&configuration.audit, postgres);
+
+    let mut health_checks: Vec<HealthCheckType> = Vec::new();
+    if let Some(pg_pool) = &pg_pool {
+        health_checks.push(Arc::new(PostgresHealthCheck::new(pg_pool.clone())));
+    }
+    // the user store's database, which the email outbox may share; kept to close it after
+    // the server has stopped
+    let sqlite_pool = if configuration.stores.users == UserStoreBackend::Sqlite {
+        let pool = configure_sqlite(&configuration.sqlite)
+            .await
+            .expect("Failed to configure SQLite");
+        health_checks.push(Arc::new(SqliteHealthCheck::new(pool.clone())));
+        Some(pool)
+    } else {
+        None
+    };
+    // `Settings::validate` ensures only the `sqlite` user store's backends use it
+    let sqlite = || {
+        sqlite_pool
+            .clone()
+            .expect("SQLite is used by a backend but was not connected")
+    };
+
+    let email_client = Arc::new(configure_email_client(configuration).await);
+    let email_outbox: EmailOutboxType = match configuration.stores.email_outbox {
+        EmailOutboxBackend::Postgres => Arc::new(PostgresEmailOutbox::new(postgres())),
+        EmailOutboxBackend::Sqlite => Arc::new(SqliteEmailOutbox::new(sqlite())),
+        EmailOutboxBackend::InMemory => Arc::new(InMemoryEmailOutbox::default()),
+    };
+    let email_queue = EmailQueue::new(email_outbox);
+
+    // kept to close them after the server has stopped
+    let mut redis_conns = Vec::new();
+
+    let user_store: UserStoreType = match configuration.stores.users {
+        UserStoreBackend::Postgres => Arc::new(RwLock::new(PostgresUserStore::new(
+            postgres(),
+            configuration.auth.password_hashing,
+        ))),
+        UserStoreBackend::Sqlite => Arc::new(RwLock::new(SqliteUserStore::new(
+            sqlite(),
+            configuration.auth.password_hashing,
+        ))),
+        UserStoreBackend::InMemory => Arc::new(RwLock::new(HashmapUserStore::new(
+            configuration.auth.password_hashing,
+        ))),
+    };
+
+    let hasher = KeyedHasher::from_settings(configuration);
+    let token_store: TokenStoreType = match configuration.stores.banned_tokens {
+        StoreBackend::Redis => {
+            let redis_conn = Arc::new(RwLock::new(configure_redis(&configuration.redis)));
+            redis_conns.push(redis_conn.clone());
+            if configuration.stores.hash_legacy_banned_tokens {
+                hash_legacy_banned_tokens(configuration, hasher.clone());
+            }
+            Arc::new(RwLock::new(RedisBannedTokenStore::new(
+                redis_conn,
+                configuration.auth.token_ttl_seconds,
+                hasher.clone(),
+            )))
+        }
+        StoreBackend::Postgres => Arc::new(RwLock::new(PostgresBannedTokenStore::new(
+            postgres(),
+            configuration.auth.token_ttl_seconds,
+            hasher.clone(),
+        ))),
+        StoreBackend::InMemory => Arc::new(RwLock::new(HashsetBannedTokenStore::new(
+            configuration.auth.token_ttl_seconds,
+        ))),
+    };
+    let two_fa_code_store: TwoFACodeStoreType = match configuration.stores.two_fa_codes {
+        StoreBackend::Redis => {
+            let redis_conn = Arc::new(RwLock::new(configure_redis(&configuration.redis)));
+            redis_conns.push(redis_conn.clone());
+            Arc::new(RwLock::new(RedisTwoFACodeStore::new(
+                redis_conn,
+                hasher.clone(),
+            )))
+        }
+        StoreBackend::Postgres => Arc::new(RwLock::new(PostgresTwoFACodeStore::new(
+            postgres(),
+            hasher.clone(),
+        ))),
+        StoreBackend::InMemory => Arc::new(RwLock::new(HashmapTwoFACodeStore::default())),
+    };
+    if !redis_conns.is_empty() {
+        health_checks.push(Arc::new(RedisHealthCheck::new(redis_conns.clone())));
+    }
+
+    if configuration.health.check_email_provider {
+        health_checks.push(Arc::new(EmailHealthCheck::new(email_client.clone())));
+    }
+    let sms_client = configuration
+        .sms
+        .enabled
+        .then(|| configure_sms_client(&configuration.sms));
+    if let (Some(sms_client), true) = (&sms_client, configuration.health.check_sms_provider) {
+        health_checks.push(Arc::new(SmsHealthCheck::new(sms_client.clone())));
+    }
+
+    let mut app_state: AppState = AppState::new(
         user_store,
         token_store,
         two_fa_code_store,
-        email_client,
+        email_queue.clone(),
+        audit_sink,
+        Arc::new(health_checks),
         configuration.clone(),
+    )
+    .with_email_templates(
+        EmailTemplates::load(&configuration.email.templates)
+            .expect("Failed to load email templates"),
     );
+    if let Some(sms_client) = sms_client {
+        app_state = app_state.with_sms_client(sms_client);
+    }
     let app: Application = Application::build(app_state, &configuration.app_address)
         .await
         .expect("Failed to build application");
 
+    let shutdown = app.shutdown_handle();
+    tokio::spawn(trigger_on_signal(shutdown.clone()));
+    let webhook_dispatcher = configuration.webhooks.enabled.then(|| {
+        tokio::spawn(
+            configure_webhook_dispatcher(
+                &configuration.webhooks,
+                SecretCipher::from_settings(configuration),
+                postgres(),
+            )
+            .run(shutdown.clone()),
+        )
+    });
+    let email_dispatcher = configuration.email.outbox.enabled.then(|| {
+        tokio::spawn(
+            EmailDispatcher::new(email_queue, email_client, &configuration.email.outbox)
+                .run(shutdown.clone()),
+        )
+    });
+    let expired_rows_purger = configuration.stores.uses(StoreBackend::Postgres).then(|| {
+        tokio::spawn(
+            ExpiredRowsPurger::new(
+                postgres(),
+                Duration::from_secs(configuration.stores.purge_interval_secs),
+            )
+            .run(shutdown.clone()),
+        )
+    });
+
     app.run().await.expect("Failed to run application");
+
+    // in-flight requests are done; stop background work before closing what it uses
+    if let Some(webhook_dispatcher) = webhook_dispatcher {
+        if let Err(e) = webhook_dispatcher.await {
+            tracing::error!(error = ?e, "Webhook dispatcher failed");
+        }
+    }
+    if let Some(email_dispatcher) = email_dispatcher {
+        if let Err(e) = email_dispatcher.await {
+            tracing::error!(error = ?e, "Email dispatcher failed");
+        }
+    }
+    if let Some(expired_rows_purger) = expired_rows_purger {
+        if let Err(e) = expired_rows_purger.await {
+            tracing::error!(error = ?e, "Expired rows purger failed");
+        }
+    }
+    if let Some(pg_pool) = pg_pool {
+        pg_pool.close().await;
+    }
+    if let Some(sqlite_pool) = sqlite_pool {
+        sqlite_pool.close().await;
+    }
+    // the server has dropped its stores, so these are the last references
+    drop(redis_conns);
+    tracing::info!("Shutdown complete");
+    shutdown_tracing();
 }
 
 async fn configure_postgresql(
     settings: &PostgresSettings,
 ) -> Result<PgPool, Box<dyn std::error::Error>> {
-    let pg_pool = get_postgres_pool(&settings.database_url)
+    let pg_pool = get_postgres_pool(&settings.database_url, settings.max_connections)
         .await
         .map_err(|e| {
             eprintln!("Failed to create Postgres connection pool: {:?}", e);
@@ -78,38 +284,190 @@ async fn configure_postgresql(
     Ok(pg_pool)
 }
 
-fn configure_redis(settings: &RedisSettings) -> redis::Connection {
+async fn configure_sqlite(
+    settings: &SqliteSettings,
+) -> Result<SqlitePool, Box<dyn std::error::Error>> {
+    let sqlite_pool = get_sqlite_pool(&settings.database_url, settings.max_connections)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to create SQLite connection pool: {:?}", e);
+            e
+        })?;
+
+    sqlx::migrate!("./migrations_sqlite")
+        .run(&sqlite_pool)
+        .await
+        .expect("Failed to run SQLite migrations");
+
+    Ok(sqlite_pool)
+}
+
+fn configure_audit_sink(
+    settings: &AuditSettings,
+    postgres: impl FnOnce() -> PgPool,
+) -> AuditSinkType {
+    match settings.sink {
+        AuditSinkKind::Postgres => Arc::new(PostgresAuditSink::new(postgres())),
+        AuditSinkKind::JsonLines => Arc::new(JsonLinesAuditSink::new(&settings.file_path)),
+        AuditSinkKind::InMemory => Arc::new(InMemoryAuditSink::default()),
+    }
+}
+
+fn configure_webhook_dispatcher(
+    settings: &WebhookSettings,
+    cipher: SecretCipher,
+    pg_pool: PgPool,
+) -> WebhookDispatcher {
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(settings.timeout_ms))
+        .build()
+        .expect("Failed to build HTTP client");
+
+    WebhookDispatcher::new(pg_pool, http_client, cipher, settings)
+}
+
+fn redis_client(settings: &RedisSettings) -> redis::Client {
     get_redis_client(
         settings.host_name.to_owned(),
         settings.password.to_owned(),
         settings.port.to_owned(),
     )
     .expect("Failed to get Redis client")
-    .get_connection()
-    .expect("Failed to get Redis connection")
 }
 
-// fn configure_postmark_email_client() -> PostmarkEmailClient {
-//     let configuration = get_configuration().expect("Failed to get configurations.");
+fn configure_redis(settings: &RedisSettings) -> redis::Connection {
+    redis_client(settings)
+        .get_connection()
+        .expect("Failed to get Redis connection")
+}
+
+// Runs in the background on a connection of its own, so the SCAN never holds up requests;
+// a failure is logged and can be retried by restarting with the setting still on
+fn hash_legacy_banned_tokens(configuration: &Settings, hasher: KeyedHasher) {
+    let conn = match redis_client(&configuration.redis).get_connection() {
+        Ok(conn) => conn,
+        Err(e) => {
+            tracing::error!(error = ?e, "Failed to connect to Redis to hash legacy banned tokens");
+            return;
+        }
+    };
+    let store = RedisBannedTokenStore::new(
+        Arc::new(RwLock::new(conn)),
+        configuration.auth.token_ttl_seconds,
+        hasher,
+    );
+    tokio::spawn(async move {
+        match store.hash_legacy_keys().await {
+            Ok(count) => tracing::info!(count, "Hashed legacy banned tokens"),
+            Err(e) => tracing::error!(error = ?e, "Failed to hash legacy banned tokens"),
+        }
+    });
+}
 
-//     let http_client = Client::builder()
-//         .timeout(prod::email_client::TIMEOUT)
-//         .build()
-//         .expect("Failed to build HTTP client");
+// The provider followed by its fallbacks
+async fn configure_email_client(configuration: &Settings) -> FailoverEmailClient {
+    let mut providers = Vec::new();
+    for provider in configuration.email.providers() {
+        providers.push((
+            provider.name(),
+            configure_email_provider(provider, configuration).await,
+        ));
+    }
+    FailoverEmailClient::new(providers)
+}
 
-//     PostmarkEmailClient::new(
-//         prod::email_client::BASE_URL.to_owned(),
-//         Email::parse(Secret::new(prod::email_client::SENDER.to_owned())).unwrap(),
-//         configuration.postmark_auth_token.to_owned(),
-//         http_client,
-//     )
-// }
+async fn configure_email_provider(
+    provider: EmailProvider,
+    configuration: &Settings,
+) -> EmailClientType {
+    let settings = &configuration.email;
+    // validated on load
+    let sender = settings.sender().expect("Invalid email sender");
+
+    match provider {
+        EmailProvider::AwsSes => {
+            let region = settings
+                .aws_ses
+                .region
+                .clone()
+                .or_else(|| configuration.region.clone());
+            Arc::new(configure_aws_ses_client(&settings.aws_ses, region, sender).await)
+        }
+        EmailProvider::Postmark => {
+            let http_client = reqwest::Client::builder()
+                .timeout(Duration::from_millis(settings.postmark.timeout_ms))
+                .build()
+                .expect("Failed to build HTTP client");
+            Arc::new(PostmarkEmailClient::new(
+                settings.postmark.base_url.to_owned(),
+                sender,
+                settings.postmark.server_token.to_owned(),
+                http_client,
+            ))
+        }
+        EmailProvider::Smtp => Arc::new(
+            SmtpEmailClient::new(&settings.smtp, sender)
+                .expect("Failed to configure the SMTP email client"),
+        ),
+        EmailProvider::Mock => {
+            tracing::warn!("email provider mock: emails are kept in memory and never sent");
+            Arc::new(CapturingEmailClient::default())
+        }
+        EmailProvider::FileOutbox => {
+            tracing::warn!(
+                "email provider file_outbox: emails are written to {} and listed at /dev/mailbox",
+                settings.file_outbox.dir
+            );
+            Arc::new(FileOutboxEmailClient::new(
+                &settings.file_outbox.dir,
+                sender,
+            ))
+        }
+    }
+}
+
+fn configure_sms_client(settings: &SmsSettings) -> SmsClientType {
+    match settings.provider {
+        SmsProvider::Twilio => {
+            let http_client = reqwest::Client::builder()
+                .timeout(Duration::from_millis(settings.twilio.timeout_ms))
+                .build()
+                .expect("Failed to build HTTP client");
+            Arc::new(TwilioSmsClient::new(
+                settings.twilio.base_url.to_owned(),
+                settings.twilio.account_sid.to_owned(),
+                settings.twilio.auth_token.to_owned(),
+                // validated on load
+                settings.twilio.from().expect("Invalid SMS sender"),
+                http_client,
+            ))
+        }
+        SmsProvider::Mock => {
+            tracing::warn!("sms provider mock: texts and calls are kept in memory and never sent");
+            Arc::new(CapturingSmsClient::default())
+        }
+    }
+}
 
-async fn configure_aws_ses_client(region: &Option<String>) -> AWSEmailClient {
-    let region_provider = RegionProviderChain::first_try(region.to_owned().map(Region::new))
+async fn configure_aws_ses_client(
+    settings: &AwsSesSettings,
+    region: Option<String>,
+    sender: EmailSender,
+) -> AWSEmailClient {
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
         .or_default_provider()
         .or_else(Region::new("us-west-1"));
-    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let mut loader = aws_config::from_env().region(region_provider);
+    if let Some(access_key_id) = &settings.access_key_id {
+        loader = loader.credentials_provider(Credentials::new(
+            access_key_id,
+            settings.secret_access_key.expose_secret(),
+            None,
+            None,
+            "settings",
+        ));
+    }
+    let shared_config = loader.load().await;
     let client = AWSClient::new(&shared_config);
-    AWSEmailClient::new(client)
+    AWSEmailClient::new(client, sender, settings.identity_arn.clone())
 }
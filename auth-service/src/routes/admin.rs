@@ -0,0 +1,104 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        audit::{AuditEvent, AuditEventKind, AuditOutcome, AuditQuery, AuditSinkError},
+        AuthAPIError,
+    },
+    utils::{admin_auth::AdminAuthorized, request_context::RequestContext},
+};
+
+const MAX_AUDIT_EVENTS_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct AuditEventsParams {
+    pub actor: Option<String>,
+    pub kind: Option<AuditEventKind>,
+    pub outcome: Option<AuditOutcome>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    // RFC 3339; `until` is exclusive
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl From<AuditEventsParams> for AuditQuery {
+    fn from(params: AuditEventsParams) -> Self {
+        AuditQuery {
+            actor: params.actor,
+            kind: params.kind,
+            outcome: params.outcome,
+            ip: params.ip,
+            request_id: params.request_id,
+            since: params.since,
+            until: params.until,
+            limit: params.limit.map(|limit| limit.min(MAX_AUDIT_EVENTS_LIMIT)),
+        }
+    }
+}
+
+// Audit events matching the filters, newest first; the lookup is itself audited
+#[tracing::instrument(name = "AuditEvents", skip_all)]
+pub async fn audit_events(
+    State(state): State<AppState>,
+    context: RequestContext,
+    _admin: AdminAuthorized,
+    Query(params): Query<AuditEventsParams>,
+) -> Result<Json<Vec<AuditEvent>>, AuthAPIError> {
+    let query = AuditQuery::from(params);
+    let result = state
+        .audit_sink
+        .query(&query)
+        .await
+        .map_err(|AuditSinkError::UnexpectedError(e)| AuthAPIError::UnexpectedError(e));
+
+    let mut event = AuditEvent::from_result(AuditEventKind::AdminAction, &result)
+        .with_actor("admin")
+        .with_context(&context);
+    if result.is_ok() {
+        event = event.with_reason(format!("audit events query: {:?}", query));
+    }
+    state.record_audit_event(event).await;
+
+    result.map(Json)
+}
+
+// Delivery status of a queued email, e.g. one named in a login's audit event;
+// 404 once a sent email has been purged
+#[tracing::instrument(name = "EmailDelivery", skip_all)]
+pub async fn email_delivery(
+    State(state): State<AppState>,
+    context: RequestContext,
+    _admin: AdminAuthorized,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AuthAPIError> {
+    let result = state
+        .email_queue
+        .outbox()
+        .delivery(id)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()));
+
+    let mut event = AuditEvent::from_result(AuditEventKind::AdminAction, &result)
+        .with_actor("admin")
+        .with_context(&context);
+    if result.is_ok() {
+        event = event.with_reason(format!("email delivery lookup: {}", id));
+    }
+    state.record_audit_event(event).await;
+
+    Ok(match result? {
+        Some(delivery) => Json(delivery).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
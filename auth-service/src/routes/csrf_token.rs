@@ -0,0 +1,39 @@
+use axum::{extract::State, Json};
+use axum_extra::extract::CookieJar;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    utils::csrf::{create_csrf_cookie, generate_csrf_token, is_well_formed},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsrfTokenResponse {
+    pub csrf_token: String,
+}
+
+// Issues the token the frontend sends back in the CSRF header.
+// An existing token is reused, so open tabs keep working.
+#[tracing::instrument(name = "CsrfToken", skip_all)]
+pub async fn csrf_token(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> (CookieJar, Json<CsrfTokenResponse>) {
+    let settings = &state.settings.csrf;
+
+    let token = jar
+        .get(&settings.cookie_name)
+        .map(|cookie| cookie.value())
+        .filter(|token| is_well_formed(token))
+        .map(str::to_owned)
+        .unwrap_or_else(generate_csrf_token);
+
+    let jar = jar.add(create_csrf_cookie(
+        settings,
+        state.settings.auth.cookie.secure,
+        token.clone(),
+    ));
+
+    (jar, Json(CsrfTokenResponse { csrf_token: token }))
+}
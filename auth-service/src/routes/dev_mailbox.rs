@@ -0,0 +1,68 @@
+use std::{net::SocketAddr, path::Path};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::{
+    app_state::AppState,
+    domain::error::AuthAPIError,
+    services::file_outbox_email_client::{read_outbox, OutboxMessage},
+    utils::html::escape_html,
+};
+
+const MAILBOX_LIMIT: usize = 50;
+
+// Only routed when `Settings::serves_dev_mailbox`; lists the newest emails in the outbox.
+// Answers clients on the same host only, as if it were not routed for anyone else.
+#[tracing::instrument(name = "Dev mailbox", skip_all)]
+pub async fn dev_mailbox(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+) -> Result<Response, AuthAPIError> {
+    if !peer.ip().to_canonical().is_loopback() {
+        tracing::warn!(%peer, "Refusing dev mailbox to a remote client");
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let outbox_dir = &state.settings.email.file_outbox.dir;
+    let messages = read_outbox(Path::new(outbox_dir), MAILBOX_LIMIT)
+        .await
+        .map_err(AuthAPIError::UnexpectedError)?;
+
+    Ok(Html(render_mailbox(outbox_dir, &messages)).into_response())
+}
+
+fn render_mailbox(outbox_dir: &str, messages: &[OutboxMessage]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Dev mailbox</title>\
+         <meta http-equiv=\"refresh\" content=\"5\"></head><body>\
+         <h1>Dev mailbox</h1><p>{} newest emails in <code>{}</code></p>",
+        messages.len(),
+        escape_html(outbox_dir)
+    );
+
+    if messages.is_empty() {
+        html.push_str("<p>No emails yet.</p>");
+    }
+    for message in messages {
+        html.push_str(&format!(
+            "<article><h2>{}</h2><p>To: {}<br>From: {}<br>Date: {}<br>File: {}</p>\
+             <pre>{}</pre><iframe sandbox srcdoc=\"{}\" title=\"HTML body\" \
+             style=\"width: 100%; height: 300px\"></iframe></article><hr>",
+            escape_html(&message.subject),
+            escape_html(&message.to),
+            escape_html(&message.from),
+            escape_html(&message.date),
+            escape_html(&message.file_name),
+            escape_html(&message.text_body),
+            // sandboxed, so scripts in it never run
+            escape_html(&message.html_body)
+        ));
+    }
+
+    html.push_str("</body></html>");
+    html
+}
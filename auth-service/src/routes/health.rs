@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    services::health::{run_health_checks, HealthReport, HealthStatus},
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct LivenessResponse {
+    pub status: HealthStatus,
+}
+
+// The process is up and serving requests; dependencies are not checked
+pub async fn health_live() -> impl IntoResponse {
+    Json(LivenessResponse {
+        status: HealthStatus::Ok,
+    })
+}
+
+// 200 only when every dependency answers within the timeout, 503 otherwise
+// and for as long as the instance is shutting down
+#[tracing::instrument(name = "Readiness", skip_all)]
+pub async fn health_ready(State(state): State<AppState>) -> impl IntoResponse {
+    if state.shutdown.is_shutting_down() {
+        let report = HealthReport {
+            status: HealthStatus::Draining,
+            checks: Default::default(),
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(report));
+    }
+
+    let timeout = Duration::from_millis(state.settings.health.timeout_ms);
+    let report = run_health_checks(&state.health_checks, timeout).await;
+
+    let status = match report.status {
+        HealthStatus::Ok => StatusCode::OK,
+        HealthStatus::Unavailable | HealthStatus::Draining => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, Json(report))
+}
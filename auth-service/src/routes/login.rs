@@ -1,19 +1,30 @@
+use std::time::Duration;
+
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 
 use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Utc};
+use metrics::counter;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use secrecy::{ExposeSecret, Secret};
 
 use crate::{
     app_state::AppState,
     domain::{
-        data_stores::{LoginAttemptId, TwoFACode},
+        audit::{AuditEvent, AuditEventKind},
+        data_stores::{LoginAttempt, LoginAttemptId, TwoFACode},
         email::Email,
         password::Password,
-        AuthAPIError,
+        AuthAPIError, TwoFAChannel, User,
+    },
+    services::{email_templates::EmailTemplate, phone_codes::send_code},
+    utils::{
+        auth::generate_auth_cookie,
+        metrics::{outcome_label, LOGINS_TOTAL, TWO_FA_CODES_SENT_TOTAL},
+        request_context::RequestContext,
     },
-    utils::auth::generate_auth_cookie,
 };
 
 #[derive(Deserialize)]
@@ -25,9 +36,34 @@ pub struct LoginRequest {
 #[tracing::instrument(name = "Login", skip_all)]
 pub async fn login(
     State(state): State<AppState>,
+    context: RequestContext,
     jar: CookieJar,
     Json(request): Json<LoginRequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let actor = request.email.expose_secret().to_owned();
+    let (jar, result) = authenticate(&state, &context, jar, request).await;
+    counter!(LOGINS_TOTAL, "outcome" => outcome_label(&result)).increment(1);
+
+    let mut event = AuditEvent::from_result(AuditEventKind::Login, &result)
+        .with_actor(actor)
+        .with_context(&context);
+    if let Ok((_, Json(LoginResponse::TwoFactorAuth(response)))) = &result {
+        event = event.with_reason(code_delivery_reason("2FA required", response.email_id));
+    }
+    state.record_audit_event(event).await;
+
+    (jar, result)
+}
+
+async fn authenticate(
+    state: &AppState,
+    context: &RequestContext,
+    jar: CookieJar,
+    request: LoginRequest,
+) -> (
+    CookieJar,
+    Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
+) {
     let email = match Email::parse(request.email) {
         Ok(email) => email,
         Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
@@ -38,26 +74,30 @@ pub async fn login(
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let user_store = &state.user_store.read().await;
+    // released before a code is sent, which may wait for the SMS provider
+    let user = {
+        let user_store = state.user_store.read().await;
 
-    if user_store.validate_user(&email, &password).await.is_err() {
-        return (jar, Err(AuthAPIError::IncorrectCredentials));
-    }
-    let user = match user_store.get_user(&email).await {
-        Ok(user) => user,
-        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+        if user_store.validate_user(&email, &password).await.is_err() {
+            return (jar, Err(AuthAPIError::IncorrectCredentials));
+        }
+        match user_store.get_user(&email).await {
+            Ok(user) => user,
+            Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+        }
     };
 
     match user.requires_2fa {
-        true => handle_2fa(&user.email, &state, jar).await,
-        false => handle_no_2fa(&user.email, jar).await,
+        true => handle_2fa(&user, state, context, jar).await,
+        false => handle_no_2fa(&user.email, state, jar).await,
     }
 }
 
 #[tracing::instrument(name = "Handle2FA", skip_all)]
 async fn handle_2fa(
-    email: &Email,
+    user: &User,
     state: &AppState,
+    context: &RequestContext,
     jar: CookieJar,
 ) -> (
     CookieJar,
@@ -65,44 +105,141 @@ async fn handle_2fa(
 ) {
     let login_attempt_id: LoginAttemptId = LoginAttemptId::default();
     let two_fa_code = TwoFACode::default();
+    let attempt = LoginAttempt::new(
+        user.email.clone(),
+        Duration::from_secs(state.settings.auth.two_fa_code_ttl_seconds),
+    );
+    let expires_at = attempt.expires_at;
 
+    // every login has its own attempt, so the user's other pending logins stay valid
     if let Err(e) = state
         .two_fa_code_store
         .write()
         .await
-        .add_code(email.clone(), login_attempt_id.clone(), two_fa_code.clone())
+        .add_code(login_attempt_id.clone(), attempt, two_fa_code.clone())
         .await
     {
         return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
     }
 
-    if let Err(e) = state
-        .email_client
-        .send_email(email, "2FA Code", two_fa_code.as_ref().expose_secret())
-        .await
+    let (channel, email_id) = match deliver_code(
+        state,
+        context,
+        user,
+        &login_attempt_id,
+        &two_fa_code,
+        expires_at,
+    )
+    .await
     {
-        return (jar, Err(AuthAPIError::UnexpectedError(e)));
-    }
+        Ok(delivery) => delivery,
+        Err(e) => return (jar, Err(e)),
+    };
 
     let response: Json<LoginResponse> = Json(LoginResponse::TwoFactorAuth(TwoFactorAuthResponse {
         message: "2FA required".to_owned(),
         login_attempt_id: login_attempt_id.as_ref().expose_secret().to_owned(),
+        channel,
+        email_id,
     }));
     (jar, Ok((StatusCode::PARTIAL_CONTENT, response)))
 }
 
+// Sends a stored attempt's code over the user's channel, returning the channel used and,
+// for emailed codes, the id of the queued email
+pub(super) async fn deliver_code(
+    state: &AppState,
+    context: &RequestContext,
+    user: &User,
+    login_attempt_id: &LoginAttemptId,
+    two_fa_code: &TwoFACode,
+    expires_at: DateTime<Utc>,
+) -> Result<(TwoFAChannel, Option<Uuid>), AuthAPIError> {
+    let expires_in_seconds = u64::try_from((expires_at - Utc::now()).num_seconds()).unwrap_or(0);
+    let expires_in_minutes = expires_in_seconds.div_ceil(60);
+    let preferred_locales = user.preferred_locales(&context.languages);
+    let (channel, sent) = match (&user.phone_number, &state.sms_client) {
+        (Some(phone_number), Some(sms_client)) if user.two_fa_channel.uses_phone() => {
+            // texts and calls are not queued; the user is waiting for the code
+            let sent = send_code(
+                sms_client.as_ref(),
+                &state.email_templates,
+                &preferred_locales,
+                user.two_fa_channel,
+                phone_number,
+                two_fa_code,
+                expires_in_minutes,
+            )
+            .await;
+            (user.two_fa_channel, sent.map(|_| None))
+        }
+        _ => {
+            if user.two_fa_channel.uses_phone() {
+                tracing::warn!("SMS is disabled, emailing the 2FA code instead");
+            }
+            let message = state.email_templates.render(
+                &EmailTemplate::TwoFaCode {
+                    code: two_fa_code.as_ref().expose_secret().to_owned(),
+                    expires_in_minutes,
+                },
+                &preferred_locales,
+            );
+            // the dispatcher sends it, so a provider outage only delays the code,
+            // up to the point where it would no longer work
+            let sent = state
+                .email_queue
+                .enqueue(&user.email, &message, Some(expires_at))
+                .await
+                .map(Some)
+                .map_err(Into::into);
+            (TwoFAChannel::Email, sent)
+        }
+    };
+    let email_id = match sent {
+        Ok(email_id) => email_id,
+        Err(e) => {
+            // don't leave a login attempt whose code the user can never receive
+            if let Err(e) = state
+                .two_fa_code_store
+                .write()
+                .await
+                .remove_code(login_attempt_id)
+                .await
+            {
+                tracing::error!(error = ?e, "Failed to remove undeliverable 2FA code");
+            }
+            return Err(AuthAPIError::UnexpectedError(e));
+        }
+    };
+    counter!(TWO_FA_CODES_SENT_TOTAL, "channel" => channel.as_str()).increment(1);
+
+    Ok((channel, email_id))
+}
+
+// The audit reason of a sent code; the email id is what `/admin/emails/{id}` takes
+pub(super) fn code_delivery_reason(reason: &str, email_id: Option<Uuid>) -> String {
+    match email_id {
+        Some(email_id) => format!("{}; code email {}", reason, email_id),
+        None => reason.to_owned(),
+    }
+}
+
 #[tracing::instrument(name = "HandleNO2FA", skip_all)]
 async fn handle_no_2fa(
     email: &Email,
+    state: &AppState,
     jar: CookieJar,
 ) -> (
     CookieJar,
     Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
 ) {
-    let auth_cookie = match generate_auth_cookie(email) {
+    let auth_cookie = match generate_auth_cookie(email, &state.settings) {
         Ok(cookie) => cookie,
         Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
     };
+    if let Err(e) = record_login(state, email).await {
+        return (jar, Err(e));
+    }
     let updated_jar = jar.add(auth_cookie);
 
     (
@@ -111,6 +248,17 @@ async fn handle_no_2fa(
     )
 }
 
+// Marks the login as accepted, which also queues its `user.logged_in` webhook event
+pub(super) async fn record_login(state: &AppState, email: &Email) -> Result<(), AuthAPIError> {
+    state
+        .user_store
+        .write()
+        .await
+        .record_login(email, Utc::now())
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum LoginResponse {
@@ -124,4 +272,9 @@ pub struct TwoFactorAuthResponse {
     pub message: String,
     #[serde(rename = "loginAttemptId")]
     pub login_attempt_id: String,
+    // where the code was sent
+    pub channel: TwoFAChannel,
+    // for the audit event, not sent to the client
+    #[serde(skip)]
+    pub email_id: Option<Uuid>,
 }
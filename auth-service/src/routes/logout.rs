@@ -1,49 +1,64 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse};
-use axum_extra::extract::{cookie::Cookie, CookieJar};
+use axum_extra::extract::CookieJar;
 
 use color_eyre::eyre::Result;
-use secrecy::Secret;
+use metrics::counter;
 
 use crate::{
     app_state::AppState,
-    domain::AuthAPIError,
-    utils::{auth::validate_token, constants::JWT_COOKIE_NAME},
+    domain::{
+        audit::{AuditEvent, AuditEventKind},
+        AuthAPIError,
+    },
+    utils::{
+        auth::removal_auth_cookie, auth_middleware::AuthenticatedUser,
+        metrics::TOKENS_REVOKED_TOTAL, request_context::RequestContext,
+    },
 };
 
 #[tracing::instrument(name = "Logout", skip_all)]
 pub async fn logout(
     State(state): State<AppState>,
+    context: RequestContext,
+    user: Result<AuthenticatedUser, AuthAPIError>,
     jar: CookieJar,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
-    // Retrieve JWT cookie from the `CookieJar`
-    // Return AuthAPIError::MissingToken is the cookie is not found
-
-    let cookie = match jar.get(JWT_COOKIE_NAME) {
-        Some(c) => c,
-        None => return (jar, Err(AuthAPIError::MissingToken)),
+    let actor = user.as_ref().ok().map(|user| user.email.clone());
+    let (jar, result) = match user {
+        Ok(user) => revoke_token(&state, user, jar).await,
+        Err(e) => (jar, Err(e)),
     };
 
-    // Validate token
-    let token = cookie.value().to_owned();
-    let _ = match validate_token(state.token_store.clone(), Secret::new(token.to_owned())).await {
-        Ok(claims) => claims,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
-    };
+    let mut event = AuditEvent::from_result(AuditEventKind::Logout, &result).with_context(&context);
+    if let Some(email) = &actor {
+        event = event.with_actor_email(email);
+    }
+    state.record_audit_event(event).await;
+
+    (jar, result)
+}
+
+async fn revoke_token(
+    state: &AppState,
+    user: AuthenticatedUser,
+    jar: CookieJar,
+) -> (CookieJar, Result<StatusCode, AuthAPIError>) {
+    // The `AuthenticatedUser` extractor has already rejected requests
+    // with a missing, invalid or banned token (cookie or bearer header)
 
     // Add token to banned list
     if let Err(e) = state
         .token_store
         .write()
         .await
-        .store_token(Secret::new(token))
+        .store_token(user.token)
         .await
     {
-        // return (jar, AuthAPIError::UnexpectedError(e.into()));
-
         return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
     }
+    counter!(TOKENS_REVOKED_TOTAL).increment(1);
 
-    let jar = jar.remove(Cookie::from(JWT_COOKIE_NAME));
+    let jar = jar.remove(removal_auth_cookie(&state.settings.auth.cookie));
 
     (jar, Ok(StatusCode::OK))
 }
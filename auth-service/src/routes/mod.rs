@@ -1,11 +1,25 @@
+mod admin;
+mod csrf_token;
+mod dev_mailbox;
+mod health;
 mod login;
 mod logout;
+mod phone_number;
+mod resend_2fa;
 mod signup;
+mod two_fa_channel;
 mod verify_2fa;
 mod verify_token;
 
+pub use admin::*;
+pub use csrf_token::*;
+pub use dev_mailbox::*;
+pub use health::*;
 pub use login::*;
 pub use logout::*;
+pub use phone_number::*;
+pub use resend_2fa::*;
 pub use signup::*;
+pub use two_fa_channel::*;
 pub use verify_2fa::*;
 pub use verify_token::*;
@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::Utc;
+use color_eyre::eyre::eyre;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        audit::{AuditEvent, AuditEventKind},
+        data_stores::{TwoFACode, UserStoreError},
+        password::Password,
+        AuthAPIError, PhoneNumber, PhoneVerification, TwoFAChannel,
+    },
+    services::phone_codes::send_code,
+    utils::{auth_middleware::AuthenticatedUser, request_context::RequestContext},
+};
+
+const PHONE_CODE_HASH_KIND: &str = "phone_verification_code";
+
+#[derive(Deserialize)]
+pub struct StartPhoneVerificationRequest {
+    #[serde(rename = "phoneNumber")]
+    pub phone_number: Secret<String>,
+    // how to send the code; "voice" for numbers that cannot receive texts
+    #[serde(default = "default_verification_channel")]
+    pub channel: TwoFAChannel,
+    // asked for again, so a stolen session can't redirect 2FA codes to another number
+    pub password: Secret<String>,
+}
+
+fn default_verification_channel() -> TwoFAChannel {
+    TwoFAChannel::Sms
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StartPhoneVerificationResponse {
+    pub message: String,
+}
+
+// Sends a code to the number; it replaces the verified one once the code is entered
+#[tracing::instrument(name = "StartPhoneVerification", skip_all)]
+pub async fn start_phone_verification(
+    State(state): State<AppState>,
+    context: RequestContext,
+    user: AuthenticatedUser,
+    Json(request): Json<StartPhoneVerificationRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let sms_client = state
+        .sms_client
+        .as_ref()
+        .ok_or_else(|| AuthAPIError::UnexpectedError(eyre!("SMS is disabled")))?;
+    if !request.channel.uses_phone() {
+        return Err(AuthAPIError::InvalidInput);
+    }
+    let phone_number =
+        PhoneNumber::parse(request.phone_number).map_err(|_| AuthAPIError::InvalidPhoneNumber)?;
+    confirm_password(&state, &user, request.password).await?;
+
+    let settings = &state.settings.sms;
+    let now = Utc::now();
+    let code = TwoFACode::default();
+    let verification = PhoneVerification {
+        phone_number: phone_number.clone(),
+        code_hash: state
+            .hasher
+            .hash(PHONE_CODE_HASH_KIND, code.as_ref().expose_secret()),
+        expires_at: now
+            + chrono::Duration::from_std(Duration::from_secs(
+                settings.phone_verification_ttl_seconds,
+            ))
+            .unwrap_or(chrono::Duration::MAX),
+        attempts: 0,
+        sent_at: now,
+    };
+    let stored = {
+        // held from the limit checks until the code is counted, so concurrent requests send one
+        let mut user_store = state.user_store.write().await;
+        let stored = user_store
+            .get_user(&user.email)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        let cooldown = chrono::Duration::from_std(Duration::from_secs(
+            settings.verification_resend_cooldown_seconds,
+        ))
+        .unwrap_or(chrono::Duration::MAX);
+        if let Some(pending) = &stored.phone_verification {
+            if now - pending.sent_at < cooldown {
+                return Err(AuthAPIError::TooManyRequests);
+            }
+        }
+        let sends = user_store
+            .phone_verifications_sent_since(
+                &user.email,
+                &phone_number,
+                now - chrono::Duration::days(1),
+            )
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        if sends.by_user >= settings.max_verifications_per_user_per_day
+            || sends.to_number >= settings.max_verifications_per_number_per_day
+        {
+            return Err(AuthAPIError::TooManyRequests);
+        }
+
+        user_store
+            .set_phone_verification(&user.email, Some(verification))
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        user_store
+            .record_phone_verification_sent(&user.email, &phone_number, now)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        stored
+    };
+
+    // the store is not locked while the provider is called
+    let sent = send_code(
+        sms_client.as_ref(),
+        &state.email_templates,
+        &stored.preferred_locales(&context.languages),
+        request.channel,
+        &phone_number,
+        &code,
+        settings.phone_verification_ttl_seconds.div_ceil(60),
+    )
+    .await;
+    if let Err(e) = sent {
+        // don't leave a code the user never received; it still counts towards the limits
+        if let Err(e) = state
+            .user_store
+            .write()
+            .await
+            .set_phone_verification(&user.email, None)
+            .await
+        {
+            tracing::error!(error = ?e, "Failed to remove undeliverable phone verification");
+        }
+        return Err(AuthAPIError::UnexpectedError(e));
+    }
+
+    let response = Json(StartPhoneVerificationResponse {
+        message: format!("Verification code sent to {}", phone_number.masked()),
+    });
+    Ok((StatusCode::ACCEPTED, response))
+}
+
+// Re-authenticates the user before a change to where their 2FA codes go
+pub(super) async fn confirm_password(
+    state: &AppState,
+    user: &AuthenticatedUser,
+    password: Secret<String>,
+) -> Result<(), AuthAPIError> {
+    let password = Password::parse(password).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    state
+        .user_store
+        .read()
+        .await
+        .validate_user(&user.email, &password)
+        .await
+        .map_err(|e| match e {
+            UserStoreError::InvalidCredentials => AuthAPIError::IncorrectCredentials,
+            e => AuthAPIError::UnexpectedError(e.into()),
+        })
+}
+
+#[derive(Deserialize)]
+pub struct VerifyPhoneNumberRequest {
+    pub code: Secret<String>,
+}
+
+#[tracing::instrument(name = "VerifyPhoneNumber", skip_all)]
+pub async fn verify_phone_number(
+    State(state): State<AppState>,
+    context: RequestContext,
+    user: AuthenticatedUser,
+    Json(request): Json<VerifyPhoneNumberRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let result = confirm_code(&state, &user, request).await;
+
+    state
+        .record_audit_event(
+            AuditEvent::from_result(AuditEventKind::VerifyPhone, &result)
+                .with_actor_email(&user.email)
+                .with_context(&context),
+        )
+        .await;
+
+    result
+}
+
+async fn confirm_code(
+    state: &AppState,
+    user: &AuthenticatedUser,
+    request: VerifyPhoneNumberRequest,
+) -> Result<StatusCode, AuthAPIError> {
+    let code = TwoFACode::parse(request.code).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    // held until the attempt is counted, so concurrent guesses can't exceed the limit
+    let mut user_store = state.user_store.write().await;
+    let stored = user_store
+        .get_user(&user.email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    let Some(mut verification) = stored.phone_verification else {
+        return Err(AuthAPIError::IncorrectCredentials);
+    };
+
+    if verification.expires_at <= Utc::now() {
+        user_store
+            .set_phone_verification(&user.email, None)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+
+    if !state.hasher.verify(
+        PHONE_CODE_HASH_KIND,
+        code.as_ref().expose_secret(),
+        &verification.code_hash,
+    ) {
+        verification.attempts += 1;
+        // too many wrong codes; the number has to be entered again for a new one
+        let remaining = (verification.attempts < state.settings.sms.max_verification_attempts)
+            .then_some(verification);
+        user_store
+            .set_phone_verification(&user.email, remaining)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+
+    user_store
+        .set_phone_number(&user.email, &verification.phone_number)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::Utc;
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::login::{code_delivery_reason, deliver_code};
+use crate::{
+    app_state::AppState,
+    domain::{
+        audit::{AuditEvent, AuditEventKind},
+        data_stores::{LoginAttemptId, TwoFACode},
+        AuthAPIError, Email, TwoFAChannel,
+    },
+    utils::request_context::RequestContext,
+};
+
+#[derive(Deserialize)]
+pub struct Resend2FARequest {
+    #[serde(rename = "loginAttemptId")]
+    pub login_attempt_id: Secret<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Resend2FAResponse {
+    pub message: String,
+    // where the new code was sent
+    pub channel: TwoFAChannel,
+    // for the audit event, not sent to the client
+    #[serde(skip)]
+    pub email_id: Option<Uuid>,
+}
+
+// Sends a new code for a pending login; the previous one stops working, and the new one
+// expires when the first one would have
+#[tracing::instrument(name = "Resend2FA", skip_all)]
+pub async fn resend_2fa(
+    State(state): State<AppState>,
+    context: RequestContext,
+    Json(request): Json<Resend2FARequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let (actor, result) = resend_code(&state, &context, request).await;
+
+    let mut event =
+        AuditEvent::from_result(AuditEventKind::Resend2FA, &result).with_context(&context);
+    if let Some(email) = &actor {
+        event = event.with_actor_email(email);
+    }
+    if let Ok(Json(response)) = &result {
+        event = event.with_reason(code_delivery_reason("2FA code resent", response.email_id));
+    }
+    state.record_audit_event(event).await;
+
+    result
+}
+
+// Also returns the email bound to the login attempt, once it is known
+async fn resend_code(
+    state: &AppState,
+    context: &RequestContext,
+    request: Resend2FARequest,
+) -> (Option<Email>, Result<Json<Resend2FAResponse>, AuthAPIError>) {
+    let Ok(login_attempt_id) = LoginAttemptId::parse(request.login_attempt_id) else {
+        return (None, Err(AuthAPIError::InvalidCredentials));
+    };
+
+    let two_fa_code = TwoFACode::default();
+    let attempt = {
+        // held from the cooldown check until the new code is stored, so concurrent resends send one
+        let mut two_fa_code_store = state.two_fa_code_store.write().await;
+        let Ok(previous) = two_fa_code_store.get_attempt(&login_attempt_id).await else {
+            return (None, Err(AuthAPIError::IncorrectCredentials));
+        };
+        let cooldown = chrono::Duration::from_std(Duration::from_secs(
+            state.settings.auth.two_fa_resend_cooldown_seconds,
+        ))
+        .unwrap_or(chrono::Duration::MAX);
+        if Utc::now() - previous.sent_at < cooldown
+            || previous.resends >= state.settings.auth.two_fa_max_resends
+        {
+            return (Some(previous.email), Err(AuthAPIError::TooManyRequests));
+        }
+
+        match two_fa_code_store
+            .resend_code(&login_attempt_id, two_fa_code.clone(), Utc::now())
+            .await
+        {
+            Ok(attempt) => attempt,
+            Err(e) => {
+                return (
+                    Some(previous.email),
+                    Err(AuthAPIError::UnexpectedError(e.into())),
+                )
+            }
+        }
+    };
+
+    // the channel may have changed since the login
+    let user = match state.user_store.read().await.get_user(&attempt.email).await {
+        Ok(user) => user,
+        Err(e) => {
+            return (
+                Some(attempt.email),
+                Err(AuthAPIError::UnexpectedError(e.into())),
+            )
+        }
+    };
+    let result = deliver_code(
+        state,
+        context,
+        &user,
+        &login_attempt_id,
+        &two_fa_code,
+        attempt.expires_at,
+    )
+    .await
+    .map(|(channel, email_id)| {
+        Json(Resend2FAResponse {
+            message: "2FA code resent".to_owned(),
+            channel,
+            email_id,
+        })
+    });
+
+    (Some(attempt.email), result)
+}
@@ -2,13 +2,19 @@ use crate::domain::{email::Email, password::Password};
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 
 use color_eyre::eyre::Result;
+use metrics::counter;
 use secrecy::Secret;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
 use crate::{
     app_state::AppState,
-    domain::{error::AuthAPIError, user::User},
+    domain::{
+        audit::{AuditEvent, AuditEventKind},
+        error::AuthAPIError,
+        user::User,
+    },
+    utils::{metrics::SIGNUPS_TOTAL, request_context::RequestContext},
 };
 
 #[derive(Deserialize, Validate)]
@@ -19,21 +25,40 @@ pub struct SignupRequest {
     pub requires_2fa: bool,
 }
 
-// fn check_valid_email_password(email: &Email, password: &Password) -> bool {
-//     dbg!(password);
-//     email.parse().is_ok() && password.parse().is_ok()
-// }
-
 #[tracing::instrument(name = "Signup", skip_all)]
 pub async fn signup(
     State(state): State<AppState>,
+    context: RequestContext,
     Json(request): Json<SignupRequest>,
 ) -> Result<impl IntoResponse, AuthAPIError> {
+    let actor = request.email.clone();
+    let result = create_user(&state, &context, request).await;
+    if result.is_ok() {
+        counter!(SIGNUPS_TOTAL).increment(1);
+    }
+
+    state
+        .record_audit_event(
+            AuditEvent::from_result(AuditEventKind::Signup, &result)
+                .with_actor(actor)
+                .with_context(&context),
+        )
+        .await;
+
+    result
+}
+
+async fn create_user(
+    state: &AppState,
+    context: &RequestContext,
+    request: SignupRequest,
+) -> Result<(StatusCode, Json<SignupResponse>), AuthAPIError> {
     let email =
         Email::parse(Secret::new(request.email)).map_err(|_| AuthAPIError::InvalidCredentials)?;
     let password =
         Password::parse(request.password).map_err(|_| AuthAPIError::InvalidCredentials)?;
-    let user = User::new(email, password, request.requires_2fa);
+    let user = User::new(email, password, request.requires_2fa)
+        .with_locale(state.email_templates.supported_locale(&context.languages));
 
     let mut user_store = state.user_store.write().await;
 
@@ -44,7 +69,7 @@ pub async fn signup(
     user_store
         .add_user(user)
         .await
-        .map_err(|e| return AuthAPIError::UnexpectedError(e.into()))?;
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
 
     let response = Json(SignupResponse {
         message: "User created successfully!".to_string(),
@@ -0,0 +1,70 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use secrecy::Secret;
+use serde::Deserialize;
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        audit::{AuditEvent, AuditEventKind},
+        AuthAPIError, TwoFAChannel,
+    },
+    utils::{auth_middleware::AuthenticatedUser, request_context::RequestContext},
+};
+
+use super::phone_number::confirm_password;
+
+#[derive(Deserialize)]
+pub struct Set2FAChannelRequest {
+    pub channel: TwoFAChannel,
+    // asked for again, so a stolen session can't change where codes go
+    pub password: Secret<String>,
+}
+
+// Selects where login sends 2FA codes; texts and calls need a verified phone number
+#[tracing::instrument(name = "Set2FAChannel", skip_all)]
+pub async fn set_2fa_channel(
+    State(state): State<AppState>,
+    context: RequestContext,
+    user: AuthenticatedUser,
+    Json(request): Json<Set2FAChannelRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let channel = request.channel;
+    let result = update_channel(&state, &user, request).await;
+
+    let mut event = AuditEvent::from_result(AuditEventKind::Set2FAChannel, &result)
+        .with_actor_email(&user.email)
+        .with_context(&context);
+    if result.is_ok() {
+        event = event.with_reason(format!("channel: {}", channel.as_str()));
+    }
+    state.record_audit_event(event).await;
+
+    result
+}
+
+async fn update_channel(
+    state: &AppState,
+    user: &AuthenticatedUser,
+    request: Set2FAChannelRequest,
+) -> Result<StatusCode, AuthAPIError> {
+    let channel = request.channel;
+    confirm_password(state, user, request.password).await?;
+
+    let mut user_store = state.user_store.write().await;
+    if channel.uses_phone() {
+        let stored = user_store
+            .get_user(&user.email)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+        if stored.phone_number.is_none() {
+            return Err(AuthAPIError::PhoneNumberNotVerified);
+        }
+    }
+
+    user_store
+        .set_two_fa_channel(&user.email, channel)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(StatusCode::OK)
+}
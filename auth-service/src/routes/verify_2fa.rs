@@ -1,68 +1,100 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use axum_extra::extract::CookieJar;
-use secrecy::{ExposeSecret, Secret};
+use metrics::counter;
+use secrecy::Secret;
 use serde::Deserialize;
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email},
-    utils::auth::generate_auth_cookie,
+    domain::{
+        audit::{AuditEvent, AuditEventKind},
+        data_stores::{LoginAttemptId, TwoFACode, TwoFACodeStoreError},
+        AuthAPIError, Email,
+    },
+    utils::{
+        auth::generate_auth_cookie,
+        metrics::{outcome_label, TWO_FA_VERIFICATIONS_TOTAL},
+        request_context::RequestContext,
+    },
 };
 
+use super::login::record_login;
+
 #[tracing::instrument(name = "Verify2FA", skip_all)]
 pub async fn verify_2fa(
     State(state): State<AppState>,
+    context: RequestContext,
     jar: CookieJar,
     Json(request): Json<Verify2FARequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
-    let email = match Email::parse(Secret::new(request.email)) {
-        Ok(email) => email,
-        Err(_) => {
-            return (jar, Err(AuthAPIError::InvalidCredentials));
-        }
+    let (jar, actor, result) = verify_code(&state, jar, request).await;
+    counter!(TWO_FA_VERIFICATIONS_TOTAL, "outcome" => outcome_label(&result)).increment(1);
+
+    let mut event =
+        AuditEvent::from_result(AuditEventKind::Verify2FA, &result).with_context(&context);
+    if let Some(email) = &actor {
+        event = event.with_actor_email(email);
+    }
+    state.record_audit_event(event).await;
+
+    (jar, result)
+}
+
+// Also returns the email bound to the login attempt, once it is known
+async fn verify_code(
+    state: &AppState,
+    jar: CookieJar,
+    request: Verify2FARequest,
+) -> (CookieJar, Option<Email>, Result<StatusCode, AuthAPIError>) {
+    let (login_attempt_id, two_fa_code) = match (
+        LoginAttemptId::parse(request.login_attempt_id),
+        TwoFACode::parse(request.two_fa_code),
+    ) {
+        (Ok(id), Ok(code)) => (id, code),
+        _ => return (jar, None, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let (login_attempt_id, two_fa_code) = {
-        let two_fa_code_store = state.two_fa_code_store.write().await;
-        match two_fa_code_store.get_code(&email).await {
-            Ok((id, code)) => (id, code),
-            Err(_) => {
-                return (jar, Err(AuthAPIError::IncorrectCredentials));
+    let email = {
+        let mut two_fa_code_store = state.two_fa_code_store.write().await;
+        // read first, so wrong codes are audited under the attempt's user
+        let email = match two_fa_code_store.get_attempt(&login_attempt_id).await {
+            Ok(attempt) => attempt.email,
+            Err(_) => return (jar, None, Err(AuthAPIError::IncorrectCredentials)),
+        };
+        // only this attempt's code is used up; the user's other pending logins stay valid
+        match two_fa_code_store
+            .consume_code(
+                &login_attempt_id,
+                &two_fa_code,
+                state.settings.auth.two_fa_max_failed_attempts,
+            )
+            .await
+        {
+            Ok(_) => email,
+            Err(TwoFACodeStoreError::UnexpectedError(e)) => {
+                return (jar, Some(email), Err(AuthAPIError::UnexpectedError(e)))
             }
+            Err(_) => return (jar, Some(email), Err(AuthAPIError::IncorrectCredentials)),
         }
     };
 
-    // Validate the credentials
-    if request.login_attempt_id != login_attempt_id.as_ref().expose_secret().to_owned()
-        || request.two_fa_code != two_fa_code.as_ref().expose_secret().to_owned()
-    {
-        return (jar, Err(AuthAPIError::IncorrectCredentials));
-    }
-
-    let auth_cookie = match generate_auth_cookie(&email) {
+    let auth_cookie = match generate_auth_cookie(&email, &state.settings) {
         Ok(cookie) => cookie,
-        Err(e) => {
-            return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
-        }
+        Err(e) => return (jar, Some(email), Err(AuthAPIError::UnexpectedError(e))),
     };
+    if let Err(e) = record_login(state, &email).await {
+        return (jar, Some(email), Err(e));
+    }
 
     let updated_jar = jar.add(auth_cookie);
-
-    {
-        let mut code_store = state.two_fa_code_store.write().await;
-        let _ = code_store
-            .remove_code(&email)
-            .await
-            .map_err(|e| return AuthAPIError::UnexpectedError(e.into()));
-    }
-    (updated_jar, Ok(StatusCode::OK.into_response()))
+    (updated_jar, Some(email), Ok(StatusCode::OK))
 }
 
+// The email is bound to the login attempt, so clients no longer send it
 #[derive(Deserialize)]
 pub struct Verify2FARequest {
-    pub email: String,
     #[serde(rename = "loginAttemptId")]
-    pub login_attempt_id: String,
+    pub login_attempt_id: Secret<String>,
     #[serde(rename = "2FACode")]
-    pub two_fa_code: String,
+    pub two_fa_code: Secret<String>,
 }
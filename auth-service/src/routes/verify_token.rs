@@ -2,7 +2,11 @@ use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use color_eyre::eyre::Result;
 use secrecy::Secret;
 
-use crate::{app_state::AppState, domain::AuthAPIError, utils::auth::validate_token};
+use crate::{
+    app_state::AppState,
+    domain::AuthAPIError,
+    utils::auth::{validate_token, ValidateTokenError},
+};
 
 use serde::Deserialize;
 
@@ -17,8 +21,15 @@ pub async fn verify_token(
     Json(request): Json<VerifyTokenRequest>,
 ) -> Result<impl IntoResponse, AuthAPIError> {
     let token = request.token;
-    match validate_token(state.token_store.clone(), Secret::new(token)).await {
+    match validate_token(
+        state.token_store.clone(),
+        Secret::new(token),
+        &state.settings.jwt_secret,
+    )
+    .await
+    {
         Ok(_) => Ok(StatusCode::OK),
-        Err(_) => Err(AuthAPIError::InvalidToken),
+        Err(ValidateTokenError::InvalidToken(_)) => Err(AuthAPIError::InvalidToken),
+        Err(ValidateTokenError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
     }
 }
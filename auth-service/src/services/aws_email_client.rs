@@ -6,60 +6,95 @@ use aws_sdk_sesv2::{
 use color_eyre::eyre::Result;
 use secrecy::ExposeSecret;
 
-use crate::domain::{Email, EmailClient};
+use crate::{
+    domain::{Email, EmailClient, EmailMessage, EmailSender},
+    utils::{metrics::record_email_sent, redact::mask_email},
+};
 
 #[derive(Debug)]
 pub struct AWSEmailClient {
     email_client: Client,
+    sender: EmailSender,
+    // for sending authorization; the sender's own identity is used when unset
+    identity_arn: Option<String>,
 }
 
 impl AWSEmailClient {
-    pub fn new(email_client: Client) -> Self {
-        Self { email_client }
+    pub fn new(email_client: Client, sender: EmailSender, identity_arn: Option<String>) -> Self {
+        Self {
+            email_client,
+            sender,
+            identity_arn,
+        }
     }
-}
 
-#[async_trait::async_trait]
-impl EmailClient for AWSEmailClient {
-    #[tracing::instrument(name = "Sending email", skip_all)]
-    async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()> {
+    async fn send(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
         let recipient_email = recipient.as_ref().expose_secret().to_owned();
-        tracing::info!("Sending email to: {}", recipient_email);
+        tracing::info!("Sending email to: {}", mask_email(&recipient_email));
 
         // let mut dest: Destination = Destination::builder().build();
         // dest.to_addresses = Some(vec![recipient.as_ref().expose_secret().to_owned()]);
         let dest = Destination::builder().to_addresses(recipient_email).build();
 
         let subject_content = Content::builder()
-            .data(subject.to_string())
+            .data(message.subject.to_owned())
             .charset("UTF-8")
             .build()
             .expect("building Content");
 
-        let body_content = Content::builder()
-            .data(content.to_string())
+        let text_content = Content::builder()
+            .data(message.text_body.to_owned())
             .charset("UTF-8")
             .build()
             .expect("building Content");
-        let body = Body::builder().text(body_content).build();
+        let html_content = Content::builder()
+            .data(message.html_body.to_owned())
+            .charset("UTF-8")
+            .build()
+            .expect("building Content");
+        let body = Body::builder()
+            .text(text_content)
+            .html(html_content)
+            .build();
         let msg = Message::builder()
             .subject(subject_content)
             .body(body)
             .build();
 
         let email_content = EmailContent::builder().simple(msg).build();
-        tracing::info!("Email content prepared: {:?}", email_content);
+        // the content holds the 2FA code, so only its presence is logged
+        tracing::debug!("Email content prepared");
 
-        self.email_client
+        let mut request = self
+            .email_client
             .send_email()
-            .from_email_address_identity_arn(
-                "arn:aws:ses:us-west-1:043152383660:identity/rustyauth.com",
-            )
-            .from_email_address("auth@rustyauth.com")
+            .from_email_address(self.sender.from.to_string())
             .content(email_content)
-            .destination(dest)
-            .send()
-            .await?;
+            .destination(dest);
+        if let Some(identity_arn) = &self.identity_arn {
+            request = request.from_email_address_identity_arn(identity_arn);
+        }
+        if let Some(reply_to) = &self.sender.reply_to {
+            request = request.reply_to_addresses(reply_to.to_string());
+        }
+        request.send().await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailClient for AWSEmailClient {
+    #[tracing::instrument(name = "Sending email", skip_all)]
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        let result = self.send(recipient, message).await;
+        record_email_sent("aws_ses", &result);
+        result
+    }
+
+    // verifies credentials and that SES is reachable without sending anything
+    #[tracing::instrument(name = "Checking SES health", skip_all)]
+    async fn health_check(&self) -> Result<()> {
+        self.email_client.get_account().send().await?;
         Ok(())
     }
 }
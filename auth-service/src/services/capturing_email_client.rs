@@ -0,0 +1,184 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    RwLock,
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+use uuid::Uuid;
+
+use crate::{
+    domain::{Email, EmailClient, EmailMessage},
+    utils::metrics::record_email_sent,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedEmail {
+    pub id: Uuid,
+    pub recipient: Email,
+    pub message: EmailMessage,
+    pub sent_at: DateTime<Utc>,
+}
+
+// Every field that is set must match; results are newest first
+#[derive(Debug, Clone, Default)]
+pub struct EmailQuery {
+    pub recipient: Option<Email>,
+    pub subject: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl EmailQuery {
+    pub fn to(recipient: &Email) -> Self {
+        Self {
+            recipient: Some(recipient.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, email: &CapturedEmail) -> bool {
+        self.recipient
+            .as_ref()
+            .is_none_or(|recipient| email.recipient == *recipient)
+            && self
+                .subject
+                .as_ref()
+                .is_none_or(|subject| email.message.subject == *subject)
+            && self.since.is_none_or(|since| email.sent_at >= since)
+    }
+}
+
+// Keeps emails in memory instead of sending them, so tests can read what a user would receive
+#[derive(Default)]
+pub struct CapturingEmailClient {
+    emails: RwLock<Vec<CapturedEmail>>,
+    unavailable: AtomicBool,
+}
+
+impl CapturingEmailClient {
+    pub fn query(&self, query: &EmailQuery) -> Vec<CapturedEmail> {
+        // captured in order, so newest first is the reverse
+        self.emails
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|email| query.matches(email))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    // Oldest first
+    pub fn sent_emails(&self) -> Vec<CapturedEmail> {
+        self.emails.read().unwrap().clone()
+    }
+
+    pub fn last_email_to(&self, recipient: &Email) -> Option<CapturedEmail> {
+        self.query(&EmailQuery {
+            limit: Some(1),
+            ..EmailQuery::to(recipient)
+        })
+        .pop()
+    }
+
+    pub fn clear(&self) {
+        self.emails.write().unwrap().clear();
+    }
+
+    // Fails every send while set, like a provider outage
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.unavailable.store(unavailable, Ordering::SeqCst);
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailClient for CapturingEmailClient {
+    #[tracing::instrument(name = "Sending email", skip_all)]
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        if self.unavailable.load(Ordering::SeqCst) {
+            let result: Result<()> = Err(eyre!("capturing email client is unavailable"));
+            record_email_sent("capturing", &result);
+            return result;
+        }
+        tracing::debug!("Capturing email with subject: {}", message.subject);
+
+        self.emails.write().unwrap().push(CapturedEmail {
+            id: Uuid::new_v4(),
+            recipient: recipient.clone(),
+            message: message.clone(),
+            sent_at: Utc::now(),
+        });
+
+        let result: Result<()> = Ok(());
+        record_email_sent("capturing", &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::*;
+
+    fn email(s: &str) -> Email {
+        Email::parse(Secret::new(s.to_owned())).unwrap()
+    }
+
+    fn message(subject: &str, text: &str) -> EmailMessage {
+        EmailMessage {
+            subject: subject.to_owned(),
+            text_body: text.to_owned(),
+            html_body: format!("<p>{}</p>", text),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_captured_emails() {
+        let client = CapturingEmailClient::default();
+        let alice = email("alice@example.com");
+        let bob = email("bob@example.com");
+
+        client
+            .send_email(&alice, &message("2FA Code", "123456"))
+            .await
+            .unwrap();
+        client
+            .send_email(&bob, &message("Welcome", "hi"))
+            .await
+            .unwrap();
+        client
+            .send_email(&alice, &message("2FA Code", "654321"))
+            .await
+            .unwrap();
+
+        let to_alice = client.query(&EmailQuery::to(&alice));
+        let contents: Vec<_> = to_alice
+            .iter()
+            .map(|e| e.message.text_body.as_str())
+            .collect();
+        assert_eq!(contents, vec!["654321", "123456"]);
+        assert_eq!(
+            client.last_email_to(&alice).unwrap().message.text_body,
+            "654321"
+        );
+
+        let welcome = client.query(&EmailQuery {
+            subject: Some("Welcome".to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(welcome.len(), 1);
+        assert_eq!(welcome[0].recipient, bob);
+
+        let since_last = client.query(&EmailQuery {
+            since: Some(to_alice[0].sent_at),
+            ..Default::default()
+        });
+        assert_eq!(since_last, vec![to_alice[0].clone()]);
+
+        client.clear();
+        assert!(client.sent_emails().is_empty());
+    }
+}
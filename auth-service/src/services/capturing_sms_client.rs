@@ -0,0 +1,96 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    RwLock,
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+
+use crate::{
+    domain::{PhoneNumber, SmsClient},
+    utils::metrics::record_sms_sent,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmsKind {
+    Text,
+    Call,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedSms {
+    pub recipient: PhoneNumber,
+    pub kind: SmsKind,
+    // the text, or the speech of a call
+    pub body: String,
+    // the language a call is read out in
+    pub language: Option<String>,
+    pub sent_at: DateTime<Utc>,
+}
+
+// Keeps texts and calls in memory instead of sending them, so tests can read what a user would receive
+#[derive(Default)]
+pub struct CapturingSmsClient {
+    messages: RwLock<Vec<CapturedSms>>,
+    unavailable: AtomicBool,
+}
+
+impl CapturingSmsClient {
+    // Oldest first
+    pub fn sent_messages(&self) -> Vec<CapturedSms> {
+        self.messages.read().unwrap().clone()
+    }
+
+    pub fn last_message_to(&self, recipient: &PhoneNumber) -> Option<CapturedSms> {
+        self.messages
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|message| message.recipient == *recipient)
+            .cloned()
+    }
+
+    // Fails every text and call while set, like a provider outage
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.unavailable.store(unavailable, Ordering::SeqCst);
+    }
+
+    fn capture(
+        &self,
+        recipient: &PhoneNumber,
+        kind: SmsKind,
+        body: &str,
+        language: Option<&str>,
+    ) -> Result<()> {
+        if self.unavailable.load(Ordering::SeqCst) {
+            return Err(eyre!("capturing SMS client is unavailable"));
+        }
+
+        self.messages.write().unwrap().push(CapturedSms {
+            recipient: recipient.clone(),
+            kind,
+            body: body.to_owned(),
+            language: language.map(str::to_owned),
+            sent_at: Utc::now(),
+        });
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl SmsClient for CapturingSmsClient {
+    #[tracing::instrument(name = "Sending SMS", skip_all)]
+    async fn send_sms(&self, recipient: &PhoneNumber, text: &str) -> Result<()> {
+        let result = self.capture(recipient, SmsKind::Text, text, None);
+        record_sms_sent("capturing", "sms", &result);
+        result
+    }
+
+    #[tracing::instrument(name = "Making voice call", skip_all)]
+    async fn call(&self, recipient: &PhoneNumber, speech: &str, language: &str) -> Result<()> {
+        let result = self.capture(recipient, SmsKind::Call, speech, Some(language));
+        record_sms_sent("capturing", "voice", &result);
+        result
+    }
+}
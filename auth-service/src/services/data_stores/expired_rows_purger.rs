@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{Context, Result};
+use sqlx::PgPool;
+
+use crate::utils::shutdown::ShutdownHandle;
+
+// Tables of the `postgres` banned token and 2FA code stores.
+// Their reads already skip expired rows, so this only keeps the tables small.
+const TABLES: [&str; 2] = ["banned_tokens", "two_fa_codes"];
+
+pub struct ExpiredRowsPurger {
+    pool: PgPool,
+    interval: Duration,
+}
+
+impl ExpiredRowsPurger {
+    pub fn new(pool: PgPool, interval: Duration) -> Self {
+        Self { pool, interval }
+    }
+
+    // Purges every `interval` until `shutdown` is triggered
+    pub async fn run(self, shutdown: ShutdownHandle) {
+        while !shutdown.is_shutting_down() {
+            match self.purge_expired().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "Purged expired rows"),
+                Err(e) => tracing::error!(error = ?e, "Failed to purge expired rows"),
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => {}
+                _ = shutdown.wait() => {}
+            }
+        }
+        tracing::info!("Expired rows purger stopped");
+    }
+
+    // Deletes expired rows, returning how many were deleted
+    #[tracing::instrument(name = "Purging expired rows", skip_all)]
+    pub async fn purge_expired(&self) -> Result<u64> {
+        let mut count = 0;
+        for table in TABLES {
+            count += sqlx::query(&format!("DELETE FROM {} WHERE expires_at <= now()", table))
+                .execute(&self.pool)
+                .await
+                .wrap_err_with(|| format!("failed to purge expired rows from {}", table))?
+                .rows_affected();
+        }
+
+        Ok(count)
+    }
+}
@@ -1,65 +1,99 @@
-// use std::collections::HashMap;
+use std::collections::HashMap;
 
-// use crate::domain::{
-//     data_stores::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
-//     email::Email,
-// };
+use chrono::{DateTime, Utc};
 
-// #[derive(Default)]
-// pub struct HashmapTwoFACodeStore {
-//     codes: HashMap<Email, (LoginAttemptId, TwoFACode)>,
-// }
+use crate::domain::data_stores::{
+    LoginAttempt, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError,
+};
 
-// #[async_trait::async_trait]
-// impl TwoFACodeStore for HashmapTwoFACodeStore {
-//     async fn add_code(
-//         &mut self,
-//         email: Email,
-//         login_attempt_id: LoginAttemptId,
-//         code: TwoFACode,
-//     ) -> Result<(), TwoFACodeStoreError> {
-//         match self.codes.insert(email, (login_attempt_id, code)) {
-//             Some(_) => Err(TwoFACodeStoreError::UnexpectedError),
-//             None => Ok(()),
-//         }
-//     }
+// In-memory `TwoFACodeStore` for tests and local use; codes expire like in Redis.
+// Nothing outlives the process, so codes are kept as they are rather than hashed.
+#[derive(Default)]
+pub struct HashmapTwoFACodeStore {
+    codes: HashMap<LoginAttemptId, (LoginAttempt, TwoFACode)>,
+}
 
-//     async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
-//         match self.codes.get(email) {
-//             Some(_) => {
-//                 let _ = self.codes.remove(email);
-//                 return Ok(());
-//             }
-//             None => return Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
-//         }
-//     }
+impl HashmapTwoFACodeStore {
+    fn get_mut(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Option<&mut (LoginAttempt, TwoFACode)> {
+        self.codes
+            .get_mut(login_attempt_id)
+            .filter(|(attempt, _)| attempt.expires_at > Utc::now())
+    }
+}
 
-//     async fn get_code(
-//         &self,
-//         email: &Email,
-//     ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
-//         match self.codes.get(email) {
-//             Some((login_attempt_id, two_fa_code)) => {
-//                 Ok((login_attempt_id.clone(), two_fa_code.clone()))
-//             }
-//             None => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
-//         }
-//     }
-// }
+#[async_trait::async_trait]
+impl TwoFACodeStore for HashmapTwoFACodeStore {
+    async fn add_code(
+        &mut self,
+        login_attempt_id: LoginAttemptId,
+        attempt: LoginAttempt,
+        code: TwoFACode,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let now = Utc::now();
+        self.codes
+            .retain(|_, (attempt, _)| attempt.expires_at > now);
+        self.codes.insert(login_attempt_id, (attempt, code));
 
-// #[cfg(test)]
-// mod tests {
+        Ok(())
+    }
 
-//     use super::*;
-//     #[tokio::test]
-//     async fn tests_add_code() {
-//         let mut store = HashmapTwoFACodeStore::default();
-//         let email = Email::parse("test@email.com".to_string()).unwrap();
-//         let login_attempt_id = LoginAttemptId::default();
-//         let code = TwoFACode::default();
+    async fn resend_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+        code: TwoFACode,
+        sent_at: DateTime<Utc>,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        let (attempt, stored) = self
+            .get_mut(login_attempt_id)
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+        attempt.sent_at = sent_at;
+        attempt.resends += 1;
+        *stored = code;
 
-//         let response = store.add_code(email.clone(), login_attempt_id, code).await;
-//         assert!(response.is_ok());
-//         assert!(store.get_code(&email).await.is_ok());
-//     }
-// }
+        Ok(attempt.clone())
+    }
+
+    async fn remove_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError> {
+        self.codes.remove(login_attempt_id);
+        Ok(())
+    }
+
+    async fn get_attempt(
+        &self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        match self.codes.get(login_attempt_id) {
+            Some((attempt, _)) if attempt.expires_at > Utc::now() => Ok(attempt.clone()),
+            _ => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
+        }
+    }
+
+    async fn consume_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+        code: &TwoFACode,
+        max_failed_attempts: u32,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        let (attempt, stored) = self
+            .get_mut(login_attempt_id)
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+        if stored != code {
+            attempt.failed_attempts += 1;
+            if attempt.failed_attempts >= max_failed_attempts {
+                self.codes.remove(login_attempt_id);
+            }
+            return Err(TwoFACodeStoreError::IncorrectCode);
+        }
+
+        self.codes
+            .remove(login_attempt_id)
+            .map(|(attempt, _)| attempt)
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)
+    }
+}
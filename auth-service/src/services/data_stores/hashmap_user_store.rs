@@ -1,44 +1,75 @@
-use crate::domain::{
-    data_stores::{UserStore, UserStoreError},
-    email::Email,
-    password::Password,
-    user::User,
+use chrono::{DateTime, Utc};
+
+use crate::{
+    domain::{
+        data_stores::{PhoneVerificationSends, UserStore, UserStoreError},
+        email::Email,
+        password::Password,
+        user::{PhoneVerification, TwoFAChannel, User},
+        PhoneNumber,
+    },
+    utils::{
+        configuration::PasswordHashingSettings,
+        password_hash::{compute_password_hash, hash_password_blocking, verify_password_hash},
+    },
 };
 use secrecy::Secret;
 use std::collections::HashMap;
 
+// Seeded, so there is a user to log in with when running without a database
+const ADMIN_EMAIL: &str = "admin@email.com";
+const ADMIN_PASSWORD: &str = "12341234";
+
+// Keeps Argon2 hashes like the durable stores, so it behaves the same in tests.
+// They are all computed with its own settings, so none is ever outdated.
 pub struct HashmapUserStore {
     users: HashMap<Email, User>,
+    phone_verification_sends: Vec<(Email, PhoneNumber, DateTime<Utc>)>,
+    password_hashing: PasswordHashingSettings,
 }
 
-impl Default for HashmapUserStore {
-    fn default() -> Self {
+impl HashmapUserStore {
+    pub fn new(password_hashing: PasswordHashingSettings) -> Self {
         let mut user_store = Self {
             users: HashMap::new(),
+            phone_verification_sends: Vec::new(),
+            password_hashing,
         };
 
+        let email = Email::parse(Secret::new(ADMIN_EMAIL.to_owned())).unwrap();
+        let password_hash =
+            hash_password_blocking(&Secret::new(ADMIN_PASSWORD.to_owned()), password_hashing)
+                .expect("Failed to hash the admin user's password");
         user_store.users.insert(
-            Email::parse(Secret::new("admin@email.com".to_owned())).unwrap(),
-            User {
-                email: Email::parse(Secret::new("admin@email.com".to_string())).unwrap(),
-                password: Password::parse(Secret::new("12341234".to_owned())).unwrap(),
-                requires_2fa: false,
-            },
+            email.clone(),
+            User::new(email, Password::parse(password_hash).unwrap(), false),
         );
         user_store
     }
 }
 
+impl Default for HashmapUserStore {
+    fn default() -> Self {
+        Self::new(PasswordHashingSettings::default())
+    }
+}
+
 #[async_trait::async_trait]
 impl UserStore for HashmapUserStore {
     async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
-        match self.users.get(&user.email) {
-            Some(_) => Err(UserStoreError::UserAlreadyExists),
-            None => {
-                self.users.insert(user.email.clone(), user);
-                Ok(())
-            }
+        if self.users.contains_key(&user.email) {
+            return Err(UserStoreError::UserAlreadyExists);
         }
+
+        let password_hash =
+            compute_password_hash(user.password.as_ref().to_owned(), self.password_hashing)
+                .await
+                .map_err(UserStoreError::UnexpectedError)?;
+        let password = Password::parse(password_hash).map_err(UserStoreError::UnexpectedError)?;
+
+        self.users
+            .insert(user.email.clone(), User { password, ..user });
+        Ok(())
     }
 
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
@@ -53,16 +84,94 @@ impl UserStore for HashmapUserStore {
         email: &Email,
         password: &Password,
     ) -> Result<(), UserStoreError> {
-        match self.users.get(email) {
-            Some(user) => {
-                if user.password.eq(password) {
-                    Ok(())
-                } else {
-                    Err(UserStoreError::InvalidCredentials)
-                }
-            }
-            None => Err(UserStoreError::UserNotFound),
-        }
+        let user = self.get_user(email).await?;
+
+        verify_password_hash(
+            user.password.as_ref().to_owned(),
+            password.as_ref().to_owned(),
+        )
+        .await
+        .map_err(|_| UserStoreError::InvalidCredentials)
+    }
+
+    async fn set_phone_verification(
+        &mut self,
+        email: &Email,
+        verification: Option<PhoneVerification>,
+    ) -> Result<(), UserStoreError> {
+        self.user_mut(email)?.phone_verification = verification;
+        Ok(())
+    }
+
+    async fn set_phone_number(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+    ) -> Result<(), UserStoreError> {
+        let user = self.user_mut(email)?;
+        user.phone_number = Some(phone_number.clone());
+        user.phone_verification = None;
+        Ok(())
+    }
+
+    async fn set_two_fa_channel(
+        &mut self,
+        email: &Email,
+        channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError> {
+        self.user_mut(email)?.two_fa_channel = channel;
+        Ok(())
+    }
+
+    async fn record_login(
+        &mut self,
+        email: &Email,
+        logged_in_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        self.user_mut(email)?.last_login_at = Some(logged_in_at);
+        Ok(())
+    }
+
+    async fn record_phone_verification_sent(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        self.user_mut(email)?;
+        self.phone_verification_sends
+            .retain(|(_, _, earlier)| *earlier > sent_at - chrono::Duration::days(1));
+        self.phone_verification_sends
+            .push((email.clone(), phone_number.clone(), sent_at));
+        Ok(())
+    }
+
+    async fn phone_verifications_sent_since(
+        &self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        since: DateTime<Utc>,
+    ) -> Result<PhoneVerificationSends, UserStoreError> {
+        let count = |matches: &dyn Fn(&Email, &PhoneNumber) -> bool| {
+            let sends = self
+                .phone_verification_sends
+                .iter()
+                .filter(|(e, n, sent_at)| *sent_at >= since && matches(e, n))
+                .count();
+            u32::try_from(sends).unwrap_or(u32::MAX)
+        };
+        Ok(PhoneVerificationSends {
+            by_user: count(&|e, _| e == email),
+            to_number: count(&|_, n| n == phone_number),
+        })
+    }
+}
+
+impl HashmapUserStore {
+    fn user_mut(&mut self, email: &Email) -> Result<&mut User, UserStoreError> {
+        self.users
+            .get_mut(email)
+            .ok_or(UserStoreError::UserNotFound)
     }
 }
 
@@ -72,14 +181,18 @@ mod tests {
 
     use super::*;
 
+    fn user() -> User {
+        User::new(
+            Email::parse(Secret::new("test@email.com".to_string())).unwrap(),
+            Password::parse(Secret::new("12341234".to_string())).unwrap(),
+            false,
+        )
+    }
+
     #[tokio::test]
     async fn test_add_user() {
         let mut user_store = HashmapUserStore::default();
-        let new_user = User {
-            email: Email::parse(Secret::new("test@email.com".to_string())).unwrap(),
-            password: Password::parse(Secret::new("12341234".to_string())).unwrap(),
-            requires_2fa: false,
-        };
+        let new_user = user();
         let result = user_store.add_user(new_user.clone()).await;
         assert!(result.is_ok());
 
@@ -89,27 +202,51 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_user() {
+        let mut user_map = HashmapUserStore::default();
+        user_map.add_user(user()).await.unwrap();
+
+        let user_result = user_map.get_user(&user().email).await.unwrap();
+        assert_eq!("test@email.com", user_result.email.as_ref().expose_secret());
+        // stores the hash, not the password
+        assert_ne!(user_result.password, user().password);
+    }
+
+    #[tokio::test]
+    async fn test_admin_user_is_seeded() {
         let user_map = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("admin@email.com".to_string())).unwrap();
 
-        let user_result = user_map
-            .get_user(&Email::parse(Secret::new("admin@email.com".to_string())).unwrap())
-            .await
-            .unwrap();
+        let user_result = user_map.get_user(&email).await.unwrap();
         assert_eq!(
             "admin@email.com",
             user_result.email.as_ref().expose_secret()
         );
+        assert!(user_map
+            .validate_user(
+                &email,
+                &Password::parse(Secret::new("12341234".to_string())).unwrap()
+            )
+            .await
+            .is_ok());
     }
 
     #[tokio::test]
     async fn test_validate_user() {
-        let user_map = HashmapUserStore::default();
+        let mut user_map = HashmapUserStore::default();
+        user_map.add_user(user()).await.unwrap();
+
         assert!(user_map
-            .validate_user(
-                &Email::parse(Secret::new("admin@email.com".to_string())).unwrap(),
-                &Password::parse(Secret::new("12341234".to_string())).unwrap()
-            )
+            .validate_user(&user().email, &user().password)
             .await
             .is_ok());
+        assert_eq!(
+            user_map
+                .validate_user(
+                    &user().email,
+                    &Password::parse(Secret::new("43214321".to_string())).unwrap()
+                )
+                .await,
+            Err(UserStoreError::InvalidCredentials)
+        );
     }
 }
@@ -1,54 +1,43 @@
-// use std::collections::HashSet;
-
-// use crate::domain::data_stores::{BannedTokenStore, BannedTokenStoreError};
-
-// #[derive(Default)]
-// pub struct HashsetBannedTokenStore {
-//     tokens: HashSet<String>,
-// }
-
-// #[async_trait::async_trait]
-// impl BannedTokenStore for HashsetBannedTokenStore {
-//     async fn store_token(&mut self, token: String) -> Result<(), BannedTokenStoreError> {
-//         match self.tokens.get(&token) {
-//             Some(_) => return Err(BannedTokenStoreError::TokenAlreadyExists),
-//             None => {
-//                 self.tokens.insert(token);
-//                 return Ok(());
-//             }
-//         }
-//     }
-
-//     async fn contains_token(&self, token: &str) -> Result<bool, BannedTokenStoreError> {
-//         match self.tokens.get(token) {
-//             Some(_) => Ok(true),
-//             None => Err(BannedTokenStoreError::TokenNotFound),
-//         }
-//     }
-// }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[tokio::test]
-//     async fn test_add_token() {
-//         let mut store = HashsetBannedTokenStore::default();
-//         let token = "test".to_string();
-
-//         let result = store.store_token(token.clone()).await;
-
-//         assert!(result.is_ok());
-//         assert!(store.contains_token(&token).await.is_ok());
-//     }
-
-//     #[tokio::test]
-//     async fn test_contains_token() {
-//         let mut store = HashsetBannedTokenStore::default();
-//         let token = "test".to_string();
-//         store.tokens.insert(token.clone());
-
-//         let result = store.contains_token(&token).await;
-//         assert!(result.is_ok());
-//     }
-// }
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::data_stores::{BannedTokenStore, BannedTokenStoreError};
+
+// In-memory `BannedTokenStore` for tests and local use; tokens expire like in Redis
+pub struct HashsetBannedTokenStore {
+    // token -> when it stops being banned
+    tokens: HashMap<String, Instant>,
+    ttl: Duration,
+}
+
+impl HashsetBannedTokenStore {
+    pub fn new(ttl_seconds: u64) -> Self {
+        Self {
+            tokens: HashMap::new(),
+            ttl: Duration::from_secs(ttl_seconds),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BannedTokenStore for HashsetBannedTokenStore {
+    async fn store_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
+        let now = Instant::now();
+        self.tokens.retain(|_, expires_at| *expires_at > now);
+        self.tokens
+            .insert(token.expose_secret().to_owned(), now + self.ttl);
+
+        Ok(())
+    }
+
+    async fn contains_token(&self, token: Secret<String>) -> Result<bool, BannedTokenStoreError> {
+        Ok(self
+            .tokens
+            .get(token.expose_secret())
+            .is_some_and(|expires_at| *expires_at > Instant::now()))
+    }
+}
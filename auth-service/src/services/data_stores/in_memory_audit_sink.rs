@@ -0,0 +1,63 @@
+use tokio::sync::RwLock;
+
+use crate::domain::audit::{AuditEvent, AuditQuery, AuditSink, AuditSinkError};
+
+// Keeps events for the lifetime of the process; for tests
+#[derive(Default)]
+pub struct InMemoryAuditSink {
+    events: RwLock<Vec<AuditEvent>>,
+}
+
+#[async_trait::async_trait]
+impl AuditSink for InMemoryAuditSink {
+    async fn record(&self, event: AuditEvent) -> Result<(), AuditSinkError> {
+        self.events.write().await.push(event);
+        Ok(())
+    }
+
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, AuditSinkError> {
+        // recorded in order, so newest first is the reverse
+        Ok(self
+            .events
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter(|event| query.matches(event))
+            .take(query.limit())
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::audit::{AuditEventKind, AuditOutcome};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_and_query() {
+        let sink = InMemoryAuditSink::default();
+
+        let login = AuditEvent::new(AuditEventKind::Login, AuditOutcome::Failure)
+            .with_actor("test@email.com");
+        let signup = AuditEvent::new(AuditEventKind::Signup, AuditOutcome::Success)
+            .with_actor("other@email.com");
+
+        sink.record(login.clone()).await.unwrap();
+        sink.record(signup.clone()).await.unwrap();
+
+        let all = sink.query(&AuditQuery::default()).await.unwrap();
+        assert_eq!(all, vec![signup, login.clone()]);
+
+        let failures = sink
+            .query(&AuditQuery {
+                outcome: Some(AuditOutcome::Failure),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(failures, vec![login]);
+    }
+}
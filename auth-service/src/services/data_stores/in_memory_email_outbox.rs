@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::domain::{
+    email_outbox::{
+        EmailDelivery, EmailDeliveryStatus, EmailOutbox, EmailOutboxError, EmailOutboxStats,
+        QueuedEmail, EXPIRED_ERROR,
+    },
+    Email, EmailMessage,
+};
+
+struct Entry {
+    recipient: Email,
+    // cleared once the email is sent or dead, like the `postgres` outbox
+    message: Option<EmailMessage>,
+    delivery: EmailDelivery,
+    next_attempt_at: DateTime<Utc>,
+    claim_token: Option<Uuid>,
+}
+
+impl Entry {
+    fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+        self.delivery.status == EmailDeliveryStatus::Pending
+            && self.delivery.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+// The entry with `id`, if `claim_token` is still its latest claim
+fn claimed_entry(
+    entries: &mut [Entry],
+    id: Uuid,
+    claim_token: Uuid,
+) -> Result<&mut Entry, EmailOutboxError> {
+    entries
+        .iter_mut()
+        .find(|e| e.delivery.id == id && e.claim_token == Some(claim_token))
+        .ok_or(EmailOutboxError::ClaimExpired)
+}
+
+// Lost on restart and not shared between instances; for tests
+#[derive(Default)]
+pub struct InMemoryEmailOutbox {
+    // in enqueue order
+    entries: RwLock<Vec<Entry>>,
+}
+
+#[async_trait::async_trait]
+impl EmailOutbox for InMemoryEmailOutbox {
+    async fn enqueue(
+        &self,
+        recipient: &Email,
+        message: &EmailMessage,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Uuid, EmailOutboxError> {
+        let now = Utc::now();
+        let id = Uuid::new_v4();
+        self.entries.write().await.push(Entry {
+            recipient: recipient.clone(),
+            message: Some(message.clone()),
+            delivery: EmailDelivery {
+                id,
+                status: EmailDeliveryStatus::Pending,
+                attempts: 0,
+                last_error: None,
+                provider: None,
+                created_at: now,
+                sent_at: None,
+                expires_at,
+            },
+            next_attempt_at: now,
+            claim_token: None,
+        });
+
+        Ok(id)
+    }
+
+    async fn claim_next(
+        &self,
+        due_by: DateTime<Utc>,
+        lease: Duration,
+    ) -> Result<Option<QueuedEmail>, EmailOutboxError> {
+        let now = Utc::now();
+        let lease_until = now + chrono::Duration::from_std(lease).unwrap_or(chrono::Duration::MAX);
+        let mut entries = self.entries.write().await;
+
+        let Some(entry) = entries
+            .iter_mut()
+            .filter(|entry| {
+                entry.delivery.status == EmailDeliveryStatus::Pending
+                    && entry.next_attempt_at <= due_by
+                    && !entry.is_overdue(now)
+            })
+            .min_by_key(|entry| entry.next_attempt_at)
+        else {
+            return Ok(None);
+        };
+
+        let claim_token = Uuid::new_v4();
+        entry.delivery.attempts += 1;
+        entry.next_attempt_at = lease_until;
+        entry.claim_token = Some(claim_token);
+        Ok(entry.message.clone().map(|message| QueuedEmail {
+            id: entry.delivery.id,
+            claim_token,
+            recipient: entry.recipient.clone(),
+            message,
+            attempts: entry.delivery.attempts,
+            expires_at: entry.delivery.expires_at,
+        }))
+    }
+
+    async fn mark_sent(
+        &self,
+        id: Uuid,
+        claim_token: Uuid,
+        provider: &str,
+    ) -> Result<(), EmailOutboxError> {
+        let mut entries = self.entries.write().await;
+        let entry = claimed_entry(&mut entries, id, claim_token)?;
+        entry.claim_token = None;
+        entry.message = None;
+        entry.delivery.status = EmailDeliveryStatus::Sent;
+        entry.delivery.provider = Some(provider.to_owned());
+        entry.delivery.sent_at = Some(Utc::now());
+
+        Ok(())
+    }
+
+    async fn mark_failed(
+        &self,
+        id: Uuid,
+        claim_token: Uuid,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxError> {
+        let mut entries = self.entries.write().await;
+        let entry = claimed_entry(&mut entries, id, claim_token)?;
+        entry.claim_token = None;
+        entry.delivery.last_error = Some(error.to_owned());
+        match retry_at {
+            Some(retry_at) => entry.next_attempt_at = retry_at,
+            None => {
+                entry.message = None;
+                entry.delivery.status = EmailDeliveryStatus::Dead;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn expire_overdue(&self) -> Result<u64, EmailOutboxError> {
+        let now = Utc::now();
+        let mut count = 0;
+        for entry in self.entries.write().await.iter_mut() {
+            if entry.is_overdue(now) {
+                entry.claim_token = None;
+                entry.message = None;
+                entry.delivery.status = EmailDeliveryStatus::Dead;
+                entry.delivery.last_error = Some(EXPIRED_ERROR.to_owned());
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    async fn delivery(&self, id: Uuid) -> Result<Option<EmailDelivery>, EmailOutboxError> {
+        Ok(self
+            .entries
+            .read()
+            .await
+            .iter()
+            .find(|entry| entry.delivery.id == id)
+            .map(|entry| entry.delivery.clone()))
+    }
+
+    async fn stats(&self) -> Result<EmailOutboxStats, EmailOutboxError> {
+        let entries = self.entries.read().await;
+        let mut stats = EmailOutboxStats::default();
+        for entry in entries.iter() {
+            match entry.delivery.status {
+                EmailDeliveryStatus::Pending => {
+                    stats.pending += 1;
+                    stats.oldest_pending_at = Some(
+                        stats
+                            .oldest_pending_at
+                            .map_or(entry.delivery.created_at, |oldest| {
+                                oldest.min(entry.delivery.created_at)
+                            }),
+                    );
+                }
+                EmailDeliveryStatus::Dead => stats.dead += 1,
+                EmailDeliveryStatus::Sent => {}
+            }
+        }
+
+        Ok(stats)
+    }
+
+    async fn purge_sent(&self, before: DateTime<Utc>) -> Result<u64, EmailOutboxError> {
+        let mut entries = self.entries.write().await;
+        let count = entries.len();
+        entries.retain(|entry| {
+            entry.delivery.status != EmailDeliveryStatus::Sent
+                || entry
+                    .delivery
+                    .sent_at
+                    .is_some_and(|sent_at| sent_at >= before)
+        });
+
+        Ok((count - entries.len()) as u64)
+    }
+}
@@ -0,0 +1,177 @@
+use std::{cmp::Reverse, collections::BTreeMap, path::PathBuf};
+
+use color_eyre::eyre::Context;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::Mutex,
+};
+
+use crate::domain::audit::{AuditEvent, AuditQuery, AuditSink, AuditSinkError};
+
+// Appends one JSON object per line, for shipping with a log forwarder
+pub struct JsonLinesAuditSink {
+    path: PathBuf,
+    // serializes appends so concurrent events never interleave within a line
+    write_lock: Mutex<()>,
+}
+
+impl JsonLinesAuditSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            write_lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl AuditSink for JsonLinesAuditSink {
+    #[tracing::instrument(name = "Recording audit event in JSON lines file", skip_all)]
+    async fn record(&self, event: AuditEvent) -> Result<(), AuditSinkError> {
+        let mut line = serde_json::to_string(&event)
+            .wrap_err("failed to serialize audit event")
+            .map_err(AuditSinkError::UnexpectedError)?;
+        line.push('\n');
+
+        let _guard = self.write_lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .wrap_err("failed to open audit log file")
+            .map_err(AuditSinkError::UnexpectedError)?;
+
+        file.write_all(line.as_bytes())
+            .await
+            .wrap_err("failed to append audit event")
+            .map_err(AuditSinkError::UnexpectedError)?;
+        // tokio writes in the background; make sure the line is in the file before returning
+        file.flush()
+            .await
+            .wrap_err("failed to append audit event")
+            .map_err(AuditSinkError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Querying audit events in JSON lines file", skip_all)]
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, AuditSinkError> {
+        let file = match File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(AuditSinkError::UnexpectedError(
+                    color_eyre::eyre::Report::new(e).wrap_err("failed to read audit log file"),
+                ))
+            }
+        };
+
+        // read line by line, keeping only the newest `limit` matches, so the whole log is
+        // never in memory; among equal timestamps the earlier line counts as newer
+        let limit = query.limit();
+        let mut newest = BTreeMap::new();
+        let mut lines = BufReader::new(file).lines();
+        let mut line_number = 0;
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .wrap_err("failed to read audit log file")
+            .map_err(AuditSinkError::UnexpectedError)?
+        {
+            line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = serde_json::from_str::<AuditEvent>(&line)
+                .wrap_err_with(|| {
+                    format!("failed to deserialize audit event on line {}", line_number)
+                })
+                .map_err(AuditSinkError::UnexpectedError)?;
+            if !query.matches(&event) {
+                continue;
+            }
+            newest.insert((event.occurred_at, Reverse(line_number)), event);
+            if newest.len() > limit {
+                newest.pop_first();
+            }
+        }
+
+        Ok(newest.into_values().rev().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::audit::{AuditEventKind, AuditOutcome};
+
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn test_record_and_query() {
+        let path = temp_path();
+        let sink = JsonLinesAuditSink::new(&path);
+
+        let login = AuditEvent::new(AuditEventKind::Login, AuditOutcome::Failure)
+            .with_actor("test@email.com")
+            .with_reason("Incorrect credentials");
+        let signup = AuditEvent::new(AuditEventKind::Signup, AuditOutcome::Success)
+            .with_actor("other@email.com");
+
+        sink.record(login.clone()).await.unwrap();
+        sink.record(signup.clone()).await.unwrap();
+
+        let all = sink.query(&AuditQuery::default()).await.unwrap();
+        assert_eq!(all, vec![signup, login.clone()]);
+
+        let failures = sink
+            .query(&AuditQuery {
+                actor: Some("test@email.com".to_owned()),
+                outcome: Some(AuditOutcome::Failure),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(failures, vec![login]);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_query_keeps_the_newest_events() {
+        let path = temp_path();
+        let sink = JsonLinesAuditSink::new(&path);
+
+        let mut events = Vec::new();
+        for seconds in 0..5 {
+            let mut event = AuditEvent::new(AuditEventKind::Login, AuditOutcome::Success);
+            event.occurred_at += chrono::Duration::seconds(seconds);
+            sink.record(event.clone()).await.unwrap();
+            events.push(event);
+        }
+
+        let newest = sink
+            .query(&AuditQuery {
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(newest, vec![events[4].clone(), events[3].clone()]);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_query_missing_file_is_empty() {
+        let sink = JsonLinesAuditSink::new(temp_path());
+
+        let events = sink.query(&AuditQuery::default()).await.unwrap();
+        assert!(events.is_empty());
+    }
+}
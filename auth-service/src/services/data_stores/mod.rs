@@ -1,7 +1,16 @@
+pub mod expired_rows_purger;
 pub mod hashmap_two_fa_code_store;
 pub mod hashmap_user_store;
 pub mod hashset_banned_token_store;
-pub mod mock_email_client;
+pub mod in_memory_audit_sink;
+pub mod in_memory_email_outbox;
+pub mod json_lines_audit_sink;
+pub mod postgres_audit_sink;
+pub mod postgres_banned_token_store;
+pub mod postgres_email_outbox;
+pub mod postgres_two_fa_code_store;
 pub mod postgres_user_store;
 pub mod redis_banned_token_store;
 pub mod redis_two_fa_code_store;
+pub mod sqlite_email_outbox;
+pub mod sqlite_user_store;
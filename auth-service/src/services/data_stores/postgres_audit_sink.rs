@@ -0,0 +1,128 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Context;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::domain::audit::{AuditEvent, AuditQuery, AuditSink, AuditSinkError};
+
+pub struct PostgresAuditSink {
+    pool: PgPool,
+}
+
+impl PostgresAuditSink {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct AuditEventRow {
+    id: Uuid,
+    occurred_at: DateTime<Utc>,
+    kind: String,
+    outcome: String,
+    actor: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+    reason: Option<String>,
+}
+
+impl TryFrom<AuditEventRow> for AuditEvent {
+    type Error = AuditSinkError;
+
+    fn try_from(row: AuditEventRow) -> Result<Self, Self::Error> {
+        Ok(AuditEvent {
+            id: row.id,
+            occurred_at: row.occurred_at,
+            kind: row.kind.parse().map_err(AuditSinkError::UnexpectedError)?,
+            outcome: row.outcome.parse().map_err(AuditSinkError::UnexpectedError)?,
+            actor: row.actor,
+            ip: row.ip,
+            user_agent: row.user_agent,
+            request_id: row.request_id,
+            reason: row.reason,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl AuditSink for PostgresAuditSink {
+    #[tracing::instrument(name = "Recording audit event in PostgreSQL", skip_all)]
+    async fn record(&self, event: AuditEvent) -> Result<(), AuditSinkError> {
+        sqlx::query(
+            r#"
+                INSERT INTO audit_events
+                    (id, occurred_at, kind, outcome, actor, ip, user_agent, request_id, reason)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(event.id)
+        .bind(event.occurred_at)
+        .bind(event.kind.as_str())
+        .bind(event.outcome.as_str())
+        .bind(event.actor)
+        .bind(event.ip)
+        .bind(event.user_agent)
+        .bind(event.request_id)
+        .bind(event.reason)
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to insert audit event")
+        .map_err(AuditSinkError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Querying audit events in PostgreSQL", skip_all)]
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, AuditSinkError> {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"
+                SELECT id, occurred_at, kind, outcome, actor, ip, user_agent, request_id, reason
+                FROM audit_events
+                WHERE TRUE
+            "#,
+        );
+
+        if let Some(actor) = &query.actor {
+            builder.push(" AND actor = ").push_bind(actor.clone());
+        }
+        if let Some(kind) = query.kind {
+            builder.push(" AND kind = ").push_bind(kind.as_str());
+        }
+        if let Some(outcome) = query.outcome {
+            builder.push(" AND outcome = ").push_bind(outcome.as_str());
+        }
+        if let Some(ip) = &query.ip {
+            builder.push(" AND ip = ").push_bind(ip.clone());
+        }
+        if let Some(request_id) = &query.request_id {
+            builder.push(" AND request_id = ").push_bind(request_id.clone());
+        }
+        if let Some(since) = query.since {
+            builder.push(" AND occurred_at >= ").push_bind(since);
+        }
+        if let Some(until) = query.until {
+            builder.push(" AND occurred_at < ").push_bind(until);
+        }
+
+        let limit: i64 = query
+            .limit()
+            .try_into()
+            .wrap_err("failed to cast audit query limit to i64")
+            .map_err(AuditSinkError::UnexpectedError)?;
+        builder
+            .push(" ORDER BY occurred_at DESC LIMIT ")
+            .push_bind(limit);
+
+        builder
+            .build_query_as::<AuditEventRow>()
+            .fetch_all(&self.pool)
+            .await
+            .wrap_err("failed to query audit events")
+            .map_err(AuditSinkError::UnexpectedError)?
+            .into_iter()
+            .map(AuditEvent::try_from)
+            .collect()
+    }
+}
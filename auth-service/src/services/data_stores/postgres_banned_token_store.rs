@@ -0,0 +1,72 @@
+use color_eyre::eyre::{Context, Result};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    domain::data_stores::{BannedTokenStore, BannedTokenStoreError},
+    utils::{keyed_hash::KeyedHasher, metrics::StoreCallTimer},
+};
+
+const TOKEN_HASH_KIND: &str = "banned_token";
+
+#[derive(Clone)]
+pub struct PostgresBannedTokenStore {
+    pool: PgPool,
+    // should match the token TTL, so a banned token expires no earlier than the token itself
+    ttl_seconds: u64,
+    // only hashes are stored, so the table can't be used to harvest tokens
+    hasher: KeyedHasher,
+}
+
+impl PostgresBannedTokenStore {
+    pub fn new(pool: PgPool, ttl_seconds: u64, hasher: KeyedHasher) -> Self {
+        Self {
+            pool,
+            ttl_seconds,
+            hasher,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BannedTokenStore for PostgresBannedTokenStore {
+    #[tracing::instrument(name = "Storing token to PostgreSQL", skip_all)]
+    async fn store_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "store_token");
+        sqlx::query(
+            r#"
+                INSERT INTO banned_tokens (token_hash, expires_at)
+                VALUES ($1, now() + make_interval(secs => $2))
+                ON CONFLICT (token_hash) DO UPDATE SET expires_at = EXCLUDED.expires_at
+            "#,
+        )
+        .bind(self.hasher.hash(TOKEN_HASH_KIND, token.expose_secret()))
+        .bind(self.ttl_seconds as f64)
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to insert banned token")
+        .map_err(BannedTokenStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Checking if token exists in PostgreSQL", skip_all)]
+    async fn contains_token(&self, token: Secret<String>) -> Result<bool, BannedTokenStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "contains_token");
+        // expired rows may not be purged yet
+        let is_banned: bool = sqlx::query_scalar(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM banned_tokens WHERE token_hash = $1 AND expires_at > now()
+                )
+            "#,
+        )
+        .bind(self.hasher.hash(TOKEN_HASH_KIND, token.expose_secret()))
+        .fetch_one(&self.pool)
+        .await
+        .wrap_err("failed to check if token is banned")
+        .map_err(BannedTokenStoreError::UnexpectedError)?;
+
+        Ok(is_banned)
+    }
+}
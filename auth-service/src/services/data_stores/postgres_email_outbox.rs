@@ -0,0 +1,330 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        email_outbox::{
+            EmailDelivery, EmailDeliveryStatus, EmailOutbox, EmailOutboxError, EmailOutboxStats,
+            QueuedEmail, EXPIRED_ERROR,
+        },
+        Email, EmailMessage,
+    },
+    utils::metrics::StoreCallTimer,
+};
+
+// Durable and shared, so any instance's dispatcher can send what another one queued
+pub struct PostgresEmailOutbox {
+    pool: PgPool,
+}
+
+impl PostgresEmailOutbox {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+// Every update of a claimed email fails with `ClaimExpired` if it matched no row
+fn expect_claimed_row(result: sqlx::postgres::PgQueryResult) -> Result<(), EmailOutboxError> {
+    match result.rows_affected() {
+        0 => Err(EmailOutboxError::ClaimExpired),
+        _ => Ok(()),
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct QueuedEmailRow {
+    id: Uuid,
+    claim_token: Uuid,
+    recipient: String,
+    subject: String,
+    text_body: String,
+    html_body: String,
+    attempts: i32,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<QueuedEmailRow> for QueuedEmail {
+    type Error = EmailOutboxError;
+
+    fn try_from(row: QueuedEmailRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            claim_token: row.claim_token,
+            recipient: Email::parse(Secret::new(row.recipient))
+                .map_err(EmailOutboxError::UnexpectedError)?,
+            message: EmailMessage {
+                subject: row.subject,
+                text_body: row.text_body,
+                html_body: row.html_body,
+            },
+            attempts: row.attempts.try_into().unwrap_or_default(),
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct EmailDeliveryRow {
+    id: Uuid,
+    status: String,
+    attempts: i32,
+    last_error: Option<String>,
+    provider: Option<String>,
+    created_at: DateTime<Utc>,
+    sent_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<EmailDeliveryRow> for EmailDelivery {
+    type Error = EmailOutboxError;
+
+    fn try_from(row: EmailDeliveryRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            status: EmailDeliveryStatus::from_str(&row.status)
+                .map_err(EmailOutboxError::UnexpectedError)?,
+            attempts: row.attempts.try_into().unwrap_or_default(),
+            last_error: row.last_error,
+            provider: row.provider,
+            created_at: row.created_at,
+            sent_at: row.sent_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct StatsRow {
+    pending: i64,
+    dead: i64,
+    oldest_pending_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait::async_trait]
+impl EmailOutbox for PostgresEmailOutbox {
+    #[tracing::instrument(name = "Enqueuing email in PostgreSQL", skip_all)]
+    async fn enqueue(
+        &self,
+        recipient: &Email,
+        message: &EmailMessage,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Uuid, EmailOutboxError> {
+        let _timer = StoreCallTimer::start("postgres", "enqueue_email");
+        let id = Uuid::new_v4();
+        sqlx::query(
+            r#"
+                INSERT INTO email_outbox (id, recipient, subject, text_body, html_body, expires_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(id)
+        .bind(recipient.as_ref().expose_secret())
+        .bind(&message.subject)
+        .bind(&message.text_body)
+        .bind(&message.html_body)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to enqueue email")
+        .map_err(EmailOutboxError::UnexpectedError)?;
+
+        Ok(id)
+    }
+
+    #[tracing::instrument(name = "Claiming the next due email from PostgreSQL", skip_all)]
+    async fn claim_next(
+        &self,
+        due_by: DateTime<Utc>,
+        lease: Duration,
+    ) -> Result<Option<QueuedEmail>, EmailOutboxError> {
+        let _timer = StoreCallTimer::start("postgres", "claim_next_email");
+        // SKIP LOCKED lets several dispatchers claim different emails
+        sqlx::query_as::<_, QueuedEmailRow>(
+            r#"
+                WITH due AS (
+                    SELECT id
+                    FROM email_outbox
+                    WHERE status = $1 AND next_attempt_at <= $2
+                        AND (expires_at IS NULL OR expires_at > now())
+                    ORDER BY next_attempt_at
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                UPDATE email_outbox o
+                SET attempts = o.attempts + 1,
+                    next_attempt_at = now() + make_interval(secs => $3),
+                    claim_token = $4
+                FROM due
+                WHERE o.id = due.id
+                RETURNING o.id, o.claim_token, o.recipient, o.subject, o.text_body, o.html_body,
+                    o.attempts, o.expires_at
+            "#,
+        )
+        .bind(EmailDeliveryStatus::Pending.as_str())
+        .bind(due_by)
+        .bind(lease.as_secs_f64())
+        .bind(Uuid::new_v4())
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to claim due email")
+        .map_err(EmailOutboxError::UnexpectedError)?
+        .map(QueuedEmail::try_from)
+        .transpose()
+    }
+
+    #[tracing::instrument(name = "Marking email as sent in PostgreSQL", skip_all)]
+    async fn mark_sent(
+        &self,
+        id: Uuid,
+        claim_token: Uuid,
+        provider: &str,
+    ) -> Result<(), EmailOutboxError> {
+        let _timer = StoreCallTimer::start("postgres", "mark_email_sent");
+        let result = sqlx::query(
+            r#"
+                UPDATE email_outbox
+                SET status = $3, provider = $4, sent_at = now(), claim_token = NULL,
+                    subject = NULL, text_body = NULL, html_body = NULL
+                WHERE id = $1 AND claim_token = $2
+            "#,
+        )
+        .bind(id)
+        .bind(claim_token)
+        .bind(EmailDeliveryStatus::Sent.as_str())
+        .bind(provider)
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to mark email as sent")
+        .map_err(EmailOutboxError::UnexpectedError)?;
+
+        expect_claimed_row(result)
+    }
+
+    #[tracing::instrument(name = "Marking email as failed in PostgreSQL", skip_all)]
+    async fn mark_failed(
+        &self,
+        id: Uuid,
+        claim_token: Uuid,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxError> {
+        let _timer = StoreCallTimer::start("postgres", "mark_email_failed");
+        let query = match retry_at {
+            Some(retry_at) => sqlx::query(
+                r#"
+                    UPDATE email_outbox
+                    SET last_error = $3, next_attempt_at = $4, claim_token = NULL
+                    WHERE id = $1 AND claim_token = $2
+                "#,
+            )
+            .bind(id)
+            .bind(claim_token)
+            .bind(error)
+            .bind(retry_at),
+            None => sqlx::query(
+                r#"
+                    UPDATE email_outbox
+                    SET status = $4, last_error = $3, claim_token = NULL,
+                        subject = NULL, text_body = NULL, html_body = NULL
+                    WHERE id = $1 AND claim_token = $2
+                "#,
+            )
+            .bind(id)
+            .bind(claim_token)
+            .bind(error)
+            .bind(EmailDeliveryStatus::Dead.as_str()),
+        };
+        let result = query
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to mark email as failed")
+            .map_err(EmailOutboxError::UnexpectedError)?;
+
+        expect_claimed_row(result)
+    }
+
+    #[tracing::instrument(name = "Expiring overdue emails in PostgreSQL", skip_all)]
+    async fn expire_overdue(&self) -> Result<u64, EmailOutboxError> {
+        let _timer = StoreCallTimer::start("postgres", "expire_overdue_emails");
+        let result = sqlx::query(
+            r#"
+                UPDATE email_outbox
+                SET status = $2, last_error = $3, claim_token = NULL,
+                    subject = NULL, text_body = NULL, html_body = NULL
+                WHERE status = $1 AND expires_at <= now()
+            "#,
+        )
+        .bind(EmailDeliveryStatus::Pending.as_str())
+        .bind(EmailDeliveryStatus::Dead.as_str())
+        .bind(EXPIRED_ERROR)
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to expire overdue emails")
+        .map_err(EmailOutboxError::UnexpectedError)?;
+
+        Ok(result.rows_affected())
+    }
+
+    #[tracing::instrument(name = "Retrieving email delivery from PostgreSQL", skip_all)]
+    async fn delivery(&self, id: Uuid) -> Result<Option<EmailDelivery>, EmailOutboxError> {
+        let _timer = StoreCallTimer::start("postgres", "email_delivery");
+        sqlx::query_as::<_, EmailDeliveryRow>(
+            r#"
+                SELECT id, status, attempts, last_error, provider, created_at, sent_at, expires_at
+                FROM email_outbox
+                WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to retrieve email delivery")
+        .map_err(EmailOutboxError::UnexpectedError)?
+        .map(EmailDelivery::try_from)
+        .transpose()
+    }
+
+    #[tracing::instrument(name = "Retrieving email outbox stats from PostgreSQL", skip_all)]
+    async fn stats(&self) -> Result<EmailOutboxStats, EmailOutboxError> {
+        let _timer = StoreCallTimer::start("postgres", "email_outbox_stats");
+        let row = sqlx::query_as::<_, StatsRow>(
+            r#"
+                SELECT
+                    count(*) FILTER (WHERE status = 'pending') AS pending,
+                    count(*) FILTER (WHERE status = 'dead') AS dead,
+                    min(created_at) FILTER (WHERE status = 'pending') AS oldest_pending_at
+                FROM email_outbox
+                WHERE status <> 'sent'
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .wrap_err("failed to retrieve email outbox stats")
+        .map_err(EmailOutboxError::UnexpectedError)?;
+
+        Ok(EmailOutboxStats {
+            pending: row.pending.try_into().unwrap_or_default(),
+            dead: row.dead.try_into().unwrap_or_default(),
+            oldest_pending_at: row.oldest_pending_at,
+        })
+    }
+
+    #[tracing::instrument(name = "Purging sent emails from PostgreSQL", skip_all)]
+    async fn purge_sent(&self, before: DateTime<Utc>) -> Result<u64, EmailOutboxError> {
+        let _timer = StoreCallTimer::start("postgres", "purge_sent_emails");
+        let result = sqlx::query("DELETE FROM email_outbox WHERE status = $1 AND sent_at < $2")
+            .bind(EmailDeliveryStatus::Sent.as_str())
+            .bind(before)
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to purge sent emails")
+            .map_err(EmailOutboxError::UnexpectedError)?;
+
+        Ok(result.rows_affected())
+    }
+}
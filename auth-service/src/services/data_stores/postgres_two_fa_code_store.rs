@@ -0,0 +1,225 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    domain::{
+        data_stores::{
+            LoginAttempt, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError,
+        },
+        Email,
+    },
+    utils::{keyed_hash::KeyedHasher, metrics::StoreCallTimer},
+};
+
+const LOGIN_ATTEMPT_ID_HASH_KIND: &str = "login_attempt_id";
+const CODE_HASH_KIND: &str = "two_fa_code";
+
+pub struct PostgresTwoFACodeStore {
+    pool: PgPool,
+    // only hashes of ids and codes are stored, so the table can't be used to complete logins
+    hasher: KeyedHasher,
+}
+
+impl PostgresTwoFACodeStore {
+    pub fn new(pool: PgPool, hasher: KeyedHasher) -> Self {
+        Self { pool, hasher }
+    }
+
+    fn id_hash(&self, login_attempt_id: &LoginAttemptId) -> String {
+        self.hasher.hash(
+            LOGIN_ATTEMPT_ID_HASH_KIND,
+            login_attempt_id.as_ref().expose_secret(),
+        )
+    }
+
+    fn code_hash(&self, code: &TwoFACode) -> String {
+        self.hasher
+            .hash(CODE_HASH_KIND, code.as_ref().expose_secret())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct LoginAttemptRow {
+    email: String,
+    sent_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    resends: i32,
+    failed_attempts: i32,
+}
+
+impl TryFrom<LoginAttemptRow> for LoginAttempt {
+    type Error = TwoFACodeStoreError;
+
+    fn try_from(row: LoginAttemptRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            email: Email::parse(Secret::new(row.email))
+                .map_err(TwoFACodeStoreError::UnexpectedError)?,
+            sent_at: row.sent_at,
+            expires_at: row.expires_at,
+            resends: row.resends.try_into().unwrap_or_default(),
+            failed_attempts: row.failed_attempts.try_into().unwrap_or_default(),
+        })
+    }
+}
+
+// expired rows may not be purged yet, so every query skips them
+fn found(row: Option<LoginAttemptRow>) -> Result<LoginAttempt, TwoFACodeStoreError> {
+    row.ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?
+        .try_into()
+}
+
+#[async_trait::async_trait]
+impl TwoFACodeStore for PostgresTwoFACodeStore {
+    #[tracing::instrument(name = "Adding 2FA code to PostgreSQL", skip_all)]
+    async fn add_code(
+        &mut self,
+        login_attempt_id: LoginAttemptId,
+        attempt: LoginAttempt,
+        code: TwoFACode,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "add_code");
+        sqlx::query(
+            r#"
+                INSERT INTO two_fa_codes (login_attempt_id_hash, email, code_hash, sent_at,
+                    expires_at, resends, failed_attempts)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (login_attempt_id_hash) DO UPDATE SET
+                    email = EXCLUDED.email,
+                    code_hash = EXCLUDED.code_hash,
+                    sent_at = EXCLUDED.sent_at,
+                    expires_at = EXCLUDED.expires_at,
+                    resends = EXCLUDED.resends,
+                    failed_attempts = EXCLUDED.failed_attempts
+            "#,
+        )
+        .bind(self.id_hash(&login_attempt_id))
+        .bind(attempt.email.as_ref().expose_secret())
+        .bind(self.code_hash(&code))
+        .bind(attempt.sent_at)
+        .bind(attempt.expires_at)
+        .bind(i32::try_from(attempt.resends).unwrap_or(i32::MAX))
+        .bind(i32::try_from(attempt.failed_attempts).unwrap_or(i32::MAX))
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to insert 2FA code")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Resending 2FA code in PostgreSQL", skip_all)]
+    async fn resend_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+        code: TwoFACode,
+        sent_at: DateTime<Utc>,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "resend_code");
+        let row = sqlx::query_as::<_, LoginAttemptRow>(
+            r#"
+                UPDATE two_fa_codes
+                SET code_hash = $2, sent_at = $3, resends = resends + 1
+                WHERE login_attempt_id_hash = $1 AND expires_at > now()
+                RETURNING email, sent_at, expires_at, resends, failed_attempts
+            "#,
+        )
+        .bind(self.id_hash(login_attempt_id))
+        .bind(self.code_hash(&code))
+        .bind(sent_at)
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to replace 2FA code")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        found(row)
+    }
+
+    #[tracing::instrument(name = "Removing 2FA code from PostgreSQL", skip_all)]
+    async fn remove_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "remove_code");
+        sqlx::query("DELETE FROM two_fa_codes WHERE login_attempt_id_hash = $1")
+            .bind(self.id_hash(login_attempt_id))
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to delete 2FA code")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving login attempt from PostgreSQL", skip_all)]
+    async fn get_attempt(
+        &self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "get_attempt");
+        let row = sqlx::query_as::<_, LoginAttemptRow>(
+            r#"
+                SELECT email, sent_at, expires_at, resends, failed_attempts
+                FROM two_fa_codes
+                WHERE login_attempt_id_hash = $1 AND expires_at > now()
+            "#,
+        )
+        .bind(self.id_hash(login_attempt_id))
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to retrieve 2FA code")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        found(row)
+    }
+
+    #[tracing::instrument(name = "Consuming 2FA code in PostgreSQL", skip_all)]
+    async fn consume_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+        code: &TwoFACode,
+        max_failed_attempts: u32,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "consume_code");
+        let id_hash = self.id_hash(login_attempt_id);
+        // the hash is deterministic, so the row is only deleted if the code matches
+        let consumed = sqlx::query_as::<_, LoginAttemptRow>(
+            r#"
+                DELETE FROM two_fa_codes
+                WHERE login_attempt_id_hash = $1 AND code_hash = $2 AND expires_at > now()
+                RETURNING email, sent_at, expires_at, resends, failed_attempts
+            "#,
+        )
+        .bind(&id_hash)
+        .bind(self.code_hash(code))
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to consume 2FA code")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        if consumed.is_some() {
+            return found(consumed);
+        }
+
+        let failed_attempts: Option<i32> = sqlx::query_scalar(
+            r#"
+                UPDATE two_fa_codes
+                SET failed_attempts = failed_attempts + 1
+                WHERE login_attempt_id_hash = $1 AND expires_at > now()
+                RETURNING failed_attempts
+            "#,
+        )
+        .bind(&id_hash)
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to count wrong 2FA code")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        let failed_attempts = failed_attempts.ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+        // the last allowed wrong code removes the attempt
+        if u32::try_from(failed_attempts).unwrap_or_default() >= max_failed_attempts {
+            self.remove_code(login_attempt_id).await?;
+        }
+
+        Err(TwoFACodeStoreError::IncorrectCode)
+    }
+}
@@ -1,26 +1,93 @@
-use argon2::{
-    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
-    PasswordVerifier, Version,
-};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::{Context, Result};
 use secrecy::{ExposeSecret, Secret};
 
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{postgres::PgArguments, query::Query, PgPool, Postgres};
 
-use crate::domain::{
-    data_stores::{UserStore, UserStoreError},
-    password::Password,
-    Email, User,
+use crate::{
+    domain::{
+        data_stores::{PhoneVerificationSends, UserStore, UserStoreError},
+        password::Password,
+        webhook::{WebhookEvent, WebhookEventType},
+        Email, PhoneNumber, PhoneVerification, TwoFAChannel, User,
+    },
+    services::webhooks::outbox::enqueue_webhook_event,
+    utils::{
+        configuration::PasswordHashingSettings,
+        metrics::StoreCallTimer,
+        password_hash::{compute_password_hash, is_outdated, verify_password_hash},
+    },
 };
 
 pub struct PostgresUserStore {
     pool: PgPool,
+    // outdated hashes are replaced with ones computed with these on login
+    password_hashing: PasswordHashingSettings,
 }
 
 impl PostgresUserStore {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn new(pool: PgPool, password_hashing: PasswordHashingSettings) -> Self {
+        Self {
+            pool,
+            password_hashing,
+        }
+    }
+
+    // Replaces the hash of a just verified password, unless it changed in the meantime
+    #[tracing::instrument(name = "Upgrading password hash in PostgreSQL", skip_all)]
+    async fn upgrade_password_hash(
+        &self,
+        email: &Email,
+        outdated_hash: &Secret<String>,
+        password: &Secret<String>,
+    ) -> Result<()> {
+        let password_hash =
+            compute_password_hash(password.to_owned(), self.password_hashing).await?;
+
+        let _timer = StoreCallTimer::start("postgres", "upgrade_password_hash");
+        sqlx::query("UPDATE users SET password_hash = $2 WHERE email = $1 AND password_hash = $3")
+            .bind(email.as_ref().expose_secret())
+            .bind(password_hash.expose_secret())
+            .bind(outdated_hash.expose_secret())
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to update password hash")?;
+
+        Ok(())
+    }
+
+    // Runs an update of one user and queues `event` in the same transaction, so subscribers
+    // only hear about changes that were committed
+    async fn update_user_with_event(
+        &self,
+        query: Query<'_, Postgres, PgArguments>,
+        event: &WebhookEvent,
+        error_context: &'static str,
+    ) -> Result<(), UserStoreError> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        let result = query
+            .execute(&mut *tx)
+            .await
+            .wrap_err(error_context)
+            .map_err(UserStoreError::UnexpectedError)?;
+        // dropping the transaction rolls it back, so unknown users queue nothing
+        expect_one_row(result)?;
+
+        enqueue_webhook_event(&mut *tx, event)
+            .await
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        tx.commit()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))
     }
 }
 
@@ -29,55 +96,137 @@ pub struct PostgresUser {
     email: String,
     password_hash: String,
     requires_2fa: bool,
+    phone_number: Option<String>,
+    two_fa_channel: String,
+    pending_phone_number: Option<String>,
+    phone_verification_code_hash: Option<String>,
+    phone_verification_expires_at: Option<DateTime<Utc>>,
+    phone_verification_sent_at: Option<DateTime<Utc>>,
+    phone_verification_attempts: i32,
+    locale: Option<String>,
+    last_login_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<PostgresUser> for User {
+    type Error = UserStoreError;
+
+    fn try_from(row: PostgresUser) -> Result<Self, Self::Error> {
+        let phone_verification = match (
+            row.pending_phone_number,
+            row.phone_verification_code_hash,
+            row.phone_verification_expires_at,
+            row.phone_verification_sent_at,
+        ) {
+            (Some(phone_number), Some(code_hash), Some(expires_at), Some(sent_at)) => {
+                Some(PhoneVerification {
+                    phone_number: PhoneNumber::parse(Secret::new(phone_number))
+                        .map_err(UserStoreError::UnexpectedError)?,
+                    code_hash,
+                    expires_at,
+                    attempts: row
+                        .phone_verification_attempts
+                        .try_into()
+                        .unwrap_or_default(),
+                    sent_at,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(User {
+            email: Email::parse(Secret::new(row.email)).map_err(UserStoreError::UnexpectedError)?,
+            password: Password::parse(Secret::new(row.password_hash))
+                .map_err(UserStoreError::UnexpectedError)?,
+            requires_2fa: row.requires_2fa,
+            phone_number: row
+                .phone_number
+                .map(|phone_number| PhoneNumber::parse(Secret::new(phone_number)))
+                .transpose()
+                .map_err(UserStoreError::UnexpectedError)?,
+            two_fa_channel: TwoFAChannel::from_str(&row.two_fa_channel)
+                .map_err(UserStoreError::UnexpectedError)?,
+            phone_verification,
+            locale: row.locale,
+            last_login_at: row.last_login_at,
+        })
+    }
+}
+
+// Every update of a single user fails with `UserNotFound` if it matched no row
+fn expect_one_row(result: sqlx::postgres::PgQueryResult) -> Result<(), UserStoreError> {
+    match result.rows_affected() {
+        0 => Err(UserStoreError::UserNotFound),
+        _ => Ok(()),
+    }
 }
 
 #[async_trait::async_trait]
 impl UserStore for PostgresUserStore {
     #[tracing::instrument(name = "Adding user to PostgreSQL", skip_all)]
     async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
-        let password_hash = compute_password_hash(user.password.as_ref().to_owned())
+        let password_hash =
+            compute_password_hash(user.password.as_ref().to_owned(), self.password_hashing)
+                .await
+                .map_err(UserStoreError::UnexpectedError)?;
+
+        let _timer = StoreCallTimer::start("postgres", "add_user");
+        let mut tx = self
+            .pool
+            .begin()
             .await
-            .map_err(UserStoreError::UnexpectedError)?;
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
 
         sqlx::query!(
             r#"
-                INSERT INTO users (email, password_hash, requires_2fa)
-                VALUES ($1, $2, $3)
+                INSERT INTO users (email, password_hash, requires_2fa, locale)
+                VALUES ($1, $2, $3, $4)
             "#,
             user.email.as_ref().expose_secret(),
             password_hash.expose_secret(),
-            user.requires_2fa
+            user.requires_2fa,
+            user.locale
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
-        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+        .map_err(|e| match e {
+            // lost a race with another signup for the same email
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                UserStoreError::UserAlreadyExists
+            }
+            e => UserStoreError::UnexpectedError(e.into()),
+        })?;
+
+        // the webhook is only queued if the user is actually created
+        let event = WebhookEvent::new(WebhookEventType::UserSignedUp, &user.email);
+        enqueue_webhook_event(&mut *tx, &event)
+            .await
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        tx.commit()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
 
         Ok(())
     }
 
     #[tracing::instrument(name = "Retrieving user from PostgreSQL", skip_all)]
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
-        sqlx::query_as!(
-            PostgresUser,
+        let _timer = StoreCallTimer::start("postgres", "get_user");
+        sqlx::query_as::<_, PostgresUser>(
             r#"
-                select email, password_hash, requires_2fa
+                select email, password_hash, requires_2fa, phone_number, two_fa_channel,
+                    pending_phone_number, phone_verification_code_hash,
+                    phone_verification_expires_at, phone_verification_attempts,
+                    phone_verification_sent_at, locale, last_login_at
                 from users
                 where email = $1
             "#,
-            email.as_ref().expose_secret()
         )
+        .bind(email.as_ref().expose_secret())
         .fetch_optional(&self.pool)
         .await
         .map_err(|e: sqlx::Error| UserStoreError::UnexpectedError(e.into()))?
-        .map(|row| {
-            Ok(User {
-                email: Email::parse(Secret::new(row.email))
-                    .map_err(UserStoreError::UnexpectedError)?,
-                password: Password::parse(Secret::new(row.password_hash))
-                    .map_err(UserStoreError::UnexpectedError)?,
-                requires_2fa: row.requires_2fa,
-            })
-        })
+        .map(User::try_from)
         .ok_or(UserStoreError::UserNotFound)?
     }
 
@@ -87,6 +236,7 @@ impl UserStore for PostgresUserStore {
         email: &Email,
         password: &Password,
     ) -> Result<(), UserStoreError> {
+        // only the lookup is a store call, timed by `get_user`; hashing has its own metric
         let user = self.get_user(email).await?;
 
         verify_password_hash(
@@ -96,56 +246,187 @@ impl UserStore for PostgresUserStore {
         .await
         .map_err(|_| UserStoreError::InvalidCredentials)?;
 
+        if is_outdated(user.password.as_ref(), &self.password_hashing) {
+            // the login goes ahead either way; a failed upgrade is retried on the next one
+            if let Err(e) = self
+                .upgrade_password_hash(email, user.password.as_ref(), password.as_ref())
+                .await
+            {
+                tracing::error!(error = ?e, "Failed to upgrade password hash");
+            }
+        }
+
         Ok(())
     }
-}
 
-#[tracing::instrument(name = "Verify password hash", skip_all)]
-async fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
-) -> Result<()> {
-    // retrieves the current span from the tracing context.
-    // The span represents the execution context for the compute_password_hash func
-    let current_span: tracing::Span = tracing::Span::current();
-
-    let result = tokio::task::spawn_blocking(move || {
-        current_span.in_scope(|| {
-            let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())?;
-
-            Argon2::default()
-                .verify_password(
-                    password_candidate.expose_secret().as_bytes(),
-                    &expected_password_hash,
-                )
-                .wrap_err("failed to verify password hash")
-        })
-    })
-    .await;
+    #[tracing::instrument(name = "Setting phone verification in PostgreSQL", skip_all)]
+    async fn set_phone_verification(
+        &mut self,
+        email: &Email,
+        verification: Option<PhoneVerification>,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "set_phone_verification");
+        let result = sqlx::query(
+            r#"
+                UPDATE users
+                SET pending_phone_number = $2, phone_verification_code_hash = $3,
+                    phone_verification_expires_at = $4, phone_verification_attempts = $5,
+                    phone_verification_sent_at = $6
+                WHERE email = $1
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .bind(
+            verification
+                .as_ref()
+                .map(|v| v.phone_number.as_ref().expose_secret()),
+        )
+        .bind(verification.as_ref().map(|v| &v.code_hash))
+        .bind(verification.as_ref().map(|v| v.expires_at))
+        .bind(
+            verification
+                .as_ref()
+                .map_or(0, |v| i32::try_from(v.attempts).unwrap_or(i32::MAX)),
+        )
+        .bind(verification.as_ref().map(|v| v.sent_at))
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to set phone verification")
+        .map_err(UserStoreError::UnexpectedError)?;
 
-    result?
-}
+        expect_one_row(result)
+    }
 
-#[tracing::instrument(name = "Computing password hash", skip_all)]
-async fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>> {
-    let current_span: tracing::Span = tracing::Span::current();
-
-    let password = password.to_owned();
-    let result = tokio::task::spawn_blocking(move || {
-        current_span.in_scope(|| {
-            let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
-            let password_hash: String = Argon2::new(
-                Algorithm::Argon2id,
-                Version::V0x13,
-                Params::new(15000, 2, 1, None)?,
-            )
-            .hash_password(password.expose_secret().as_bytes(), &salt)?
-            .to_string();
-
-            Ok(Secret::new(password_hash))
-        })
-    })
-    .await;
+    #[tracing::instrument(name = "Setting phone number in PostgreSQL", skip_all)]
+    async fn set_phone_number(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "set_phone_number");
+        let query = sqlx::query(
+            r#"
+                UPDATE users
+                SET phone_number = $2, pending_phone_number = NULL,
+                    phone_verification_code_hash = NULL, phone_verification_expires_at = NULL,
+                    phone_verification_attempts = 0, phone_verification_sent_at = NULL
+                WHERE email = $1
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .bind(phone_number.as_ref().expose_secret());
+
+        let event = WebhookEvent::new(WebhookEventType::UserPhoneNumberVerified, email);
+        self.update_user_with_event(query, &event, "failed to set phone number")
+            .await
+    }
+
+    #[tracing::instrument(name = "Setting 2FA channel in PostgreSQL", skip_all)]
+    async fn set_two_fa_channel(
+        &mut self,
+        email: &Email,
+        channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "set_two_fa_channel");
+        let query = sqlx::query("UPDATE users SET two_fa_channel = $2 WHERE email = $1")
+            .bind(email.as_ref().expose_secret())
+            .bind(channel.as_str());
+
+        let event = WebhookEvent::new(WebhookEventType::UserTwoFAChannelChanged, email)
+            .with_two_fa_channel(channel);
+        self.update_user_with_event(query, &event, "failed to set 2FA channel")
+            .await
+    }
+
+    #[tracing::instrument(name = "Recording login in PostgreSQL", skip_all)]
+    async fn record_login(
+        &mut self,
+        email: &Email,
+        logged_in_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "record_login");
+        let query = sqlx::query("UPDATE users SET last_login_at = $2 WHERE email = $1")
+            .bind(email.as_ref().expose_secret())
+            .bind(logged_in_at);
+
+        let event = WebhookEvent::new(WebhookEventType::UserLoggedIn, email);
+        self.update_user_with_event(query, &event, "failed to record login")
+            .await
+    }
 
-    result?
+    #[tracing::instrument(name = "Recording phone verification in PostgreSQL", skip_all)]
+    async fn record_phone_verification_sent(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "record_phone_verification_sent");
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .wrap_err("failed to begin transaction")
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        let result = sqlx::query(
+            r#"
+                INSERT INTO phone_verification_sends (email, phone_number, sent_at)
+                SELECT email, $2, $3 FROM users WHERE email = $1
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .bind(phone_number.as_ref().expose_secret())
+        .bind(sent_at)
+        .execute(&mut *transaction)
+        .await
+        .wrap_err("failed to record phone verification")
+        .map_err(UserStoreError::UnexpectedError)?;
+        expect_one_row(result)?;
+
+        // only the last day is counted
+        sqlx::query("DELETE FROM phone_verification_sends WHERE sent_at < $1")
+            .bind(sent_at - chrono::Duration::days(1))
+            .execute(&mut *transaction)
+            .await
+            .wrap_err("failed to delete old phone verifications")
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        transaction
+            .commit()
+            .await
+            .wrap_err("failed to commit transaction")
+            .map_err(UserStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Counting phone verifications in PostgreSQL", skip_all)]
+    async fn phone_verifications_sent_since(
+        &self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        since: DateTime<Utc>,
+    ) -> Result<PhoneVerificationSends, UserStoreError> {
+        let _timer = StoreCallTimer::start("postgres", "phone_verifications_sent_since");
+        let (by_user, to_number): (i64, i64) = sqlx::query_as(
+            r#"
+                SELECT
+                    count(*) FILTER (WHERE email = $1),
+                    count(*) FILTER (WHERE phone_number = $2)
+                FROM phone_verification_sends
+                WHERE sent_at >= $3
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .bind(phone_number.as_ref().expose_secret())
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+        .wrap_err("failed to count phone verifications")
+        .map_err(UserStoreError::UnexpectedError)?;
+
+        Ok(PhoneVerificationSends {
+            by_user: by_user.try_into().unwrap_or_default(),
+            to_number: to_number.try_into().unwrap_or_default(),
+        })
+    }
 }
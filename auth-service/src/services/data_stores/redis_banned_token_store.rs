@@ -7,17 +7,67 @@ use tokio::sync::RwLock;
 
 use crate::{
     domain::data_stores::{BannedTokenStore, BannedTokenStoreError},
-    utils::auth::TOKEN_TTL_SECONDS,
+    utils::{keyed_hash::KeyedHasher, metrics::StoreCallTimer},
 };
 
+const TOKEN_HASH_KIND: &str = "banned_token";
+
 #[derive(Clone)]
 pub struct RedisBannedTokenStore {
     conn: Arc<RwLock<Connection>>,
+    // should match the token TTL, so a banned token expires no earlier than the token itself
+    ttl_seconds: u64,
+    // keys hold a hash of the token, so they can't be used to harvest tokens
+    hasher: KeyedHasher,
 }
 
 impl RedisBannedTokenStore {
-    pub fn new(conn: Arc<RwLock<Connection>>) -> Self {
-        Self { conn }
+    pub fn new(conn: Arc<RwLock<Connection>>, ttl_seconds: u64, hasher: KeyedHasher) -> Self {
+        Self {
+            conn,
+            ttl_seconds,
+            hasher,
+        }
+    }
+
+    // Rewrites the keys of tokens that were banned before they were hashed, keeping their TTL,
+    // and returns how many were rewritten
+    #[tracing::instrument(name = "Hashing legacy banned tokens in Redis", skip_all)]
+    pub async fn hash_legacy_keys(&self) -> Result<u64> {
+        let mut conn = self.conn.write().await;
+        let legacy_keys: Vec<String> = conn
+            .scan_match(format!("{}*", LEGACY_BANNED_TOKEN_KEY_PREFIX))
+            .wrap_err("failed to scan legacy banned tokens")?
+            .collect();
+
+        let mut count = 0;
+        for legacy_key in legacy_keys {
+            let token = &legacy_key[LEGACY_BANNED_TOKEN_KEY_PREFIX.len()..];
+            // -2 when it has expired since the scan, -1 when it has no TTL
+            let ttl_ms: i64 = conn.pttl(&legacy_key)?;
+            let ttl_ms = match ttl_ms {
+                -1 => self.ttl_seconds * 1000,
+                ttl_ms if ttl_ms > 0 => ttl_ms as u64,
+                _ => continue,
+            };
+            let _: () = conn
+                .pset_ex(self.get_key(token), true, ttl_ms)
+                .wrap_err("failed to set hashed legacy banned token in Redis")?;
+            let _: () = conn
+                .del(&legacy_key)
+                .wrap_err("failed to delete legacy banned token from Redis")?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn get_key(&self, token: &str) -> String {
+        format!(
+            "{}{}",
+            BANNED_TOKEN_KEY_PREFIX,
+            self.hasher.hash(TOKEN_HASH_KIND, token)
+        )
     }
 }
 
@@ -25,20 +75,16 @@ impl RedisBannedTokenStore {
 impl BannedTokenStore for RedisBannedTokenStore {
     #[tracing::instrument(name = "Storing token to Redis", skip_all)]
     async fn store_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
-        let token_key = get_key(token.expose_secret());
+        let token_key = self.get_key(token.expose_secret());
 
         let value = true;
 
-        let ttl: u64 = TOKEN_TTL_SECONDS
-            .try_into()
-            .wrap_err("failed to cast TOKEN_TTL_SECONDS to u64")
-            .map_err(BannedTokenStoreError::UnexpectedError)?;
-
+        let _timer = StoreCallTimer::start("redis", "store_token");
         let _ = self
             .conn
             .write()
             .await
-            .set_ex(&token_key, value, ttl)
+            .set_ex(&token_key, value, self.ttl_seconds)
             .wrap_err("failed to set banned token in Redis")
             .map_err(BannedTokenStoreError::UnexpectedError)?;
 
@@ -47,9 +93,10 @@ impl BannedTokenStore for RedisBannedTokenStore {
 
     #[tracing::instrument(name = "Checking if token exists in Redis", skip_all)]
     async fn contains_token(&self, token: Secret<String>) -> Result<bool, BannedTokenStoreError> {
+        let key = self.get_key(token.expose_secret());
         let mut conn = self.conn.write().await;
 
-        let key = get_key(token.expose_secret());
+        let _timer = StoreCallTimer::start("redis", "contains_token");
         let is_banned = conn
             .exists(&key)
             .wrap_err("failed to check if token exists in Redis")
@@ -59,8 +106,6 @@ impl BannedTokenStore for RedisBannedTokenStore {
     }
 }
 
-const BANNED_TOKEN_KEY_PREFIX: &str = "banned_token:";
-
-fn get_key(token: &str) -> String {
-    format!("{}{}", BANNED_TOKEN_KEY_PREFIX, token)
-}
+const BANNED_TOKEN_KEY_PREFIX: &str = "banned_token_hash:";
+// keys of older versions, which held the token itself
+const LEGACY_BANNED_TOKEN_KEY_PREFIX: &str = "banned_token:";
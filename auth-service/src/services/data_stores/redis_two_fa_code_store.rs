@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::Context;
 use redis::{Commands, Connection};
 use secrecy::{ExposeSecret, Secret};
@@ -7,54 +8,151 @@ use tokio::sync::RwLock;
 
 use color_eyre::eyre::Result;
 
-use crate::domain::{
-    data_stores::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
-    Email,
+use crate::{
+    domain::{
+        data_stores::{
+            LoginAttempt, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError,
+        },
+        Email,
+    },
+    utils::{keyed_hash::KeyedHasher, metrics::StoreCallTimer},
 };
 
+const LOGIN_ATTEMPT_ID_HASH_KIND: &str = "login_attempt_id";
+const CODE_HASH_KIND: &str = "two_fa_code";
+
 pub struct RedisTwoFACodeStore {
     conn: Arc<RwLock<Connection>>,
+    // keys and values hold hashes of ids and codes, so they can't be used to complete logins
+    hasher: KeyedHasher,
 }
 
 impl RedisTwoFACodeStore {
-    pub fn new(conn: Arc<RwLock<Connection>>) -> Self {
-        Self { conn }
+    pub fn new(conn: Arc<RwLock<Connection>>, hasher: KeyedHasher) -> Self {
+        Self { conn, hasher }
+    }
+
+    fn get_key(&self, login_attempt_id: &str) -> String {
+        format!(
+            "{}{}",
+            TWO_FA_CODE_PREFIX,
+            self.hasher
+                .hash(LOGIN_ATTEMPT_ID_HASH_KIND, login_attempt_id)
+        )
+    }
+
+    fn code_hash(&self, code: &TwoFACode) -> String {
+        self.hasher
+            .hash(CODE_HASH_KIND, code.as_ref().expose_secret())
     }
 }
 
+// Reads the attempt stored at `key`, taking it out of Redis in the same step when `take` is set
+fn read_record(
+    conn: &mut Connection,
+    key: &str,
+    take: bool,
+) -> Result<Option<LoginAttemptRecord>, TwoFACodeStoreError> {
+    let (ttl_ms, value): (i64, Option<String>) = redis::pipe()
+        .atomic()
+        .pttl(key)
+        .cmd(if take { "GETDEL" } else { "GET" })
+        .arg(key)
+        .query(conn)
+        .wrap_err("failed to get 2FA code from Redis")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+    let Some(value) = value else {
+        return Ok(None);
+    };
+
+    let mut record: LoginAttemptRecord = serde_json::from_str(&value)
+        .wrap_err("failed to deserialize login attempt")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+    // older versions left the expiry to the key's TTL
+    if record.expires_at.is_none() {
+        record.expires_at = Some(Utc::now() + chrono::Duration::milliseconds(ttl_ms.max(0)));
+    }
+    Ok(Some(record))
+}
+
+// `condition` is `XX` to only replace the attempt, or `NX` to only put it back
+fn write_record(
+    conn: &mut Connection,
+    key: &str,
+    record: &LoginAttemptRecord,
+    condition: Option<&str>,
+) -> Result<(), TwoFACodeStoreError> {
+    let serialized_data = serde_json::to_string(record)
+        .wrap_err("failed to serialize login attempt")
+        .map_err(TwoFACodeStoreError::UnexpectedError)?;
+    let expires_at = record.expires_at.unwrap_or_else(Utc::now);
+
+    let mut cmd = redis::cmd("SET");
+    cmd.arg(key)
+        .arg(serialized_data)
+        .arg("PXAT")
+        .arg(expires_at.timestamp_millis());
+    if let Some(condition) = condition {
+        cmd.arg(condition);
+    }
+    cmd.query::<()>(conn)
+        .wrap_err("failed to set 2FA code in Redis")
+        .map_err(TwoFACodeStoreError::UnexpectedError)
+}
+
 #[async_trait::async_trait]
 impl TwoFACodeStore for RedisTwoFACodeStore {
     #[tracing::instrument(name = "Adding 2FA code to Redis", skip_all)]
     async fn add_code(
         &mut self,
-        email: Email,
         login_attempt_id: LoginAttemptId,
+        attempt: LoginAttempt,
         code: TwoFACode,
     ) -> Result<(), TwoFACodeStoreError> {
-        let new_key = get_key(&email);
-        let tup = TwoFATuple(
-            login_attempt_id.as_ref().expose_secret().to_owned(),
-            code.as_ref().expose_secret().to_owned(),
-        );
-        let serialized_data = serde_json::to_string::<TwoFATuple>(&tup)
-            .wrap_err("failed to serialize 2FA tuple")
-            .map_err(TwoFACodeStoreError::UnexpectedError)?;
-
-        let _ = self
-            .conn
-            .write()
-            .await
-            .set_ex(new_key, serialized_data, TEN_MINUTES_IN_SECONDS)
-            .wrap_err("failed to set 2FA code in Redis")
-            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        let new_key = self.get_key(login_attempt_id.as_ref().expose_secret());
+        let record = LoginAttemptRecord {
+            email: attempt.email.as_ref().expose_secret().to_owned(),
+            code_hash: self.code_hash(&code),
+            sent_at: attempt.sent_at,
+            expires_at: Some(attempt.expires_at),
+            resends: attempt.resends,
+            failed_attempts: attempt.failed_attempts,
+        };
+
+        let _timer = StoreCallTimer::start("redis", "add_code");
+        write_record(&mut *self.conn.write().await, &new_key, &record, None)
+    }
 
-        Ok(())
+    #[tracing::instrument(name = "Resending 2FA code in Redis", skip_all)]
+    async fn resend_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+        code: TwoFACode,
+        sent_at: DateTime<Utc>,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        let key = self.get_key(login_attempt_id.as_ref().expose_secret());
+
+        let _timer = StoreCallTimer::start("redis", "resend_code");
+        let mut conn = self.conn.write().await;
+        let mut record = read_record(&mut conn, &key, false)?
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+        record.code_hash = self.code_hash(&code);
+        record.sent_at = sent_at;
+        record.resends += 1;
+        // the key keeps its deadline, and isn't brought back if it expired meanwhile
+        write_record(&mut conn, &key, &record, Some("XX"))?;
+
+        record.try_into()
     }
 
     #[tracing::instrument(name = "Removing 2FA code from Redis", skip_all)]
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
-        let key = get_key(email);
+    async fn remove_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let key = self.get_key(login_attempt_id.as_ref().expose_secret());
 
+        let _timer = StoreCallTimer::start("redis", "remove_code");
         let _ = self
             .conn
             .write()
@@ -65,39 +163,77 @@ impl TwoFACodeStore for RedisTwoFACodeStore {
         Ok(())
     }
 
-    #[tracing::instrument(name = "Retrieving 2FA code from Redis", skip_all)]
-    async fn get_code(
+    #[tracing::instrument(name = "Retrieving login attempt from Redis", skip_all)]
+    async fn get_attempt(
         &self,
-        email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
-        let new_key = get_key(email);
-
-        match self.conn.write().await.get::<_, String>(new_key) {
-            Ok(value) => {
-                let data: TwoFATuple = serde_json::from_str(&value)
-                    .wrap_err("failed to deserialize 2FA tuple")
-                    .map_err(TwoFACodeStoreError::UnexpectedError)?;
-
-                let login_attempt_id = LoginAttemptId::parse(Secret::new(data.0))
-                    .map_err(TwoFACodeStoreError::UnexpectedError)?;
-
-                let two_fa_code = TwoFACode::parse(Secret::new(data.1))
-                    .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        let key = self.get_key(login_attempt_id.as_ref().expose_secret());
+
+        let _timer = StoreCallTimer::start("redis", "get_attempt");
+        read_record(&mut *self.conn.write().await, &key, false)?
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?
+            .try_into()
+    }
 
-                Ok((login_attempt_id, two_fa_code))
-            }
+    #[tracing::instrument(name = "Consuming 2FA code in Redis", skip_all)]
+    async fn consume_code(
+        &mut self,
+        login_attempt_id: &LoginAttemptId,
+        code: &TwoFACode,
+        max_failed_attempts: u32,
+    ) -> Result<LoginAttempt, TwoFACodeStoreError> {
+        let key = self.get_key(login_attempt_id.as_ref().expose_secret());
+
+        let _timer = StoreCallTimer::start("redis", "consume_code");
+        let mut conn = self.conn.write().await;
+        // taken out with GETDEL, so a code is only ever accepted once
+        let mut record = read_record(&mut conn, &key, true)?
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+        if self.hasher.verify(
+            CODE_HASH_KIND,
+            code.as_ref().expose_secret(),
+            &record.code_hash,
+        ) {
+            return record.try_into();
+        }
 
-            Err(_) => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
+        // put back with the wrong code counted, unless that was the last one allowed
+        record.failed_attempts += 1;
+        if record.failed_attempts < max_failed_attempts {
+            write_record(&mut conn, &key, &record, Some("NX"))?;
         }
+        Err(TwoFACodeStoreError::IncorrectCode)
     }
 }
 
 #[derive(Serialize, Deserialize)]
-struct TwoFATuple(pub String, pub String);
-
-const TEN_MINUTES_IN_SECONDS: u64 = 600;
-const TWO_FA_CODE_PREFIX: &str = "two_fa_code";
+struct LoginAttemptRecord {
+    email: String,
+    code_hash: String,
+    sent_at: DateTime<Utc>,
+    // missing in values of older versions
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    resends: u32,
+    #[serde(default)]
+    failed_attempts: u32,
+}
 
-fn get_key(email: &Email) -> String {
-    format!("{}{}", TWO_FA_CODE_PREFIX, email.as_ref().expose_secret())
+impl TryFrom<LoginAttemptRecord> for LoginAttempt {
+    type Error = TwoFACodeStoreError;
+
+    fn try_from(record: LoginAttemptRecord) -> Result<Self, Self::Error> {
+        Ok(Self {
+            email: Email::parse(Secret::new(record.email))
+                .map_err(TwoFACodeStoreError::UnexpectedError)?,
+            sent_at: record.sent_at,
+            expires_at: record.expires_at.unwrap_or_else(Utc::now),
+            resends: record.resends,
+            failed_attempts: record.failed_attempts,
+        })
+    }
 }
+
+const TWO_FA_CODE_PREFIX: &str = "two_fa_attempt_hash:";
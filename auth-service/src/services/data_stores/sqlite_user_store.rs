@@ -0,0 +1,492 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{sqlite::SqliteQueryResult, SqlitePool};
+
+use crate::{
+    domain::{
+        data_stores::{PhoneVerificationSends, UserStore, UserStoreError},
+        password::Password,
+        Email, PhoneNumber, PhoneVerification, TwoFAChannel, User,
+    },
+    utils::{
+        configuration::PasswordHashingSettings,
+        metrics::StoreCallTimer,
+        password_hash::{compute_password_hash, is_outdated, verify_password_hash},
+    },
+};
+
+// `UserStore` for single-node and local use, migrated from `migrations_sqlite`.
+// Unlike `PostgresUserStore` it queues no webhook events, as the outbox lives in PostgreSQL, so
+// `Settings::validate` rejects enabling webhooks with this store.
+pub struct SqliteUserStore {
+    pool: SqlitePool,
+    // outdated hashes are replaced with ones computed with these on login
+    password_hashing: PasswordHashingSettings,
+}
+
+impl SqliteUserStore {
+    pub fn new(pool: SqlitePool, password_hashing: PasswordHashingSettings) -> Self {
+        Self {
+            pool,
+            password_hashing,
+        }
+    }
+
+    // Replaces the hash of a just verified password, unless it changed in the meantime
+    #[tracing::instrument(name = "Upgrading password hash in SQLite", skip_all)]
+    async fn upgrade_password_hash(
+        &self,
+        email: &Email,
+        outdated_hash: &Secret<String>,
+        password: &Secret<String>,
+    ) -> Result<()> {
+        let password_hash =
+            compute_password_hash(password.to_owned(), self.password_hashing).await?;
+
+        let _timer = StoreCallTimer::start("sqlite", "upgrade_password_hash");
+        sqlx::query("UPDATE users SET password_hash = $2 WHERE email = $1 AND password_hash = $3")
+            .bind(email.as_ref().expose_secret())
+            .bind(password_hash.expose_secret())
+            .bind(outdated_hash.expose_secret())
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to update password hash")?;
+
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SqliteUser {
+    email: String,
+    password_hash: String,
+    requires_2fa: bool,
+    phone_number: Option<String>,
+    two_fa_channel: String,
+    pending_phone_number: Option<String>,
+    phone_verification_code_hash: Option<String>,
+    phone_verification_expires_at: Option<DateTime<Utc>>,
+    phone_verification_sent_at: Option<DateTime<Utc>>,
+    phone_verification_attempts: i64,
+    locale: Option<String>,
+    last_login_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<SqliteUser> for User {
+    type Error = UserStoreError;
+
+    fn try_from(row: SqliteUser) -> Result<Self, Self::Error> {
+        let phone_verification = match (
+            row.pending_phone_number,
+            row.phone_verification_code_hash,
+            row.phone_verification_expires_at,
+            row.phone_verification_sent_at,
+        ) {
+            (Some(phone_number), Some(code_hash), Some(expires_at), Some(sent_at)) => {
+                Some(PhoneVerification {
+                    phone_number: PhoneNumber::parse(Secret::new(phone_number))
+                        .map_err(UserStoreError::UnexpectedError)?,
+                    code_hash,
+                    expires_at,
+                    attempts: row
+                        .phone_verification_attempts
+                        .try_into()
+                        .unwrap_or_default(),
+                    sent_at,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(User {
+            email: Email::parse(Secret::new(row.email)).map_err(UserStoreError::UnexpectedError)?,
+            password: Password::parse(Secret::new(row.password_hash))
+                .map_err(UserStoreError::UnexpectedError)?,
+            requires_2fa: row.requires_2fa,
+            phone_number: row
+                .phone_number
+                .map(|phone_number| PhoneNumber::parse(Secret::new(phone_number)))
+                .transpose()
+                .map_err(UserStoreError::UnexpectedError)?,
+            two_fa_channel: TwoFAChannel::from_str(&row.two_fa_channel)
+                .map_err(UserStoreError::UnexpectedError)?,
+            phone_verification,
+            locale: row.locale,
+            last_login_at: row.last_login_at,
+        })
+    }
+}
+
+fn expect_one_row(result: SqliteQueryResult) -> Result<(), UserStoreError> {
+    match result.rows_affected() {
+        0 => Err(UserStoreError::UserNotFound),
+        _ => Ok(()),
+    }
+}
+
+#[async_trait::async_trait]
+impl UserStore for SqliteUserStore {
+    #[tracing::instrument(name = "Adding user to SQLite", skip_all)]
+    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
+        let password_hash =
+            compute_password_hash(user.password.as_ref().to_owned(), self.password_hashing)
+                .await
+                .map_err(UserStoreError::UnexpectedError)?;
+
+        let _timer = StoreCallTimer::start("sqlite", "add_user");
+        sqlx::query(
+            r#"
+                INSERT INTO users (email, password_hash, requires_2fa, locale)
+                VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(user.email.as_ref().expose_secret())
+        .bind(password_hash.expose_secret())
+        .bind(user.requires_2fa)
+        .bind(user.locale.as_deref())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                UserStoreError::UserAlreadyExists
+            }
+            e => UserStoreError::UnexpectedError(e.into()),
+        })?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving user from SQLite", skip_all)]
+    async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
+        let _timer = StoreCallTimer::start("sqlite", "get_user");
+        sqlx::query_as::<_, SqliteUser>(
+            r#"
+                SELECT email, password_hash, requires_2fa, phone_number, two_fa_channel,
+                    pending_phone_number, phone_verification_code_hash,
+                    phone_verification_expires_at, phone_verification_attempts,
+                    phone_verification_sent_at, locale, last_login_at
+                FROM users
+                WHERE email = $1
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to retrieve user")
+        .map_err(UserStoreError::UnexpectedError)?
+        .ok_or(UserStoreError::UserNotFound)?
+        .try_into()
+    }
+
+    #[tracing::instrument(name = "Validating user credentials in SQLite", skip_all)]
+    async fn validate_user(
+        &self,
+        email: &Email,
+        password: &Password,
+    ) -> Result<(), UserStoreError> {
+        // only the lookup is a store call, timed by `get_user`; hashing has its own metric
+        let user = self.get_user(email).await?;
+
+        verify_password_hash(
+            user.password.as_ref().to_owned(),
+            password.as_ref().to_owned(),
+        )
+        .await
+        .map_err(|_| UserStoreError::InvalidCredentials)?;
+
+        if is_outdated(user.password.as_ref(), &self.password_hashing) {
+            // the login goes ahead either way; a failed upgrade is retried on the next one
+            if let Err(e) = self
+                .upgrade_password_hash(email, user.password.as_ref(), password.as_ref())
+                .await
+            {
+                tracing::error!(error = ?e, "Failed to upgrade password hash");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Setting phone verification in SQLite", skip_all)]
+    async fn set_phone_verification(
+        &mut self,
+        email: &Email,
+        verification: Option<PhoneVerification>,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("sqlite", "set_phone_verification");
+        let result = sqlx::query(
+            r#"
+                UPDATE users
+                SET pending_phone_number = $2, phone_verification_code_hash = $3,
+                    phone_verification_expires_at = $4, phone_verification_attempts = $5,
+                    phone_verification_sent_at = $6
+                WHERE email = $1
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .bind(
+            verification
+                .as_ref()
+                .map(|v| v.phone_number.as_ref().expose_secret()),
+        )
+        .bind(verification.as_ref().map(|v| &v.code_hash))
+        .bind(verification.as_ref().map(|v| v.expires_at))
+        .bind(verification.as_ref().map_or(0, |v| i64::from(v.attempts)))
+        .bind(verification.as_ref().map(|v| v.sent_at))
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to set phone verification")
+        .map_err(UserStoreError::UnexpectedError)?;
+
+        expect_one_row(result)
+    }
+
+    #[tracing::instrument(name = "Setting phone number in SQLite", skip_all)]
+    async fn set_phone_number(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("sqlite", "set_phone_number");
+        let result = sqlx::query(
+            r#"
+                UPDATE users
+                SET phone_number = $2, pending_phone_number = NULL,
+                    phone_verification_code_hash = NULL, phone_verification_expires_at = NULL,
+                    phone_verification_attempts = 0, phone_verification_sent_at = NULL
+                WHERE email = $1
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .bind(phone_number.as_ref().expose_secret())
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to set phone number")
+        .map_err(UserStoreError::UnexpectedError)?;
+
+        expect_one_row(result)
+    }
+
+    #[tracing::instrument(name = "Setting 2FA channel in SQLite", skip_all)]
+    async fn set_two_fa_channel(
+        &mut self,
+        email: &Email,
+        channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("sqlite", "set_two_fa_channel");
+        let result = sqlx::query("UPDATE users SET two_fa_channel = $2 WHERE email = $1")
+            .bind(email.as_ref().expose_secret())
+            .bind(channel.as_str())
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to set 2FA channel")
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        expect_one_row(result)
+    }
+
+    #[tracing::instrument(name = "Recording login in SQLite", skip_all)]
+    async fn record_login(
+        &mut self,
+        email: &Email,
+        logged_in_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("sqlite", "record_login");
+        let result = sqlx::query("UPDATE users SET last_login_at = $2 WHERE email = $1")
+            .bind(email.as_ref().expose_secret())
+            .bind(logged_in_at)
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to record login")
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        expect_one_row(result)
+    }
+
+    #[tracing::instrument(name = "Recording phone verification in SQLite", skip_all)]
+    async fn record_phone_verification_sent(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        let _timer = StoreCallTimer::start("sqlite", "record_phone_verification_sent");
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .wrap_err("failed to begin transaction")
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        let result = sqlx::query(
+            r#"
+                INSERT INTO phone_verification_sends (email, phone_number, sent_at)
+                SELECT email, $2, $3 FROM users WHERE email = $1
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .bind(phone_number.as_ref().expose_secret())
+        .bind(sent_at)
+        .execute(&mut *transaction)
+        .await
+        .wrap_err("failed to record phone verification")
+        .map_err(UserStoreError::UnexpectedError)?;
+        expect_one_row(result)?;
+
+        // only the last day is counted
+        sqlx::query("DELETE FROM phone_verification_sends WHERE sent_at < $1")
+            .bind(sent_at - chrono::Duration::days(1))
+            .execute(&mut *transaction)
+            .await
+            .wrap_err("failed to delete old phone verifications")
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        transaction
+            .commit()
+            .await
+            .wrap_err("failed to commit transaction")
+            .map_err(UserStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Counting phone verifications in SQLite", skip_all)]
+    async fn phone_verifications_sent_since(
+        &self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        since: DateTime<Utc>,
+    ) -> Result<PhoneVerificationSends, UserStoreError> {
+        let _timer = StoreCallTimer::start("sqlite", "phone_verifications_sent_since");
+        let (by_user, to_number): (i64, i64) = sqlx::query_as(
+            r#"
+                SELECT
+                    count(*) FILTER (WHERE email = $1),
+                    count(*) FILTER (WHERE phone_number = $2)
+                FROM phone_verification_sends
+                WHERE sent_at >= $3
+            "#,
+        )
+        .bind(email.as_ref().expose_secret())
+        .bind(phone_number.as_ref().expose_secret())
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+        .wrap_err("failed to count phone verifications")
+        .map_err(UserStoreError::UnexpectedError)?;
+
+        Ok(PhoneVerificationSends {
+            by_user: by_user.try_into().unwrap_or_default(),
+            to_number: to_number.try_into().unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_sqlite_pool;
+
+    async fn store() -> SqliteUserStore {
+        // every ":memory:" pool gets a database of its own
+        let pool = get_sqlite_pool(&Secret::new("sqlite::memory:".to_owned()), 1)
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations_sqlite")
+            .run(&pool)
+            .await
+            .unwrap();
+        SqliteUserStore::new(pool, PasswordHashingSettings::default())
+    }
+
+    fn user(email: &str, password: &str) -> User {
+        User::new(
+            Email::parse(Secret::new(email.to_owned())).unwrap(),
+            Password::parse(Secret::new(password.to_owned())).unwrap(),
+            true,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_user() {
+        let mut store = store().await;
+        let user = user("test@example.com", "password123");
+
+        store.add_user(user.clone()).await.unwrap();
+        let stored = store.get_user(&user.email).await.unwrap();
+
+        assert_eq!(stored.email, user.email);
+        assert!(stored.requires_2fa);
+        // stores the hash, not the password
+        assert!(stored
+            .password
+            .as_ref()
+            .expose_secret()
+            .starts_with("$argon2id$"));
+        assert_eq!(
+            store.add_user(user).await,
+            Err(UserStoreError::UserAlreadyExists)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_user() {
+        let mut store = store().await;
+        let user = user("test@example.com", "password123");
+        store.add_user(user.clone()).await.unwrap();
+
+        assert_eq!(
+            store.validate_user(&user.email, &user.password).await,
+            Ok(())
+        );
+
+        let wrong_password = Password::parse(Secret::new("password456".to_owned())).unwrap();
+        assert_eq!(
+            store.validate_user(&user.email, &wrong_password).await,
+            Err(UserStoreError::InvalidCredentials)
+        );
+
+        let unknown = Email::parse(Secret::new("unknown@example.com".to_owned())).unwrap();
+        assert_eq!(
+            store.validate_user(&unknown, &user.password).await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outdated_hash_is_upgraded_on_login() {
+        let mut store = store().await;
+        let user = user("test@example.com", "password123");
+        store.add_user(user.clone()).await.unwrap();
+        // as if imported from a system that used bcrypt
+        sqlx::query("UPDATE users SET password_hash = $2 WHERE email = $1")
+            .bind(user.email.as_ref().expose_secret())
+            .bind(bcrypt::hash("password123", 4).unwrap())
+            .execute(&store.pool)
+            .await
+            .unwrap();
+
+        let wrong_password = Password::parse(Secret::new("password456".to_owned())).unwrap();
+        assert_eq!(
+            store.validate_user(&user.email, &wrong_password).await,
+            Err(UserStoreError::InvalidCredentials)
+        );
+        let stored = store.get_user(&user.email).await.unwrap();
+        assert!(stored.password.as_ref().expose_secret().starts_with("$2b$"));
+
+        assert_eq!(
+            store.validate_user(&user.email, &user.password).await,
+            Ok(())
+        );
+        let stored = store.get_user(&user.email).await.unwrap();
+        assert!(!is_outdated(
+            stored.password.as_ref(),
+            &PasswordHashingSettings::default()
+        ));
+        assert_eq!(
+            store.validate_user(&user.email, &user.password).await,
+            Ok(())
+        );
+    }
+}
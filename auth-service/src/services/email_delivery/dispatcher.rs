@@ -0,0 +1,355 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use color_eyre::eyre::Result;
+use metrics::{counter, gauge};
+
+use crate::{
+    domain::email_outbox::{EmailOutboxError, QueuedEmail},
+    utils::{
+        configuration::EmailOutboxSettings,
+        metrics::{
+            EMAIL_DELIVERIES_TOTAL, EMAIL_OUTBOX_DEAD, EMAIL_OUTBOX_OLDEST_PENDING_SECONDS,
+            EMAIL_OUTBOX_PENDING,
+        },
+        retry::RetryPolicy,
+        shutdown::ShutdownHandle,
+    },
+};
+
+use super::{failover::FailoverEmailClient, EmailQueue};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(3_600);
+
+// Sends queued emails, retrying with back-off until every provider has failed
+// `max_attempts` times in a row
+pub struct EmailDispatcher {
+    queue: EmailQueue,
+    client: Arc<FailoverEmailClient>,
+    retry_policy: RetryPolicy,
+    batch_size: usize,
+    poll_interval: Duration,
+    lease: Duration,
+    sent_retention: Duration,
+}
+
+impl EmailDispatcher {
+    pub fn new(
+        queue: EmailQueue,
+        client: Arc<FailoverEmailClient>,
+        settings: &EmailOutboxSettings,
+    ) -> Self {
+        Self {
+            queue,
+            client,
+            retry_policy: RetryPolicy {
+                max_attempts: settings.max_attempts,
+                base_delay: Duration::from_millis(settings.base_delay_ms),
+                max_delay: Duration::from_secs(settings.max_delay_secs),
+            },
+            batch_size: settings.batch_size,
+            poll_interval: Duration::from_millis(settings.poll_interval_ms),
+            lease: Duration::from_secs(settings.lease_secs),
+            sent_retention: Duration::from_secs(settings.sent_retention_hours * 3_600),
+        }
+    }
+
+    // Polls until `shutdown` is triggered, or sooner when this instance queues an email.
+    // A batch in progress is always finished.
+    pub async fn run(self, shutdown: ShutdownHandle) {
+        let mut last_stats: Option<Instant> = None;
+        let mut last_purge: Option<Instant> = None;
+        while !shutdown.is_shutting_down() {
+            if last_stats.is_none_or(|at| at.elapsed() >= self.poll_interval) {
+                self.record_stats().await;
+                last_stats = Some(Instant::now());
+            }
+            if last_purge.is_none_or(|at| at.elapsed() >= PURGE_INTERVAL) {
+                self.purge_sent().await;
+                last_purge = Some(Instant::now());
+            }
+            match self.dispatch_due().await {
+                // keep draining a backlog without waiting
+                Ok(count) if count > 0 => continue,
+                Ok(_) => {}
+                Err(e) => tracing::error!(error = ?e, "Failed to dispatch emails"),
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.poll_interval) => {}
+                _ = self.queue.wakeup.notified() => {}
+                _ = shutdown.wait() => {}
+            }
+        }
+        tracing::info!("Email dispatcher stopped");
+    }
+
+    // Attempt up to `batch_size` due emails, returning how many were attempted.
+    // Each one is claimed on its own right before it is sent, so its lease only has to cover
+    // its own send. An email whose outcome cannot be recorded is sent again once its lease expires.
+    #[tracing::instrument(name = "Dispatching due emails", skip_all)]
+    pub async fn dispatch_due(&self) -> Result<usize> {
+        let outbox = self.queue.outbox();
+        match outbox.expire_overdue().await? {
+            0 => {}
+            count => {
+                tracing::warn!(count, "Emails expired before they could be sent");
+                counter!(EMAIL_DELIVERIES_TOTAL, "outcome" => "expired").increment(count);
+            }
+        }
+
+        // retries scheduled during this pass wait for the next one
+        let due_by = Utc::now();
+        let mut attempted = 0;
+        while attempted < self.batch_size {
+            let Some(email) = outbox.claim_next(due_by, self.lease).await? else {
+                break;
+            };
+            attempted += 1;
+            self.attempt(email).await?;
+        }
+
+        Ok(attempted)
+    }
+
+    async fn attempt(&self, email: QueuedEmail) -> Result<()> {
+        let outbox = self.queue.outbox();
+        let (outcome, recorded) = match self.client.deliver(&email.recipient, &email.message).await
+        {
+            Ok(provider) => (
+                "sent",
+                outbox
+                    .mark_sent(email.id, email.claim_token, provider)
+                    .await,
+            ),
+            Err(e) => {
+                // not retried once it would arrive too late to be of use
+                let retry_at = self
+                    .retry_policy
+                    .next_attempt_at(email.attempts)
+                    .filter(|at| email.expires_at.is_none_or(|expires_at| *at < expires_at));
+                let outcome = match retry_at {
+                    Some(_) => "retry",
+                    None => "dead",
+                };
+                tracing::warn!(
+                    email_id = %email.id,
+                    attempts = email.attempts,
+                    outcome,
+                    "Email delivery failed"
+                );
+                let error = format!("{:#}", e);
+                (
+                    outcome,
+                    outbox
+                        .mark_failed(email.id, email.claim_token, &error, retry_at)
+                        .await,
+                )
+            }
+        };
+        counter!(EMAIL_DELIVERIES_TOTAL, "outcome" => outcome).increment(1);
+
+        match recorded {
+            Ok(()) => Ok(()),
+            // the lease expired and another dispatcher claimed the email in the meantime
+            Err(EmailOutboxError::ClaimExpired) => {
+                tracing::warn!(
+                    email_id = %email.id,
+                    "Email delivery outcome not recorded; its lease had expired"
+                );
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn record_stats(&self) {
+        match self.queue.outbox().stats().await {
+            Ok(stats) => {
+                gauge!(EMAIL_OUTBOX_PENDING).set(stats.pending as f64);
+                gauge!(EMAIL_OUTBOX_DEAD).set(stats.dead as f64);
+                let oldest_pending_age = stats
+                    .oldest_pending_at
+                    .map(|at| (Utc::now() - at).num_milliseconds() as f64 / 1_000.0)
+                    .unwrap_or_default();
+                gauge!(EMAIL_OUTBOX_OLDEST_PENDING_SECONDS).set(oldest_pending_age);
+            }
+            Err(e) => tracing::error!(error = ?e, "Failed to read email outbox stats"),
+        }
+    }
+
+    async fn purge_sent(&self) {
+        let Ok(retention) = chrono::Duration::from_std(self.sent_retention) else {
+            return;
+        };
+        match self.queue.outbox().purge_sent(Utc::now() - retention).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!(count, "Purged sent emails"),
+            Err(e) => tracing::error!(error = ?e, "Failed to purge sent emails"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::eyre;
+    use secrecy::Secret;
+
+    use super::*;
+    use crate::{
+        domain::{
+            email_outbox::{EmailDeliveryStatus, EXPIRED_ERROR},
+            Email, EmailClient, EmailMessage,
+        },
+        services::{
+            capturing_email_client::CapturingEmailClient,
+            data_stores::in_memory_email_outbox::InMemoryEmailOutbox,
+        },
+    };
+
+    struct FailingEmailClient;
+
+    #[async_trait::async_trait]
+    impl EmailClient for FailingEmailClient {
+        async fn send_email(&self, _: &Email, _: &EmailMessage) -> Result<()> {
+            Err(eyre!("provider unavailable"))
+        }
+    }
+
+    fn settings() -> EmailOutboxSettings {
+        EmailOutboxSettings {
+            max_attempts: 2,
+            base_delay_ms: 0,
+            ..EmailOutboxSettings::default()
+        }
+    }
+
+    fn recipient() -> Email {
+        Email::parse(Secret::new("test@example.com".to_owned())).unwrap()
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage {
+            subject: "subject".to_owned(),
+            text_body: "text".to_owned(),
+            html_body: "<p>html</p>".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatch_due_sends_queued_emails_once() {
+        let queue = EmailQueue::new(Arc::new(InMemoryEmailOutbox::default()));
+        let email_client = Arc::new(CapturingEmailClient::default());
+        let dispatcher = EmailDispatcher::new(
+            queue.clone(),
+            Arc::new(FailoverEmailClient::new(vec![(
+                "mock",
+                email_client.clone(),
+            )])),
+            &settings(),
+        );
+        let id = queue.enqueue(&recipient(), &message(), None).await.unwrap();
+
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 0);
+
+        let delivery = queue.outbox().delivery(id).await.unwrap().unwrap();
+        assert_eq!(delivery.status, EmailDeliveryStatus::Sent);
+        assert_eq!(delivery.provider.as_deref(), Some("mock"));
+        assert_eq!(email_client.sent_emails()[0].message, message());
+    }
+
+    #[tokio::test]
+    async fn dispatch_due_retries_until_the_email_is_dead() {
+        let queue = EmailQueue::new(Arc::new(InMemoryEmailOutbox::default()));
+        let dispatcher = EmailDispatcher::new(
+            queue.clone(),
+            Arc::new(FailoverEmailClient::new(vec![(
+                "failing",
+                Arc::new(FailingEmailClient),
+            )])),
+            &settings(),
+        );
+        let id = queue.enqueue(&recipient(), &message(), None).await.unwrap();
+
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+        let delivery = queue.outbox().delivery(id).await.unwrap().unwrap();
+        assert_eq!(delivery.status, EmailDeliveryStatus::Pending);
+        assert_eq!(
+            delivery.last_error.as_deref(),
+            Some("failing: provider unavailable")
+        );
+
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+        let delivery = queue.outbox().delivery(id).await.unwrap().unwrap();
+        assert_eq!(delivery.status, EmailDeliveryStatus::Dead);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(queue.outbox().stats().await.unwrap().dead, 1);
+    }
+
+    #[tokio::test]
+    async fn dispatch_due_gives_up_on_emails_at_their_expiry() {
+        let queue = EmailQueue::new(Arc::new(InMemoryEmailOutbox::default()));
+        let dispatcher = EmailDispatcher::new(
+            queue.clone(),
+            Arc::new(FailoverEmailClient::new(vec![(
+                "failing",
+                Arc::new(FailingEmailClient),
+            )])),
+            &EmailOutboxSettings {
+                max_attempts: 10,
+                base_delay_ms: 60_000,
+                ..EmailOutboxSettings::default()
+            },
+        );
+        // the first retry would be after the code expired
+        let expires_at = Utc::now() + chrono::Duration::seconds(30);
+        let id = queue
+            .enqueue(&recipient(), &message(), Some(expires_at))
+            .await
+            .unwrap();
+
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+        let delivery = queue.outbox().delivery(id).await.unwrap().unwrap();
+        assert_eq!(delivery.status, EmailDeliveryStatus::Dead);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.expires_at, Some(expires_at));
+
+        // one that is already overdue is never attempted
+        let id = queue
+            .enqueue(&recipient(), &message(), Some(Utc::now()))
+            .await
+            .unwrap();
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 0);
+        let delivery = queue.outbox().delivery(id).await.unwrap().unwrap();
+        assert_eq!(delivery.status, EmailDeliveryStatus::Dead);
+        assert_eq!(delivery.attempts, 0);
+        assert_eq!(delivery.last_error.as_deref(), Some(EXPIRED_ERROR));
+    }
+
+    #[tokio::test]
+    async fn dispatch_due_attempts_at_most_a_batch() {
+        let queue = EmailQueue::new(Arc::new(InMemoryEmailOutbox::default()));
+        let email_client = Arc::new(CapturingEmailClient::default());
+        let dispatcher = EmailDispatcher::new(
+            queue.clone(),
+            Arc::new(FailoverEmailClient::new(vec![(
+                "mock",
+                email_client.clone(),
+            )])),
+            &EmailOutboxSettings {
+                batch_size: 2,
+                ..settings()
+            },
+        );
+        for _ in 0..3 {
+            queue.enqueue(&recipient(), &message(), None).await.unwrap();
+        }
+
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+        assert_eq!(email_client.sent_emails().len(), 3);
+    }
+}
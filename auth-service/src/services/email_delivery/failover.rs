@@ -0,0 +1,127 @@
+use color_eyre::eyre::{eyre, Result};
+
+use crate::{
+    app_state::EmailClientType,
+    domain::{Email, EmailClient, EmailMessage},
+};
+
+// Tries each provider in order until one accepts the email
+pub struct FailoverEmailClient {
+    providers: Vec<(&'static str, EmailClientType)>,
+}
+
+impl FailoverEmailClient {
+    // `providers` are (name, client) pairs, primary first
+    pub fn new(providers: Vec<(&'static str, EmailClientType)>) -> Self {
+        Self { providers }
+    }
+
+    // The name of the provider that accepted the email, or every provider's error
+    #[tracing::instrument(name = "Delivering email", skip_all)]
+    pub async fn deliver(&self, recipient: &Email, message: &EmailMessage) -> Result<&'static str> {
+        let mut errors = Vec::new();
+        for (name, client) in &self.providers {
+            match client.send_email(recipient, message).await {
+                Ok(()) => {
+                    if !errors.is_empty() {
+                        tracing::warn!(provider = name, "Email sent by a fallback provider");
+                    }
+                    return Ok(name);
+                }
+                Err(e) => {
+                    tracing::warn!(provider = name, error = ?e, "Email provider failed");
+                    errors.push(format!("{}: {:#}", name, e));
+                }
+            }
+        }
+
+        Err(eyre!(errors.join("; ")))
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailClient for FailoverEmailClient {
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        self.deliver(recipient, message).await.map(|_| ())
+    }
+
+    // Emails still go out while any provider is up
+    async fn health_check(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        for (name, client) in &self.providers {
+            match client.health_check().await {
+                Ok(()) => return Ok(()),
+                Err(e) => errors.push(format!("{}: {:#}", name, e)),
+            }
+        }
+
+        Err(eyre!(errors.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use secrecy::Secret;
+
+    use super::*;
+    use crate::services::capturing_email_client::CapturingEmailClient;
+
+    struct FailingEmailClient;
+
+    #[async_trait::async_trait]
+    impl EmailClient for FailingEmailClient {
+        async fn send_email(&self, _: &Email, _: &EmailMessage) -> Result<()> {
+            Err(eyre!("provider unavailable"))
+        }
+
+        async fn health_check(&self) -> Result<()> {
+            Err(eyre!("provider unavailable"))
+        }
+    }
+
+    fn recipient() -> Email {
+        Email::parse(Secret::new("test@example.com".to_owned())).unwrap()
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage {
+            subject: "subject".to_owned(),
+            text_body: "text".to_owned(),
+            html_body: "<p>html</p>".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_falls_back_to_the_next_provider() {
+        let fallback = Arc::new(CapturingEmailClient::default());
+        let client = FailoverEmailClient::new(vec![
+            ("primary", Arc::new(FailingEmailClient)),
+            ("fallback", fallback.clone()),
+        ]);
+
+        assert_eq!(
+            client.deliver(&recipient(), &message()).await.unwrap(),
+            "fallback"
+        );
+        assert_eq!(fallback.sent_emails().len(), 1);
+        assert!(client.health_check().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn deliver_reports_every_provider_error() {
+        let client = FailoverEmailClient::new(vec![
+            ("primary", Arc::new(FailingEmailClient)),
+            ("fallback", Arc::new(FailingEmailClient)),
+        ]);
+
+        let error = client.deliver(&recipient(), &message()).await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "primary: provider unavailable; fallback: provider unavailable"
+        );
+        assert!(client.health_check().await.is_err());
+    }
+}
@@ -0,0 +1,54 @@
+pub mod dispatcher;
+pub mod failover;
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+    app_state::EmailOutboxType,
+    domain::{email_outbox::EmailOutboxError, Email, EmailMessage},
+    utils::metrics::EMAILS_QUEUED_TOTAL,
+};
+
+// Where handlers put emails: a single outbox write, so providers are never on the request path.
+// The `EmailDispatcher` sharing this queue sends them.
+#[derive(Clone)]
+pub struct EmailQueue {
+    outbox: EmailOutboxType,
+    // wakes the dispatcher of this instance instead of waiting for its next poll
+    wakeup: Arc<Notify>,
+}
+
+impl EmailQueue {
+    pub fn new(outbox: EmailOutboxType) -> Self {
+        Self {
+            outbox,
+            wakeup: Arc::new(Notify::new()),
+        }
+    }
+
+    // Returns the id of the queued email, for looking up its delivery status.
+    // It is given up on at `expires_at`, if set, rather than sent late.
+    #[tracing::instrument(name = "Enqueuing email", skip_all)]
+    pub async fn enqueue(
+        &self,
+        recipient: &Email,
+        message: &EmailMessage,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Uuid, EmailOutboxError> {
+        let id = self.outbox.enqueue(recipient, message, expires_at).await?;
+        counter!(EMAILS_QUEUED_TOTAL).increment(1);
+        tracing::debug!(email_id = %id, "Email queued");
+        self.wakeup.notify_one();
+
+        Ok(id)
+    }
+
+    pub fn outbox(&self) -> &EmailOutboxType {
+        &self.outbox
+    }
+}
@@ -0,0 +1,678 @@
+use std::{collections::HashMap, path::Path};
+
+use color_eyre::eyre::{eyre, Result};
+
+use crate::{
+    domain::EmailMessage,
+    utils::{configuration::EmailTemplateSettings, html::escape_html},
+};
+
+// Every email has a subject, a plain-text and an HTML template per locale,
+// e.g. `en/two_fa_code.subject.txt`, `en/two_fa_code.txt` and `en/two_fa_code.html`;
+// texts and calls only have a plain-text one, e.g. `en/two_fa_code_sms.txt`
+const SUBJECT_SUFFIX: &str = ".subject.txt";
+const TEXT_SUFFIX: &str = ".txt";
+const HTML_SUFFIX: &str = ".html";
+
+macro_rules! built_in {
+    ($($locale:literal / $file:literal),* $(,)?) => {
+        &[$(($locale, $file, include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"), "/templates/email/", $locale, "/", $file
+        )))),*]
+    };
+}
+
+// Compiled in, so the binary works without the templates directory
+const BUILT_IN: &[(&str, &str, &str)] = built_in![
+    "en" / "two_fa_code.subject.txt",
+    "en" / "two_fa_code.txt",
+    "en" / "two_fa_code.html",
+    "en" / "password_reset.subject.txt",
+    "en" / "password_reset.txt",
+    "en" / "password_reset.html",
+    "en" / "verification.subject.txt",
+    "en" / "verification.txt",
+    "en" / "verification.html",
+    "en" / "lockout_notice.subject.txt",
+    "en" / "lockout_notice.txt",
+    "en" / "lockout_notice.html",
+    "de" / "two_fa_code.subject.txt",
+    "de" / "two_fa_code.txt",
+    "de" / "two_fa_code.html",
+    "de" / "password_reset.subject.txt",
+    "de" / "password_reset.txt",
+    "de" / "password_reset.html",
+    "de" / "verification.subject.txt",
+    "de" / "verification.txt",
+    "de" / "verification.html",
+    "de" / "lockout_notice.subject.txt",
+    "de" / "lockout_notice.txt",
+    "de" / "lockout_notice.html",
+    "en" / "two_fa_code_sms.txt",
+    "en" / "two_fa_code_voice.txt",
+    "de" / "two_fa_code_sms.txt",
+    "de" / "two_fa_code_voice.txt",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    TwoFaCode,
+    PasswordReset,
+    Verification,
+    LockoutNotice,
+    TwoFaCodeSms,
+    TwoFaCodeVoice,
+}
+
+impl MessageKind {
+    const ALL: [MessageKind; 6] = [
+        Self::TwoFaCode,
+        Self::PasswordReset,
+        Self::Verification,
+        Self::LockoutNotice,
+        Self::TwoFaCodeSms,
+        Self::TwoFaCodeVoice,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TwoFaCode => "two_fa_code",
+            Self::PasswordReset => "password_reset",
+            Self::Verification => "verification",
+            Self::LockoutNotice => "lockout_notice",
+            Self::TwoFaCodeSms => "two_fa_code_sms",
+            Self::TwoFaCodeVoice => "two_fa_code_voice",
+        }
+    }
+
+    // the variables its templates may use
+    fn variables(&self) -> &'static [&'static str] {
+        match self {
+            Self::TwoFaCode | Self::TwoFaCodeSms => &["code", "expires_in_minutes"],
+            Self::TwoFaCodeVoice => &["code"],
+            Self::PasswordReset => &["reset_url", "expires_in_minutes"],
+            Self::Verification => &["verification_url"],
+            Self::LockoutNotice => &["locked_until"],
+        }
+    }
+
+    fn suffixes(&self) -> &'static [&'static str] {
+        match self {
+            Self::TwoFaCode | Self::PasswordReset | Self::Verification | Self::LockoutNotice => {
+                &[SUBJECT_SUFFIX, TEXT_SUFFIX, HTML_SUFFIX]
+            }
+            Self::TwoFaCodeSms | Self::TwoFaCodeVoice => &[TEXT_SUFFIX],
+        }
+    }
+}
+
+// The values of a message's template variables
+trait TemplateValues {
+    fn kind(&self) -> MessageKind;
+    fn value(&self, variable: &str) -> Option<String>;
+}
+
+// What an email says, with the values of its template variables
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmailTemplate {
+    TwoFaCode {
+        code: String,
+        expires_in_minutes: u64,
+    },
+    PasswordReset {
+        reset_url: String,
+        expires_in_minutes: u64,
+    },
+    Verification {
+        verification_url: String,
+    },
+    LockoutNotice {
+        locked_until: String,
+    },
+}
+
+impl TemplateValues for EmailTemplate {
+    fn kind(&self) -> MessageKind {
+        match self {
+            Self::TwoFaCode { .. } => MessageKind::TwoFaCode,
+            Self::PasswordReset { .. } => MessageKind::PasswordReset,
+            Self::Verification { .. } => MessageKind::Verification,
+            Self::LockoutNotice { .. } => MessageKind::LockoutNotice,
+        }
+    }
+
+    fn value(&self, variable: &str) -> Option<String> {
+        match (self, variable) {
+            (Self::TwoFaCode { code, .. }, "code") => Some(code.to_owned()),
+            (
+                Self::TwoFaCode {
+                    expires_in_minutes, ..
+                }
+                | Self::PasswordReset {
+                    expires_in_minutes, ..
+                },
+                "expires_in_minutes",
+            ) => Some(expires_in_minutes.to_string()),
+            (Self::PasswordReset { reset_url, .. }, "reset_url") => Some(reset_url.to_owned()),
+            (Self::Verification { verification_url }, "verification_url") => {
+                Some(verification_url.to_owned())
+            }
+            (Self::LockoutNotice { locked_until }, "locked_until") => Some(locked_until.to_owned()),
+            _ => None,
+        }
+    }
+}
+
+// What a text message or a call says
+#[derive(Debug, Clone, PartialEq)]
+pub enum PhoneTemplate {
+    TwoFaCodeSms {
+        code: String,
+        expires_in_minutes: u64,
+    },
+    // `code` is written the way it should be read out, e.g. "1, 2, 3"
+    TwoFaCodeVoice {
+        code: String,
+    },
+}
+
+impl TemplateValues for PhoneTemplate {
+    fn kind(&self) -> MessageKind {
+        match self {
+            Self::TwoFaCodeSms { .. } => MessageKind::TwoFaCodeSms,
+            Self::TwoFaCodeVoice { .. } => MessageKind::TwoFaCodeVoice,
+        }
+    }
+
+    fn value(&self, variable: &str) -> Option<String> {
+        match (self, variable) {
+            (Self::TwoFaCodeSms { code, .. } | Self::TwoFaCodeVoice { code }, "code") => {
+                Some(code.to_owned())
+            }
+            (
+                Self::TwoFaCodeSms {
+                    expires_in_minutes, ..
+                },
+                "expires_in_minutes",
+            ) => Some(expires_in_minutes.to_string()),
+            _ => None,
+        }
+    }
+}
+
+// A rendered text message or call, and the locale it is in
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhoneMessage {
+    pub locale: String,
+    pub body: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Variable(String),
+}
+
+// `{{ name }}` is replaced by the value of `name`; everything else is copied as is
+#[derive(Debug)]
+struct Template(Vec<Segment>);
+
+impl Template {
+    fn parse(source: &str, variables: &[&str]) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| "unclosed \"{{\"".to_owned())?;
+            let variable = rest[start + 2..start + end].trim();
+            if !variables.contains(&variable) {
+                return Err(format!(
+                    "unknown variable {:?}, expected one of {:?}",
+                    variable, variables
+                ));
+            }
+            segments.push(Segment::Variable(variable.to_owned()));
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+
+        Ok(Self(segments))
+    }
+
+    fn render(&self, template: &dyn TemplateValues, escape: fn(&str) -> String) -> String {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.to_owned(),
+                Segment::Variable(variable) => {
+                    escape(&template.value(variable).unwrap_or_default())
+                }
+            })
+            .collect()
+    }
+}
+
+// Texts and calls have no subject or HTML template
+struct TemplateSet {
+    subject: Option<Template>,
+    text: Template,
+    html: Option<Template>,
+}
+
+// The templates of emails, and of the texts and calls that carry 2FA codes
+pub struct EmailTemplates {
+    // locale -> message type -> templates
+    locales: HashMap<String, HashMap<MessageKind, TemplateSet>>,
+    default_locale: String,
+}
+
+impl EmailTemplates {
+    pub fn built_in() -> Self {
+        Self::load(&EmailTemplateSettings::default()).expect("Invalid built-in email templates")
+    }
+
+    // Files in `settings.dir` replace the built-in ones of the same locale and name,
+    // and may add locales. Reports every problem at once.
+    pub fn load(settings: &EmailTemplateSettings) -> Result<Self> {
+        let mut sources: HashMap<(String, String), String> = BUILT_IN
+            .iter()
+            .map(|(locale, file, source)| {
+                ((locale.to_string(), file.to_string()), source.to_string())
+            })
+            .collect();
+        let mut problems = Vec::new();
+        if let Some(dir) = &settings.dir {
+            read_dir(Path::new(dir), &mut sources, &mut problems);
+        }
+
+        let mut locales: HashMap<String, HashMap<MessageKind, TemplateSet>> = HashMap::new();
+        let locale_names: Vec<String> = {
+            let mut names: Vec<String> = sources.keys().map(|(locale, _)| locale.clone()).collect();
+            names.sort_unstable();
+            names.dedup();
+            names
+        };
+        for locale in locale_names {
+            for kind in MessageKind::ALL {
+                let found: Vec<(&str, &String)> = kind
+                    .suffixes()
+                    .iter()
+                    .filter_map(|suffix| {
+                        sources
+                            .get(&(locale.clone(), format!("{}{}", kind.name(), suffix)))
+                            .map(|source| (*suffix, source))
+                    })
+                    .collect();
+                if found.is_empty() {
+                    continue;
+                }
+                if found.len() < kind.suffixes().len() {
+                    problems.push(format!(
+                        "{}/{}: needs {} templates",
+                        locale,
+                        kind.name(),
+                        kind.suffixes().join(", ")
+                    ));
+                    continue;
+                }
+
+                let mut parsed = HashMap::new();
+                for (suffix, source) in found {
+                    match Template::parse(source, kind.variables()) {
+                        Ok(template) => {
+                            parsed.insert(suffix, template);
+                        }
+                        Err(e) => {
+                            problems.push(format!("{}/{}{}: {}", locale, kind.name(), suffix, e))
+                        }
+                    }
+                }
+                if let Some(text) = parsed.remove(TEXT_SUFFIX) {
+                    if parsed.len() + 1 == kind.suffixes().len() {
+                        locales.entry(locale.clone()).or_default().insert(
+                            kind,
+                            TemplateSet {
+                                subject: parsed.remove(SUBJECT_SUFFIX),
+                                text,
+                                html: parsed.remove(HTML_SUFFIX),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        let default_locale = normalize_locale(&settings.default_locale);
+        for kind in MessageKind::ALL {
+            let found = locales
+                .get(&default_locale)
+                .is_some_and(|kinds| kinds.contains_key(&kind));
+            if !found {
+                problems.push(format!(
+                    "the default locale {:?} has no {} templates",
+                    default_locale,
+                    kind.name()
+                ));
+            }
+        }
+
+        match problems.is_empty() {
+            true => Ok(Self {
+                locales,
+                default_locale,
+            }),
+            false => Err(eyre!("Invalid email templates: {}", problems.join("; "))),
+        }
+    }
+
+    // The first of `preferred_locales` there are templates for, e.g. "de" for "de-AT",
+    // so it can be stored with the user
+    pub fn supported_locale(&self, preferred_locales: &[String]) -> Option<String> {
+        candidate_locales(preferred_locales).find(|locale| self.locales.contains_key(locale))
+    }
+
+    // The first of `preferred_locales` that has templates for the message, matching "de-AT"
+    // to "de" if needed, otherwise the default locale
+    pub fn render(&self, template: &EmailTemplate, preferred_locales: &[String]) -> EmailMessage {
+        let (_, templates) = self.templates(template.kind(), preferred_locales);
+        let render = |part: &Option<Template>, escape| {
+            part.as_ref()
+                .expect("emails have every template")
+                .render(template, escape)
+        };
+
+        EmailMessage {
+            // a line break would end the header
+            subject: render(&templates.subject, str::to_owned)
+                .trim()
+                .replace(['\r', '\n'], " "),
+            text_body: templates.text.render(template, str::to_owned),
+            html_body: render(&templates.html, escape_html),
+        }
+    }
+
+    // Chosen like the locale of an email
+    pub fn render_phone(
+        &self,
+        template: &PhoneTemplate,
+        preferred_locales: &[String],
+    ) -> PhoneMessage {
+        let (locale, templates) = self.templates(template.kind(), preferred_locales);
+
+        PhoneMessage {
+            locale,
+            body: templates
+                .text
+                .render(template, str::to_owned)
+                .trim()
+                .to_owned(),
+        }
+    }
+
+    fn templates(&self, kind: MessageKind, preferred_locales: &[String]) -> (String, &TemplateSet) {
+        candidate_locales(preferred_locales)
+            .chain([self.default_locale.clone()])
+            .find_map(|locale| {
+                let templates = self.locales.get(&locale)?.get(&kind)?;
+                Some((locale, templates))
+            })
+            .expect("the default locale has every template")
+    }
+}
+
+fn read_dir(
+    dir: &Path,
+    sources: &mut HashMap<(String, String), String>,
+    problems: &mut Vec<String>,
+) {
+    let locale_dirs = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            problems.push(format!("Failed to read {}: {}", dir.display(), e));
+            return;
+        }
+    };
+
+    for locale_dir in locale_dirs.flatten() {
+        if !locale_dir.path().is_dir() {
+            continue;
+        }
+        let locale = normalize_locale(&locale_dir.file_name().to_string_lossy());
+        let files = match std::fs::read_dir(locale_dir.path()) {
+            Ok(files) => files,
+            Err(e) => {
+                problems.push(format!(
+                    "Failed to read {}: {}",
+                    locale_dir.path().display(),
+                    e
+                ));
+                continue;
+            }
+        };
+
+        for file in files.flatten() {
+            let file_name = file.file_name().to_string_lossy().into_owned();
+            // a misspelt name would silently keep the built-in template
+            let known = MessageKind::ALL.iter().any(|kind| {
+                kind.suffixes()
+                    .iter()
+                    .any(|suffix| file_name == format!("{}{}", kind.name(), suffix))
+            });
+            if !known {
+                problems.push(format!("{}/{}: unknown template", locale, file_name));
+                continue;
+            }
+            match std::fs::read_to_string(file.path()) {
+                Ok(source) => {
+                    sources.insert((locale.clone(), file_name), source);
+                }
+                Err(e) => problems.push(format!("Failed to read {}: {}", file.path().display(), e)),
+            }
+        }
+    }
+}
+
+// each locale, followed by its language if it has a region
+fn candidate_locales(preferred_locales: &[String]) -> impl Iterator<Item = String> + '_ {
+    preferred_locales
+        .iter()
+        .map(|locale| normalize_locale(locale))
+        .flat_map(|locale| {
+            let language = locale.split('-').next().unwrap_or_default().to_owned();
+            [locale, language]
+        })
+}
+
+// "pt_BR" and "pt-BR" both select the "pt-br" templates
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().to_ascii_lowercase().replace('_', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn two_fa_code() -> EmailTemplate {
+        EmailTemplate::TwoFaCode {
+            code: "123456".to_owned(),
+            expires_in_minutes: 10,
+        }
+    }
+
+    fn locales(locales: &[&str]) -> Vec<String> {
+        locales.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_built_in_templates_render_every_message_in_every_locale() {
+        let templates = EmailTemplates::built_in();
+        let messages = [
+            two_fa_code(),
+            EmailTemplate::PasswordReset {
+                reset_url: "https://example.com/reset?token=a&b".to_owned(),
+                expires_in_minutes: 30,
+            },
+            EmailTemplate::Verification {
+                verification_url: "https://example.com/verify".to_owned(),
+            },
+            EmailTemplate::LockoutNotice {
+                locked_until: "2026-01-01 12:00 UTC".to_owned(),
+            },
+        ];
+
+        for locale in ["en", "de"] {
+            for message in &messages {
+                let email = templates.render(message, &locales(&[locale]));
+                assert!(!email.subject.is_empty());
+                assert!(!email.text_body.contains("{{"));
+                assert!(email
+                    .html_body
+                    .contains(&format!("<html lang=\"{}\">", locale)));
+            }
+            let email = templates.render(&two_fa_code(), &locales(&[locale]));
+            assert!(email.text_body.contains("123456"));
+
+            for template in [
+                PhoneTemplate::TwoFaCodeSms {
+                    code: "123456".to_owned(),
+                    expires_in_minutes: 10,
+                },
+                PhoneTemplate::TwoFaCodeVoice {
+                    code: "1, 2, 3".to_owned(),
+                },
+            ] {
+                let message = templates.render_phone(&template, &locales(&[locale]));
+                assert_eq!(message.locale, locale);
+                assert!(!message.body.contains("{{"));
+                assert!(!message.body.ends_with('\n'));
+            }
+        }
+
+        // variables are escaped in HTML only
+        let email = templates.render(
+            &EmailTemplate::TwoFaCode {
+                code: "<b>&".to_owned(),
+                expires_in_minutes: 10,
+            },
+            &[],
+        );
+        assert!(email.text_body.contains("<b>&"));
+        assert!(email.html_body.contains("&lt;b&gt;&amp;"));
+
+        let reset = templates.render(&messages[1], &[]);
+        assert!(reset
+            .text_body
+            .contains("https://example.com/reset?token=a&b"));
+        assert!(reset
+            .html_body
+            .contains("href=\"https://example.com/reset?token=a&amp;b\""));
+    }
+
+    #[test]
+    fn test_locale_is_chosen_from_preferences() {
+        let templates = EmailTemplates::built_in();
+        let subject = |preferred: &[&str]| {
+            templates
+                .render(&two_fa_code(), &locales(preferred))
+                .subject
+        };
+
+        assert_eq!(subject(&[]), "Your login code");
+        assert_eq!(subject(&["de"]), "Ihr Anmeldecode");
+        assert_eq!(subject(&["de_AT"]), "Ihr Anmeldecode");
+        assert_eq!(subject(&["fr", "de-CH", "en"]), "Ihr Anmeldecode");
+        assert_eq!(subject(&["fr"]), "Your login code");
+    }
+
+    #[test]
+    fn test_supported_locale() {
+        let templates = EmailTemplates::built_in();
+
+        assert_eq!(templates.supported_locale(&[]), None);
+        assert_eq!(templates.supported_locale(&locales(&["fr"])), None);
+        assert_eq!(
+            templates.supported_locale(&locales(&["fr", "de_AT", "en"])),
+            Some("de".to_owned())
+        );
+        assert_eq!(
+            templates.supported_locale(&locales(&["EN-gb"])),
+            Some("en".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_templates_are_overridden_from_a_directory() {
+        let dir = std::env::temp_dir().join(format!("email-templates-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("fr")).unwrap();
+        std::fs::write(dir.join("fr/two_fa_code.subject.txt"), "Votre code").unwrap();
+        std::fs::write(dir.join("fr/two_fa_code.txt"), "Code : {{ code }}").unwrap();
+        std::fs::write(dir.join("fr/two_fa_code.html"), "<b>{{code}}</b>").unwrap();
+        std::fs::create_dir_all(dir.join("en")).unwrap();
+        std::fs::write(dir.join("en/two_fa_code.subject.txt"), "Code {{ code }}\n").unwrap();
+
+        let settings = EmailTemplateSettings {
+            dir: Some(dir.display().to_string()),
+            ..EmailTemplateSettings::default()
+        };
+        let templates = EmailTemplates::load(&settings).unwrap();
+
+        let fr = templates.render(&two_fa_code(), &locales(&["fr"]));
+        assert_eq!(fr.subject, "Votre code");
+        assert_eq!(fr.text_body, "Code : 123456");
+        assert_eq!(fr.html_body, "<b>123456</b>");
+        // only the subject was replaced
+        let en = templates.render(&two_fa_code(), &locales(&["en"]));
+        assert_eq!(en.subject, "Code 123456");
+        assert!(en.text_body.starts_with("Your login code is 123456."));
+        // fr has no other message types, so falls back to the default locale
+        let verification = EmailTemplate::Verification {
+            verification_url: "https://example.com".to_owned(),
+        };
+        assert_eq!(
+            templates.render(&verification, &locales(&["fr"])).subject,
+            "Verify your email address"
+        );
+        assert_eq!(
+            templates.supported_locale(&locales(&["fr"])),
+            Some("fr".to_owned())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_templates_are_reported() {
+        let dir = std::env::temp_dir().join(format!("email-templates-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("en")).unwrap();
+        std::fs::create_dir_all(dir.join("fr")).unwrap();
+        std::fs::write(dir.join("en/two_fa_code.txt"), "{{ password }}").unwrap();
+        std::fs::write(dir.join("en/two_fa_code.html"), "{{ code").unwrap();
+        std::fs::write(dir.join("en/2fa.txt"), "").unwrap();
+        std::fs::write(dir.join("fr/two_fa_code.txt"), "").unwrap();
+        std::fs::write(dir.join("fr/verification.txt"), "").unwrap();
+
+        let settings = EmailTemplateSettings {
+            dir: Some(dir.display().to_string()),
+            default_locale: "es".to_owned(),
+        };
+        let error = EmailTemplates::load(&settings).err().unwrap().to_string();
+
+        assert!(error.contains("en/two_fa_code.txt: unknown variable \"password\""));
+        assert!(error.contains("en/two_fa_code.html: unclosed"));
+        assert!(error.contains("en/2fa.txt: unknown template"));
+        assert!(error.contains("fr/two_fa_code: needs"));
+        assert!(error.contains("fr/verification: needs"));
+        assert!(error.contains("the default locale \"es\""));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
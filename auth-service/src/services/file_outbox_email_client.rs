@@ -0,0 +1,263 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context, Result};
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+use crate::{
+    domain::{Email, EmailClient, EmailMessage, EmailSender},
+    utils::metrics::record_email_sent,
+};
+
+const EML_EXTENSION: &str = "eml";
+const TEXT_PLAIN: &str = "text/plain";
+const TEXT_HTML: &str = "text/html";
+
+// A message read back from the outbox
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxMessage {
+    pub file_name: String,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub date: String,
+    pub text_body: String,
+    pub html_body: String,
+}
+
+// For local development: writes each email to `<dir>/<timestamp>-<id>.eml` instead of sending it.
+// The files open in any mail client and are listed by `/dev/mailbox`.
+pub struct FileOutboxEmailClient {
+    dir: PathBuf,
+    sender: EmailSender,
+}
+
+impl FileOutboxEmailClient {
+    pub fn new(dir: impl Into<PathBuf>, sender: EmailSender) -> Self {
+        Self {
+            dir: dir.into(),
+            sender,
+        }
+    }
+
+    async fn write(&self, recipient: &Email, message: &EmailMessage) -> Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .wrap_err_with(|| format!("Failed to create outbox {}", self.dir.display()))?;
+
+        let sent_at = Utc::now();
+        let id = Uuid::new_v4();
+        let message = render_eml(
+            id,
+            sent_at,
+            &self.sender,
+            recipient.as_ref().expose_secret(),
+            message,
+        );
+
+        // renamed into place so the mailbox never reads a partial file
+        let path = self.dir.join(format!(
+            "{}-{}.{}",
+            sent_at.format("%Y%m%dT%H%M%S%.6fZ"),
+            id,
+            EML_EXTENSION
+        ));
+        let partial_path = path.with_extension("partial");
+        tokio::fs::write(&partial_path, message).await?;
+        tokio::fs::rename(&partial_path, &path).await?;
+
+        Ok(path)
+    }
+
+    pub async fn messages(&self, limit: usize) -> Result<Vec<OutboxMessage>> {
+        read_outbox(&self.dir, limit).await
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailClient for FileOutboxEmailClient {
+    #[tracing::instrument(name = "Sending email", skip_all)]
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        let result = self.write(recipient, message).await;
+        record_email_sent("file_outbox", &result);
+        let path = result?;
+
+        tracing::info!("Email written to {}", path.display());
+        Ok(())
+    }
+}
+
+// Newest first; files that are not valid messages are skipped
+pub async fn read_outbox(dir: &Path, limit: usize) -> Result<Vec<OutboxMessage>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        // nothing has been sent yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut file_names = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == EML_EXTENSION) {
+            file_names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    // the timestamp prefix sorts chronologically
+    file_names.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut messages = Vec::new();
+    for file_name in file_names.into_iter().take(limit) {
+        let raw = tokio::fs::read_to_string(dir.join(&file_name)).await?;
+        match parse_eml(file_name.clone(), &raw) {
+            Ok(message) => messages.push(message),
+            Err(e) => tracing::warn!(error = ?e, file_name, "Skipping unreadable outbox file"),
+        }
+    }
+
+    Ok(messages)
+}
+
+fn render_eml(
+    id: Uuid,
+    sent_at: DateTime<Utc>,
+    sender: &EmailSender,
+    recipient: &str,
+    message: &EmailMessage,
+) -> String {
+    let boundary = format!("=_{}", id.simple());
+    let mut headers = vec![
+        ("From", header_value(&sender.from.to_string())),
+        ("To", header_value(recipient)),
+        ("Subject", header_value(&message.subject)),
+        ("Date", sent_at.to_rfc2822()),
+        ("Message-ID", format!("<{}@localhost>", id)),
+        ("MIME-Version", "1.0".to_owned()),
+        (
+            "Content-Type",
+            format!("multipart/alternative; boundary=\"{}\"", boundary),
+        ),
+    ];
+    if let Some(reply_to) = &sender.reply_to {
+        headers.insert(1, ("Reply-To", header_value(&reply_to.to_string())));
+    }
+
+    let mut eml = String::new();
+    for (name, value) in headers {
+        eml.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    // mail clients show the last part they can display, so HTML goes last
+    for (content_type, body) in [
+        (TEXT_PLAIN, &message.text_body),
+        (TEXT_HTML, &message.html_body),
+    ] {
+        eml.push_str(&format!(
+            "\r\n--{}\r\nContent-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            boundary, content_type
+        ));
+        eml.push_str(&body.replace("\r\n", "\n").replace('\n', "\r\n"));
+    }
+    eml.push_str(&format!("\r\n--{}--\r\n", boundary));
+    eml
+}
+
+// A line break in a value would start a new header
+fn header_value(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+fn header(head: &str, name: &str) -> String {
+    head.split("\r\n")
+        .filter_map(|line| line.split_once(": "))
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.to_owned())
+        .unwrap_or_default()
+}
+
+fn parse_eml(file_name: String, raw: &str) -> Result<OutboxMessage> {
+    let (head, body) = raw
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| eyre!("No blank line after the headers"))?;
+    let boundary = header(head, "Content-Type")
+        .split_once("boundary=")
+        .map(|(_, boundary)| boundary.trim_matches('"').to_owned())
+        .ok_or_else(|| eyre!("Not a multipart message"))?;
+
+    let mut text_body = String::new();
+    let mut html_body = String::new();
+    // the CRLF before a delimiter belongs to the delimiter
+    for part in format!("\r\n{}", body).split(&format!("\r\n--{}", boundary)) {
+        let Some((part_head, content)) = part.split_once("\r\n\r\n") else {
+            continue;
+        };
+        let content = content.replace("\r\n", "\n");
+        match header(part_head, "Content-Type") {
+            content_type if content_type.starts_with(TEXT_PLAIN) => text_body = content,
+            content_type if content_type.starts_with(TEXT_HTML) => html_body = content,
+            _ => {}
+        }
+    }
+
+    Ok(OutboxMessage {
+        file_name,
+        from: header(head, "From"),
+        to: header(head, "To"),
+        subject: header(head, "Subject"),
+        date: header(head, "Date"),
+        text_body,
+        html_body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_written_messages_are_read_back_newest_first() {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+        let sender = EmailSender {
+            from: "Rusty Auth <auth@example.com>".parse().unwrap(),
+            reply_to: None,
+        };
+        let client = FileOutboxEmailClient::new(&dir, sender);
+        assert!(client.messages(10).await.unwrap().is_empty());
+
+        let recipient = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let message = |subject: &str, text: &str| EmailMessage {
+            subject: subject.to_owned(),
+            text_body: text.to_owned(),
+            html_body: format!("<p>{}</p>\n", text),
+        };
+        client
+            .send_email(&recipient, &message("2FA Code", "123456"))
+            .await
+            .unwrap();
+        client
+            .send_email(
+                &recipient,
+                &message("Injected\r\nBcc: x@example.com", "line 1\nline 2\n"),
+            )
+            .await
+            .unwrap();
+
+        let messages = client.messages(10).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].subject, "Injected  Bcc: x@example.com");
+        assert_eq!(messages[0].text_body, "line 1\nline 2\n");
+        assert_eq!(messages[0].html_body, "<p>line 1\nline 2\n</p>\n");
+        assert_eq!(messages[1].from, "Rusty Auth <auth@example.com>");
+        assert_eq!(messages[1].to, "test@example.com");
+        assert_eq!(messages[1].subject, "2FA Code");
+        assert_eq!(messages[1].text_body, "123456");
+        assert_eq!(messages[1].html_body, "<p>123456</p>\n");
+        assert!(messages[1].file_name.ends_with(".eml"));
+
+        assert_eq!(client.messages(1).await.unwrap(), messages[..1]);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use redis::Connection;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, SqlitePool};
+use tokio::{sync::RwLock, task::JoinSet, time::Instant};
+
+use crate::{
+    app_state::{EmailClientType, HealthCheckType, SmsClientType},
+    domain::health::HealthCheck,
+};
+
+pub struct PostgresHealthCheck {
+    pool: PgPool,
+}
+
+impl PostgresHealthCheck {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for PostgresHealthCheck {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn check(&self) -> Result<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to query PostgreSQL")?;
+        Ok(())
+    }
+}
+
+pub struct SqliteHealthCheck {
+    pool: SqlitePool,
+}
+
+impl SqliteHealthCheck {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for SqliteHealthCheck {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn check(&self) -> Result<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to query SQLite")?;
+        Ok(())
+    }
+}
+
+// Pings Redis over the connections the stores use, as they don't reconnect once Redis has
+// dropped them. The blocking PING runs off the runtime, so `health.timeout_ms` still applies.
+pub struct RedisHealthCheck {
+    conns: Vec<Arc<RwLock<Connection>>>,
+}
+
+impl RedisHealthCheck {
+    pub fn new(conns: Vec<Arc<RwLock<Connection>>>) -> Self {
+        Self { conns }
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for RedisHealthCheck {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn check(&self) -> Result<()> {
+        for conn in &self.conns {
+            let mut conn = conn.clone().write_owned().await;
+            tokio::task::spawn_blocking(move || redis::cmd("PING").query::<String>(&mut *conn))
+                .await
+                .wrap_err("Redis ping task failed")?
+                .wrap_err("failed to ping Redis")?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct EmailHealthCheck {
+    email_client: EmailClientType,
+}
+
+impl EmailHealthCheck {
+    pub fn new(email_client: EmailClientType) -> Self {
+        Self { email_client }
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for EmailHealthCheck {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.email_client.health_check().await
+    }
+}
+
+pub struct SmsHealthCheck {
+    sms_client: SmsClientType,
+}
+
+impl SmsHealthCheck {
+    pub fn new(sms_client: SmsClientType) -> Self {
+        Self { sms_client }
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for SmsHealthCheck {
+    fn name(&self) -> &'static str {
+        "sms"
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.sms_client.health_check().await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Unavailable,
+    // shutting down; dependencies are not checked
+    Draining,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckReport {
+    pub status: HealthStatus,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: BTreeMap<String, CheckReport>,
+}
+
+// Runs all checks concurrently, each bounded by `timeout`
+#[tracing::instrument(name = "Running health checks", skip_all)]
+pub async fn run_health_checks(checks: &[HealthCheckType], timeout: Duration) -> HealthReport {
+    let mut tasks = JoinSet::new();
+    for check in checks {
+        let check = check.clone();
+        tasks.spawn(async move {
+            let start = Instant::now();
+            let outcome = match tokio::time::timeout(timeout, check.check()).await {
+                Ok(outcome) => outcome,
+                Err(_) => Err(eyre!("timed out after {}ms", timeout.as_millis())),
+            };
+            let report = CheckReport {
+                status: match outcome {
+                    Ok(()) => HealthStatus::Ok,
+                    Err(_) => HealthStatus::Unavailable,
+                },
+                latency_ms: start.elapsed().as_millis(),
+                error: outcome.err().map(|e| format!("{:#}", e)),
+            };
+            (check.name().to_owned(), report)
+        });
+    }
+
+    let mut reports = BTreeMap::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((name, report)) => {
+                if let Some(error) = &report.error {
+                    tracing::warn!(check = %name, error = %error, "Health check failed");
+                }
+                reports.insert(name, report);
+            }
+            Err(e) => tracing::error!(error = ?e, "Health check panicked"),
+        }
+    }
+
+    // a panicked check leaves a gap, which must not read as healthy
+    let all_ok = reports.len() == checks.len()
+        && reports
+            .values()
+            .all(|report| report.status == HealthStatus::Ok);
+
+    HealthReport {
+        status: if all_ok {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Unavailable
+        },
+        checks: reports,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct StubCheck {
+        name: &'static str,
+        delay: Duration,
+        healthy: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl HealthCheck for StubCheck {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn check(&self) -> Result<()> {
+            tokio::time::sleep(self.delay).await;
+            match self.healthy {
+                true => Ok(()),
+                false => Err(eyre!("connection refused")),
+            }
+        }
+    }
+
+    fn stub(name: &'static str, delay: Duration, healthy: bool) -> HealthCheckType {
+        Arc::new(StubCheck {
+            name,
+            delay,
+            healthy,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_all_checks_ok() {
+        let checks = [
+            stub("postgres", Duration::ZERO, true),
+            stub("redis", Duration::ZERO, true),
+        ];
+
+        let report = run_health_checks(&checks, Duration::from_secs(1)).await;
+
+        assert_eq!(report.status, HealthStatus::Ok);
+        assert_eq!(report.checks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failing_and_slow_checks_are_unavailable() {
+        let checks = [
+            stub("postgres", Duration::ZERO, true),
+            stub("redis", Duration::ZERO, false),
+            stub("email", Duration::from_secs(60), true),
+        ];
+
+        let report = run_health_checks(&checks, Duration::from_millis(50)).await;
+
+        assert_eq!(report.status, HealthStatus::Unavailable);
+        assert_eq!(report.checks["postgres"].status, HealthStatus::Ok);
+        assert_eq!(report.checks["redis"].status, HealthStatus::Unavailable);
+        assert_eq!(
+            report.checks["redis"].error.as_deref(),
+            Some("connection refused")
+        );
+        assert_eq!(
+            report.checks["email"].error.as_deref(),
+            Some("timed out after 50ms")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redis_check_times_out_on_an_unresponsive_server() {
+        use std::io::{Read, Write};
+
+        // answers the connection handshake, then nothing until told to hang up
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (hang_up, hung_up) = std::sync::mpsc::channel::<()>();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).unwrap();
+            stream.write_all(b"+OK\r\n+OK\r\n").unwrap();
+            let _ = hung_up.recv();
+        });
+        let conn = redis::Client::open(format!("redis://{}/", address))
+            .unwrap()
+            .get_connection()
+            .unwrap();
+        let checks = [
+            Arc::new(RedisHealthCheck::new(vec![Arc::new(RwLock::new(conn))])) as HealthCheckType,
+        ];
+
+        let report = run_health_checks(&checks, Duration::from_millis(100)).await;
+
+        assert_eq!(report.status, HealthStatus::Unavailable);
+        // closes the connection, so the blocked PING returns
+        hang_up.send(()).unwrap();
+        server.join().unwrap();
+    }
+}
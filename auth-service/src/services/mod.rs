@@ -1,3 +1,13 @@
 pub mod aws_email_client;
+pub mod capturing_email_client;
+pub mod capturing_sms_client;
 pub mod data_stores;
+pub mod email_delivery;
+pub mod email_templates;
+pub mod file_outbox_email_client;
+pub mod health;
+pub mod phone_codes;
 pub mod postmark_email_client;
+pub mod smtp_email_client;
+pub mod twilio_sms_client;
+pub mod webhooks;
@@ -0,0 +1,113 @@
+use color_eyre::eyre::{eyre, Result};
+use secrecy::ExposeSecret;
+
+use crate::{
+    domain::{data_stores::TwoFACode, PhoneNumber, SmsClient, TwoFAChannel},
+    services::email_templates::{EmailTemplates, PhoneTemplate},
+};
+
+// Texts the code, or calls and reads it out, as `channel` says, in the first of
+// `preferred_locales` there are templates for
+pub async fn send_code(
+    sms_client: &(dyn SmsClient + Send + Sync),
+    templates: &EmailTemplates,
+    preferred_locales: &[String],
+    channel: TwoFAChannel,
+    recipient: &PhoneNumber,
+    code: &TwoFACode,
+    expires_in_minutes: u64,
+) -> Result<()> {
+    let code = code.as_ref().expose_secret();
+    match channel {
+        TwoFAChannel::Sms => {
+            let template = PhoneTemplate::TwoFaCodeSms {
+                code: code.to_owned(),
+                expires_in_minutes,
+            };
+            let message = templates.render_phone(&template, preferred_locales);
+            sms_client.send_sms(recipient, &message.body).await
+        }
+        TwoFAChannel::Voice => {
+            let template = PhoneTemplate::TwoFaCodeVoice {
+                code: spoken_digits(code),
+            };
+            let message = templates.render_phone(&template, preferred_locales);
+            sms_client
+                .call(recipient, &message.body, &message.locale)
+                .await
+        }
+        TwoFAChannel::Email => Err(eyre!("codes for the email channel are emailed")),
+    }
+}
+
+// Digits are read one by one
+fn spoken_digits(code: &str) -> String {
+    code.chars()
+        .map(String::from)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::*;
+    use crate::services::capturing_sms_client::{CapturingSmsClient, SmsKind};
+
+    #[tokio::test]
+    async fn test_send_code_by_channel() {
+        let client = CapturingSmsClient::default();
+        let templates = EmailTemplates::built_in();
+        let recipient = PhoneNumber::parse(Secret::new("+14155550100".to_owned())).unwrap();
+        let code = TwoFACode::parse(Secret::new("123456".to_owned())).unwrap();
+
+        for channel in [TwoFAChannel::Sms, TwoFAChannel::Voice] {
+            send_code(&client, &templates, &[], channel, &recipient, &code, 10)
+                .await
+                .unwrap();
+        }
+        assert!(send_code(
+            &client,
+            &templates,
+            &[],
+            TwoFAChannel::Email,
+            &recipient,
+            &code,
+            10
+        )
+        .await
+        .is_err());
+
+        let messages = client.sent_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].kind, SmsKind::Text);
+        assert!(messages[0].body.contains("123456"));
+        assert!(messages[0].body.contains("10 minutes"));
+        assert_eq!(messages[1].kind, SmsKind::Call);
+        assert!(messages[1].body.contains("1, 2, 3, 4, 5, 6"));
+        assert_eq!(messages[1].language.as_deref(), Some("en"));
+    }
+
+    #[tokio::test]
+    async fn test_send_code_in_the_preferred_locale() {
+        let client = CapturingSmsClient::default();
+        let templates = EmailTemplates::built_in();
+        let recipient = PhoneNumber::parse(Secret::new("+14155550100".to_owned())).unwrap();
+        let code = TwoFACode::parse(Secret::new("123456".to_owned())).unwrap();
+        let locales = ["de-AT".to_owned()];
+
+        for channel in [TwoFAChannel::Sms, TwoFAChannel::Voice] {
+            send_code(
+                &client, &templates, &locales, channel, &recipient, &code, 10,
+            )
+            .await
+            .unwrap();
+        }
+
+        let messages = client.sent_messages();
+        assert!(messages[0].body.contains("10 Minuten"));
+        assert!(messages[1].body.contains("Noch einmal"));
+        assert_eq!(messages[1].language.as_deref(), Some("de"));
+    }
+}
@@ -2,19 +2,22 @@ use color_eyre::eyre::Result;
 use reqwest::{Client, Url};
 use secrecy::{ExposeSecret, Secret};
 
-use crate::domain::{Email, EmailClient};
+use crate::{
+    domain::{Email, EmailClient, EmailMessage, EmailSender},
+    utils::metrics::record_email_sent,
+};
 
 pub struct PostmarkEmailClient {
     http_client: Client,
     base_url: String,
-    sender: Email,
+    sender: EmailSender,
     authorization_token: Secret<String>,
 }
 
 impl PostmarkEmailClient {
     pub fn new(
         base_url: String,
-        sender: Email,
+        sender: EmailSender,
         authorization_token: Secret<String>,
         http_client: Client,
     ) -> Self {
@@ -25,21 +28,20 @@ impl PostmarkEmailClient {
             authorization_token,
         }
     }
-}
 
-#[async_trait::async_trait]
-impl EmailClient for PostmarkEmailClient {
-    #[tracing::instrument(name = "Sending email", skip_all)]
-    async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()> {
+    async fn send(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
         let base = Url::parse(&self.base_url)?;
         let url = base.join("/email")?;
 
+        let from = self.sender.from.to_string();
+        let reply_to = self.sender.reply_to.as_ref().map(|r| r.to_string());
         let request_body = SendEmailRequest {
-            from: self.sender.as_ref().expose_secret(),
+            from: &from,
+            reply_to: reply_to.as_deref(),
             to: recipient.as_ref().expose_secret(),
-            subject,
-            html_body: content,
-            text_body: content,
+            subject: &message.subject,
+            html_body: &message.html_body,
+            text_body: &message.text_body,
             message_stream: MESSAGE_STREAM,
         };
 
@@ -59,6 +61,34 @@ impl EmailClient for PostmarkEmailClient {
     }
 }
 
+#[async_trait::async_trait]
+impl EmailClient for PostmarkEmailClient {
+    #[tracing::instrument(name = "Sending email", skip_all)]
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        let result = self.send(recipient, message).await;
+        record_email_sent("postmark", &result);
+        result
+    }
+
+    // fetches the server the token belongs to, which fails if the token is revoked
+    #[tracing::instrument(name = "Checking Postmark health", skip_all)]
+    async fn health_check(&self) -> Result<()> {
+        let url = Url::parse(&self.base_url)?.join("/server")?;
+
+        self.http_client
+            .get(url)
+            .header(
+                POSTMARK_AUTH_HEADER,
+                self.authorization_token.expose_secret(),
+            )
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
 // Constants for message stream and authorization header
 const MESSAGE_STREAM: &str = "outbound";
 const POSTMARK_AUTH_HEADER: &str = "X-Postmark-Server-Token";
@@ -67,6 +97,8 @@ const POSTMARK_AUTH_HEADER: &str = "X-Postmark-Server-Token";
 #[serde(rename_all = "PascalCase")]
 struct SendEmailRequest<'a> {
     from: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<&'a str>,
     to: &'a str,
     subject: &'a str,
     html_body: &'a str,
@@ -84,30 +116,38 @@ mod tests {
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
-    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
     use super::PostmarkEmailClient;
 
-    fn subject() -> String {
-        Sentence(1..2).fake()
-    }
-
-    fn content() -> String {
-        Paragraph(1..10).fake()
+    fn message() -> EmailMessage {
+        let text: String = Paragraph(1..10).fake();
+        EmailMessage {
+            subject: Sentence(1..2).fake(),
+            html_body: format!("<p>{}</p>", text),
+            text_body: text,
+        }
     }
 
     fn email() -> Email {
         Email::parse(Secret::new(SafeEmail().fake())).unwrap()
     }
 
+    fn sender() -> EmailSender {
+        EmailSender {
+            from: "Rusty Auth <auth@example.com>".parse().unwrap(),
+            reply_to: Some("support@example.com".parse().unwrap()),
+        }
+    }
+
     fn email_client(base_url: String) -> PostmarkEmailClient {
         let http_client = Client::builder()
             .timeout(Duration::from_millis(200))
             .build()
             .unwrap();
 
-        PostmarkEmailClient::new(base_url, email(), Secret::new(Faker.fake()), http_client)
+        PostmarkEmailClient::new(base_url, sender(), Secret::new(Faker.fake()), http_client)
     }
 
     // Custom matcher to validate the email request body
@@ -145,9 +185,26 @@ mod tests {
             .mount(&mock_server)
             .await;
 
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content())
-            .await;
+        let outcome = email_client.send_email(&email(), &message()).await;
+
+        assert!(outcome.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_email_uses_the_configured_sender() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(body_partial_json(serde_json::json!({
+            "From": "Rusty Auth <auth@example.com>",
+            "ReplyTo": "support@example.com",
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        let outcome = email_client.send_email(&email(), &message()).await;
 
         assert!(outcome.is_ok());
     }
@@ -163,13 +220,27 @@ mod tests {
             .mount(&mock_server)
             .await;
 
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content())
-            .await;
+        let outcome = email_client.send_email(&email(), &message()).await;
 
         assert!(outcome.is_err());
     }
 
+    #[tokio::test]
+    async fn health_check_calls_the_server_endpoint() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists(POSTMARK_AUTH_HEADER))
+            .and(path("/server"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert!(email_client.health_check().await.is_ok());
+    }
+
     #[tokio::test]
     async fn send_email_times_out_if_the_server_takes_too_long() {
         let mock_server = MockServer::start().await;
@@ -183,9 +254,7 @@ mod tests {
             .mount(&mock_server)
             .await;
 
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content())
-            .await;
+        let outcome = email_client.send_email(&email(), &message()).await;
 
         assert!(outcome.is_err());
     }
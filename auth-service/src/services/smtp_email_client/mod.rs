@@ -0,0 +1,343 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context, Result};
+use lettre::{
+    message::MultiPart,
+    transport::smtp::{
+        authentication::{Credentials, Mechanism},
+        client::{Certificate, Tls, TlsParameters},
+        PoolConfig,
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use secrecy::ExposeSecret;
+
+use crate::{
+    domain::{Email, EmailClient, EmailMessage, EmailSender},
+    utils::{
+        configuration::{SmtpAuthMechanism, SmtpSettings, SmtpTlsMode},
+        metrics::record_email_sent,
+        redact::mask_email,
+    },
+};
+
+#[cfg(test)]
+mod stub;
+
+// Sends through any SMTP relay, for deployments that can use neither SES nor Postmark.
+// Connections are pooled and reused between emails.
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: EmailSender,
+    // lettre only applies its own timeout to connecting
+    timeout: Duration,
+}
+
+impl SmtpEmailClient {
+    pub fn new(settings: &SmtpSettings, sender: EmailSender) -> Result<Self> {
+        let tls = match settings.tls {
+            SmtpTlsMode::StartTls => Tls::Required(tls_parameters(settings)?),
+            SmtpTlsMode::Implicit => Tls::Wrapper(tls_parameters(settings)?),
+            SmtpTlsMode::None => Tls::None,
+        };
+        let timeout = Duration::from_millis(settings.timeout_ms);
+        let pool_config = PoolConfig::new()
+            .max_size(settings.pool_max_size)
+            .idle_timeout(Duration::from_secs(settings.pool_idle_timeout_secs));
+
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            .port(settings.port())
+            .tls(tls)
+            .timeout(Some(timeout))
+            .pool_config(pool_config);
+        if let Some(username) = &settings.username {
+            let mechanisms = settings
+                .auth_mechanisms()
+                .map_err(|e| eyre!(e))?
+                .into_iter()
+                .map(|mechanism| match mechanism {
+                    SmtpAuthMechanism::Plain => Mechanism::Plain,
+                    SmtpAuthMechanism::Login => Mechanism::Login,
+                })
+                .collect();
+            transport = transport
+                .credentials(Credentials::new(
+                    username.to_owned(),
+                    settings.password.expose_secret().to_owned(),
+                ))
+                .authentication(mechanisms);
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            sender,
+            timeout,
+        })
+    }
+
+    async fn send(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        let recipient = recipient.as_ref().expose_secret();
+        tracing::info!("Sending email to: {}", mask_email(recipient));
+
+        let mut builder = Message::builder()
+            .from(self.sender.from.clone())
+            .to(recipient.parse().wrap_err("Invalid recipient")?);
+        if let Some(reply_to) = &self.sender.reply_to {
+            builder = builder.reply_to(reply_to.clone());
+        }
+        let email =
+            builder
+                .subject(&message.subject)
+                .multipart(MultiPart::alternative_plain_html(
+                    message.text_body.to_owned(),
+                    message.html_body.to_owned(),
+                ))?;
+
+        tokio::time::timeout(self.timeout, self.transport.send(email))
+            .await
+            .map_err(|_| eyre!("Timed out sending email over SMTP"))?
+            .wrap_err("Failed to send email over SMTP")?;
+        Ok(())
+    }
+}
+
+fn tls_parameters(settings: &SmtpSettings) -> Result<TlsParameters> {
+    let mut parameters = TlsParameters::builder(settings.host.to_owned());
+    if let Some(path) = &settings.root_certificate_path {
+        let pem = std::fs::read(path).wrap_err_with(|| {
+            format!("Failed to read email.smtp.root_certificate_path {}", path)
+        })?;
+        parameters = parameters.add_root_certificate(
+            Certificate::from_pem(&pem).wrap_err("Invalid email.smtp.root_certificate_path")?,
+        );
+    }
+
+    Ok(parameters.build()?)
+}
+
+#[async_trait::async_trait]
+impl EmailClient for SmtpEmailClient {
+    #[tracing::instrument(name = "Sending email", skip_all)]
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        let result = self.send(recipient, message).await;
+        record_email_sent("smtp", &result);
+        result
+    }
+
+    // connects, negotiates TLS and authenticates without sending anything
+    #[tracing::instrument(name = "Checking SMTP health", skip_all)]
+    async fn health_check(&self) -> Result<()> {
+        let connected = tokio::time::timeout(self.timeout, self.transport.test_connection())
+            .await
+            .map_err(|_| eyre!("Timed out connecting to the SMTP server"))??;
+        match connected {
+            true => Ok(()),
+            false => Err(eyre!("SMTP server did not answer NOOP")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::stub::{SmtpStub, StubTls};
+    use super::*;
+
+    const USERNAME: &str = "mailer";
+    const PASSWORD: &str = "s3cret";
+
+    fn fixture(name: &str) -> String {
+        format!(
+            "{}/tests/fixtures/smtp/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    }
+
+    fn settings(stub: &SmtpStub, tls: SmtpTlsMode) -> SmtpSettings {
+        SmtpSettings {
+            host: "localhost".to_owned(),
+            port: Some(stub.port()),
+            tls,
+            root_certificate_path: Some(fixture("ca.pem")),
+            timeout_ms: 2_000,
+            ..SmtpSettings::default()
+        }
+    }
+
+    fn with_credentials(settings: SmtpSettings, mechanisms: &[&str]) -> SmtpSettings {
+        SmtpSettings {
+            username: Some(USERNAME.to_owned()),
+            password: Secret::new(PASSWORD.to_owned()),
+            auth_mechanisms: mechanisms.iter().map(|m| m.to_string()).collect(),
+            ..settings
+        }
+    }
+
+    fn sender() -> EmailSender {
+        EmailSender {
+            from: "Rusty Auth <auth@example.com>".parse().unwrap(),
+            reply_to: Some("support@example.com".parse().unwrap()),
+        }
+    }
+
+    fn message(code: &str) -> EmailMessage {
+        EmailMessage {
+            subject: "2FA Code".to_owned(),
+            text_body: format!("Your code is {}", code),
+            html_body: format!("<p>Your code is <b>{}</b></p>", code),
+        }
+    }
+
+    fn recipient() -> Email {
+        Email::parse(Secret::new("user@example.com".to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sends_over_starttls_with_auth_plain() {
+        let stub = SmtpStub::start(StubTls::StartTls, Some((USERNAME, PASSWORD))).await;
+        let client = SmtpEmailClient::new(
+            &with_credentials(settings(&stub, SmtpTlsMode::StartTls), &["plain"]),
+            sender(),
+        )
+        .unwrap();
+
+        client
+            .send_email(&recipient(), &message("123456"))
+            .await
+            .unwrap();
+
+        let messages = stub.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].tls);
+        assert_eq!(messages[0].auth.as_deref(), Some("PLAIN"));
+        assert_eq!(messages[0].mail_from, "auth@example.com");
+        assert_eq!(messages[0].rcpt_to, vec!["user@example.com"]);
+        assert!(messages[0].data.contains("Reply-To: support@example.com"));
+        assert!(messages[0].data.contains("Subject: 2FA Code"));
+        assert!(messages[0].data.contains("multipart/alternative"));
+        assert!(messages[0].data.contains("Your code is 123456"));
+        assert!(messages[0]
+            .data
+            .contains("<p>Your code is <b>123456</b></p>"));
+    }
+
+    #[tokio::test]
+    async fn test_sends_over_implicit_tls_with_auth_login() {
+        let stub = SmtpStub::start(StubTls::Implicit, Some((USERNAME, PASSWORD))).await;
+        let client = SmtpEmailClient::new(
+            &with_credentials(settings(&stub, SmtpTlsMode::Implicit), &["login"]),
+            sender(),
+        )
+        .unwrap();
+
+        client
+            .send_email(&recipient(), &message("123456"))
+            .await
+            .unwrap();
+
+        let messages = stub.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].tls);
+        assert_eq!(messages[0].auth.as_deref(), Some("LOGIN"));
+    }
+
+    #[tokio::test]
+    async fn test_fails_with_wrong_password() {
+        let stub = SmtpStub::start(StubTls::StartTls, Some((USERNAME, "other"))).await;
+        let client = SmtpEmailClient::new(
+            &with_credentials(settings(&stub, SmtpTlsMode::StartTls), &["plain"]),
+            sender(),
+        )
+        .unwrap();
+
+        let result = client.send_email(&recipient(), &message("123456")).await;
+
+        assert!(result.is_err());
+        assert!(stub.messages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_starttls_is_required_when_configured() {
+        // the server does not offer STARTTLS, so nothing may be sent in the clear
+        let stub = SmtpStub::start(StubTls::None, None).await;
+        let client =
+            SmtpEmailClient::new(&settings(&stub, SmtpTlsMode::StartTls), sender()).unwrap();
+
+        let result = client.send_email(&recipient(), &message("123456")).await;
+
+        assert!(result.is_err());
+        assert!(stub.messages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_untrusted_certificate() {
+        let stub = SmtpStub::start(StubTls::Implicit, None).await;
+        let client = SmtpEmailClient::new(
+            &SmtpSettings {
+                root_certificate_path: None,
+                ..settings(&stub, SmtpTlsMode::Implicit)
+            },
+            sender(),
+        )
+        .unwrap();
+
+        assert!(client
+            .send_email(&recipient(), &message("123456"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reuses_pooled_connection() {
+        let stub = SmtpStub::start(StubTls::None, None).await;
+        let client = SmtpEmailClient::new(&settings(&stub, SmtpTlsMode::None), sender()).unwrap();
+
+        for code in ["111111", "222222", "333333"] {
+            client
+                .send_email(&recipient(), &message(code))
+                .await
+                .unwrap();
+            // connections are returned to the pool in the background
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert_eq!(stub.messages().len(), 3);
+        assert_eq!(stub.connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_times_out_on_unresponsive_server() {
+        let stub = SmtpStub::start_unresponsive().await;
+        let client = SmtpEmailClient::new(
+            &SmtpSettings {
+                timeout_ms: 200,
+                ..settings(&stub, SmtpTlsMode::None)
+            },
+            sender(),
+        )
+        .unwrap();
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.send_email(&recipient(), &message("123456")),
+        )
+        .await;
+
+        assert!(matches!(result, Ok(Err(_))));
+    }
+
+    #[tokio::test]
+    async fn test_health_check_authenticates() {
+        let stub = SmtpStub::start(StubTls::StartTls, Some((USERNAME, PASSWORD))).await;
+        let client = SmtpEmailClient::new(
+            &with_credentials(settings(&stub, SmtpTlsMode::StartTls), &["plain"]),
+            sender(),
+        )
+        .unwrap();
+
+        client.health_check().await.unwrap();
+        assert!(stub.messages().is_empty());
+    }
+}
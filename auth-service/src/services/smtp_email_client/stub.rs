@@ -0,0 +1,309 @@
+// A minimal in-process SMTP server for the client tests: EHLO, STARTTLS, AUTH PLAIN/LOGIN,
+// MAIL, RCPT, DATA, RSET, NOOP and QUIT. TLS uses the certificate in tests/fixtures/smtp,
+// which is signed by the test CA in the same directory.
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    },
+    TlsAcceptor,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StubTls {
+    None,
+    StartTls,
+    Implicit,
+}
+
+#[derive(Debug, Clone)]
+pub struct StubMessage {
+    pub mail_from: String,
+    pub rcpt_to: Vec<String>,
+    pub data: String,
+    // the mechanism used to authenticate, if any
+    pub auth: Option<String>,
+    pub tls: bool,
+}
+
+struct StubState {
+    tls: StubTls,
+    credentials: Option<(String, String)>,
+    acceptor: TlsAcceptor,
+    messages: Mutex<Vec<StubMessage>>,
+    connections: AtomicUsize,
+}
+
+pub struct SmtpStub {
+    address: SocketAddr,
+    state: Arc<StubState>,
+}
+
+impl SmtpStub {
+    // With credentials, mail is only accepted after a successful AUTH
+    pub async fn start(tls: StubTls, credentials: Option<(&str, &str)>) -> Self {
+        let state = Arc::new(StubState {
+            tls,
+            credentials: credentials.map(|(user, pass)| (user.to_owned(), pass.to_owned())),
+            acceptor: tls_acceptor(),
+            messages: Mutex::new(Vec::new()),
+            connections: AtomicUsize::new(0),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let accept_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accept_state.connections.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(serve(stream, accept_state.clone()));
+            }
+        });
+
+        Self { address, state }
+    }
+
+    // Accepts connections but never answers
+    pub async fn start_unresponsive() -> Self {
+        let stub = Self::start(StubTls::None, None).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let address = listener.local_addr().unwrap();
+        let state = stub.state.clone();
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                state.connections.fetch_add(1, Ordering::SeqCst);
+                open.push(stream);
+            }
+        });
+
+        Self { address, ..stub }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+
+    pub fn messages(&self) -> Vec<StubMessage> {
+        self.state.messages.lock().unwrap().clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+}
+
+fn tls_acceptor() -> TlsAcceptor {
+    let dir = format!("{}/tests/fixtures/smtp", env!("CARGO_MANIFEST_DIR"));
+    let certs = CertificateDer::pem_file_iter(format!("{}/localhost.pem", dir))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key = PrivateKeyDer::from_pem_file(format!("{}/localhost.key", dir)).unwrap();
+
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .unwrap();
+    TlsAcceptor::from(Arc::new(config))
+}
+
+async fn serve(stream: TcpStream, state: Arc<StubState>) -> std::io::Result<()> {
+    if state.tls == StubTls::Implicit {
+        let stream = state.acceptor.accept(stream).await?;
+        return session(stream, &state, true, true).await.map(|_| ());
+    }
+
+    if let Some(stream) = session(stream, &state, false, true).await? {
+        let stream = state.acceptor.accept(stream).await?;
+        session(stream, &state, true, false).await?;
+    }
+    Ok(())
+}
+
+// Returns the stream when the client asked for STARTTLS
+async fn session<S>(
+    stream: S,
+    state: &StubState,
+    tls: bool,
+    greet: bool,
+) -> std::io::Result<Option<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    if greet {
+        reply(&mut stream, "220 stub ESMTP").await?;
+    }
+
+    let mut auth = None;
+    let mut mail_from = None;
+    let mut rcpt_to = Vec::new();
+
+    while let Some(line) = read_line(&mut stream).await? {
+        let upper = line.to_ascii_uppercase();
+        let verb = upper.split_whitespace().next().unwrap_or_default();
+
+        match verb {
+            "EHLO" => {
+                let mut lines = vec!["250-stub".to_owned()];
+                if state.tls == StubTls::StartTls && !tls {
+                    lines.push("250-STARTTLS".to_owned());
+                }
+                if state.credentials.is_some() && (tls || state.tls == StubTls::None) {
+                    lines.push("250-AUTH PLAIN LOGIN".to_owned());
+                }
+                lines.push("250 8BITMIME".to_owned());
+                reply(&mut stream, &lines.join("\r\n")).await?;
+            }
+            "HELO" | "NOOP" => reply(&mut stream, "250 OK").await?,
+            "STARTTLS" if state.tls == StubTls::StartTls && !tls => {
+                reply(&mut stream, "220 Ready to start TLS").await?;
+                return Ok(Some(stream.into_inner()));
+            }
+            "AUTH" => {
+                let mechanism = upper
+                    .split_whitespace()
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_owned();
+                let initial = line.split_whitespace().nth(2).map(str::to_owned);
+                let credentials = match mechanism.as_str() {
+                    "PLAIN" => {
+                        let response = match initial {
+                            Some(response) => response,
+                            None => challenge(&mut stream, "334 ").await?,
+                        };
+                        let decoded = decode(&response);
+                        let mut parts = decoded.split('\0').skip(1);
+                        (
+                            parts.next().unwrap_or_default().to_owned(),
+                            parts.next().unwrap_or_default().to_owned(),
+                        )
+                    }
+                    "LOGIN" => {
+                        let username = match initial {
+                            Some(response) => response,
+                            None => challenge(&mut stream, "334 VXNlcm5hbWU6").await?,
+                        };
+                        let password = challenge(&mut stream, "334 UGFzc3dvcmQ6").await?;
+                        (decode(&username), decode(&password))
+                    }
+                    _ => {
+                        reply(&mut stream, "504 Unrecognized authentication type").await?;
+                        continue;
+                    }
+                };
+                if state.credentials.as_ref() == Some(&credentials) {
+                    auth = Some(mechanism);
+                    reply(&mut stream, "235 Authentication succeeded").await?;
+                } else {
+                    reply(&mut stream, "535 Authentication failed").await?;
+                }
+            }
+            "MAIL" if state.credentials.is_some() && auth.is_none() => {
+                reply(&mut stream, "530 Authentication required").await?;
+            }
+            "MAIL" => {
+                mail_from = Some(address(&line));
+                rcpt_to.clear();
+                reply(&mut stream, "250 OK").await?;
+            }
+            "RCPT" if mail_from.is_some() => {
+                rcpt_to.push(address(&line));
+                reply(&mut stream, "250 OK").await?;
+            }
+            "DATA" if !rcpt_to.is_empty() => {
+                reply(&mut stream, "354 End data with <CR><LF>.<CR><LF>").await?;
+                let mut data = String::new();
+                while let Some(line) = read_line(&mut stream).await? {
+                    if line == "." {
+                        break;
+                    }
+                    // dot-stuffing
+                    data.push_str(line.strip_prefix('.').unwrap_or(&line));
+                    data.push_str("\r\n");
+                }
+                state.messages.lock().unwrap().push(StubMessage {
+                    mail_from: mail_from.take().unwrap_or_default(),
+                    rcpt_to: std::mem::take(&mut rcpt_to),
+                    data,
+                    auth: auth.clone(),
+                    tls,
+                });
+                reply(&mut stream, "250 OK: queued").await?;
+            }
+            "RSET" => {
+                mail_from = None;
+                rcpt_to.clear();
+                reply(&mut stream, "250 OK").await?;
+            }
+            "QUIT" => {
+                reply(&mut stream, "221 Bye").await?;
+                return Ok(None);
+            }
+            _ => reply(&mut stream, "503 Bad sequence of commands").await?,
+        }
+    }
+
+    Ok(None)
+}
+
+async fn read_line<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    match stream.read_line(&mut line).await? {
+        0 => Ok(None),
+        _ => Ok(Some(line.trim_end_matches(['\r', '\n']).to_owned())),
+    }
+}
+
+async fn reply<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    message: &str,
+) -> std::io::Result<()> {
+    let stream = stream.get_mut();
+    stream
+        .write_all(format!("{}\r\n", message).as_bytes())
+        .await?;
+    stream.flush().await
+}
+
+async fn challenge<S>(stream: &mut BufReader<S>, prompt: &str) -> std::io::Result<String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    reply(stream, prompt).await?;
+    Ok(read_line(stream).await?.unwrap_or_default())
+}
+
+fn decode(response: &str) -> String {
+    String::from_utf8(STANDARD.decode(response).unwrap_or_default()).unwrap_or_default()
+}
+
+// "MAIL FROM:<a@b> BODY=8BITMIME" -> "a@b"
+fn address(line: &str) -> String {
+    line.split_once('<')
+        .and_then(|(_, rest)| rest.split_once('>'))
+        .map(|(address, _)| address.to_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,234 @@
+use color_eyre::eyre::Result;
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    domain::{PhoneNumber, SmsClient},
+    utils::{html::escape_html, metrics::record_sms_sent},
+};
+
+// Sends texts and makes calls through Twilio's REST API, or anything that speaks it
+pub struct TwilioSmsClient {
+    http_client: Client,
+    base_url: String,
+    account_sid: String,
+    auth_token: Secret<String>,
+    from: PhoneNumber,
+}
+
+impl TwilioSmsClient {
+    pub fn new(
+        base_url: String,
+        account_sid: String,
+        auth_token: Secret<String>,
+        from: PhoneNumber,
+        http_client: Client,
+    ) -> Self {
+        Self {
+            http_client,
+            base_url,
+            account_sid,
+            auth_token,
+            from,
+        }
+    }
+
+    fn url(&self, resource: &str) -> Result<Url> {
+        let path = format!("/{}/Accounts/{}{}", API_VERSION, self.account_sid, resource);
+        Ok(Url::parse(&self.base_url)?.join(&path)?)
+    }
+
+    async fn create(&self, resource: &str, params: &[(&str, &str)]) -> Result<()> {
+        self.http_client
+            .post(self.url(resource)?)
+            .basic_auth(&self.account_sid, Some(self.auth_token.expose_secret()))
+            .form(params)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl SmsClient for TwilioSmsClient {
+    #[tracing::instrument(name = "Sending SMS", skip_all)]
+    async fn send_sms(&self, recipient: &PhoneNumber, text: &str) -> Result<()> {
+        let params = [
+            ("To", recipient.as_ref().expose_secret().as_str()),
+            ("From", self.from.as_ref().expose_secret()),
+            ("Body", text),
+        ];
+        let result = self.create("/Messages.json", &params).await;
+        record_sms_sent("twilio", "sms", &result);
+        result
+    }
+
+    #[tracing::instrument(name = "Making voice call", skip_all)]
+    async fn call(&self, recipient: &PhoneNumber, speech: &str, language: &str) -> Result<()> {
+        let twiml = format!(
+            "<Response><Say language=\"{}\">{}</Say></Response>",
+            escape_html(language),
+            escape_html(speech)
+        );
+        let params = [
+            ("To", recipient.as_ref().expose_secret().as_str()),
+            ("From", self.from.as_ref().expose_secret()),
+            ("Twiml", &twiml),
+        ];
+        let result = self.create("/Calls.json", &params).await;
+        record_sms_sent("twilio", "voice", &result);
+        result
+    }
+
+    // fetches the account, which fails if the credentials are revoked
+    #[tracing::instrument(name = "Checking Twilio health", skip_all)]
+    async fn health_check(&self) -> Result<()> {
+        self.http_client
+            .get(self.url(".json")?)
+            .basic_auth(&self.account_sid, Some(self.auth_token.expose_secret()))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+const API_VERSION: &str = "2010-04-01";
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use wiremock::matchers::{any, body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    const ACCOUNT_SID: &str = "AC0123456789";
+    // base64 of "AC0123456789:token"
+    const BASIC_AUTH: &str = "Basic QUMwMTIzNDU2Nzg5OnRva2Vu";
+
+    fn phone_number(s: &str) -> PhoneNumber {
+        PhoneNumber::parse(Secret::new(s.to_owned())).unwrap()
+    }
+
+    fn sms_client(base_url: String) -> TwilioSmsClient {
+        let http_client = Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+
+        TwilioSmsClient::new(
+            base_url,
+            ACCOUNT_SID.to_owned(),
+            Secret::new("token".to_owned()),
+            phone_number("+15005550006"),
+            http_client,
+        )
+    }
+
+    #[tokio::test]
+    async fn send_sms_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+        let sms_client = sms_client(mock_server.uri());
+
+        Mock::given(method("POST"))
+            .and(path("/2010-04-01/Accounts/AC0123456789/Messages.json"))
+            .and(header("Authorization", BASIC_AUTH))
+            .and(header("Content-Type", "application/x-www-form-urlencoded"))
+            .and(body_string_contains("To=%2B14155550100"))
+            .and(body_string_contains("From=%2B15005550006"))
+            .and(body_string_contains("Body=Your+code+is+123456"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = sms_client
+            .send_sms(&phone_number("+14155550100"), "Your code is 123456")
+            .await;
+
+        assert!(outcome.is_ok());
+    }
+
+    #[tokio::test]
+    async fn call_reads_the_speech_out() {
+        let mock_server = MockServer::start().await;
+        let sms_client = sms_client(mock_server.uri());
+
+        Mock::given(method("POST"))
+            .and(path("/2010-04-01/Accounts/AC0123456789/Calls.json"))
+            .and(header("Authorization", BASIC_AUTH))
+            .and(body_string_contains("To=%2B14155550100"))
+            // "<Response><Say language="de">1 &amp; 2</Say></Response>"
+            .and(body_string_contains(
+                "Twiml=%3CResponse%3E%3CSay+language%3D%22de%22%3E1+%26amp%3B+2%3C%2FSay%3E%3C%2FResponse%3E",
+            ))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = sms_client
+            .call(&phone_number("+14155550100"), "1 & 2", "de")
+            .await;
+
+        assert!(outcome.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_sms_fails_if_the_server_rejects_it() {
+        let mock_server = MockServer::start().await;
+        let sms_client = sms_client(mock_server.uri());
+
+        // e.g. a number that cannot receive texts
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = sms_client
+            .send_sms(&phone_number("+14155550100"), "Your code is 123456")
+            .await;
+
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_sms_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let sms_client = sms_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(201).set_delay(Duration::from_secs(180)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = sms_client
+            .send_sms(&phone_number("+14155550100"), "Your code is 123456")
+            .await;
+
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    async fn health_check_fetches_the_account() {
+        let mock_server = MockServer::start().await;
+        let sms_client = sms_client(mock_server.uri());
+
+        Mock::given(method("GET"))
+            .and(path("/2010-04-01/Accounts/AC0123456789.json"))
+            .and(header("Authorization", BASIC_AUTH))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert!(sms_client.health_check().await.is_ok());
+    }
+}
@@ -0,0 +1,335 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use reqwest::{header::CONTENT_TYPE, Client};
+use secrecy::Secret;
+use sqlx::{types::Json, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    domain::webhook::WebhookDeliveryStatus,
+    utils::{
+        configuration::WebhookSettings, retry::RetryPolicy, secret_cipher::SecretCipher,
+        shutdown::ShutdownHandle,
+    },
+};
+
+use super::signature::{
+    sign_payload, WEBHOOK_ID_HEADER, WEBHOOK_SIGNATURE_HEADER, WEBHOOK_TIMESTAMP_HEADER,
+};
+
+// Delivers queued webhook events from the `webhook_outbox` table
+pub struct WebhookDispatcher {
+    pool: PgPool,
+    http_client: Client,
+    cipher: SecretCipher,
+    retry_policy: RetryPolicy,
+    batch_size: i64,
+    lease: Duration,
+    poll_interval: Duration,
+}
+
+// A claimed delivery; `attempts` includes the one about to be made
+#[derive(sqlx::FromRow)]
+struct DueDelivery {
+    id: Uuid,
+    claim_token: Uuid,
+    event_id: Uuid,
+    attempts: i32,
+    payload: Json<serde_json::Value>,
+    url: String,
+    secret: String,
+}
+
+impl WebhookDispatcher {
+    pub fn new(
+        pool: PgPool,
+        http_client: Client,
+        cipher: SecretCipher,
+        settings: &WebhookSettings,
+    ) -> Self {
+        Self {
+            pool,
+            http_client,
+            cipher,
+            retry_policy: RetryPolicy {
+                max_attempts: settings.max_attempts,
+                base_delay: Duration::from_millis(settings.base_delay_ms),
+                max_delay: Duration::from_secs(settings.max_delay_secs),
+            },
+            batch_size: settings.batch_size,
+            lease: Duration::from_secs(settings.lease_secs),
+            poll_interval: Duration::from_millis(settings.poll_interval_ms),
+        }
+    }
+
+    // Polls until `shutdown` is triggered; a batch in progress is always finished
+    pub async fn run(self, shutdown: ShutdownHandle) {
+        while !shutdown.is_shutting_down() {
+            match self.dispatch_due().await {
+                // keep draining a backlog without waiting
+                Ok(count) if count > 0 => continue,
+                Ok(_) => {}
+                Err(e) => tracing::error!(error = ?e, "Failed to dispatch webhooks"),
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.poll_interval) => {}
+                _ = shutdown.wait() => {}
+            }
+        }
+        tracing::info!("Webhook dispatcher stopped");
+    }
+
+    // Attempt up to `batch_size` due deliveries, returning how many were attempted.
+    // Each one is claimed on its own and the claim committed before sending, so no transaction
+    // stays open during HTTP calls and several dispatchers can run side by side. A delivery
+    // whose dispatcher dies, or that outlives its lease, is attempted again.
+    #[tracing::instrument(name = "Dispatching due webhooks", skip_all)]
+    pub async fn dispatch_due(&self) -> Result<usize> {
+        // retries scheduled during this pass wait for the next one
+        let due_by = Utc::now();
+        let mut attempted = 0;
+        while attempted < self.batch_size {
+            let Some(delivery) = self.claim_next(due_by).await? else {
+                break;
+            };
+            attempted += 1;
+            self.attempt(delivery).await?;
+        }
+
+        Ok(attempted.try_into().unwrap_or(usize::MAX))
+    }
+
+    async fn claim_next(&self, due_by: DateTime<Utc>) -> Result<Option<DueDelivery>> {
+        sqlx::query_as::<_, DueDelivery>(
+            r#"
+                WITH due AS (
+                    SELECT id
+                    FROM webhook_outbox
+                    WHERE status = $1 AND next_attempt_at <= $4
+                    ORDER BY next_attempt_at
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                UPDATE webhook_outbox o
+                SET attempts = o.attempts + 1,
+                    next_attempt_at = now() + make_interval(secs => $2),
+                    claim_token = $3
+                FROM due, webhook_subscriptions s
+                WHERE o.id = due.id AND s.id = o.subscription_id
+                RETURNING o.id, o.claim_token, o.event_id, o.attempts, o.payload, s.url, s.secret
+            "#,
+        )
+        .bind(WebhookDeliveryStatus::Pending.as_str())
+        .bind(self.lease.as_secs_f64())
+        .bind(Uuid::new_v4())
+        .bind(due_by)
+        .fetch_optional(&self.pool)
+        .await
+        .wrap_err("failed to claim due webhook delivery")
+    }
+
+    async fn attempt(&self, delivery: DueDelivery) -> Result<()> {
+        let body = serde_json::to_vec(&delivery.payload.0)
+            .wrap_err("failed to serialize webhook payload")?;
+
+        let outcome = match self.cipher.decrypt(&delivery.secret) {
+            Ok(secret) => {
+                deliver(
+                    &self.http_client,
+                    &delivery.url,
+                    &secret,
+                    delivery.event_id,
+                    body,
+                )
+                .await
+            }
+            Err(e) => Err(e.wrap_err("failed to decrypt webhook secret")),
+        };
+
+        let attempts = delivery.attempts;
+        let (status, last_error, next_attempt_at) = match outcome {
+            Ok(()) => (WebhookDeliveryStatus::Delivered, None, None),
+            Err(e) => {
+                let next_attempt_at = self
+                    .retry_policy
+                    .next_attempt_at(attempts.try_into().unwrap_or(u32::MAX));
+                let status = match next_attempt_at {
+                    Some(_) => WebhookDeliveryStatus::Pending,
+                    None => WebhookDeliveryStatus::Dead,
+                };
+                tracing::warn!(error = ?e, attempts, status = status.as_str(), "Webhook delivery failed");
+                (status, Some(format!("{:#}", e)), next_attempt_at)
+            }
+        };
+
+        let result = sqlx::query(
+            r#"
+                UPDATE webhook_outbox
+                SET status = $3,
+                    last_error = $4,
+                    next_attempt_at = COALESCE($5, next_attempt_at),
+                    delivered_at = CASE WHEN $3 = 'delivered' THEN now() ELSE NULL END,
+                    claim_token = NULL
+                WHERE id = $1 AND claim_token = $2
+            "#,
+        )
+        .bind(delivery.id)
+        .bind(delivery.claim_token)
+        .bind(status.as_str())
+        .bind(last_error)
+        .bind(next_attempt_at)
+        .execute(&self.pool)
+        .await
+        .wrap_err("failed to update webhook delivery")?;
+        if result.rows_affected() == 0 {
+            // the lease expired and another dispatcher claimed the delivery in the meantime
+            tracing::warn!(
+                delivery_id = %delivery.id,
+                "Webhook delivery outcome not recorded; its lease had expired"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// POST a signed payload to a subscriber; any non-2xx response is a failure
+#[tracing::instrument(name = "Delivering webhook", skip_all)]
+async fn deliver(
+    http_client: &Client,
+    url: &str,
+    secret: &Secret<String>,
+    event_id: Uuid,
+    body: Vec<u8>,
+) -> Result<()> {
+    let timestamp = Utc::now().timestamp();
+    let signature = sign_payload(secret, timestamp, &body);
+
+    http_client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(WEBHOOK_ID_HEADER, event_id.to_string())
+        .header(WEBHOOK_TIMESTAMP_HEADER, timestamp.to_string())
+        .header(WEBHOOK_SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{any, header_exists, method};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    use super::super::signature::verify_signature;
+    use super::*;
+
+    fn secret() -> Secret<String> {
+        Secret::new("whsec_test".to_owned())
+    }
+
+    fn http_client() -> Client {
+        Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap()
+    }
+
+    // Accepts only requests whose signature matches the body and timestamp
+    struct ValidSignatureMatcher;
+
+    impl wiremock::Match for ValidSignatureMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let header = |name: &str| {
+                request
+                    .headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned)
+            };
+
+            match (
+                header(WEBHOOK_TIMESTAMP_HEADER).and_then(|ts| ts.parse::<i64>().ok()),
+                header(WEBHOOK_SIGNATURE_HEADER),
+            ) {
+                (Some(timestamp), Some(signature)) => {
+                    verify_signature(&secret(), timestamp, &request.body, &signature)
+                }
+                _ => false,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_sends_a_signed_request() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(header_exists(WEBHOOK_ID_HEADER))
+            .and(ValidSignatureMatcher)
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = deliver(
+            &http_client(),
+            &mock_server.uri(),
+            &secret(),
+            Uuid::new_v4(),
+            br#"{"type":"user.signed_up"}"#.to_vec(),
+        )
+        .await;
+
+        assert!(outcome.is_ok());
+    }
+
+    #[tokio::test]
+    async fn deliver_fails_if_the_receiver_returns_500() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = deliver(
+            &http_client(),
+            &mock_server.uri(),
+            &secret(),
+            Uuid::new_v4(),
+            b"{}".to_vec(),
+        )
+        .await;
+
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    async fn deliver_times_out_if_the_receiver_takes_too_long() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(180)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = deliver(
+            &http_client(),
+            &mock_server.uri(),
+            &secret(),
+            Uuid::new_v4(),
+            b"{}".to_vec(),
+        )
+        .await;
+
+        assert!(outcome.is_err());
+    }
+}
@@ -0,0 +1,4 @@
+pub mod dispatcher;
+pub mod outbox;
+pub mod signature;
+pub mod subscriptions;
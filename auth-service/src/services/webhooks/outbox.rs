@@ -0,0 +1,30 @@
+use color_eyre::eyre::{Context, Result};
+use sqlx::{types::Json, PgExecutor};
+
+use crate::domain::webhook::WebhookEvent;
+
+// Queue `event` for every active subscription interested in it.
+// Pass the transaction that performs the user change, so the event is
+// committed or rolled back together with it.
+#[tracing::instrument(name = "Enqueuing webhook event", skip_all)]
+pub async fn enqueue_webhook_event<'e, E>(executor: E, event: &WebhookEvent) -> Result<u64>
+where
+    E: PgExecutor<'e>,
+{
+    let result = sqlx::query(
+        r#"
+            INSERT INTO webhook_outbox (id, subscription_id, event_id, event_type, payload)
+            SELECT gen_random_uuid(), id, $1, $2, $3
+            FROM webhook_subscriptions
+            WHERE active AND $2 = ANY(event_types)
+        "#,
+    )
+    .bind(event.id)
+    .bind(event.event_type.as_str())
+    .bind(Json(event))
+    .execute(executor)
+    .await
+    .wrap_err("failed to enqueue webhook event")?;
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,107 @@
+use color_eyre::eyre::{Context, Result};
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::webhook::{WebhookEventType, WebhookSubscription},
+    utils::secret_cipher::SecretCipher,
+};
+
+pub struct PostgresWebhookSubscriptionStore {
+    pool: PgPool,
+    cipher: SecretCipher,
+}
+
+#[derive(sqlx::FromRow)]
+struct WebhookSubscriptionRow {
+    id: Uuid,
+    url: String,
+    secret: String,
+    event_types: Vec<String>,
+    active: bool,
+}
+
+impl WebhookSubscriptionRow {
+    fn decrypt(self, cipher: &SecretCipher) -> Result<WebhookSubscription> {
+        Ok(WebhookSubscription {
+            id: self.id,
+            url: self.url,
+            secret: cipher
+                .decrypt(&self.secret)
+                .wrap_err("failed to decrypt webhook secret")?,
+            event_types: self
+                .event_types
+                .iter()
+                .map(|event_type| event_type.parse())
+                .collect::<Result<_>>()?,
+            active: self.active,
+        })
+    }
+}
+
+impl PostgresWebhookSubscriptionStore {
+    pub fn new(pool: PgPool, cipher: SecretCipher) -> Self {
+        Self { pool, cipher }
+    }
+
+    #[tracing::instrument(name = "Adding webhook subscription to PostgreSQL", skip_all)]
+    pub async fn add_subscription(
+        &self,
+        url: &str,
+        secret: Secret<String>,
+        event_types: &[WebhookEventType],
+    ) -> Result<WebhookSubscription> {
+        reqwest::Url::parse(url).wrap_err("invalid webhook url")?;
+
+        let event_types: Vec<String> = event_types
+            .iter()
+            .map(|event_type| event_type.as_str().to_owned())
+            .collect();
+
+        sqlx::query_as::<_, WebhookSubscriptionRow>(
+            r#"
+                INSERT INTO webhook_subscriptions (id, url, secret, event_types)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id, url, secret, event_types, active
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(url)
+        .bind(self.cipher.encrypt(&secret)?)
+        .bind(event_types)
+        .fetch_one(&self.pool)
+        .await
+        .wrap_err("failed to insert webhook subscription")?
+        .decrypt(&self.cipher)
+    }
+
+    #[tracing::instrument(name = "Listing webhook subscriptions from PostgreSQL", skip_all)]
+    pub async fn list_subscriptions(&self) -> Result<Vec<WebhookSubscription>> {
+        sqlx::query_as::<_, WebhookSubscriptionRow>(
+            r#"
+                SELECT id, url, secret, event_types, active
+                FROM webhook_subscriptions
+                ORDER BY created_at
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .wrap_err("failed to list webhook subscriptions")?
+        .into_iter()
+        .map(|row| row.decrypt(&self.cipher))
+        .collect()
+    }
+
+    // Stops new events from being queued; already queued deliveries are still attempted
+    #[tracing::instrument(name = "Deactivating webhook subscription in PostgreSQL", skip_all)]
+    pub async fn deactivate_subscription(&self, id: Uuid) -> Result<()> {
+        sqlx::query("UPDATE webhook_subscriptions SET active = FALSE WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to deactivate webhook subscription")?;
+
+        Ok(())
+    }
+}
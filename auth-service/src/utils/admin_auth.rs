@@ -0,0 +1,91 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    app_state::AppState,
+    domain::AuthAPIError,
+    utils::{auth_middleware::bearer_token, keyed_hash::KeyedHasher},
+};
+
+const OPERATOR_TOKEN_KIND: &str = "operator_token";
+
+// `admin.token`; `None` rejects every request
+#[derive(Clone)]
+pub struct AdminToken(Option<Secret<String>>);
+
+impl FromRef<AppState> for AdminToken {
+    fn from_ref(state: &AppState) -> Self {
+        AdminToken(state.settings.admin.token.clone())
+    }
+}
+
+// `telemetry.metrics_token`; `None` rejects every request
+#[derive(Clone)]
+pub struct MetricsToken(Option<Secret<String>>);
+
+impl FromRef<AppState> for MetricsToken {
+    fn from_ref(state: &AppState) -> Self {
+        MetricsToken(state.settings.telemetry.metrics_token.clone())
+    }
+}
+
+// An operator presenting `admin.token` as `Authorization: Bearer <token>`.
+// Use it as a handler argument to protect an /admin route.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminAuthorized;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminAuthorized
+where
+    AdminToken: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let AdminToken(expected) = AdminToken::from_ref(state);
+        check_operator_token(&parts.headers, expected.as_ref())?;
+
+        Ok(AdminAuthorized)
+    }
+}
+
+// A metrics scraper presenting `telemetry.metrics_token` as `Authorization: Bearer <token>`
+#[derive(Debug, Clone, Copy)]
+pub struct MetricsAuthorized;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for MetricsAuthorized
+where
+    MetricsToken: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let MetricsToken(expected) = MetricsToken::from_ref(state);
+        check_operator_token(&parts.headers, expected.as_ref())?;
+
+        Ok(MetricsAuthorized)
+    }
+}
+
+fn check_operator_token(
+    headers: &HeaderMap,
+    expected: Option<&Secret<String>>,
+) -> Result<(), AuthAPIError> {
+    let candidate = bearer_token(headers).ok_or(AuthAPIError::MissingToken)?;
+    let expected = expected.ok_or(AuthAPIError::InvalidToken)?;
+
+    // compare MACs in constant time, so response times don't leak the token
+    let hasher = KeyedHasher::new(expected.clone());
+    let expected_hash = hasher.hash(OPERATOR_TOKEN_KIND, expected.expose_secret());
+    match hasher.verify(OPERATOR_TOKEN_KIND, candidate, &expected_hash) {
+        true => Ok(()),
+        false => Err(AuthAPIError::InvalidToken),
+    }
+}
@@ -5,27 +5,54 @@ use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 
 use crate::{app_state::TokenStoreType, domain::email::Email};
-use color_eyre::eyre::{eyre, Context, ContextCompat, Result};
+use color_eyre::eyre::{eyre, Context, ContextCompat, Report, Result};
+use thiserror::Error;
 
-use super::configuration::{get_jwt_seret, JWT_COOKIE_NAME};
+use super::configuration::{AuthCookieSettings, AuthSettings, SameSiteMode, Settings};
 
 // Create cookie with a new JWT auth token
 #[tracing::instrument(name = "Generating auth cookie", skip_all)]
-pub fn generate_auth_cookie(email: &Email) -> Result<Cookie<'static>> {
-    let token = generate_auth_token(email)?;
-    Ok(create_auth_cookie(token))
+pub fn generate_auth_cookie(email: &Email, settings: &Settings) -> Result<Cookie<'static>> {
+    let token = generate_auth_token(email, settings.auth.token_ttl_seconds, &settings.jwt_secret)?;
+    create_auth_cookie(token, &settings.auth)
 }
 
-// Create cookie and set the value to the passed-in token string
+// Create cookie and set the value to the passed-in token string.
+// It expires with the token, so browsers do not keep sending a dead one.
 #[tracing::instrument(name = "Creating auth cookie", skip_all)]
-fn create_auth_cookie(token: String) -> Cookie<'static> {
-    let cookie = Cookie::build((JWT_COOKIE_NAME, token))
-        .path("/") // apply cookie to all URLs on the server
-        .http_only(true) // prevent JavaScript from accessing the cookie
-        .same_site(SameSite::Lax) // Send cookie with "same-site" requests, and with "cross-site" top-level navigations
-        .build();
+fn create_auth_cookie(token: String, settings: &AuthSettings) -> Result<Cookie<'static>> {
+    let max_age: i64 = settings
+        .token_ttl_seconds
+        .try_into()
+        .wrap_err("token TTL does not fit in i64")?;
+
+    let mut cookie = build_auth_cookie(&settings.cookie, token);
+    cookie.set_max_age(time::Duration::seconds(max_age));
+
+    Ok(cookie)
+}
 
-    cookie
+// Pass to `CookieJar::remove`. Browsers only drop a cookie when the removal has the same
+// name, path and domain, and reject it without `Secure` when SameSite is None.
+pub fn removal_auth_cookie(settings: &AuthCookieSettings) -> Cookie<'static> {
+    build_auth_cookie(settings, String::new())
+}
+
+fn build_auth_cookie(settings: &AuthCookieSettings, value: String) -> Cookie<'static> {
+    let builder = Cookie::build((settings.cookie_name(), value))
+        .path(settings.path.clone())
+        .http_only(true) // prevent JavaScript from accessing the cookie
+        .secure(settings.secure)
+        .same_site(match settings.same_site {
+            SameSiteMode::Strict => SameSite::Strict,
+            SameSiteMode::Lax => SameSite::Lax,
+            SameSiteMode::None => SameSite::None,
+        });
+
+    match &settings.domain {
+        Some(domain) => builder.domain(domain.clone()).build(),
+        None => builder.build(),
+    }
 }
 
 #[derive(Debug)]
@@ -34,19 +61,23 @@ pub enum GenerateTokenError {
     UnexpectedError,
 }
 
-// determines how long the JWT auth token is valid for
-pub const TOKEN_TTL_SECONDS: i64 = 600; // 10 min
-
-// Create JWT auth token
+// Create JWT auth token, valid for `ttl_seconds`
 #[tracing::instrument(name = "Generating auth token", skip_all)]
-fn generate_auth_token(email: &Email) -> Result<String> {
-    let delta = chrono::Duration::try_seconds(TOKEN_TTL_SECONDS)
-        .wrap_err("failed to create 10 minute time delta")?;
+fn generate_auth_token(
+    email: &Email,
+    ttl_seconds: u64,
+    jwt_secret: &Secret<String>,
+) -> Result<String> {
+    let ttl_seconds: i64 = ttl_seconds
+        .try_into()
+        .wrap_err("token TTL does not fit in i64")?;
+    let delta = chrono::Duration::try_seconds(ttl_seconds)
+        .wrap_err("failed to create token TTL time delta")?;
 
     // Create JWT expiration time
     let exp = Utc::now()
         .checked_add_signed(delta)
-        .ok_or(eyre!("failed to add 10 minutes to current time"))?
+        .ok_or(eyre!("failed to add token TTL to current time"))?
         .timestamp();
 
     let exp: usize = exp.try_into().wrap_err(format!(
@@ -57,37 +88,51 @@ fn generate_auth_token(email: &Email) -> Result<String> {
 
     let claims = Claims { sub, exp };
 
-    create_token(&claims)
+    create_token(&claims, jwt_secret)
+}
+
+#[derive(Debug, Error)]
+pub enum ValidateTokenError {
+    // malformed, expired, signed with another secret or banned
+    #[error("Invalid token")]
+    InvalidToken(#[source] Report),
+    // the banned token store failed, so whether the token is banned is unknown
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
 }
 
 // Check if JWT auth token is valid by decoding it using the JWT secret
 #[tracing::instrument(name = "Validating JWT auth token", skip_all)]
-pub async fn validate_token(token_store: TokenStoreType, token: Secret<String>) -> Result<Claims> {
-    let jwt_secret = get_jwt_seret();
-    let banned_token_store = token_store.read().await;
-
-    match banned_token_store.contains_token(token.to_owned()).await {
-        Ok(value) => {
-            if value {
-                return Err(eyre!("token is banned"));
-            }
-        }
-        Err(e) => return Err(e.into()),
-    }
-
-    decode(
+pub async fn validate_token(
+    token_store: TokenStoreType,
+    token: Secret<String>,
+    jwt_secret: &Secret<String>,
+) -> Result<Claims, ValidateTokenError> {
+    let claims = decode(
         token.expose_secret(),
         &DecodingKey::from_secret(jwt_secret.expose_secret().as_bytes()),
         &Validation::default(),
     )
     .map(|data| data.claims)
     .wrap_err("failed to decode token")
+    .map_err(ValidateTokenError::InvalidToken)?;
+
+    let banned = token_store
+        .read()
+        .await
+        .contains_token(token)
+        .await
+        .map_err(|e| ValidateTokenError::UnexpectedError(e.into()))?;
+    if banned {
+        return Err(ValidateTokenError::InvalidToken(eyre!("token is banned")));
+    }
+
+    Ok(claims)
 }
 
 // Create JWT auth token by encoding claims using the JWT secret
 #[tracing::instrument(name = "Creating JWT auth token", skip_all)]
-fn create_token(claims: &Claims) -> Result<String> {
-    let jwt_secret = get_jwt_seret();
+fn create_token(claims: &Claims, jwt_secret: &Secret<String>) -> Result<String> {
     encode(
         &jsonwebtoken::Header::default(),
         &claims,
@@ -96,7 +141,7 @@ fn create_token(claims: &Claims) -> Result<String> {
     .wrap_err("failed to create token")
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
@@ -0,0 +1,202 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, Request},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::CookieJar;
+use secrecy::Secret;
+use tower::{Layer, Service};
+
+use crate::{
+    app_state::{AppState, TokenStoreType},
+    domain::{AuthAPIError, Email},
+    utils::auth::{validate_token, Claims, ValidateTokenError},
+};
+
+const BEARER_SCHEME: &str = "Bearer";
+
+// Everything needed to authenticate a request, independent of the rest of `AppState`.
+// Services embedding `auth_service` as a library can build one from their own token store.
+#[derive(Clone)]
+pub struct AuthVerifier {
+    token_store: TokenStoreType,
+    jwt_secret: Secret<String>,
+    cookie_name: String,
+}
+
+impl AuthVerifier {
+    pub fn new(
+        token_store: TokenStoreType,
+        jwt_secret: Secret<String>,
+        cookie_name: String,
+    ) -> Self {
+        Self {
+            token_store,
+            jwt_secret,
+            cookie_name,
+        }
+    }
+
+    // Authenticate a request from its headers.
+    // The `Authorization: Bearer` header takes precedence over the JWT cookie.
+    #[tracing::instrument(name = "Authenticating request", skip_all)]
+    pub async fn authenticate(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<AuthenticatedUser, AuthAPIError> {
+        let token = extract_token(headers, &self.cookie_name).ok_or(AuthAPIError::MissingToken)?;
+
+        let claims = validate_token(self.token_store.clone(), token.clone(), &self.jwt_secret)
+            .await
+            .map_err(|e| match e {
+                ValidateTokenError::InvalidToken(_) => AuthAPIError::InvalidToken,
+                // a store outage must not look like every session ended
+                ValidateTokenError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+            })?;
+
+        let email = Email::parse(Secret::new(claims.sub.clone()))
+            .map_err(|_| AuthAPIError::InvalidToken)?;
+
+        Ok(AuthenticatedUser {
+            email,
+            claims,
+            token,
+        })
+    }
+}
+
+impl FromRef<AppState> for AuthVerifier {
+    fn from_ref(state: &AppState) -> Self {
+        AuthVerifier::new(
+            state.token_store.clone(),
+            state.settings.jwt_secret.clone(),
+            state.settings.auth.cookie.cookie_name(),
+        )
+    }
+}
+
+// The token from a non-empty `Authorization: Bearer` header; the scheme is case-insensitive
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case(BEARER_SCHEME))
+        .map(|(_, token)| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+fn extract_token(headers: &HeaderMap, cookie_name: &str) -> Option<Secret<String>> {
+    if let Some(token) = bearer_token(headers) {
+        return Some(Secret::new(token.to_owned()));
+    }
+
+    CookieJar::from_headers(headers)
+        .get(cookie_name)
+        .map(|cookie| Secret::new(cookie.value().to_owned()))
+}
+
+// The user behind a valid, non-banned JWT.
+// Use it as a handler argument to protect a route:
+//
+//     async fn handler(user: AuthenticatedUser) -> impl IntoResponse { ... }
+//
+// If `RequireAuthLayer` already authenticated the request, the stored value is reused.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub email: Email,
+    pub claims: Claims,
+    pub token: Secret<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthenticatedUser
+where
+    AuthVerifier: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<AuthenticatedUser>() {
+            return Ok(user.clone());
+        }
+
+        let user = AuthVerifier::from_ref(state)
+            .authenticate(&parts.headers)
+            .await?;
+        parts.extensions.insert(user.clone());
+
+        Ok(user)
+    }
+}
+
+// Tower layer rejecting unauthenticated requests before they reach the wrapped service.
+// On success the `AuthenticatedUser` is stored in the request extensions, so handlers can
+// read it with `Extension<AuthenticatedUser>` or the `AuthenticatedUser` extractor.
+#[derive(Clone)]
+pub struct RequireAuthLayer {
+    verifier: AuthVerifier,
+}
+
+impl RequireAuthLayer {
+    pub fn new(verifier: AuthVerifier) -> Self {
+        Self { verifier }
+    }
+}
+
+impl<S> Layer<S> for RequireAuthLayer {
+    type Service = RequireAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireAuth {
+            inner,
+            verifier: self.verifier.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequireAuth<S> {
+    inner: S,
+    verifier: AuthVerifier,
+}
+
+impl<S> Service<Request<Body>> for RequireAuth<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        // the clone is not guaranteed to be ready, so keep the instance `poll_ready` was called on
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let verifier = self.verifier.clone();
+
+        Box::pin(async move {
+            match verifier.authenticate(request.headers()).await {
+                Ok(user) => {
+                    request.extensions_mut().insert(user);
+                    inner.call(request).await
+                }
+                Err(e) => Ok(e.into_response()),
+            }
+        })
+    }
+}
@@ -0,0 +1,268 @@
+use std::{str::FromStr, time::Duration};
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use super::{configuration::CorsSettings, request_context::REQUEST_ID_HEADER};
+
+const WILDCARD: &str = "*";
+const SUBDOMAIN_WILDCARD: &str = "*.";
+
+// An allowed origin: either exact ("https://app.example.com") or any subdomain
+// ("https://*.example.com", which does not match "https://example.com" itself)
+#[derive(Debug, Clone, PartialEq)]
+pub enum OriginPattern {
+    Exact(String),
+    Subdomain {
+        scheme: String,
+        // ".example.com"
+        domain_suffix: String,
+        // ":8443", or empty for the default port
+        port: String,
+    },
+}
+
+impl FromStr for OriginPattern {
+    type Err = String;
+
+    // Browsers send `scheme://host[:port]`, so anything else would never match
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, authority) = s
+            .split_once("://")
+            .filter(|(scheme, _)| matches!(*scheme, "http" | "https"))
+            .ok_or("must start with http:// or https://")?;
+        if authority.is_empty() || authority.contains('/') {
+            return Err("must be scheme://host[:port] without a path".to_owned());
+        }
+        HeaderValue::from_str(s).map_err(|_| "is not a valid header value")?;
+
+        let Some(domain) = authority.strip_prefix(SUBDOMAIN_WILDCARD) else {
+            if authority.contains('*') {
+                return Err(
+                    "may only use a wildcard as the leftmost label, e.g. https://*.example.com"
+                        .to_owned(),
+                );
+            }
+            return Ok(OriginPattern::Exact(s.to_lowercase()));
+        };
+
+        let (host, port) = match domain.rsplit_once(':') {
+            Some((host, port)) => (host, format!(":{}", port)),
+            None => (domain, String::new()),
+        };
+        // "*.com" would allow any site; "localhost" is the one single-label host worth allowing
+        if host.contains('*') || !(host.contains('.') || host == "localhost") {
+            return Err(
+                "must wildcard the subdomains of a registrable domain, e.g. https://*.example.com"
+                    .to_owned(),
+            );
+        }
+
+        Ok(OriginPattern::Subdomain {
+            scheme: scheme.to_owned(),
+            domain_suffix: format!(".{}", host.to_lowercase()),
+            port,
+        })
+    }
+}
+
+impl OriginPattern {
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_lowercase();
+        match self {
+            OriginPattern::Exact(allowed) => *allowed == origin,
+            OriginPattern::Subdomain {
+                scheme,
+                domain_suffix,
+                port,
+            } => {
+                let Some(authority) = origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                else {
+                    return false;
+                };
+                let Some(host) = authority.strip_suffix(port.as_str()) else {
+                    return false;
+                };
+                // the port must match exactly, so "a.example.com:8080" is not "a.example.com"
+                if host.contains(':') {
+                    return false;
+                }
+                match host.strip_suffix(domain_suffix.as_str()) {
+                    Some(subdomain) => {
+                        !subdomain.is_empty()
+                            && subdomain.split('.').all(|label| {
+                                !label.is_empty()
+                                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                            })
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+// Problems with `settings` that would make the layer reject everything, or panic when built
+pub fn validate_cors_settings(settings: &CorsSettings) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for origin in &settings.allowed_origins {
+        if origin == WILDCARD {
+            continue;
+        }
+        if let Err(reason) = origin.parse::<OriginPattern>() {
+            problems.push(format!("cors.allowed_origins: {:?} {}", origin, reason));
+        }
+    }
+    for method in &settings.allowed_methods {
+        if method != WILDCARD && Method::from_bytes(method.as_bytes()).is_err() {
+            problems.push(format!(
+                "cors.allowed_methods: {:?} is not a valid method",
+                method
+            ));
+        }
+    }
+    for header in &settings.allowed_headers {
+        if header != WILDCARD && HeaderName::from_str(header).is_err() {
+            problems.push(format!(
+                "cors.allowed_headers: {:?} is not a valid header name",
+                header
+            ));
+        }
+    }
+
+    if settings.allow_credentials {
+        for (key, values) in [
+            ("allowed_origins", &settings.allowed_origins),
+            ("allowed_methods", &settings.allowed_methods),
+            ("allowed_headers", &settings.allowed_headers),
+        ] {
+            if values.iter().any(|value| value == WILDCARD) {
+                problems.push(format!(
+                    "cors.{} cannot be \"*\" when cors.allow_credentials is true",
+                    key
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+// Expects settings that passed `validate_cors_settings`; invalid entries are skipped
+pub fn cors_layer(settings: &CorsSettings) -> CorsLayer {
+    let allow_origin = if settings.allowed_origins.iter().any(|o| o == WILDCARD) {
+        AllowOrigin::any()
+    } else {
+        let patterns: Vec<OriginPattern> = settings
+            .allowed_origins
+            .iter()
+            .filter_map(|origin| origin.parse().ok())
+            .collect();
+        AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            origin
+                .to_str()
+                .map(|origin| patterns.iter().any(|pattern| pattern.matches(origin)))
+                .unwrap_or(false)
+        })
+    };
+
+    let allow_methods = if settings.allowed_methods.iter().any(|m| m == WILDCARD) {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(
+            settings
+                .allowed_methods
+                .iter()
+                .filter_map(|method| Method::from_bytes(method.as_bytes()).ok()),
+        )
+    };
+
+    let allow_headers = if settings.allowed_headers.iter().any(|h| h == WILDCARD) {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(
+            settings
+                .allowed_headers
+                .iter()
+                .filter_map(|header| HeaderName::from_str(header).ok()),
+        )
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers)
+        .allow_credentials(settings.allow_credentials)
+        .max_age(Duration::from_secs(settings.max_age_secs))
+        // let the frontend read the ids it needs to correlate logs and traces
+        .expose_headers([
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static("traceparent"),
+            HeaderName::from_static("tracestate"),
+        ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_origin() {
+        let pattern: OriginPattern = "https://App.example.com".parse().unwrap();
+
+        assert!(pattern.matches("https://app.example.com"));
+        assert!(!pattern.matches("http://app.example.com"));
+        assert!(!pattern.matches("https://app.example.com:8443"));
+        assert!(!pattern.matches("https://evil.app.example.com"));
+    }
+
+    #[test]
+    fn test_subdomain_wildcard() {
+        let pattern: OriginPattern = "https://*.example.com".parse().unwrap();
+
+        assert!(pattern.matches("https://app.example.com"));
+        assert!(pattern.matches("https://eu.app.example.com"));
+        assert!(!pattern.matches("https://example.com"));
+        assert!(!pattern.matches("https://evilexample.com"));
+        assert!(!pattern.matches("https://app.example.com.evil.com"));
+        assert!(!pattern.matches("http://app.example.com"));
+        assert!(!pattern.matches("https://app.example.com:8443"));
+
+        let pattern: OriginPattern = "http://*.localhost:3000".parse().unwrap();
+        assert!(pattern.matches("http://app.localhost:3000"));
+        assert!(!pattern.matches("http://app.localhost"));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        for pattern in [
+            "example.com",
+            "ftp://example.com",
+            "https://example.com/",
+            "https://app.*.example.com",
+            "https://*.com",
+            "https://*",
+        ] {
+            assert!(pattern.parse::<OriginPattern>().is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_wildcards_are_rejected_with_credentials() {
+        let mut settings = CorsSettings {
+            allowed_origins: vec!["*".to_owned()],
+            allowed_methods: vec!["GET".to_owned(), "NOT A METHOD".to_owned()],
+            ..CorsSettings::default()
+        };
+        assert_eq!(validate_cors_settings(&settings).len(), 2);
+
+        settings.allow_credentials = false;
+        settings.allowed_methods = vec!["*".to_owned()];
+        assert!(validate_cors_settings(&settings).is_empty());
+        // builds without panicking
+        let _ = cors_layer(&settings);
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use rand::Rng;
+use tower::{Layer, Service};
+
+use crate::domain::AuthAPIError;
+
+use super::{auth_middleware::bearer_token, configuration::CsrfSettings};
+
+const TOKEN_BYTES: usize = 32;
+
+pub fn generate_csrf_token() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; TOKEN_BYTES]>())
+}
+
+pub fn is_well_formed(token: &str) -> bool {
+    token.len() == TOKEN_BYTES * 2 && token.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// HttpOnly, as the frontend reads the token from the `/csrf-token` response instead.
+// `secure` follows `auth.cookie.secure`, so both cookies travel over the same schemes.
+pub fn create_csrf_cookie(settings: &CsrfSettings, secure: bool, token: String) -> Cookie<'static> {
+    Cookie::build((settings.cookie_name.clone(), token))
+        .path("/")
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Strict)
+        .build()
+}
+
+// Double-submit check: state-changing requests that carry the JWT cookie, and requests to
+// `session_paths` (login and signup), must echo the CSRF cookie in the CSRF header.
+// Another site can make the browser send the cookies, but cannot read them to set the header.
+// Requests with an `Authorization: Bearer` header are exempt, as browsers never add it on their own.
+#[derive(Clone)]
+pub struct CsrfLayer {
+    settings: Arc<CsrfSettings>,
+    auth_cookie_name: Arc<str>,
+}
+
+impl CsrfLayer {
+    pub fn new(settings: CsrfSettings, auth_cookie_name: String) -> Self {
+        Self {
+            settings: Arc::new(settings),
+            auth_cookie_name: auth_cookie_name.into(),
+        }
+    }
+}
+
+impl<S> Layer<S> for CsrfLayer {
+    type Service = CsrfProtection<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CsrfProtection {
+            inner,
+            settings: self.settings.clone(),
+            auth_cookie_name: self.auth_cookie_name.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CsrfProtection<S> {
+    inner: S,
+    settings: Arc<CsrfSettings>,
+    auth_cookie_name: Arc<str>,
+}
+
+impl<S> Service<Request<Body>> for CsrfProtection<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // the clone is not guaranteed to be ready, so keep the instance `poll_ready` was called on
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let rejected = requires_check(&self.settings, &self.auth_cookie_name, &request)
+            && !has_valid_token(&self.settings, request.headers());
+
+        Box::pin(async move {
+            if rejected {
+                tracing::warn!("Rejecting cookie-authenticated request without a valid CSRF token");
+                return Ok(AuthAPIError::InvalidCsrfToken.into_response());
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+fn requires_check(
+    settings: &CsrfSettings,
+    auth_cookie_name: &str,
+    request: &Request<Body>,
+) -> bool {
+    let headers = request.headers();
+
+    settings.enabled
+        && !request.method().is_safe()
+        && !settings
+            .exempt_paths
+            .iter()
+            .any(|path| path == request.uri().path())
+        && bearer_token(headers).is_none()
+        && (settings
+            .session_paths
+            .iter()
+            .any(|path| path == request.uri().path())
+            || CookieJar::from_headers(headers)
+                .get(auth_cookie_name)
+                .is_some())
+}
+
+fn has_valid_token(settings: &CsrfSettings, headers: &HeaderMap) -> bool {
+    let jar = CookieJar::from_headers(headers);
+    let Some(cookie) = jar.get(&settings.cookie_name) else {
+        return false;
+    };
+    let Some(header) = headers
+        .get(settings.header_name.as_str())
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    is_well_formed(cookie.value()) && constant_time_eq(cookie.value().as_bytes(), header.as_bytes())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::utils::constants::JWT_COOKIE_NAME;
+
+    fn router(settings: CsrfSettings) -> Router {
+        Router::new()
+            .route("/logout", post(|| async { "ok" }))
+            .route("/login", post(|| async { "ok" }))
+            .route("/session", get(|| async { "ok" }))
+            .route("/webhooks/incoming", post(|| async { "ok" }))
+            .layer(CsrfLayer::new(settings, JWT_COOKIE_NAME.to_owned()))
+    }
+
+    async fn status(settings: CsrfSettings, request: Request<Body>) -> u16 {
+        router(settings)
+            .oneshot(request)
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+
+    fn cookie_request(method: &str, path: &str, csrf_cookie: &str) -> axum::http::request::Builder {
+        Request::builder().method(method).uri(path).header(
+            "Cookie",
+            format!("{}=token; csrf_token={}", JWT_COOKIE_NAME, csrf_cookie),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_cookie_authenticated_post_requires_matching_token() {
+        let token = generate_csrf_token();
+        let other_token = generate_csrf_token();
+
+        let missing = cookie_request("POST", "/logout", &token).body(Body::empty());
+        assert_eq!(status(CsrfSettings::default(), missing.unwrap()).await, 403);
+
+        let mismatched = cookie_request("POST", "/logout", &token)
+            .header("x-csrf-token", &other_token)
+            .body(Body::empty());
+        assert_eq!(
+            status(CsrfSettings::default(), mismatched.unwrap()).await,
+            403
+        );
+
+        let matching = cookie_request("POST", "/logout", &token)
+            .header("x-csrf-token", &token)
+            .body(Body::empty());
+        assert_eq!(
+            status(CsrfSettings::default(), matching.unwrap()).await,
+            200
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exempt_requests() {
+        let token = generate_csrf_token();
+
+        let safe_method = cookie_request("GET", "/session", &token).body(Body::empty());
+        assert_eq!(
+            status(CsrfSettings::default(), safe_method.unwrap()).await,
+            200
+        );
+
+        let bearer = cookie_request("POST", "/logout", &token)
+            .header("Authorization", "Bearer token")
+            .body(Body::empty());
+        assert_eq!(status(CsrfSettings::default(), bearer.unwrap()).await, 200);
+
+        let without_jwt_cookie = Request::post("/logout").body(Body::empty());
+        assert_eq!(
+            status(CsrfSettings::default(), without_jwt_cookie.unwrap()).await,
+            200
+        );
+
+        let settings = CsrfSettings {
+            exempt_paths: vec!["/webhooks/incoming".to_owned()],
+            ..CsrfSettings::default()
+        };
+        let exempt_path = cookie_request("POST", "/webhooks/incoming", &token).body(Body::empty());
+        assert_eq!(status(settings, exempt_path.unwrap()).await, 200);
+
+        let settings = CsrfSettings {
+            enabled: false,
+            ..CsrfSettings::default()
+        };
+        let disabled = cookie_request("POST", "/logout", &token).body(Body::empty());
+        assert_eq!(status(settings, disabled.unwrap()).await, 200);
+    }
+
+    #[tokio::test]
+    async fn test_session_paths_require_a_token_without_jwt_cookie() {
+        let token = generate_csrf_token();
+
+        let missing = Request::post("/login")
+            .header("Cookie", format!("csrf_token={}", token))
+            .body(Body::empty());
+        assert_eq!(status(CsrfSettings::default(), missing.unwrap()).await, 403);
+
+        let matching = Request::post("/login")
+            .header("Cookie", format!("csrf_token={}", token))
+            .header("x-csrf-token", &token)
+            .body(Body::empty());
+        assert_eq!(
+            status(CsrfSettings::default(), matching.unwrap()).await,
+            200
+        );
+    }
+
+    #[test]
+    fn test_cookie_is_secure_when_the_auth_cookie_is() {
+        let token = generate_csrf_token();
+
+        assert_eq!(
+            create_csrf_cookie(&CsrfSettings::default(), true, token.clone()).secure(),
+            Some(true)
+        );
+        assert_eq!(
+            create_csrf_cookie(&CsrfSettings::default(), false, token).secure(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_malformed_tokens_are_rejected() {
+        assert!(is_well_formed(&generate_csrf_token()));
+        assert!(!is_well_formed(""));
+        assert!(!is_well_formed(&"z".repeat(TOKEN_BYTES * 2)));
+    }
+}
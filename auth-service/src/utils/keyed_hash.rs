@@ -0,0 +1,100 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+use super::configuration::Settings;
+
+// HMAC-SHA256 of the secrets the stores look up, so someone who can read Redis or Postgres
+// can't use what they find there
+#[derive(Clone)]
+pub struct KeyedHasher {
+    key: Secret<String>,
+}
+
+impl KeyedHasher {
+    pub fn new(key: Secret<String>) -> Self {
+        Self { key }
+    }
+
+    // `stores.hmac_secret`, or a key derived from `jwt_secret` when it is unset
+    pub fn from_settings(settings: &Settings) -> Self {
+        match &settings.stores.hmac_secret {
+            Some(secret) => Self::new(secret.clone()),
+            None => {
+                let derived = Self::new(settings.jwt_secret.clone()).hash("store_key", "v1");
+                Self::new(Secret::new(derived))
+            }
+        }
+    }
+
+    // Hex encoded; `kind` keeps equal values of different kinds from hashing alike
+    pub fn hash(&self, kind: &str, value: &str) -> String {
+        hex::encode(self.mac(kind, value).finalize().into_bytes())
+    }
+
+    // Constant-time check of `value` against a stored hash
+    pub fn verify(&self, kind: &str, value: &str, hash: &str) -> bool {
+        let Ok(hash) = hex::decode(hash) else {
+            return false;
+        };
+        self.mac(kind, value).verify_slice(&hash).is_ok()
+    }
+
+    fn mac(&self, kind: &str, value: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(kind.as_bytes());
+        // not part of any kind, so "a" + "b:c" and "a:b" + "c" differ
+        mac.update(b"\0");
+        mac.update(value.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hasher(key: &str) -> KeyedHasher {
+        KeyedHasher::new(Secret::new(key.to_owned()))
+    }
+
+    #[test]
+    fn test_hash_depends_on_key_kind_and_value() {
+        let hash = hasher("key").hash("token", "value");
+
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("value"));
+        assert_eq!(hash, hasher("key").hash("token", "value"));
+        assert_ne!(hash, hasher("other").hash("token", "value"));
+        assert_ne!(hash, hasher("key").hash("code", "value"));
+        assert_ne!(hash, hasher("key").hash("token", "other"));
+    }
+
+    #[test]
+    fn test_verify() {
+        let hasher = hasher("key");
+        let hash = hasher.hash("code", "123456");
+
+        assert!(hasher.verify("code", "123456", &hash));
+        assert!(!hasher.verify("code", "654321", &hash));
+        assert!(!hasher.verify("token", "123456", &hash));
+        assert!(!hasher.verify("code", "123456", "not hex"));
+    }
+
+    #[test]
+    fn test_key_is_derived_from_the_jwt_secret_when_unset() {
+        let mut settings = Settings {
+            jwt_secret: Secret::new("jwt".to_owned()),
+            ..Default::default()
+        };
+        let derived = KeyedHasher::from_settings(&settings).hash("token", "value");
+
+        assert_ne!(derived, hasher("jwt").hash("token", "value"));
+        settings.stores.hmac_secret = Some(Secret::new("hmac".to_owned()));
+        assert_eq!(
+            KeyedHasher::from_settings(&settings).hash("token", "value"),
+            hasher("hmac").hash("token", "value")
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use super::admin_auth::MetricsAuthorized;
+
+pub const HTTP_REQUESTS_TOTAL: &str = "http_requests_total";
+pub const HTTP_REQUEST_DURATION_SECONDS: &str = "http_request_duration_seconds";
+pub const SIGNUPS_TOTAL: &str = "auth_signups_total";
+pub const LOGINS_TOTAL: &str = "auth_logins_total";
+pub const TWO_FA_CODES_SENT_TOTAL: &str = "auth_2fa_codes_sent_total";
+pub const TWO_FA_VERIFICATIONS_TOTAL: &str = "auth_2fa_verifications_total";
+pub const TOKENS_REVOKED_TOTAL: &str = "auth_tokens_revoked_total";
+pub const PASSWORD_HASH_DURATION_SECONDS: &str = "auth_password_hash_duration_seconds";
+pub const STORE_CALL_DURATION_SECONDS: &str = "auth_store_call_duration_seconds";
+pub const EMAILS_SENT_TOTAL: &str = "auth_emails_sent_total";
+pub const SMS_SENT_TOTAL: &str = "auth_sms_sent_total";
+pub const EMAILS_QUEUED_TOTAL: &str = "auth_emails_queued_total";
+pub const EMAIL_DELIVERIES_TOTAL: &str = "auth_email_deliveries_total";
+pub const EMAIL_OUTBOX_PENDING: &str = "auth_email_outbox_pending";
+pub const EMAIL_OUTBOX_DEAD: &str = "auth_email_outbox_dead";
+pub const EMAIL_OUTBOX_OLDEST_PENDING_SECONDS: &str = "auth_email_outbox_oldest_pending_seconds";
+
+// seconds; wide enough to cover both store round trips and Argon2 hashing
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static PROMETHEUS_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+// Installs the global Prometheus recorder on first use.
+// Later calls (e.g. every TestApp in one test binary) share the same recorder.
+pub fn prometheus_handle() -> PrometheusHandle {
+    PROMETHEUS_HANDLE
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Suffix("_duration_seconds".to_owned()),
+                    DURATION_BUCKETS,
+                )
+                .expect("duration buckets are not empty")
+                .install_recorder()
+                .expect("failed to install Prometheus recorder")
+        })
+        .clone()
+}
+
+// GET /metrics; only routed when `telemetry.metrics_token` is set
+pub async fn metrics_handler(_scraper: MetricsAuthorized) -> impl IntoResponse {
+    prometheus_handle().render()
+}
+
+// Records count and latency per route template (e.g. "/verify-2fa"), method and status.
+// Requests that match no route are grouped under "unmatched" to keep label cardinality bounded.
+pub async fn track_http_metrics(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_owned());
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("path", path),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    counter!(HTTP_REQUESTS_TOTAL, &labels).increment(1);
+    histogram!(HTTP_REQUEST_DURATION_SECONDS, &labels).record(start.elapsed());
+
+    response
+}
+
+pub fn outcome_label<T, E>(result: &Result<T, E>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(_) => "failure",
+    }
+}
+
+pub fn record_password_hash_duration(operation: &'static str, duration: Duration) {
+    histogram!(PASSWORD_HASH_DURATION_SECONDS, "operation" => operation).record(duration);
+}
+
+pub fn record_email_sent<T, E>(client: &'static str, result: &Result<T, E>) {
+    counter!(EMAILS_SENT_TOTAL, "client" => client, "outcome" => outcome_label(result))
+        .increment(1);
+}
+
+// `kind` is "sms" or "voice"
+pub fn record_sms_sent<T, E>(client: &'static str, kind: &'static str, result: &Result<T, E>) {
+    counter!(SMS_SENT_TOTAL, "client" => client, "kind" => kind, "outcome" => outcome_label(result))
+        .increment(1);
+}
+
+// Records the latency of a single store call when dropped, so early returns are timed too
+pub struct StoreCallTimer {
+    backend: &'static str,
+    operation: &'static str,
+    start: Instant,
+}
+
+impl StoreCallTimer {
+    pub fn start(backend: &'static str, operation: &'static str) -> Self {
+        Self {
+            backend,
+            operation,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for StoreCallTimer {
+    fn drop(&mut self) {
+        histogram!(
+            STORE_CALL_DURATION_SECONDS,
+            "backend" => self.backend,
+            "operation" => self.operation
+        )
+        .record(self.start.elapsed());
+    }
+}
@@ -1,4 +1,17 @@
+pub mod admin_auth;
 pub mod auth;
+pub mod auth_middleware;
 pub mod configuration;
 pub mod constants;
+pub mod cors;
+pub mod csrf;
+pub mod html;
+pub mod keyed_hash;
+pub mod metrics;
+pub mod password_hash;
+pub mod redact;
+pub mod request_context;
+pub mod retry;
+pub mod secret_cipher;
+pub mod shutdown;
 pub mod tracing;
@@ -0,0 +1,193 @@
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version,
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use scrypt::Scrypt;
+use secrecy::{ExposeSecret, Secret};
+use std::time::Instant;
+
+use super::{configuration::PasswordHashingSettings, metrics::record_password_hash_duration};
+
+// Argon2 hashing shared by the durable user stores, run off the async runtime.
+// Hashes imported from other systems are verified too: bcrypt, and scrypt in PHC format.
+
+#[tracing::instrument(name = "Verify password hash", skip_all)]
+pub async fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<()> {
+    // retrieves the current span from the tracing context.
+    // The span represents the execution context for the compute_password_hash func
+    let current_span: tracing::Span = tracing::Span::current();
+
+    let result = tokio::task::spawn_blocking(move || {
+        current_span.in_scope(|| {
+            let expected_password_hash = expected_password_hash.expose_secret();
+            let password_candidate = password_candidate.expose_secret().as_bytes();
+
+            let start = Instant::now();
+            let verified = if is_bcrypt(expected_password_hash) {
+                match bcrypt::verify(password_candidate, expected_password_hash) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(eyre!("password does not match the bcrypt hash")),
+                    Err(e) => Err(e).wrap_err("failed to verify bcrypt hash"),
+                }
+            } else {
+                // each uses the parameters stored in the hash
+                PasswordHash::new(expected_password_hash)?
+                    .verify_password(&[&Argon2::default(), &Scrypt], password_candidate)
+                    .wrap_err("failed to verify password hash")
+            };
+            record_password_hash_duration("verify", start.elapsed());
+
+            verified
+        })
+    })
+    .await;
+
+    result?
+}
+
+#[tracing::instrument(name = "Computing password hash", skip_all)]
+pub async fn compute_password_hash(
+    password: Secret<String>,
+    settings: PasswordHashingSettings,
+) -> Result<Secret<String>> {
+    let current_span: tracing::Span = tracing::Span::current();
+
+    let password = password.to_owned();
+    let result = tokio::task::spawn_blocking(move || {
+        current_span.in_scope(|| hash_password_blocking(&password, settings))
+    })
+    .await;
+
+    result?
+}
+
+// Blocks for as long as hashing takes, so async code goes through `compute_password_hash`
+pub fn hash_password_blocking(
+    password: &Secret<String>,
+    settings: PasswordHashingSettings,
+) -> Result<Secret<String>> {
+    let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
+    let start = Instant::now();
+    let password_hash: String = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(
+            settings.memory_kib,
+            settings.iterations,
+            settings.parallelism,
+            None,
+        )?,
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+    record_password_hash_duration("hash", start.elapsed());
+
+    Ok(Secret::new(password_hash))
+}
+
+// Whether a stored hash should be replaced by one computed with `settings`
+pub fn is_outdated(password_hash: &Secret<String>, settings: &PasswordHashingSettings) -> bool {
+    // bcrypt hashes are not in PHC format
+    let Ok(hash) = PasswordHash::new(password_hash.expose_secret()) else {
+        return true;
+    };
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&hash) {
+        Ok(params) => {
+            (params.m_cost(), params.t_cost(), params.p_cost())
+                != (
+                    settings.memory_kib,
+                    settings.iterations,
+                    settings.parallelism,
+                )
+        }
+        Err(_) => true,
+    }
+}
+
+fn is_bcrypt(password_hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| password_hash.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(s: &str) -> Secret<String> {
+        Secret::new(s.to_owned())
+    }
+
+    // cheap, so the tests stay fast
+    const SETTINGS: PasswordHashingSettings = PasswordHashingSettings {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[tokio::test]
+    async fn test_hash_uses_the_settings() {
+        let hash = compute_password_hash(secret("password123"), SETTINGS)
+            .await
+            .unwrap();
+
+        assert!(hash
+            .expose_secret()
+            .starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(!is_outdated(&hash, &SETTINGS));
+        assert!(is_outdated(
+            &hash,
+            &PasswordHashingSettings {
+                iterations: 2,
+                ..SETTINGS
+            }
+        ));
+        assert!(verify_password_hash(hash.clone(), secret("password123"))
+            .await
+            .is_ok());
+        assert!(verify_password_hash(hash, secret("password456"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_imported_hashes_are_verified_and_outdated() {
+        let bcrypt_hash = secret(&bcrypt::hash("password123", 4).unwrap());
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let scrypt_hash = secret(
+            &Scrypt
+                .hash_password_customized(
+                    b"password123",
+                    None,
+                    None,
+                    scrypt::Params::new(4, 8, 1, 32).unwrap(),
+                    &salt,
+                )
+                .unwrap()
+                .to_string(),
+        );
+        let argon2i_hash = secret(
+            &Argon2::new(Algorithm::Argon2i, Version::V0x13, Params::default())
+                .hash_password(b"password123", &salt)
+                .unwrap()
+                .to_string(),
+        );
+
+        for hash in [bcrypt_hash, scrypt_hash, argon2i_hash] {
+            assert!(is_outdated(&hash, &SETTINGS));
+            assert!(verify_password_hash(hash.clone(), secret("password123"))
+                .await
+                .is_ok());
+            assert!(verify_password_hash(hash, secret("password456"))
+                .await
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{
+        header::{ACCEPT_LANGUAGE, USER_AGENT},
+        request::Parts,
+        HeaderMap,
+    },
+};
+
+use crate::{app_state::AppState, utils::configuration::Settings};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+// An address, or a CIDR range such as "10.0.0.0/8"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{:?} is not an IP address or CIDR range", s);
+        let (network, prefix_len) = match s.split_once('/') {
+            Some((network, prefix_len)) => (
+                network.parse::<IpAddr>().map_err(|_| invalid())?,
+                Some(prefix_len.parse::<u8>().map_err(|_| invalid())?),
+            ),
+            None => (s.parse::<IpAddr>().map_err(|_| invalid())?, None),
+        };
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+fn prefix_matches(network: &[u8], ip: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = usize::from(prefix_len / 8);
+    let remaining_bits = prefix_len % 8;
+    if network[..full_bytes] != ip[..full_bytes] {
+        return false;
+    }
+    if remaining_bits == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - remaining_bits);
+    network[full_bytes] & mask == ip[full_bytes] & mask
+}
+
+// Reverse proxies whose X-Forwarded-For is believed; from `trusted_proxies`
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpRange>>);
+
+impl TrustedProxies {
+    pub fn new(ranges: Vec<IpRange>) -> Self {
+        Self(Arc::new(ranges))
+    }
+
+    // Entries that do not parse are rejected by `Settings::validate`
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self::new(
+            settings
+                .trusted_proxies
+                .iter()
+                .filter_map(|proxy| proxy.parse().ok())
+                .collect(),
+        )
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    // The peer, unless it is a trusted proxy: then the last address in X-Forwarded-For that
+    // a trusted proxy did not add, since clients can put anything in front of it
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?;
+        if !self.contains(&client) {
+            return Some(client);
+        }
+        let forwarded_for = header_value(headers, FORWARDED_FOR_HEADER).unwrap_or_default();
+        for hop in forwarded_for.rsplit(',') {
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = hop;
+            if !self.contains(&client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+impl FromRef<AppState> for TrustedProxies {
+    fn from_ref(state: &AppState) -> Self {
+        state.trusted_proxies.clone()
+    }
+}
+
+// Who is calling: client IP, user agent and the id assigned to the request
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    // from Accept-Language, most preferred first
+    pub languages: Vec<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestContext
+where
+    TrustedProxies: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let ip = TrustedProxies::from_ref(state)
+            .client_ip(peer, &parts.headers)
+            .map(|ip| ip.to_string());
+
+        Ok(Self {
+            ip,
+            user_agent: header_value(&parts.headers, USER_AGENT.as_str()),
+            request_id: header_value(&parts.headers, REQUEST_ID_HEADER),
+            languages: header_value(&parts.headers, ACCEPT_LANGUAGE.as_str())
+                .map(|value| parse_accept_language(&value))
+                .unwrap_or_default(),
+        })
+    }
+}
+
+// "de-CH, fr;q=0.5, *;q=0.1" -> ["de-CH", "fr"]
+fn parse_accept_language(value: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let language = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (!language.is_empty() && language != "*" && quality > 0.0)
+                .then(|| (language.to_owned(), quality))
+        })
+        .collect();
+    // stable, so equally preferred languages keep their order
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages
+        .into_iter()
+        .map(|(language, _)| language)
+        .collect()
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(forwarded_for: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_FOR_HEADER, forwarded_for.parse().unwrap());
+        headers
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let range: IpRange = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains(&"10.1.255.3".parse().unwrap()));
+        assert!(!range.contains(&"10.2.0.1".parse().unwrap()));
+        // IPv4-mapped IPv6, as reported by dual-stack listeners
+        assert!(range.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+
+        let range: IpRange = "fd00::/8".parse().unwrap();
+        assert!(range.contains(&"fd12::1".parse().unwrap()));
+        assert!(!range.contains(&"fe80::1".parse().unwrap()));
+
+        let single: IpRange = "127.0.0.1".parse().unwrap();
+        assert!(single.contains(&"127.0.0.1".parse().unwrap()));
+        assert!(!single.contains(&"127.0.0.2".parse().unwrap()));
+
+        for invalid in ["", "localhost", "10.0.0.0/33", "10.0.0.0/", "::/129"] {
+            assert!(invalid.parse::<IpRange>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_forwarded_for_is_ignored_from_untrusted_peers() {
+        let proxies = TrustedProxies::default();
+
+        assert_eq!(
+            proxies.client_ip(ip("203.0.113.7"), &headers("198.51.100.1")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(proxies.client_ip(None, &headers("198.51.100.1")), None);
+    }
+
+    #[test]
+    fn test_client_ip_behind_trusted_proxies() {
+        let proxies = TrustedProxies::new(vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "172.17.0.1".parse().unwrap(),
+        ]);
+        let proxy = ip("172.17.0.1");
+
+        assert_eq!(
+            proxies.client_ip(proxy, &headers("198.51.100.1")),
+            ip("198.51.100.1")
+        );
+        // the client's own X-Forwarded-For entries are skipped
+        assert_eq!(
+            proxies.client_ip(proxy, &headers("1.2.3.4, 198.51.100.1, 10.0.0.5")),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            proxies.client_ip(proxy, &headers("garbage, 198.51.100.1")),
+            ip("198.51.100.1")
+        );
+        // only proxies: the first one is the closest we get to the client
+        assert_eq!(
+            proxies.client_ip(proxy, &headers("10.0.0.5")),
+            ip("10.0.0.5")
+        );
+        assert_eq!(proxies.client_ip(proxy, &HeaderMap::new()), proxy);
+    }
+
+    #[test]
+    fn test_parse_accept_language() {
+        assert_eq!(
+            parse_accept_language("fr;q=0.5, de-CH, *;q=0.1, en;q=0, es;q=0.5"),
+            vec!["de-CH", "fr", "es"]
+        );
+        assert!(parse_accept_language("").is_empty());
+        assert!(parse_accept_language("de;q=high").is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+// Exponential back-off: base, 2 * base, 4 * base, ... capped at `max_delay`
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // Delay before the next attempt, or `None` once the attempt budget is spent
+    pub fn delay_after(&self, attempts: u32) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        Some(self.base_delay.saturating_mul(factor).min(self.max_delay))
+    }
+
+    // When to make the next attempt, or `None` once the attempt budget is spent
+    pub fn next_attempt_at(&self, attempts: u32) -> Option<DateTime<Utc>> {
+        self.delay_after(attempts)
+            .and_then(|delay| chrono::Duration::from_std(delay).ok())
+            .map(|delay| Utc::now() + delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_policy_backs_off_exponentially_until_dead() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+        };
+
+        assert_eq!(policy.delay_after(1), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay_after(2), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay_after(3), Some(Duration::from_secs(4)));
+        assert_eq!(policy.delay_after(4), Some(Duration::from_secs(5)));
+        assert_eq!(policy.delay_after(5), None);
+        assert!(policy.next_attempt_at(5).is_none());
+    }
+}
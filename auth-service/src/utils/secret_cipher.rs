@@ -0,0 +1,99 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use color_eyre::eyre::{eyre, Result};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{configuration::Settings, keyed_hash::KeyedHasher};
+
+const FORMAT_VERSION: &str = "v1";
+const NONCE_LEN: usize = 12;
+
+// AES-256-GCM for secrets the service must read back, such as webhook signing secrets,
+// so someone who can read Postgres can't sign payloads with them
+#[derive(Clone)]
+pub struct SecretCipher {
+    cipher: Aes256Gcm,
+}
+
+impl SecretCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+
+    // Derived from the store key (see `KeyedHasher::from_settings`); rotating it makes
+    // stored secrets unreadable
+    pub fn from_settings(settings: &Settings) -> Self {
+        let derived = KeyedHasher::from_settings(settings).hash("secret_cipher_key", "v1");
+        let mut key = [0u8; 32];
+        hex::decode_to_slice(derived, &mut key).expect("HMAC-SHA256 is 32 bytes");
+        Self::new(&key)
+    }
+
+    // "v1:<hex nonce and ciphertext>", with a fresh random nonce each time
+    pub fn encrypt(&self, secret: &Secret<String>) -> Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, secret.expose_secret().as_bytes())
+            .map_err(|_| eyre!("failed to encrypt secret"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!("{}:{}", FORMAT_VERSION, hex::encode(sealed)))
+    }
+
+    pub fn decrypt(&self, encrypted: &str) -> Result<Secret<String>> {
+        let sealed = encrypted
+            .strip_prefix(FORMAT_VERSION)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .and_then(|sealed| hex::decode(sealed).ok())
+            .filter(|sealed| sealed.len() > NONCE_LEN)
+            .ok_or_else(|| eyre!("not an encrypted secret"))?;
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| eyre!("failed to decrypt secret; was the key changed?"))?;
+        String::from_utf8(plaintext)
+            .map(Secret::new)
+            .map_err(|_| eyre!("decrypted secret is not UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> Secret<String> {
+        Secret::new("whsec_test".to_owned())
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let cipher = SecretCipher::new(&[7; 32]);
+
+        let encrypted = cipher.encrypt(&secret()).unwrap();
+        assert!(encrypted.starts_with("v1:"));
+        assert!(!encrypted.contains("whsec_test"));
+        // nonces are never reused
+        assert_ne!(encrypted, cipher.encrypt(&secret()).unwrap());
+
+        let decrypted = cipher.decrypt(&encrypted).unwrap();
+        assert_eq!(decrypted.expose_secret(), "whsec_test");
+    }
+
+    #[test]
+    fn test_decrypt_rejects_other_keys_and_plaintext() {
+        let encrypted = SecretCipher::new(&[7; 32]).encrypt(&secret()).unwrap();
+        let other = SecretCipher::new(&[8; 32]);
+
+        assert!(other.decrypt(&encrypted).is_err());
+        assert!(other.decrypt("whsec_test").is_err());
+        assert!(other.decrypt("v1:00").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+// Shared shutdown flag. Triggering it flips readiness to unhealthy, stops the server
+// from accepting connections and tells background tasks to finish their current work.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    // Resolves once `trigger` has been called, immediately if it already was
+    pub async fn wait(&self) {
+        let mut receiver = self.sender.subscribe();
+        // the sender lives as long as `self`, so this cannot fail
+        let _ = receiver.wait_for(|shutting_down| *shutting_down).await;
+    }
+}
+
+// Triggers `handle` on SIGINT (Ctrl+C) or SIGTERM (sent by ECS and docker on stop)
+pub async fn trigger_on_signal(handle: ShutdownHandle) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+        _ = handle.wait() => return,
+    }
+
+    handle.trigger();
+}
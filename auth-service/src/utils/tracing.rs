@@ -1,14 +1,45 @@
 use std::time::Duration;
 
-use axum::{body::Body, extract::Request, response::Response};
+use axum::{body::Body, extract::Request, http::HeaderMap, middleware::Next, response::Response};
 use color_eyre::eyre::Result;
-use tracing::{Level, Span};
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, runtime, trace::TracerProvider, Resource,
+};
+use tracing::{Level, Span, Subscriber};
 use tracing_error::ErrorLayer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::prelude::*;
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    registry::LookupSpan,
+    EnvFilter, Layer,
+};
 
-pub fn init_tracing() -> Result<()> {
-    let fmt_layer = fmt::layer().pretty();
+use super::{
+    configuration::{LogFormat, TelemetrySettings},
+    redact::RedactingMakeWriter,
+    request_context::REQUEST_ID_HEADER,
+};
+
+const LOG_FORMAT_ENV_VAR: &str = "LOG_FORMAT";
+
+pub fn init_tracing(settings: &TelemetrySettings) -> Result<()> {
+    let log_format = match std::env::var(LOG_FORMAT_ENV_VAR) {
+        Ok(value) => value.parse().map_err(color_eyre::eyre::Report::msg)?,
+        Err(_) => settings.log_format,
+    };
+    let fmt_layer = fmt_layer(log_format, std::io::stdout);
+
+    // read and write W3C `traceparent`/`tracestate` headers
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let tracer_provider = build_tracer_provider(settings)?;
+    let otel_layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer_provider.tracer(settings.service_name.clone()));
+    global::set_tracer_provider(tracer_provider);
 
     // Create a filter layer to control the verbosity of logs
     // Try to get the filter config from the environment variables
@@ -23,23 +54,98 @@ pub fn init_tracing() -> Result<()> {
     tracing_subscriber::registry()
         .with(filter_layer)
         .with(fmt_layer)
+        .with(otel_layer)
         .with(ErrorLayer::default())
         .init();
 
     Ok(())
 }
 
-// Creates a new tracing span with a unique request ID for each incoming request.
+// Formats events as `format`; emails and tokens are redacted before anything is written
+pub fn fmt_layer<S, W>(format: LogFormat, make_writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(RedactingMakeWriter::new(make_writer));
+
+    match format {
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        // one object per line, with the fields of the enclosing spans (e.g. `request_id`)
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    }
+}
+
+// Spans always get W3C trace ids so they can be propagated,
+// but are only exported when an OTLP endpoint is configured.
+pub fn build_tracer_provider(settings: &TelemetrySettings) -> Result<TracerProvider> {
+    let mut builder = TracerProvider::builder().with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        settings.service_name.clone(),
+    )]));
+
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .with_timeout(Duration::from_millis(settings.export_timeout_ms))
+            .build()?;
+        builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+    }
+
+    Ok(builder.build())
+}
+
+// Flushes spans that are still buffered; call before the process exits
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+// Creates a new tracing span carrying the request ID assigned by `SetRequestIdLayer`.
 pub fn make_span_with_request_id(request: &Request<Body>) -> Span {
-    let request_id = uuid::Uuid::new_v4();
-    tracing::span!(
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let span = tracing::span!(
         Level::INFO,
         "[REQUEST]",
         method = tracing::field::display(request.method()),
         uri = tracing::field::display(request.uri()),
         version = tracing::field::debug(request.version()),
         request_id = tracing::field::display(request_id),
-    )
+    );
+
+    // continue the caller's trace if it sent `traceparent`
+    let parent_context = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent_context);
+
+    span
+}
+
+// Returns `traceparent`/`tracestate` of the request span, so callers can find our spans.
+// Must run inside the `TraceLayer`.
+pub async fn propagate_trace_context(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    inject_trace_context(&Span::current(), response.headers_mut());
+    response
+}
+
+// Writes the trace context of `span` into outgoing headers
+pub fn inject_trace_context(span: &Span, headers: &mut HeaderMap) {
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
 }
 
 // Logs an event indicating the start of a request.
@@ -73,3 +179,125 @@ pub fn on_response(response: &Response, latency: Duration, _span: &Span) {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    fn test_subscriber(provider: &TracerProvider) -> impl tracing::Subscriber + Send + Sync {
+        tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("auth-service-test")))
+    }
+
+    #[tokio::test]
+    async fn continues_incoming_trace_and_returns_it_on_the_response() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = build_tracer_provider(&TelemetrySettings::default()).unwrap();
+        let _guard = tracing::subscriber::set_default(test_subscriber(&provider));
+
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(propagate_trace_context))
+            .layer(TraceLayer::new_for_http().make_span_with(make_span_with_request_id));
+
+        let request = Request::get("/")
+            .header(
+                "traceparent",
+                format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+            )
+            .header("tracestate", "vendor=value")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+
+        let traceparent = response.headers()["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+        // our own span, not the caller's
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+        assert_eq!(response.headers()["tracestate"], "vendor=value");
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_logs_include_span_fields_and_redact_emails() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber =
+            tracing_subscriber::registry().with(fmt_layer(LogFormat::Json, move || writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!(
+                "[REQUEST]",
+                request_id = "req-1",
+                method = "POST",
+                uri = "/login"
+            )
+            .in_scope(|| tracing::info!("Sending email to: {}", "john.doe@example.com"));
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+
+        let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(
+            event["fields"]["message"],
+            "Sending email to: j***@example.com"
+        );
+        assert_eq!(event["span"]["request_id"], "req-1");
+        assert_eq!(event["span"]["method"], "POST");
+        assert_eq!(event["span"]["uri"], "/login");
+        assert!(!output.contains("john.doe"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn exports_spans_to_the_otlp_collector() {
+        let collector = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+
+        let settings = TelemetrySettings {
+            otlp_endpoint: Some(format!("{}/v1/traces", collector.uri())),
+            ..Default::default()
+        };
+        let provider = build_tracer_provider(&settings).unwrap();
+
+        tracing::subscriber::with_default(test_subscriber(&provider), || {
+            tracing::info_span!("login").in_scope(|| tracing::info!("logged in"));
+        });
+
+        for result in provider.force_flush() {
+            result.unwrap();
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use auth_service::{
+    domain::audit::{AuditEvent, AuditEventKind, AuditOutcome, AuditQuery},
+    utils::configuration::AuditSinkKind,
+};
+use secrecy::Secret;
+
+use crate::helpers::{get_random_email, TestApp};
+
+#[tokio::test]
+async fn should_record_signup_and_login_events() {
+    let mut app = TestApp::new().await;
+    let email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "wrong-password",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 401);
+
+    let events = app
+        .audit_sink
+        .query(&AuditQuery {
+            actor: Some(email.clone()),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to query audit events");
+
+    assert_eq!(events.len(), 2);
+
+    // newest first
+    assert_eq!(events[0].kind, AuditEventKind::Login);
+    assert_eq!(events[0].outcome, AuditOutcome::Failure);
+    assert_eq!(events[0].reason.as_deref(), Some("Incorrect credentials"));
+    assert!(events[0].ip.is_some());
+    assert!(events[0].request_id.is_some());
+
+    assert_eq!(events[1].kind, AuditEventKind::Signup);
+    assert_eq!(events[1].outcome, AuditOutcome::Success);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_append_events_to_the_json_lines_file() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.audit.sink = AuditSinkKind::JsonLines;
+    })
+    .await;
+    let email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let path = app.audit_log_file.as_ref().expect("No audit log file");
+    let lines: Vec<AuditEvent> = std::fs::read_to_string(path)
+        .expect("Failed to read audit log file")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Invalid audit event line"))
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].kind, AuditEventKind::Signup);
+    assert_eq!(lines[0].actor.as_deref(), Some(email.as_str()));
+
+    let events = app
+        .audit_sink
+        .query(&AuditQuery::default())
+        .await
+        .expect("Failed to query audit events");
+    assert_eq!(events, lines);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_record_failed_logout_without_actor() {
+    let mut app = TestApp::new().await;
+
+    let logout_response = app.post_logout().await;
+    assert_eq!(logout_response.status().as_u16(), 400);
+
+    let events = app
+        .audit_sink
+        .query(&AuditQuery {
+            kind: Some(AuditEventKind::Logout),
+            outcome: Some(AuditOutcome::Failure),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to query audit events");
+
+    assert_eq!(events.len(), 1);
+    assert!(events[0].actor.is_none());
+    assert_eq!(events[0].reason.as_deref(), Some("Missing token"));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_ignore_forwarded_for_from_untrusted_peers() {
+    let mut app = TestApp::new().await;
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .http_client
+        .post(format!("{}/logout", &app.address))
+        .header("X-Forwarded-For", "203.0.113.7")
+        .header("X-Request-Id", &request_id)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 400);
+
+    let events = app
+        .audit_sink
+        .query(&AuditQuery {
+            request_id: Some(request_id),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to query audit events");
+
+    assert_eq!(events[0].ip.as_deref(), Some("127.0.0.1"));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_use_forwarded_for_from_trusted_proxies() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.trusted_proxies = vec!["127.0.0.0/8".to_owned()];
+    })
+    .await;
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .http_client
+        .post(format!("{}/logout", &app.address))
+        .header("X-Forwarded-For", "198.51.100.1, 203.0.113.7")
+        .header("X-Request-Id", &request_id)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 400);
+
+    let events = app
+        .audit_sink
+        .query(&AuditQuery {
+            request_id: Some(request_id),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to query audit events");
+
+    // the entry the proxy added, not the one the client sent
+    assert_eq!(events[0].ip.as_deref(), Some("203.0.113.7"));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_404_for_audit_events_without_admin_token() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_admin_audit_events(Some(&"a".repeat(32)), &[]).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_query_audit_events_with_admin_token() {
+    let admin_token = "a".repeat(32);
+    let mut app = TestApp::with_settings(|settings| {
+        settings.admin.token = Some(Secret::new(admin_token.clone()));
+    })
+    .await;
+    let email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let response = app.get_admin_audit_events(None, &[]).await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = app.get_admin_audit_events(Some(&"b".repeat(32)), &[]).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .get_admin_audit_events(Some(&admin_token), &[("kind", "not_a_kind")])
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .get_admin_audit_events(
+            Some(&admin_token),
+            &[("actor", &email), ("kind", "signup"), ("limit", "10")],
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let events = response
+        .json::<Vec<AuditEvent>>()
+        .await
+        .expect("Could not deserialize response body to audit events");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, AuditEventKind::Signup);
+    assert_eq!(events[0].actor.as_deref(), Some(email.as_str()));
+
+    // the lookup itself is audited
+    let events = app
+        .audit_sink
+        .query(&AuditQuery {
+            kind: Some(AuditEventKind::AdminAction),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to query audit events");
+    assert!(events
+        .iter()
+        .any(|event| event.outcome == AuditOutcome::Success
+            && event
+                .reason
+                .as_deref()
+                .is_some_and(|reason| reason.contains(&email))));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_look_up_the_2fa_email_named_in_the_login_audit_event() {
+    let admin_token = "a".repeat(32);
+    let mut app = TestApp::with_settings(|settings| {
+        settings.admin.token = Some(Secret::new(admin_token.clone()));
+    })
+    .await;
+    let email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 206);
+
+    let events = app
+        .audit_sink
+        .query(&AuditQuery {
+            actor: Some(email.clone()),
+            kind: Some(AuditEventKind::Login),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to query audit events");
+    let email_id = events[0]
+        .reason
+        .as_deref()
+        .and_then(|reason| reason.strip_prefix("2FA required; code email "))
+        .expect("No email id in the login audit event")
+        .to_owned();
+
+    let response = app.get_admin_email(None, &email_id).await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = app.get_admin_email(Some(&"b".repeat(32)), &email_id).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let unknown_id = uuid::Uuid::new_v4().to_string();
+    let response = app.get_admin_email(Some(&admin_token), &unknown_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    app.two_fa_code_sent_to(&email).await;
+    // the dispatcher records the outcome just after sending
+    let mut delivery = serde_json::Value::Null;
+    for _ in 0..50 {
+        let response = app.get_admin_email(Some(&admin_token), &email_id).await;
+        assert_eq!(response.status().as_u16(), 200);
+        delivery = response.json().await.expect("Invalid email delivery");
+        if delivery["status"] == "sent" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(delivery["id"], email_id.as_str());
+    assert_eq!(delivery["status"], "sent");
+    assert_eq!(delivery["attempts"], 1);
+    assert!(delivery["expires_at"].is_string());
+
+    app.clean_up().await
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use auth_service::{
+    domain::data_stores::{BannedTokenStore, BannedTokenStoreError},
+    utils::{
+        auth_middleware::{AuthVerifier, AuthenticatedUser, RequireAuthLayer},
+        constants::JWT_COOKIE_NAME,
+    },
+};
+use axum::{
+    body::Body,
+    http::{header::AUTHORIZATION, Request},
+    routing::get,
+    Extension, Router,
+};
+use color_eyre::eyre::eyre;
+use secrecy::Secret;
+use tokio::sync::RwLock;
+use tower::ServiceExt;
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn protected(Extension(user): Extension<AuthenticatedUser>) -> String {
+    user.claims.sub
+}
+
+async fn login_and_get_token(app: &TestApp) -> String {
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+    });
+    let login_response = app.post_login(&login_body).await;
+    assert_eq!(login_response.status().as_u16(), 200);
+
+    let cookie = login_response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+
+    cookie.value().to_owned()
+}
+
+fn protected_router(app: &TestApp) -> Router {
+    Router::new()
+        .route("/protected", get(protected))
+        .layer(RequireAuthLayer::new(AuthVerifier::new(
+            app.token_store.clone(),
+            app.jwt_secret.clone(),
+            JWT_COOKIE_NAME.to_owned(),
+        )))
+}
+
+#[tokio::test]
+async fn layer_should_return_400_if_token_missing() {
+    let mut app = TestApp::new().await;
+
+    let response = protected_router(&app)
+        .oneshot(Request::get("/protected").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn layer_should_accept_bearer_token() {
+    let mut app = TestApp::new().await;
+    let token = login_and_get_token(&app).await;
+
+    // the scheme is case-insensitive (RFC 7235)
+    for scheme in ["Bearer", "bearer", "BEARER"] {
+        let response = protected_router(&app)
+            .oneshot(
+                Request::get("/protected")
+                    .header(AUTHORIZATION, format!("{} {}", scheme, token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 200, "{}", scheme);
+    }
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn layer_should_accept_jwt_cookie() {
+    let mut app = TestApp::new().await;
+    let token = login_and_get_token(&app).await;
+
+    let response = protected_router(&app)
+        .oneshot(
+            Request::get("/protected")
+                .header("Cookie", format!("{}={}", JWT_COOKIE_NAME, token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn layer_should_return_401_if_token_banned() {
+    let mut app = TestApp::new().await;
+    let token = login_and_get_token(&app).await;
+
+    let logout_response = app.post_logout().await;
+    assert_eq!(logout_response.status().as_u16(), 200);
+
+    let response = protected_router(&app)
+        .oneshot(
+            Request::get("/protected")
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await
+}
+
+// A banned token store that is down, like Redis during an outage
+struct UnavailableTokenStore;
+
+#[async_trait::async_trait]
+impl BannedTokenStore for UnavailableTokenStore {
+    async fn store_token(&mut self, _token: Secret<String>) -> Result<(), BannedTokenStoreError> {
+        Err(BannedTokenStoreError::UnexpectedError(eyre!(
+            "store unavailable"
+        )))
+    }
+
+    async fn contains_token(&self, _token: Secret<String>) -> Result<bool, BannedTokenStoreError> {
+        Err(BannedTokenStoreError::UnexpectedError(eyre!(
+            "store unavailable"
+        )))
+    }
+}
+
+#[tokio::test]
+async fn layer_should_return_500_if_the_banned_token_store_fails() {
+    let mut app = TestApp::new().await;
+    let token = login_and_get_token(&app).await;
+
+    let router = Router::new()
+        .route("/protected", get(protected))
+        .layer(RequireAuthLayer::new(AuthVerifier::new(
+            Arc::new(RwLock::new(UnavailableTokenStore)),
+            app.jwt_secret.clone(),
+            JWT_COOKIE_NAME.to_owned(),
+        )));
+    let response = router
+        .clone()
+        .oneshot(
+            Request::get("/protected")
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    // whether the token is banned is unknown, which is not the client's fault
+    assert_eq!(response.status().as_u16(), 500);
+
+    // tokens that can't be valid are still rejected without asking the store
+    let response = router
+        .oneshot(
+            Request::get("/protected")
+                .header(AUTHORIZATION, "Bearer invalid")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await
+}
@@ -0,0 +1,126 @@
+use auth_service::utils::configuration::CorsSettings;
+
+use crate::helpers::TestApp;
+
+async fn app_with_cors() -> TestApp {
+    TestApp::with_settings(|settings| {
+        settings.cors = CorsSettings {
+            allowed_origins: vec![
+                "https://app.example.com".to_owned(),
+                "https://*.staging.example.com".to_owned(),
+            ],
+            allowed_methods: vec!["GET".to_owned(), "POST".to_owned()],
+            allowed_headers: vec!["content-type".to_owned(), "authorization".to_owned()],
+            max_age_secs: 1800,
+            allow_credentials: true,
+            ..CorsSettings::default()
+        };
+    })
+    .await
+}
+
+fn header<'a>(response: &'a reqwest::Response, name: &str) -> Option<&'a str> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().expect("header is not ASCII"))
+}
+
+#[tokio::test]
+async fn preflight_from_allowed_origin_returns_policy() {
+    let mut app = app_with_cors().await;
+
+    let response = app
+        .preflight("/login", "https://app.example.com", "POST", "content-type")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        header(&response, "access-control-allow-origin"),
+        Some("https://app.example.com")
+    );
+    assert_eq!(
+        header(&response, "access-control-allow-credentials"),
+        Some("true")
+    );
+    assert_eq!(
+        header(&response, "access-control-allow-methods"),
+        Some("GET,POST")
+    );
+    assert_eq!(
+        header(&response, "access-control-allow-headers"),
+        Some("content-type,authorization")
+    );
+    assert_eq!(header(&response, "access-control-max-age"), Some("1800"));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn preflight_from_wildcard_subdomain_is_allowed() {
+    let mut app = app_with_cors().await;
+
+    let response = app
+        .preflight(
+            "/signup",
+            "https://eu.staging.example.com",
+            "POST",
+            "content-type",
+        )
+        .await;
+
+    assert_eq!(
+        header(&response, "access-control-allow-origin"),
+        Some("https://eu.staging.example.com")
+    );
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn preflight_from_other_origin_is_not_allowed() {
+    let mut app = app_with_cors().await;
+
+    for origin in [
+        "https://evil.example.com",
+        "https://staging.example.com",
+        "http://app.example.com",
+        "https://app.example.com.evil.com",
+    ] {
+        let response = app
+            .preflight("/login", origin, "POST", "content-type")
+            .await;
+
+        assert_eq!(
+            header(&response, "access-control-allow-origin"),
+            None,
+            "{}",
+            origin
+        );
+    }
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn response_to_allowed_origin_exposes_request_id() {
+    let mut app = app_with_cors().await;
+
+    let response = app
+        .http_client
+        .get(format!("{}/health/live", &app.address))
+        .header("Origin", "https://app.example.com")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(
+        header(&response, "access-control-allow-origin"),
+        Some("https://app.example.com")
+    );
+    assert!(header(&response, "access-control-expose-headers")
+        .expect("No expose headers")
+        .contains("x-request-id"));
+
+    app.clean_up().await
+}
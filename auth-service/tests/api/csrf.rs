@@ -0,0 +1,214 @@
+use auth_service::{
+    routes::{CsrfTokenResponse, TwoFactorAuthResponse},
+    utils::constants::JWT_COOKIE_NAME,
+    ErrorResponse,
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+// Logs in through the app's client, leaving the JWT and CSRF cookies in its jar
+async fn log_in(app: &TestApp) -> String {
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+
+    cookie.value().to_owned()
+}
+
+// Shares the cookie jar but, like a cross-site form, sends no CSRF header
+fn client_without_csrf_header(app: &TestApp) -> reqwest::Client {
+    reqwest::Client::builder()
+        .cookie_provider(app.cookie_jar.clone())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn csrf_token_is_reused_while_the_cookie_is_valid() {
+    let mut app = TestApp::new().await;
+
+    let first = app.get_csrf_token().await;
+    assert_eq!(first.status().as_u16(), 200);
+    let first = first.json::<CsrfTokenResponse>().await.unwrap().csrf_token;
+    let second = app
+        .get_csrf_token()
+        .await
+        .json::<CsrfTokenResponse>()
+        .await
+        .unwrap()
+        .csrf_token;
+
+    assert_eq!(first.len(), 64);
+    assert_eq!(first, second);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn logout_without_csrf_token_returns_403() {
+    let mut app = TestApp::new().await;
+    log_in(&app).await;
+
+    let response = client_without_csrf_header(&app)
+        .post(format!("{}/logout", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Invalid CSRF token".to_owned()
+    );
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn login_and_signup_without_csrf_token_return_403() {
+    let mut app = TestApp::new().await;
+    let client = client_without_csrf_header(&app);
+
+    let body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    for path in ["signup", "login"] {
+        let response = client
+            .post(format!("{}/{}", &app.address, path))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert_eq!(response.status().as_u16(), 403, "{}", path);
+    }
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn verify_2fa_without_csrf_token_returns_403() {
+    let mut app = TestApp::new().await;
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    // a cross-site form that completes the attacker's login, without any of the browser's cookies
+    let response = reqwest::Client::new()
+        .post(format!("{}/verify-2fa", &app.address))
+        .json(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": app.two_fa_code_sent_to(&email).await,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 403);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn logout_with_wrong_csrf_token_returns_403() {
+    let mut app = TestApp::new().await;
+    log_in(&app).await;
+
+    let response = client_without_csrf_header(&app)
+        .post(format!("{}/logout", &app.address))
+        .header("x-csrf-token", "0".repeat(64))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 403);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn logout_with_csrf_token_returns_200() {
+    let mut app = TestApp::new().await;
+    log_in(&app).await;
+
+    // the app's client sends the token it fetched on startup
+    let response = app.post_logout().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn bearer_token_clients_are_exempt() {
+    let mut app = TestApp::new().await;
+    let token = log_in(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/logout", &app.address))
+        .bearer_auth(&token)
+        .header("Cookie", format!("{}={}", JWT_COOKIE_NAME, token))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn exempt_paths_are_not_checked() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.csrf.exempt_paths = vec!["/logout".to_owned()];
+    })
+    .await;
+    log_in(&app).await;
+
+    let response = client_without_csrf_header(&app)
+        .post(format!("{}/logout", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
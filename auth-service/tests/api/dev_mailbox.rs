@@ -0,0 +1,78 @@
+use auth_service::{
+    domain::{Email, EmailClient, EmailMessage},
+    services::file_outbox_email_client::FileOutboxEmailClient,
+    utils::configuration::{EmailProvider, EmailSettings},
+};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::helpers::{get_random_email, TestApp};
+
+#[tokio::test]
+async fn should_list_outbox_emails() {
+    let outbox_dir = std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+    let mut app = TestApp::with_settings(|settings| {
+        settings.email.provider = EmailProvider::FileOutbox;
+        settings.email.file_outbox.dir = outbox_dir.display().to_string();
+        settings.email.file_outbox.mailbox = true;
+    })
+    .await;
+
+    let recipient = Email::parse(Secret::new(get_random_email())).unwrap();
+    let sender = EmailSettings::default().sender().unwrap();
+    FileOutboxEmailClient::new(&outbox_dir, sender)
+        .send_email(
+            &recipient,
+            &EmailMessage {
+                subject: "2FA Code".to_owned(),
+                text_body: "<b>123456</b>".to_owned(),
+                html_body: "<p onclick=\"alert(1)\">123456</p>".to_owned(),
+            },
+        )
+        .await
+        .unwrap();
+
+    let response = app.get_dev_mailbox().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("2FA Code"));
+    // content is shown as text, never as markup
+    assert!(body.contains("&lt;b&gt;123456&lt;/b&gt;"));
+    // the HTML body only appears inside a sandboxed iframe
+    assert!(body.contains(
+        "<iframe sandbox srcdoc=\"&lt;p onclick=&quot;alert(1)&quot;&gt;123456&lt;/p&gt;\""
+    ));
+
+    std::fs::remove_dir_all(&outbox_dir).unwrap();
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_404_for_other_email_providers() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_dev_mailbox().await;
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_404_without_opt_in_or_outside_development() {
+    for (mailbox, environment) in [(false, "development"), (true, "staging")] {
+        let mut app = TestApp::with_settings(|settings| {
+            settings.email.provider = EmailProvider::FileOutbox;
+            settings.email.file_outbox.mailbox = mailbox;
+            settings.environment = environment.to_owned();
+        })
+        .await;
+
+        let response = app.get_dev_mailbox().await;
+
+        assert_eq!(response.status().as_u16(), 404, "{}", environment);
+
+        app.clean_up().await
+    }
+}
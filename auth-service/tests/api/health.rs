@@ -0,0 +1,61 @@
+use crate::helpers::TestApp;
+
+#[tokio::test]
+async fn live_should_return_200() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_health("live").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn ready_should_return_200_without_external_dependencies() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+
+    app.clean_up().await
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn ready_should_report_each_dependency() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+    for dependency in ["postgres", "redis"] {
+        assert_eq!(body["checks"][dependency]["status"], "ok", "{}", dependency);
+    }
+
+    app.clean_up().await
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn ready_should_return_503_if_postgres_is_down() {
+    let mut app = TestApp::new().await;
+
+    app.db_pool().close().await;
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["checks"]["postgres"]["status"], "unavailable");
+    assert_eq!(body["checks"]["redis"]["status"], "ok");
+
+    app.clean_up().await
+}
@@ -1,23 +1,55 @@
 use auth_service::{
-    app_state::{AppState, EmailClientType, TokenStoreType, TwoFACodeStoreType},
-    get_postgres_pool,
-    services::data_stores::{
-        mock_email_client::MockEmailClient, postgres_user_store::PostgresUserStore,
-        redis_banned_token_store::RedisBannedTokenStore,
-        redis_two_fa_code_store::RedisTwoFACodeStore,
+    app_state::{
+        AppState, AuditSinkType, EmailOutboxType, HealthCheckType, TokenStoreType,
+        TwoFACodeStoreType, UserStoreType,
+    },
+    domain::{Email, PhoneNumber},
+    get_postgres_pool, get_sqlite_pool,
+    routes::CsrfTokenResponse,
+    services::{
+        capturing_email_client::{CapturedEmail, CapturingEmailClient, EmailQuery},
+        capturing_sms_client::{CapturedSms, CapturingSmsClient},
+        data_stores::{
+            hashmap_two_fa_code_store::HashmapTwoFACodeStore, hashmap_user_store::HashmapUserStore,
+            hashset_banned_token_store::HashsetBannedTokenStore,
+            in_memory_audit_sink::InMemoryAuditSink, in_memory_email_outbox::InMemoryEmailOutbox,
+            json_lines_audit_sink::JsonLinesAuditSink, postgres_audit_sink::PostgresAuditSink,
+            postgres_banned_token_store::PostgresBannedTokenStore,
+            postgres_email_outbox::PostgresEmailOutbox,
+            postgres_two_fa_code_store::PostgresTwoFACodeStore,
+            postgres_user_store::PostgresUserStore,
+            redis_banned_token_store::RedisBannedTokenStore,
+            redis_two_fa_code_store::RedisTwoFACodeStore, sqlite_email_outbox::SqliteEmailOutbox,
+            sqlite_user_store::SqliteUserStore,
+        },
+        email_delivery::{dispatcher::EmailDispatcher, failover::FailoverEmailClient, EmailQueue},
+        email_templates::EmailTemplates,
+        health::{PostgresHealthCheck, RedisHealthCheck},
+    },
+    utils::{
+        configuration::{
+            get_configuration, AuditSinkKind, AuthCookieSettings, CsrfSettings, EmailOutboxBackend,
+            EmailProvider, Settings, ShutdownSettings, SmsProvider, StoreBackend, TestSettings,
+            UserStoreBackend,
+        },
+        keyed_hash::KeyedHasher,
+        shutdown::ShutdownHandle,
     },
-    utils::configuration::{get_configuration, TestSettings},
     Application,
 };
-use reqwest::cookie::Jar;
+use reqwest::{
+    cookie::Jar,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
 use secrecy::{ExposeSecret, Secret};
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
-    Connection, Executor, PgConnection, PgPool,
+    Connection, Executor, PgConnection, PgPool, SqlitePool,
 };
 use tokio::sync::RwLock;
 
-use std::{str::FromStr, sync::Arc};
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
 pub struct TestApp {
@@ -25,44 +57,169 @@ pub struct TestApp {
     pub cookie_jar: Arc<Jar>,
     pub token_store: TokenStoreType,
     pub two_fa_code_store: TwoFACodeStoreType,
-    pub email_client: EmailClientType,
+    pub email_client: Arc<CapturingEmailClient>,
+    pub email_outbox: EmailOutboxType,
+    pub sms_client: Arc<CapturingSmsClient>,
+    pub audit_sink: AuditSinkType,
+    // only set for the `json_lines` audit sink; removed by `clean_up`
+    pub audit_log_file: Option<PathBuf>,
     pub http_client: reqwest::Client,
-    pub db_name: String,
+    // only created when a store or the audit sink uses Postgres
+    pub database: Option<TestDatabase>,
+    pub jwt_secret: Secret<String>,
+    pub shutdown_handle: ShutdownHandle,
+    pub server: JoinHandle<Result<(), std::io::Error>>,
     pub clean_up_called: bool,
 }
 
 impl TestApp {
     pub async fn new() -> Self {
-        let configuration = get_configuration().expect("Failed to read configuration.");
+        Self::with_settings(|_| {}).await
+    }
+
+    // For tests that depend on specific settings; applied after the test defaults
+    pub async fn with_settings(configure: impl FnOnce(&mut Settings)) -> Self {
+        Self::build(configure, None).await
+    }
 
-        let pg_pool = configure_postgresql(&configuration.test).await;
+    // For tests that need to observe or hold up calls into the user store
+    pub async fn with_user_store(user_store: UserStoreType) -> Self {
+        Self::build(|_| {}, Some(user_store)).await
+    }
 
-        let db_name = match pg_pool.connect_options().get_database() {
-            Some(name) => name.to_owned(),
-            None => {
-                panic!("Failed to retrieve db name")
-            }
+    async fn build(
+        configure: impl FnOnce(&mut Settings),
+        user_store: Option<UserStoreType>,
+    ) -> Self {
+        let mut configuration = base_configuration();
+        // short enough for tests, long enough to observe readiness while draining
+        configuration.shutdown = ShutdownSettings {
+            unready_delay_ms: 200,
+            drain_timeout_secs: 5,
         };
+        // per app, so tokens issued by one test app are rejected by another
+        configuration.jwt_secret = Secret::new(Uuid::new_v4().to_string());
+        // tests look for the default cookie name
+        configuration.auth.cookie = AuthCookieSettings::default();
+        configure(&mut configuration);
+
+        let database = if configuration.uses_postgres() {
+            Some(TestDatabase::new().await)
+        } else {
+            None
+        };
+        let pg_pool = || {
+            database
+                .as_ref()
+                .map(|database| database.pool.clone())
+                .expect("No test database")
+        };
+        let mut health_checks: Vec<HealthCheckType> = Vec::new();
+        if database.is_some() {
+            health_checks.push(Arc::new(PostgresHealthCheck::new(pg_pool())));
+        }
 
-        let redis_config = configure_redis(&configuration.test);
-        let redis_two_fa_conig = configure_redis(&configuration.test);
-
-        let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
-        let token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(Arc::new(
-            RwLock::new(redis_config),
-        ))));
-        let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(Arc::new(
-            RwLock::new(redis_two_fa_conig),
-        ))));
-        let email_client = Arc::new(MockEmailClient);
+        let mut audit_log_file = None;
+        let audit_sink: AuditSinkType = match configuration.audit.sink {
+            AuditSinkKind::Postgres => Arc::new(PostgresAuditSink::new(pg_pool())),
+            AuditSinkKind::JsonLines => {
+                // a file of its own, so tests only read their own events
+                let path = std::env::temp_dir().join(format!("audit-{}.jsonl", Uuid::new_v4()));
+                audit_log_file = Some(path.clone());
+                Arc::new(JsonLinesAuditSink::new(path))
+            }
+            AuditSinkKind::InMemory => Arc::new(InMemoryAuditSink::default()),
+        };
+        // AUTH__STORES__USERS=sqlite runs the suite against SQLite, which then also keeps the
+        // email outbox
+        let sqlite_pool = match (
+            configuration.stores.users,
+            configuration.stores.email_outbox,
+        ) {
+            (UserStoreBackend::Sqlite, _) | (_, EmailOutboxBackend::Sqlite) => {
+                Some(configure_sqlite().await)
+            }
+            _ => None,
+        };
+        let sqlite = || sqlite_pool.clone().expect("No SQLite database");
+        let user_store: UserStoreType = match (user_store, configuration.stores.users) {
+            (Some(user_store), _) => user_store,
+            (None, UserStoreBackend::Postgres) => Arc::new(RwLock::new(PostgresUserStore::new(
+                pg_pool(),
+                configuration.auth.password_hashing,
+            ))),
+            (None, UserStoreBackend::Sqlite) => Arc::new(RwLock::new(SqliteUserStore::new(
+                sqlite(),
+                configuration.auth.password_hashing,
+            ))),
+            (None, UserStoreBackend::InMemory) => Arc::new(RwLock::new(HashmapUserStore::new(
+                configuration.auth.password_hashing,
+            ))),
+        };
+        let email_client = Arc::new(CapturingEmailClient::default());
+        let email_outbox: EmailOutboxType = match configuration.stores.email_outbox {
+            EmailOutboxBackend::Postgres => Arc::new(PostgresEmailOutbox::new(pg_pool())),
+            EmailOutboxBackend::Sqlite => Arc::new(SqliteEmailOutbox::new(sqlite())),
+            EmailOutboxBackend::InMemory => Arc::new(InMemoryEmailOutbox::default()),
+        };
+        let email_queue = EmailQueue::new(email_outbox.clone());
+        let sms_client = Arc::new(CapturingSmsClient::default());
+
+        let mut redis_conns = Vec::new();
+        let hasher = KeyedHasher::from_settings(&configuration);
+        let token_store: TokenStoreType = match configuration.stores.banned_tokens {
+            StoreBackend::Redis => {
+                let redis_conn = test_redis_connection();
+                redis_conns.push(redis_conn.clone());
+                Arc::new(RwLock::new(RedisBannedTokenStore::new(
+                    redis_conn,
+                    configuration.auth.token_ttl_seconds,
+                    hasher.clone(),
+                )))
+            }
+            StoreBackend::Postgres => Arc::new(RwLock::new(PostgresBannedTokenStore::new(
+                pg_pool(),
+                configuration.auth.token_ttl_seconds,
+                hasher.clone(),
+            ))),
+            StoreBackend::InMemory => Arc::new(RwLock::new(HashsetBannedTokenStore::new(
+                configuration.auth.token_ttl_seconds,
+            ))),
+        };
+        let two_fa_code_store: TwoFACodeStoreType = match configuration.stores.two_fa_codes {
+            StoreBackend::Redis => {
+                let redis_conn = test_redis_connection();
+                redis_conns.push(redis_conn.clone());
+                Arc::new(RwLock::new(RedisTwoFACodeStore::new(
+                    redis_conn,
+                    hasher.clone(),
+                )))
+            }
+            StoreBackend::Postgres => {
+                Arc::new(RwLock::new(PostgresTwoFACodeStore::new(pg_pool(), hasher)))
+            }
+            StoreBackend::InMemory => Arc::new(RwLock::new(HashmapTwoFACodeStore::default())),
+        };
+        if !redis_conns.is_empty() {
+            health_checks.push(Arc::new(RedisHealthCheck::new(redis_conns)));
+        }
 
-        let app_state = AppState::new(
+        let mut app_state = AppState::new(
             user_store,
             token_store.clone(),
             two_fa_code_store.clone(),
-            email_client.clone(),
+            email_queue.clone(),
+            audit_sink.clone(),
+            Arc::new(health_checks),
             configuration.clone(),
+        )
+        .with_email_templates(
+            EmailTemplates::load(&configuration.email.templates)
+                .expect("Failed to load email templates"),
         );
+        if configuration.sms.enabled {
+            app_state = app_state.with_sms_client(sms_client.clone());
+        }
         let app: Application =
             Application::build(app_state.clone(), &configuration.test_app_address)
                 .await
@@ -71,12 +228,31 @@ impl TestApp {
         let address = format!("http://{}", app.address.clone());
 
         // separate async task to avoid blocking the main thread
-        #[allow(clippy::let_underscore_future)]
-        let _ = tokio::spawn(app.run());
+        let shutdown_handle = app.shutdown_handle();
+        let server = tokio::spawn(app.run());
+        if configuration.email.outbox.enabled {
+            let dispatcher = EmailDispatcher::new(
+                email_queue,
+                Arc::new(FailoverEmailClient::new(vec![(
+                    "mock",
+                    email_client.clone(),
+                )])),
+                &configuration.email.outbox,
+            );
+            tokio::spawn(dispatcher.run(shutdown_handle.clone()));
+        }
 
         let cookie_jar = Arc::new(Jar::default());
+        // behave like the frontend: fetch a CSRF token once and send it with every request
+        let csrf_token = fetch_csrf_token(&address, &configuration.csrf, &cookie_jar).await;
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(
+            HeaderName::from_str(&configuration.csrf.header_name).unwrap(),
+            HeaderValue::from_str(&csrf_token).unwrap(),
+        );
         let http_client = reqwest::Client::builder()
             .cookie_provider(cookie_jar.clone())
+            .default_headers(default_headers)
             .build()
             .unwrap();
 
@@ -87,11 +263,91 @@ impl TestApp {
             token_store,
             two_fa_code_store,
             email_client,
-            db_name,
+            email_outbox,
+            sms_client,
+            audit_sink,
+            audit_log_file,
+            database,
+            jwt_secret: configuration.jwt_secret,
+            shutdown_handle,
+            server,
             clean_up_called: false,
         }
     }
 
+    #[cfg(feature = "db-tests")]
+    pub fn db_pool(&self) -> &PgPool {
+        &self
+            .database
+            .as_ref()
+            .expect("This test app has no database; select a Postgres backend in its settings")
+            .pool
+    }
+
+    // The last email sent to `recipient`, waiting for the dispatcher to send it if needed
+    pub async fn wait_for_email_to(&self, recipient: &Email) -> CapturedEmail {
+        for _ in 0..50 {
+            if let Some(email) = self.email_client.last_email_to(recipient) {
+                return email;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("No email sent to {}", recipient.as_ref().expose_secret());
+    }
+
+    // The code of the last 2FA email sent to `email`, as the user would read it
+    pub async fn two_fa_code_sent_to(&self, email: &str) -> String {
+        let email = Email::parse(Secret::new(email.to_owned())).expect("Invalid email");
+        two_fa_code_in(&self.wait_for_email_to(&email).await)
+    }
+
+    // The codes of the first `count` emails sent to `email`, oldest first, waiting for them
+    // if needed. Stores only keep hashes, so this is how tests learn codes.
+    pub async fn two_fa_codes_sent_to(&self, email: &str, count: usize) -> Vec<String> {
+        let email = Email::parse(Secret::new(email.to_owned())).expect("Invalid email");
+        for _ in 0..50 {
+            let emails = self.email_client.query(&EmailQuery::to(&email));
+            if emails.len() >= count {
+                return emails
+                    .iter()
+                    .rev()
+                    .take(count)
+                    .map(two_fa_code_in)
+                    .collect();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!(
+            "Fewer than {} emails sent to {}",
+            count,
+            email.as_ref().expose_secret()
+        );
+    }
+
+    // The last text or call to `phone_number`; they are sent before the request returns
+    pub fn last_sms_to(&self, phone_number: &str) -> CapturedSms {
+        let phone_number =
+            PhoneNumber::parse(Secret::new(phone_number.to_owned())).expect("Invalid phone number");
+        self.sms_client
+            .last_message_to(&phone_number)
+            .unwrap_or_else(|| panic!("No SMS sent to {}", phone_number.masked()))
+    }
+
+    // The code of the last text or call to `phone_number`; calls read it out digit by digit
+    pub fn code_sent_to_phone(&self, phone_number: &str) -> String {
+        let digits: String = self
+            .last_sms_to(phone_number)
+            .body
+            .split('.')
+            .next()
+            .expect("Empty SMS")
+            .chars()
+            .filter(char::is_ascii_digit)
+            .collect();
+        assert_eq!(digits.len(), 6, "No 2FA code in the SMS");
+        digits
+    }
+
     pub async fn get_root(&self) -> reqwest::Response {
         self.http_client
             .get(&format!("{}/", &self.address))
@@ -100,6 +356,83 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn get_health(&self, probe: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/health/{}", &self.address, probe))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn preflight(
+        &self,
+        path: &str,
+        origin: &str,
+        method: &str,
+        headers: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .request(
+                reqwest::Method::OPTIONS,
+                format!("{}{}", &self.address, path),
+            )
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", method)
+            .header("Access-Control-Request-Headers", headers)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_csrf_token(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/csrf-token", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_dev_mailbox(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/dev/mailbox", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_metrics(&self, token: Option<&str>) -> reqwest::Response {
+        let mut request = self.http_client.get(format!("{}/metrics", &self.address));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_audit_events(
+        &self,
+        token: Option<&str>,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        let mut request = self
+            .http_client
+            .get(format!("{}/admin/audit-events", &self.address))
+            .query(query);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_email(&self, token: Option<&str>, id: &str) -> reqwest::Response {
+        let mut request = self
+            .http_client
+            .get(format!("{}/admin/emails/{}", &self.address, id));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.http_client
             .post(&format!("{}/logout", &self.address))
@@ -108,6 +441,15 @@ impl TestApp {
             .expect(" -> StringFailed to execute request")
     }
 
+    pub async fn post_logout_with_bearer(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/logout", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn post_verify_token<Body: serde::Serialize>(
         &self,
         body: &Body,
@@ -132,6 +474,23 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_signup_with_language<Body>(
+        &self,
+        body: &Body,
+        accept_language: &str,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/signup", &self.address))
+            .header(reqwest::header::ACCEPT_LANGUAGE, accept_language)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -144,6 +503,23 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_login_with_language<Body>(
+        &self,
+        body: &Body,
+        accept_language: &str,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/login", &self.address))
+            .header(reqwest::header::ACCEPT_LANGUAGE, accept_language)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_verify_2fa<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -156,12 +532,63 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_resend_2fa<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/resend-2fa", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_phone_number<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/phone-number", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_verify_phone_number<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/phone-number/verify", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_2fa_channel<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/2fa-channel", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn clean_up(&mut self) {
         if self.clean_up_called {
             return;
         }
-        if let Err(e) = delete_database(&self.db_name).await {
-            eprintln!("Failed to drop the database.: {:?}", e);
+        if let Some(database) = self.database.take() {
+            database.clean_up().await;
+        }
+        if let Some(audit_log_file) = self.audit_log_file.take() {
+            let _ = std::fs::remove_file(audit_log_file);
         }
 
         self.clean_up_called = true;
@@ -176,11 +603,103 @@ impl Drop for TestApp {
     }
 }
 
+// A migrated database of its own, for tests of the stores that need no running app
+pub struct TestDatabase {
+    pub pool: PgPool,
+    name: String,
+}
+
+impl TestDatabase {
+    pub async fn new() -> Self {
+        let configuration = get_configuration().expect("Failed to read configuration.");
+        let pool =
+            configure_postgresql(&configuration.test, configuration.postgres.max_connections).await;
+        let name = pool
+            .connect_options()
+            .get_database()
+            .expect("Failed to retrieve db name")
+            .to_owned();
+
+        Self { pool, name }
+    }
+
+    pub async fn clean_up(self) {
+        self.pool.close().await;
+        if let Err(e) = delete_database(&self.name).await {
+            eprintln!("Failed to drop the database.: {:?}", e);
+        }
+    }
+}
+
+// In-memory stores unless built with `--features db-tests`, which reads the configuration
+// and so uses Postgres and Redis from the [test] settings
+fn base_configuration() -> Settings {
+    let mut configuration = if cfg!(feature = "db-tests") {
+        get_configuration().expect("Failed to read configuration.")
+    } else {
+        let mut configuration = Settings::default();
+        configuration.stores.users = UserStoreBackend::InMemory;
+        configuration.stores.banned_tokens = StoreBackend::InMemory;
+        configuration.stores.two_fa_codes = StoreBackend::InMemory;
+        configuration.stores.email_outbox = EmailOutboxBackend::InMemory;
+        configuration.audit.sink = AuditSinkKind::InMemory;
+        // the webhook outbox needs Postgres
+        configuration.webhooks.enabled = false;
+        configuration
+    };
+    // emails, texts and calls are always captured by the `TestApp` clients
+    configuration.email.provider = EmailProvider::Mock;
+    configuration.sms.enabled = true;
+    configuration.sms.provider = SmsProvider::Mock;
+    configuration
+}
+
+// A connection to the test Redis; tests share it, so keys must be unique per test
+pub fn test_redis_connection() -> Arc<RwLock<redis::Connection>> {
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    Arc::new(RwLock::new(configure_redis(&configuration.test)))
+}
+
+async fn fetch_csrf_token(address: &str, settings: &CsrfSettings, cookie_jar: &Arc<Jar>) -> String {
+    let client = reqwest::Client::builder()
+        .cookie_provider(cookie_jar.clone())
+        .build()
+        .unwrap();
+
+    let response = client
+        .get(format!("{}/csrf-token", address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(
+        response
+            .cookies()
+            .any(|cookie| cookie.name() == settings.cookie_name),
+        "No CSRF cookie found"
+    );
+
+    response
+        .json::<CsrfTokenResponse>()
+        .await
+        .expect("Could not deserialize response body to CsrfTokenResponse")
+        .csrf_token
+}
+
+fn two_fa_code_in(email: &CapturedEmail) -> String {
+    email
+        .message
+        .text_body
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|word| word.len() == 6)
+        .expect("No 2FA code in the email")
+        .to_owned()
+}
+
 pub fn get_random_email() -> String {
     format!("{}@example.com", Uuid::new_v4())
 }
 
-async fn configure_postgresql(settings: &TestSettings) -> PgPool {
+async fn configure_postgresql(settings: &TestSettings, max_connections: u32) -> PgPool {
     let postgresql_conn_url = settings.test_database_url.to_owned();
 
     let db_name = Uuid::new_v4().to_string();
@@ -190,7 +709,7 @@ async fn configure_postgresql(settings: &TestSettings) -> PgPool {
     let postgresql_conn_url_with_db =
         format!("{}/{}", postgresql_conn_url.expose_secret(), db_name);
 
-    get_postgres_pool(&Secret::new(postgresql_conn_url_with_db))
+    get_postgres_pool(&Secret::new(postgresql_conn_url_with_db), max_connections)
         .await
         .expect("Failed to create Postgres connection pool!")
 }
@@ -222,14 +741,30 @@ async fn configure_database(db_conn_string: &str, db_name: &str) {
         .expect("Failed to migrate the database.");
 }
 
-fn configure_redis(settings: &TestSettings) -> redis::Connection {
-    let client = redis::Client::open(format!(
+// In memory, so every test app starts empty and nothing is left behind
+async fn configure_sqlite() -> SqlitePool {
+    let sqlite_pool = get_sqlite_pool(&Secret::new("sqlite::memory:".to_owned()), 5)
+        .await
+        .expect("Failed to create SQLite connection pool!");
+
+    sqlx::migrate!("./migrations_sqlite")
+        .run(&sqlite_pool)
+        .await
+        .expect("Failed to migrate the SQLite database.");
+
+    sqlite_pool
+}
+
+fn test_redis_client(settings: &TestSettings) -> redis::Client {
+    redis::Client::open(format!(
         "redis://{}:{}/",
         settings.test_redis_host_name, settings.test_redis_port
-    ));
+    ))
+    .expect("Failed to create Redis client")
+}
 
-    client
-        .expect("Failed to create Redis client")
+fn configure_redis(settings: &TestSettings) -> redis::Connection {
+    test_redis_client(settings)
         .get_connection()
         .expect("Failed to get Redis connection")
 }
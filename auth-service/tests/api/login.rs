@@ -1,5 +1,11 @@
 use auth_service::{
-    domain::email::Email, routes::TwoFactorAuthResponse, utils::constants::JWT_COOKIE_NAME,
+    domain::{
+        data_stores::{LoginAttemptId, TwoFACode},
+        email::Email,
+    },
+    routes::TwoFactorAuthResponse,
+    services::capturing_email_client::EmailQuery,
+    utils::constants::JWT_COOKIE_NAME,
 };
 
 use secrecy::{ExposeSecret, Secret};
@@ -69,15 +75,37 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
         .await
         .expect("Could not deserialize response body to TwoFactorAuthResponse");
 
+    let login_attempt_id = LoginAttemptId::parse(Secret::new(json_body.login_attempt_id)).unwrap();
+    let attempt = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_attempt(&login_attempt_id)
+        .await
+        .expect("No login attempt stored");
+    assert_eq!(attempt.email, random_email);
+    let code = app
+        .two_fa_code_sent_to(random_email.as_ref().expose_secret())
+        .await;
+    let emails = app.email_client.query(&EmailQuery::to(&random_email));
+    assert_eq!(emails.len(), 1);
+    let message = &emails[0].message;
+    assert_eq!(message.subject, "Your login code");
+    assert!(message
+        .text_body
+        .contains(&format!("Your login code is {}.", code)));
+    assert!(message.html_body.contains(&code));
+    // the emailed code is the stored one
+    let code = TwoFACode::parse(Secret::new(code)).unwrap();
+    assert!(app
+        .two_fa_code_store
+        .write()
+        .await
+        .consume_code(&login_attempt_id, &code, 1)
+        .await
+        .is_ok());
+
     app.clean_up().await;
-    let two_fa_code_store = app.two_fa_code_store.read().await;
-
-    let get_response = two_fa_code_store.get_code(&random_email).await;
-    assert!(get_response.is_ok());
-    assert_eq!(
-        get_response.unwrap().0.as_ref().expose_secret().to_owned(),
-        json_body.login_attempt_id
-    );
 }
 
 #[tokio::test]
@@ -132,3 +160,144 @@ async fn should_return_422_if_malformed_credentials() {
 
     app.clean_up().await
 }
+
+async fn signup_with_2fa(app: &TestApp) -> serde_json::Value {
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+    })
+}
+
+#[tokio::test]
+async fn should_send_the_2fa_email_in_the_preferred_language() {
+    let mut app = TestApp::new().await;
+    let login_body = signup_with_2fa(&app).await;
+
+    let response = app
+        .post_login_with_language(&login_body, "fr-CH, de;q=0.8, en;q=0.5")
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    let email = login_body["email"].as_str().unwrap();
+    let recipient = Email::parse(Secret::new(email.to_owned())).unwrap();
+    let message = app.wait_for_email_to(&recipient).await.message;
+    assert_eq!(message.subject, "Ihr Anmeldecode");
+    assert!(message.html_body.contains("<html lang=\"de\">"));
+    assert!(message
+        .text_body
+        .contains(&app.two_fa_code_sent_to(email).await));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_send_the_2fa_email_in_the_language_chosen_at_signup() {
+    let mut app = TestApp::new().await;
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    let response = app
+        .post_signup_with_language(&signup_body, "fr, de-AT;q=0.9")
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    // the stored locale wins over the language of the login request
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+    });
+    let response = app.post_login_with_language(&login_body, "en").await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    let recipient = Email::parse(Secret::new(email.clone())).unwrap();
+    let message = app.wait_for_email_to(&recipient).await.message;
+    assert_eq!(message.subject, "Ihr Anmeldecode");
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_send_the_2fa_email_from_overridden_templates() {
+    let dir = std::env::temp_dir().join(format!("email-templates-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("en")).unwrap();
+    std::fs::write(dir.join("en/two_fa_code.subject.txt"), "Acme sign-in").unwrap();
+    std::fs::write(dir.join("en/two_fa_code.txt"), "Acme code: {{ code }}").unwrap();
+    std::fs::write(dir.join("en/two_fa_code.html"), "<p>{{ code }}</p>").unwrap();
+    let templates_dir = dir.display().to_string();
+    let mut app = TestApp::with_settings(|settings| {
+        settings.email.templates.dir = Some(templates_dir);
+    })
+    .await;
+    let login_body = signup_with_2fa(&app).await;
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    let email = login_body["email"].as_str().unwrap();
+    let recipient = Email::parse(Secret::new(email.to_owned())).unwrap();
+    let message = app.wait_for_email_to(&recipient).await.message;
+    let code = app.two_fa_code_sent_to(email).await;
+    assert_eq!(message.subject, "Acme sign-in");
+    assert_eq!(message.text_body, format!("Acme code: {}", code));
+    assert_eq!(message.html_body, format!("<p>{}</p>", code));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_206_and_send_the_2fa_email_once_the_provider_recovers() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.email.outbox.base_delay_ms = 50;
+        settings.email.outbox.max_delay_secs = 1;
+    })
+    .await;
+    let login_body = signup_with_2fa(&app).await;
+    let recipient = Email::parse(Secret::new(
+        login_body["email"].as_str().unwrap().to_owned(),
+    ))
+    .unwrap();
+    app.email_client.set_unavailable(true);
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .unwrap()
+        .login_attempt_id;
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(app.email_client.last_email_to(&recipient).is_none());
+    assert_eq!(app.email_outbox.stats().await.unwrap().pending, 1);
+
+    app.email_client.set_unavailable(false);
+    let code = app
+        .two_fa_code_sent_to(recipient.as_ref().expose_secret())
+        .await;
+    let code = TwoFACode::parse(Secret::new(code)).unwrap();
+    assert!(app
+        .two_fa_code_store
+        .write()
+        .await
+        .consume_code(
+            &LoginAttemptId::parse(Secret::new(login_attempt_id)).unwrap(),
+            &code,
+            1
+        )
+        .await
+        .is_ok());
+
+    app.clean_up().await;
+}
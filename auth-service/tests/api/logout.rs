@@ -1,5 +1,8 @@
-use auth_service::utils::constants::JWT_COOKIE_NAME;
-use reqwest::Url;
+use auth_service::utils::{
+    configuration::{AuthCookieSettings, SameSiteMode},
+    constants::JWT_COOKIE_NAME,
+};
+use reqwest::{header::SET_COOKIE, Url};
 use secrecy::Secret;
 
 use crate::helpers::{get_random_email, TestApp};
@@ -42,15 +45,17 @@ async fn should_return_200_if_valid_jwt_cookie() {
         .expect("No auth cookie found");
     assert!(cookie.value().is_empty());
 
-    app.clean_up().await;
-
-    let token_store = app.token_store.read().await;
-    let get_token_response: bool = token_store
+    let get_token_response: bool = app
+        .token_store
+        .read()
+        .await
         .contains_token(Secret::new(token.to_owned()))
         .await
         .expect("Failed to check if token banned");
 
     assert!(get_token_response);
+
+    app.clean_up().await;
 }
 
 #[tokio::test]
@@ -136,3 +141,106 @@ async fn should_return_401_if_invalid_token() {
     assert_eq!(logout_response.status().as_u16(), 401);
     app.clean_up().await
 }
+
+#[tokio::test]
+async fn should_return_200_if_valid_bearer_token() {
+    let mut app = TestApp::new().await;
+    let email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    let signup_response = app.post_signup(&signup_body).await;
+    assert_eq!(signup_response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+    });
+    let login_response = app.post_login(&login_body).await;
+    assert_eq!(login_response.status().as_u16(), 200);
+
+    let token = login_response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    // a client without cookies authenticates with the header only
+    let bearer_client = reqwest::Client::new();
+    let logout_response = bearer_client
+        .post(format!("{}/logout", &app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(logout_response.status().as_u16(), 200);
+
+    let second_logout_response = app.post_logout_with_bearer(&token).await;
+    assert_eq!(second_logout_response.status().as_u16(), 401);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_remove_cookie_with_configured_attributes() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.auth.cookie = AuthCookieSettings {
+            same_site: SameSiteMode::Strict,
+            host_prefix: true,
+            ..AuthCookieSettings::default()
+        };
+    })
+    .await;
+    let email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    let signup_response = app.post_signup(&signup_body).await;
+    assert_eq!(signup_response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+    });
+    let login_response = app.post_login(&login_body).await;
+    assert_eq!(login_response.status().as_u16(), 200);
+
+    let set_cookie = set_cookie_header(&login_response, "__Host-jwt");
+    assert!(set_cookie.contains("Max-Age=600"), "{}", set_cookie);
+    assert!(set_cookie.contains("Secure"), "{}", set_cookie);
+    assert!(set_cookie.contains("HttpOnly"), "{}", set_cookie);
+    assert!(set_cookie.contains("SameSite=Strict"), "{}", set_cookie);
+    assert!(set_cookie.contains("Path=/"), "{}", set_cookie);
+    assert!(!set_cookie.contains("Domain"), "{}", set_cookie);
+
+    let logout_response = app.post_logout().await;
+    assert_eq!(logout_response.status().as_u16(), 200);
+
+    // browsers only drop the cookie if the removal matches its name, path and domain
+    let removal = set_cookie_header(&logout_response, "__Host-jwt");
+    assert!(removal.starts_with("__Host-jwt=;"), "{}", removal);
+    assert!(removal.contains("Max-Age=0"), "{}", removal);
+    assert!(removal.contains("Secure"), "{}", removal);
+    assert!(removal.contains("Path=/"), "{}", removal);
+    assert!(!removal.contains("Domain"), "{}", removal);
+
+    app.clean_up().await
+}
+
+fn set_cookie_header(response: &reqwest::Response, name: &str) -> String {
+    response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find(|value| value.starts_with(&format!("{}=", name)))
+        .expect("No auth cookie found")
+        .to_owned()
+}
@@ -1,7 +1,22 @@
+mod audit;
+mod auth_middleware;
+mod cors;
+mod csrf;
+mod dev_mailbox;
+mod health;
 mod helpers;
 mod login;
 mod logout;
+mod metrics;
+mod phone_number;
+#[cfg(feature = "db-tests")]
+mod postgres_stores;
+mod resend_2fa;
 mod root;
+mod shutdown;
 mod signup;
+mod store_conformance;
 mod verify_2fa;
 mod verify_token;
+#[cfg(feature = "db-tests")]
+mod webhooks;
@@ -0,0 +1,77 @@
+use auth_service::utils::configuration::UserStoreBackend;
+use secrecy::Secret;
+
+use crate::helpers::{get_random_email, TestApp};
+
+#[tokio::test]
+async fn should_expose_request_and_domain_metrics() {
+    // the store call metrics are labelled with the backend
+    let metrics_token = "m".repeat(32);
+    let mut app = TestApp::with_settings(|settings| {
+        settings.stores.users = UserStoreBackend::Sqlite;
+        settings.telemetry.metrics_token = Some(Secret::new(metrics_token.clone()));
+    })
+    .await;
+
+    let signup_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": signup_body["email"],
+        "password": "wrong-password",
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 401);
+
+    let response = app.get_metrics(Some(&metrics_token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = response.text().await.expect("Failed to read metrics body");
+    assert!(body.contains(r#"http_requests_total{method="POST",path="/signup",status="201"}"#));
+    assert!(body.contains("http_request_duration_seconds_bucket"));
+    assert!(body.contains("auth_signups_total"));
+    assert!(body.contains(r#"auth_logins_total{outcome="failure"}"#));
+    assert!(body.contains(r#"auth_password_hash_duration_seconds_count{operation="hash"}"#));
+    assert!(body.contains(
+        r#"auth_store_call_duration_seconds_count{backend="sqlite",operation="get_user"}"#
+    ));
+    // the password check is timed as hashing, not as store latency
+    assert!(body.contains(r#"auth_password_hash_duration_seconds_count{operation="verify"}"#));
+    assert!(!body.contains(r#"operation="validate_user""#));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_require_the_metrics_token() {
+    let metrics_token = "m".repeat(32);
+    let mut app = TestApp::with_settings(|settings| {
+        settings.telemetry.metrics_token = Some(Secret::new(metrics_token.clone()));
+    })
+    .await;
+
+    assert_eq!(app.get_metrics(None).await.status().as_u16(), 400);
+    let wrong_token = "x".repeat(32);
+    assert_eq!(
+        app.get_metrics(Some(&wrong_token)).await.status().as_u16(),
+        401
+    );
+    // the token must match exactly, not just start with it
+    let response = app.get_metrics(Some(&format!("{}-", metrics_token))).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_404_without_a_metrics_token() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_metrics(Some(&"m".repeat(32))).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    app.clean_up().await
+}
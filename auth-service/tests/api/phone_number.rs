@@ -0,0 +1,439 @@
+use auth_service::{
+    domain::TwoFAChannel,
+    routes::{StartPhoneVerificationResponse, TwoFactorAuthResponse},
+    services::capturing_sms_client::SmsKind,
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PHONE_NUMBER: &str = "+14155550100";
+
+// Signs up a user without 2FA and logs them in, so the app's cookie jar holds their token
+async fn logged_in_user(app: &TestApp) -> String {
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    let body = serde_json::json!({
+        "email": email,
+        "password": "pass1234"
+    });
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 200);
+    email
+}
+
+async fn verify_phone_number(app: &TestApp, phone_number: &str) {
+    let response = app
+        .post_phone_number(
+            &serde_json::json!({ "phoneNumber": phone_number, "password": "pass1234" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let code = app.code_sent_to_phone(phone_number);
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_verify_the_phone_number_with_the_texted_code() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+
+    let response = app
+        .post_phone_number(
+            &serde_json::json!({ "phoneNumber": "+1 (415) 555-0100", "password": "pass1234" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let message = response
+        .json::<StartPhoneVerificationResponse>()
+        .await
+        .expect("Could not deserialize response body to StartPhoneVerificationResponse")
+        .message;
+    assert_eq!(message, "Verification code sent to +*******0100");
+    assert_eq!(app.last_sms_to(PHONE_NUMBER).kind, SmsKind::Text);
+
+    let code = app.code_sent_to_phone(PHONE_NUMBER);
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // the code can only be used once
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_call_the_phone_number_if_voice_is_requested() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+
+    let response = app
+        .post_phone_number(&serde_json::json!({
+            "phoneNumber": PHONE_NUMBER,
+            "channel": "voice",
+            "password": "pass1234"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(app.last_sms_to(PHONE_NUMBER).kind, SmsKind::Call);
+
+    let code = app.code_sent_to_phone(PHONE_NUMBER);
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_400_if_invalid_input() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+
+    for body in [
+        serde_json::json!({ "phoneNumber": "4155550100", "password": "pass1234" }),
+        serde_json::json!({ "phoneNumber": "+1415555010012345", "password": "pass1234" }),
+    ] {
+        let response = app.post_phone_number(&body).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "Failed for input: {}",
+            body
+        );
+    }
+    // codes are texted or read out, never emailed
+    let response = app
+        .post_phone_number(&serde_json::json!({
+            "phoneNumber": PHONE_NUMBER,
+            "channel": "email",
+            "password": "pass1234"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": "12345" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(app.sms_client.sent_messages().is_empty());
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_require_authentication() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .post_phone_number(
+            &serde_json::json!({ "phoneNumber": PHONE_NUMBER, "password": "pass1234" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = app
+        .post_2fa_channel(&serde_json::json!({ "channel": "sms", "password": "pass1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(app.sms_client.sent_messages().is_empty());
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_reject_every_code_after_too_many_wrong_ones() {
+    let mut app =
+        TestApp::with_settings(|settings| settings.sms.max_verification_attempts = 2).await;
+    logged_in_user(&app).await;
+
+    let response = app
+        .post_phone_number(
+            &serde_json::json!({ "phoneNumber": PHONE_NUMBER, "password": "pass1234" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let code = app.code_sent_to_phone(PHONE_NUMBER);
+    let wrong_code = if code == "123456" { "654321" } else { "123456" };
+
+    for _ in 0..2 {
+        let response = app
+            .post_verify_phone_number(&serde_json::json!({ "code": wrong_code }))
+            .await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // a new code can be requested
+    verify_phone_number(&app, PHONE_NUMBER).await;
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_keep_no_code_if_the_provider_fails() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+    app.sms_client.set_unavailable(true);
+
+    let response = app
+        .post_phone_number(
+            &serde_json::json!({ "phoneNumber": PHONE_NUMBER, "password": "pass1234" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 500);
+
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": "123456" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_401_if_the_password_is_wrong() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+    verify_phone_number(&app, PHONE_NUMBER).await;
+    let sent = app.sms_client.sent_messages().len();
+
+    // a stolen session alone can't redirect codes to another number or channel
+    let response = app
+        .post_phone_number(&serde_json::json!({
+            "phoneNumber": "+442071838750",
+            "password": "wrong1234"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_2fa_channel(&serde_json::json!({ "channel": "sms", "password": "wrong1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(app.sms_client.sent_messages().len(), sent);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_429_if_a_code_is_requested_again_too_soon() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+    let body = serde_json::json!({ "phoneNumber": PHONE_NUMBER, "password": "pass1234" });
+
+    assert_eq!(app.post_phone_number(&body).await.status().as_u16(), 202);
+    let code = app.code_sent_to_phone(PHONE_NUMBER);
+    assert_eq!(app.post_phone_number(&body).await.status().as_u16(), 429);
+    assert_eq!(app.sms_client.sent_messages().len(), 1);
+
+    // the first code still works
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_429_once_a_user_reaches_the_daily_limit() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.sms.verification_resend_cooldown_seconds = 0;
+        settings.sms.max_verifications_per_user_per_day = 2;
+    })
+    .await;
+    logged_in_user(&app).await;
+
+    for phone_number in ["+14155550100", "+442071838750", "+4930901820"] {
+        let response = app
+            .post_phone_number(&serde_json::json!({
+                "phoneNumber": phone_number,
+                "password": "pass1234"
+            }))
+            .await;
+        let expected = if phone_number == "+4930901820" {
+            429
+        } else {
+            202
+        };
+        assert_eq!(response.status().as_u16(), expected);
+    }
+    assert_eq!(app.sms_client.sent_messages().len(), 2);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_429_once_a_number_reaches_the_daily_limit() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.sms.verification_resend_cooldown_seconds = 0;
+        settings.sms.max_verifications_per_number_per_day = 2;
+    })
+    .await;
+    let body = serde_json::json!({ "phoneNumber": PHONE_NUMBER, "password": "pass1234" });
+
+    // counted across users, so nobody can flood someone else's phone
+    for expected in [202, 202, 429] {
+        logged_in_user(&app).await;
+        assert_eq!(
+            app.post_phone_number(&body).await.status().as_u16(),
+            expected
+        );
+    }
+    assert_eq!(app.sms_client.sent_messages().len(), 2);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_text_the_code_in_the_language_chosen_at_signup() {
+    let mut app = TestApp::new().await;
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    let response = app.post_signup_with_language(&body, "de-AT").await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body = serde_json::json!({
+        "email": email,
+        "password": "pass1234"
+    });
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 200);
+
+    let response = app
+        .post_phone_number(&serde_json::json!({
+            "phoneNumber": PHONE_NUMBER,
+            "channel": "voice",
+            "password": "pass1234"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let call = app.last_sms_to(PHONE_NUMBER);
+    assert!(call.body.starts_with("Ihr Rusty-Auth-Code"));
+    assert_eq!(call.language.as_deref(), Some("de"));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_409_if_a_phone_channel_is_selected_without_a_verified_number() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+
+    for channel in ["sms", "voice"] {
+        let response = app
+            .post_2fa_channel(&serde_json::json!({ "channel": channel, "password": "pass1234" }))
+            .await;
+        assert_eq!(response.status().as_u16(), 409);
+    }
+    // a pending number is not enough
+    let response = app
+        .post_phone_number(
+            &serde_json::json!({ "phoneNumber": PHONE_NUMBER, "password": "pass1234" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let response = app
+        .post_2fa_channel(&serde_json::json!({ "channel": "sms", "password": "pass1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = app
+        .post_2fa_channel(&serde_json::json!({ "channel": "email", "password": "pass1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_send_2fa_codes_over_the_selected_channel() {
+    let mut app = TestApp::new().await;
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234"
+    });
+
+    // the first login is by email, as no phone number is verified yet
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response = response.json::<TwoFactorAuthResponse>().await.unwrap();
+    assert_eq!(response.channel, TwoFAChannel::Email);
+    let code = app.two_fa_code_sent_to(&email).await;
+    let verify_body = serde_json::json!({
+        "loginAttemptId": response.login_attempt_id,
+        "2FACode": code
+    });
+    assert_eq!(
+        app.post_verify_2fa(&verify_body).await.status().as_u16(),
+        200
+    );
+
+    verify_phone_number(&app, PHONE_NUMBER).await;
+    for (channel, kind) in [("sms", SmsKind::Text), ("voice", SmsKind::Call)] {
+        let response = app
+            .post_2fa_channel(&serde_json::json!({ "channel": channel, "password": "pass1234" }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        let response = app.post_login(&login_body).await;
+        assert_eq!(response.status().as_u16(), 206);
+        let response = response.json::<TwoFactorAuthResponse>().await.unwrap();
+        assert_eq!(response.channel.as_str(), channel);
+        assert_eq!(app.last_sms_to(PHONE_NUMBER).kind, kind);
+
+        let verify_body = serde_json::json!({
+            "loginAttemptId": response.login_attempt_id,
+            "2FACode": app.code_sent_to_phone(PHONE_NUMBER)
+        });
+        assert_eq!(
+            app.post_verify_2fa(&verify_body).await.status().as_u16(),
+            200
+        );
+    }
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_not_route_phone_requests_when_sms_is_disabled() {
+    let mut app = TestApp::with_settings(|settings| settings.sms.enabled = false).await;
+    logged_in_user(&app).await;
+
+    // the requests fall through to the static assets, which only serve GET
+    let response = app
+        .post_phone_number(
+            &serde_json::json!({ "phoneNumber": PHONE_NUMBER, "password": "pass1234" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 405);
+    let response = app
+        .post_2fa_channel(&serde_json::json!({ "channel": "sms", "password": "pass1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 405);
+
+    app.clean_up().await
+}
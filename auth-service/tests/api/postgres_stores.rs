@@ -0,0 +1,274 @@
+use std::time::Duration;
+
+use auth_service::{
+    domain::{
+        data_stores::{LoginAttempt, LoginAttemptId, TwoFACode, TwoFACodeStoreError},
+        Email,
+    },
+    routes::TwoFactorAuthResponse,
+    services::data_stores::expired_rows_purger::ExpiredRowsPurger,
+    utils::{configuration::StoreBackend, constants::JWT_COOKIE_NAME},
+};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn postgres_only_app() -> TestApp {
+    TestApp::with_settings(|settings| {
+        settings.stores.banned_tokens = StoreBackend::Postgres;
+        settings.stores.two_fa_codes = StoreBackend::Postgres;
+    })
+    .await
+}
+
+#[tokio::test]
+async fn should_verify_2fa_and_log_out_without_redis() {
+    let mut app = postgres_only_app().await;
+    let email = Email::parse(Secret::new(get_random_email())).unwrap();
+
+    let signup_body = serde_json::json!({
+        "email": email.as_ref().expose_secret(),
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    let signup_response = app.post_signup(&signup_body).await;
+    assert_eq!(signup_response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email.as_ref().expose_secret(),
+        "password": "pass1234"
+    });
+    let login_response = app.post_login(&login_body).await;
+    assert_eq!(login_response.status().as_u16(), 206);
+    let attempt_id = login_response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .unwrap()
+        .login_attempt_id;
+
+    let attempt = {
+        let two_fa_code_store = app.two_fa_code_store.read().await;
+        let attempt_id = LoginAttemptId::parse(Secret::new(attempt_id.clone())).unwrap();
+        two_fa_code_store.get_attempt(&attempt_id).await.unwrap()
+    };
+    assert_eq!(attempt.email, email);
+    let code = app
+        .two_fa_code_sent_to(email.as_ref().expose_secret())
+        .await;
+    // only hashes of the id and code are stored
+    let (id_hash, code_hash): (String, String) =
+        sqlx::query_as("SELECT login_attempt_id_hash, code_hash FROM two_fa_codes")
+            .fetch_one(app.db_pool())
+            .await
+            .unwrap();
+    assert!(!id_hash.contains(&attempt_id));
+    assert!(!code_hash.contains(&code));
+    let verify_two_fa_body = serde_json::json!({
+        "loginAttemptId": attempt_id,
+        "2FACode": code
+    });
+    let verify_two_fa_response = app.post_verify_2fa(&verify_two_fa_body).await;
+    assert_eq!(verify_two_fa_response.status().as_u16(), 200);
+
+    let token = verify_two_fa_response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    let logout_response = app.post_logout().await;
+    assert_eq!(logout_response.status().as_u16(), 200);
+    let token_hash: String = sqlx::query_scalar("SELECT token_hash FROM banned_tokens")
+        .fetch_one(app.db_pool())
+        .await
+        .unwrap();
+    assert_ne!(token_hash, token);
+
+    let is_banned = {
+        let token_store = app.token_store.read().await;
+        token_store
+            .contains_token(Secret::new(token.clone()))
+            .await
+            .unwrap()
+    };
+    assert!(is_banned);
+    let second_logout_response = app.post_logout_with_bearer(&token).await;
+    assert_eq!(second_logout_response.status().as_u16(), 401);
+
+    // readiness no longer depends on Redis
+    let response = app.get_health("ready").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["checks"]["redis"].is_null());
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_only_store_a_hash_of_the_phone_verification_code() {
+    let mut app = postgres_only_app().await;
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    let body = serde_json::json!({ "email": email, "password": "pass1234" });
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 200);
+
+    let body = serde_json::json!({ "phoneNumber": "+14155550100", "password": "pass1234" });
+    assert_eq!(app.post_phone_number(&body).await.status().as_u16(), 202);
+    let code = app.code_sent_to_phone("+14155550100");
+
+    let code_hash: String =
+        sqlx::query_scalar("SELECT phone_verification_code_hash FROM users WHERE email = $1")
+            .bind(&email)
+            .fetch_one(app.db_pool())
+            .await
+            .unwrap();
+    assert_eq!(code_hash.len(), 64);
+    assert!(!code_hash.contains(&code));
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_ignore_expired_rows_before_they_are_purged() {
+    let mut app = postgres_only_app().await;
+    let email = Email::parse(Secret::new(get_random_email())).unwrap();
+    let attempt_id = LoginAttemptId::default();
+
+    {
+        let mut two_fa_code_store = app.two_fa_code_store.write().await;
+        two_fa_code_store
+            .add_code(
+                attempt_id.clone(),
+                LoginAttempt::new(email, Duration::from_secs(60)),
+                TwoFACode::default(),
+            )
+            .await
+            .unwrap();
+    }
+    {
+        let mut token_store = app.token_store.write().await;
+        token_store
+            .store_token(Secret::new("expired-token".to_owned()))
+            .await
+            .unwrap();
+    }
+    for table in ["two_fa_codes", "banned_tokens"] {
+        sqlx::query(&format!(
+            "UPDATE {} SET expires_at = now() - interval '1 second'",
+            table
+        ))
+        .execute(app.db_pool())
+        .await
+        .unwrap();
+    }
+
+    let result = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_attempt(&attempt_id)
+        .await;
+    assert_eq!(
+        result.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+    let is_banned = app
+        .token_store
+        .read()
+        .await
+        .contains_token(Secret::new("expired-token".to_owned()))
+        .await
+        .unwrap();
+    assert!(!is_banned);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_purge_only_expired_rows() {
+    let mut app = postgres_only_app().await;
+
+    sqlx::query(
+        r#"
+            INSERT INTO banned_tokens (token_hash, expires_at) VALUES
+                ('expired', now() - interval '1 minute'),
+                ('active', now() + interval '1 minute')
+        "#,
+    )
+    .execute(app.db_pool())
+    .await
+    .unwrap();
+    sqlx::query(
+        r#"
+            INSERT INTO two_fa_codes
+                (login_attempt_id_hash, email, code_hash, sent_at, expires_at)
+            VALUES
+                ('expired', 'user@example.com', '123456', now(), now() - interval '1 minute'),
+                ('active', 'user@example.com', '123456', now(), now() + interval '1 minute')
+        "#,
+    )
+    .execute(app.db_pool())
+    .await
+    .unwrap();
+
+    let purger = ExpiredRowsPurger::new(app.db_pool().clone(), Duration::from_secs(60));
+    assert_eq!(purger.purge_expired().await.unwrap(), 2);
+    assert_eq!(purger.purge_expired().await.unwrap(), 0);
+
+    let remaining: Vec<String> = sqlx::query_scalar(
+        "SELECT token_hash FROM banned_tokens UNION ALL SELECT login_attempt_id_hash FROM two_fa_codes",
+    )
+    .fetch_all(app.db_pool())
+    .await
+    .unwrap();
+    assert_eq!(remaining, vec!["active", "active"]);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_upgrade_imported_password_hashes_on_login() {
+    let mut app = postgres_only_app().await;
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    // as if imported from a system that used bcrypt
+    sqlx::query("UPDATE users SET password_hash = $2 WHERE email = $1")
+        .bind(&email)
+        .bind(bcrypt::hash("password123", 4).unwrap())
+        .execute(app.db_pool())
+        .await
+        .unwrap();
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123"
+    });
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+
+    let password_hash: String =
+        sqlx::query_scalar("SELECT password_hash FROM users WHERE email = $1")
+            .bind(&email)
+            .fetch_one(app.db_pool())
+            .await
+            .unwrap();
+    assert!(password_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+
+    app.clean_up().await
+}
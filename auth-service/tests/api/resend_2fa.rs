@@ -0,0 +1,186 @@
+use auth_service::{
+    domain::TwoFAChannel,
+    routes::{Resend2FAResponse, TwoFactorAuthResponse},
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+// Signs up a user with 2FA and logs them in, returning the email and login attempt id
+async fn pending_login(app: &TestApp) -> (String, String) {
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    let body = serde_json::json!({
+        "email": email,
+        "password": "pass1234"
+    });
+    let response = app.post_login(&body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    (email, login_attempt_id)
+}
+
+#[tokio::test]
+async fn should_send_a_new_code_that_replaces_the_previous_one() {
+    let mut app =
+        TestApp::with_settings(|settings| settings.auth.two_fa_resend_cooldown_seconds = 0).await;
+    let (email, login_attempt_id) = pending_login(&app).await;
+    let old_code = app.two_fa_code_sent_to(&email).await;
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({ "loginAttemptId": login_attempt_id }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = response
+        .json::<Resend2FAResponse>()
+        .await
+        .expect("Could not deserialize response body to Resend2FAResponse");
+    assert_eq!(response.channel, TwoFAChannel::Email);
+
+    let new_code = app.two_fa_codes_sent_to(&email, 2).await.remove(1);
+    if new_code != old_code {
+        let response = app
+            .post_verify_2fa(&serde_json::json!({
+                "loginAttemptId": login_attempt_id,
+                "2FACode": old_code
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": new_code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_429_during_the_cooldown() {
+    let mut app = TestApp::new().await;
+    let (email, login_attempt_id) = pending_login(&app).await;
+    let code = app.two_fa_code_sent_to(&email).await;
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({ "loginAttemptId": login_attempt_id }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    // the pending code still works
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_429_after_the_maximum_resends() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.auth.two_fa_resend_cooldown_seconds = 0;
+        settings.auth.two_fa_max_resends = 2;
+    })
+    .await;
+    let (email, login_attempt_id) = pending_login(&app).await;
+
+    for status in [200, 200, 429] {
+        let response = app
+            .post_resend_2fa(&serde_json::json!({ "loginAttemptId": login_attempt_id }))
+            .await;
+        assert_eq!(response.status().as_u16(), status);
+    }
+
+    // the last code sent still works
+    let code = app.two_fa_codes_sent_to(&email, 3).await.remove(2);
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_not_extend_the_expiry_of_the_login_attempt() {
+    let mut app = TestApp::with_settings(|settings| {
+        settings.auth.two_fa_code_ttl_seconds = 2;
+        settings.auth.two_fa_resend_cooldown_seconds = 0;
+    })
+    .await;
+    let (email, login_attempt_id) = pending_login(&app).await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    let response = app
+        .post_resend_2fa(&serde_json::json!({ "loginAttemptId": login_attempt_id }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let code = app.two_fa_codes_sent_to(&email, 2).await.remove(1);
+
+    // past the first code's expiry, though the new one was sent less than its TTL ago
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_401_if_the_login_attempt_is_unknown() {
+    let mut app =
+        TestApp::with_settings(|settings| settings.auth.two_fa_resend_cooldown_seconds = 0).await;
+    let (email, login_attempt_id) = pending_login(&app).await;
+    let code = app.two_fa_code_sent_to(&email).await;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    for login_attempt_id in [login_attempt_id, uuid::Uuid::new_v4().to_string()] {
+        let response = app
+            .post_resend_2fa(&serde_json::json!({ "loginAttemptId": login_attempt_id }))
+            .await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_400_if_invalid_input() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({ "loginAttemptId": "123123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = app.post_resend_2fa(&serde_json::json!({})).await;
+    assert_eq!(response.status().as_u16(), 422);
+
+    app.clean_up().await
+}
@@ -11,3 +11,32 @@ async fn root_returns_auth_ui() {
 
     app.clean_up().await
 }
+
+#[tokio::test]
+async fn should_echo_incoming_request_id() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .http_client
+        .get(format!("{}/", &app.address))
+        .header("x-request-id", "req-1234")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.headers().get("x-request-id").unwrap(), "req-1234");
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_return_generated_request_id() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_root().await;
+
+    let request_id = response.headers().get("x-request-id").unwrap();
+    assert!(uuid::Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+
+    app.clean_up().await
+}
@@ -0,0 +1,174 @@
+use std::{sync::Arc, time::Duration};
+
+use auth_service::{
+    domain::{
+        data_stores::{PhoneVerificationSends, UserStore, UserStoreError},
+        password::Password,
+        Email, PhoneNumber, PhoneVerification, TwoFAChannel, User,
+    },
+    services::data_stores::hashmap_user_store::HashmapUserStore,
+    utils::configuration::PasswordHashingSettings,
+};
+use chrono::{DateTime, Utc};
+use tokio::{
+    net::TcpStream,
+    sync::{Notify, RwLock},
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+#[tokio::test]
+async fn ready_should_return_503_while_draining() {
+    let mut app = TestApp::new().await;
+
+    app.shutdown_handle.trigger();
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "draining");
+
+    // liveness is unaffected
+    assert_eq!(app.get_health("live").await.status().as_u16(), 200);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn server_should_stop_after_shutdown_is_triggered() {
+    let mut app = TestApp::new().await;
+
+    app.shutdown_handle.trigger();
+
+    let result = tokio::time::timeout(Duration::from_secs(5), &mut app.server)
+        .await
+        .expect("Server did not stop in time")
+        .expect("Server task panicked");
+    assert!(result.is_ok());
+
+    let response = app
+        .http_client
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await;
+    assert!(response.is_err());
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn in_flight_request_should_complete_during_drain() {
+    let entered = Arc::new(Notify::new());
+    let release = Arc::new(Notify::new());
+    let user_store = GatedUserStore {
+        inner: HashmapUserStore::new(PasswordHashingSettings::default()),
+        entered: entered.clone(),
+        release: release.clone(),
+    };
+    let mut app = TestApp::with_user_store(Arc::new(RwLock::new(user_store))).await;
+
+    let signup_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "pass1234",
+        "requires2FA": true
+    });
+
+    let (response, _) = tokio::join!(app.post_signup(&signup_body), async {
+        // the signup handler is now parked inside the user store
+        entered.notified().await;
+        app.shutdown_handle.trigger();
+
+        // only let it finish once the server has stopped accepting connections
+        let address = app.address.trim_start_matches("http://").to_owned();
+        while TcpStream::connect(&address).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        release.notify_one();
+    });
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    app.clean_up().await
+}
+
+// Parks `add_user` until released, so a request is reliably in flight during shutdown
+struct GatedUserStore {
+    inner: HashmapUserStore,
+    entered: Arc<Notify>,
+    release: Arc<Notify>,
+}
+
+#[async_trait::async_trait]
+impl UserStore for GatedUserStore {
+    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
+        self.entered.notify_one();
+        self.release.notified().await;
+        self.inner.add_user(user).await
+    }
+
+    async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
+        self.inner.get_user(email).await
+    }
+
+    async fn validate_user(
+        &self,
+        email: &Email,
+        password: &Password,
+    ) -> Result<(), UserStoreError> {
+        self.inner.validate_user(email, password).await
+    }
+
+    async fn set_phone_verification(
+        &mut self,
+        email: &Email,
+        verification: Option<PhoneVerification>,
+    ) -> Result<(), UserStoreError> {
+        self.inner.set_phone_verification(email, verification).await
+    }
+
+    async fn set_phone_number(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+    ) -> Result<(), UserStoreError> {
+        self.inner.set_phone_number(email, phone_number).await
+    }
+
+    async fn set_two_fa_channel(
+        &mut self,
+        email: &Email,
+        channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError> {
+        self.inner.set_two_fa_channel(email, channel).await
+    }
+
+    async fn record_login(
+        &mut self,
+        email: &Email,
+        logged_in_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        self.inner.record_login(email, logged_in_at).await
+    }
+
+    async fn record_phone_verification_sent(
+        &mut self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), UserStoreError> {
+        self.inner
+            .record_phone_verification_sent(email, phone_number, sent_at)
+            .await
+    }
+
+    async fn phone_verifications_sent_since(
+        &self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        since: DateTime<Utc>,
+    ) -> Result<PhoneVerificationSends, UserStoreError> {
+        self.inner
+            .phone_verifications_sent_since(email, phone_number, since)
+            .await
+    }
+}
@@ -0,0 +1,155 @@
+use std::{future::Future, sync::Arc};
+
+#[cfg(feature = "db-tests")]
+use auth_service::services::data_stores::{
+    postgres_banned_token_store::PostgresBannedTokenStore,
+    redis_banned_token_store::RedisBannedTokenStore,
+};
+use auth_service::{
+    app_state::TokenStoreType,
+    services::data_stores::hashset_banned_token_store::HashsetBannedTokenStore,
+};
+use secrecy::Secret;
+use tokio::{sync::RwLock, task::JoinSet};
+use uuid::Uuid;
+
+#[cfg(feature = "db-tests")]
+use super::{separate_stores, test_hasher};
+use super::{CONCURRENT_TASKS, LONG_TTL_SECONDS, PAST_SHORT_TTL, SHORT_TTL_SECONDS};
+#[cfg(feature = "db-tests")]
+use crate::helpers::{test_redis_connection, TestDatabase};
+
+// `new_store(ttl_seconds)` must return a store that shares nothing with earlier ones,
+// apart from entries with other tokens. `new_handles(ttl_seconds)` must return
+// `CONCURRENT_TASKS` separate stores over the same storage, like the instances of a deployment.
+async fn conformance<F, Fut, H, HFut>(new_store: F, new_handles: H)
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = TokenStoreType>,
+    H: Fn(u64) -> HFut,
+    HFut: Future<Output = Vec<TokenStoreType>>,
+{
+    unknown_token_is_not_banned(new_store(LONG_TTL_SECONDS).await).await;
+    stored_token_is_banned(new_store(LONG_TTL_SECONDS).await).await;
+    storing_a_token_twice_is_not_an_error(new_store(LONG_TTL_SECONDS).await).await;
+    banned_token_expires(new_store(SHORT_TTL_SECONDS).await).await;
+    concurrent_bans_are_all_kept(new_handles(LONG_TTL_SECONDS).await).await;
+}
+
+fn random_token() -> Secret<String> {
+    Secret::new(Uuid::new_v4().to_string())
+}
+
+async fn unknown_token_is_not_banned(store: TokenStoreType) {
+    let store = store.read().await;
+
+    assert!(!store.contains_token(random_token()).await.unwrap());
+}
+
+async fn stored_token_is_banned(store: TokenStoreType) {
+    let mut store = store.write().await;
+    let token = random_token();
+
+    store.store_token(token.clone()).await.unwrap();
+
+    assert!(store.contains_token(token).await.unwrap());
+    assert!(!store.contains_token(random_token()).await.unwrap());
+}
+
+async fn storing_a_token_twice_is_not_an_error(store: TokenStoreType) {
+    let mut store = store.write().await;
+    let token = random_token();
+
+    store.store_token(token.clone()).await.unwrap();
+    store.store_token(token.clone()).await.unwrap();
+
+    assert!(store.contains_token(token).await.unwrap());
+}
+
+async fn banned_token_expires(store: TokenStoreType) {
+    let token = random_token();
+    store
+        .write()
+        .await
+        .store_token(token.clone())
+        .await
+        .unwrap();
+
+    tokio::time::sleep(PAST_SHORT_TTL).await;
+
+    assert!(!store.read().await.contains_token(token).await.unwrap());
+}
+
+async fn concurrent_bans_are_all_kept(stores: Vec<TokenStoreType>) {
+    let tokens: Vec<Secret<String>> = (0..CONCURRENT_TASKS).map(|_| random_token()).collect();
+
+    let mut tasks = JoinSet::new();
+    for (store, token) in stores.iter().cloned().zip(tokens.clone()) {
+        tasks.spawn(async move { store.write().await.store_token(token).await });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result.unwrap().unwrap();
+    }
+
+    for store in &stores {
+        let store = store.read().await;
+        for token in &tokens {
+            assert!(store.contains_token(token.clone()).await.unwrap());
+        }
+    }
+}
+
+#[tokio::test]
+async fn hashset_banned_token_store_conforms() {
+    let new_store = |ttl_seconds| async move {
+        Arc::new(RwLock::new(HashsetBannedTokenStore::new(ttl_seconds))) as TokenStoreType
+    };
+
+    // the set lives in the process, so instances can only share it through one store
+    conformance(new_store, |ttl_seconds| async move {
+        vec![new_store(ttl_seconds).await; CONCURRENT_TASKS]
+    })
+    .await;
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn redis_banned_token_store_conforms() {
+    // each store has its own connection; the shared test Redis keeps tokens of other cases apart
+    let new_store = |ttl_seconds| async move {
+        Arc::new(RwLock::new(RedisBannedTokenStore::new(
+            test_redis_connection(),
+            ttl_seconds,
+            test_hasher(),
+        ))) as TokenStoreType
+    };
+
+    conformance(new_store, |ttl_seconds| {
+        separate_stores(move || new_store(ttl_seconds))
+    })
+    .await;
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn postgres_banned_token_store_conforms() {
+    let db = TestDatabase::new().await;
+
+    let new_store = |ttl_seconds| {
+        let pool = db.pool.clone();
+        async move {
+            Arc::new(RwLock::new(PostgresBannedTokenStore::new(
+                pool,
+                ttl_seconds,
+                test_hasher(),
+            ))) as TokenStoreType
+        }
+    };
+
+    conformance(new_store, |ttl_seconds| {
+        separate_stores(move || new_store(ttl_seconds))
+    })
+    .await;
+
+    db.clean_up().await;
+}
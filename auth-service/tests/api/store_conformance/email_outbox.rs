@@ -0,0 +1,304 @@
+use std::{collections::HashSet, future::Future, sync::Arc, time::Duration};
+
+#[cfg(feature = "db-tests")]
+use auth_service::services::data_stores::postgres_email_outbox::PostgresEmailOutbox;
+use auth_service::{
+    app_state::EmailOutboxType,
+    domain::{
+        email_outbox::{EmailDeliveryStatus, EmailOutboxError, QueuedEmail, EXPIRED_ERROR},
+        Email, EmailMessage,
+    },
+    get_sqlite_pool,
+    services::data_stores::{
+        in_memory_email_outbox::InMemoryEmailOutbox, sqlite_email_outbox::SqliteEmailOutbox,
+    },
+};
+use chrono::Utc;
+use secrecy::Secret;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use super::CONCURRENT_TASKS;
+use crate::helpers::get_random_email;
+#[cfg(feature = "db-tests")]
+use crate::helpers::TestDatabase;
+
+const LEASE: Duration = Duration::from_secs(60);
+
+// `new_outbox()` must return an empty outbox that shares nothing with earlier ones
+async fn conformance<F, Fut>(new_outbox: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = EmailOutboxType>,
+{
+    enqueued_email_is_claimed_once(new_outbox().await).await;
+    due_emails_are_claimed_oldest_first(new_outbox().await).await;
+    failed_email_is_claimed_again_when_due(new_outbox().await).await;
+    dead_email_is_not_claimed(new_outbox().await).await;
+    sent_email_keeps_its_status_until_purged(new_outbox().await).await;
+    email_is_claimed_again_after_its_lease(new_outbox().await).await;
+    overdue_email_is_expired_instead_of_claimed(new_outbox().await).await;
+    concurrent_claims_are_disjoint(new_outbox().await).await;
+}
+
+fn random_email() -> Email {
+    Email::parse(Secret::new(get_random_email())).unwrap()
+}
+
+fn message(subject: &str) -> EmailMessage {
+    EmailMessage {
+        subject: subject.to_owned(),
+        text_body: format!("{} text", subject),
+        html_body: format!("<p>{}</p>", subject),
+    }
+}
+
+async fn enqueue(outbox: &EmailOutboxType, subject: &str) -> Uuid {
+    outbox
+        .enqueue(&random_email(), &message(subject), None)
+        .await
+        .unwrap()
+}
+
+async fn claim(outbox: &EmailOutboxType, lease: Duration) -> Option<QueuedEmail> {
+    outbox.claim_next(Utc::now(), lease).await.unwrap()
+}
+
+async fn enqueued_email_is_claimed_once(outbox: EmailOutboxType) {
+    let recipient = random_email();
+    let expires_at = Utc::now() + chrono::Duration::minutes(10);
+    let id = outbox
+        .enqueue(&recipient, &message("hello"), Some(expires_at))
+        .await
+        .unwrap();
+
+    let claimed = claim(&outbox, LEASE).await.unwrap();
+    assert_eq!(claimed.id, id);
+    assert_eq!(claimed.recipient, recipient);
+    assert_eq!(claimed.message, message("hello"));
+    assert_eq!(claimed.attempts, 1);
+    // microseconds in PostgreSQL
+    assert!(claimed
+        .expires_at
+        .is_some_and(|at| (at - expires_at).num_milliseconds().abs() < 1));
+    assert!(claim(&outbox, LEASE).await.is_none());
+
+    let delivery = outbox.delivery(id).await.unwrap().unwrap();
+    assert_eq!(delivery.status, EmailDeliveryStatus::Pending);
+    assert_eq!(delivery.attempts, 1);
+    assert_eq!(outbox.stats().await.unwrap().pending, 1);
+}
+
+async fn due_emails_are_claimed_oldest_first(outbox: EmailOutboxType) {
+    let mut ids = Vec::new();
+    for subject in ["first", "second", "third"] {
+        ids.push(enqueue(&outbox, subject).await);
+    }
+    let before_the_third = outbox.delivery(ids[2]).await.unwrap().unwrap().created_at
+        - chrono::Duration::microseconds(1);
+
+    let mut claimed = Vec::new();
+    while let Some(email) = outbox.claim_next(before_the_third, LEASE).await.unwrap() {
+        claimed.push(email.id);
+    }
+    assert_eq!(claimed, ids[..2]);
+    assert_eq!(claim(&outbox, LEASE).await.unwrap().id, ids[2]);
+}
+
+async fn failed_email_is_claimed_again_when_due(outbox: EmailOutboxType) {
+    let id = enqueue(&outbox, "retry").await;
+    let claimed = claim(&outbox, LEASE).await.unwrap();
+
+    outbox
+        .mark_failed(
+            id,
+            claimed.claim_token,
+            "provider unavailable",
+            Some(Utc::now()),
+        )
+        .await
+        .unwrap();
+    let claimed = claim(&outbox, LEASE).await.unwrap();
+    assert_eq!(claimed.attempts, 2);
+
+    outbox
+        .mark_failed(
+            id,
+            claimed.claim_token,
+            "still unavailable",
+            Some(Utc::now() + chrono::Duration::hours(1)),
+        )
+        .await
+        .unwrap();
+    assert!(claim(&outbox, LEASE).await.is_none());
+    let delivery = outbox.delivery(id).await.unwrap().unwrap();
+    assert_eq!(delivery.status, EmailDeliveryStatus::Pending);
+    assert_eq!(delivery.last_error.as_deref(), Some("still unavailable"));
+}
+
+async fn dead_email_is_not_claimed(outbox: EmailOutboxType) {
+    let id = enqueue(&outbox, "dead").await;
+    let claimed = claim(&outbox, LEASE).await.unwrap();
+
+    outbox
+        .mark_failed(id, claimed.claim_token, "rejected", None)
+        .await
+        .unwrap();
+
+    assert!(claim(&outbox, LEASE).await.is_none());
+    let delivery = outbox.delivery(id).await.unwrap().unwrap();
+    assert_eq!(delivery.status, EmailDeliveryStatus::Dead);
+    let stats = outbox.stats().await.unwrap();
+    assert_eq!((stats.pending, stats.dead), (0, 1));
+    assert_eq!(stats.oldest_pending_at, None);
+    // kept for inspection
+    assert_eq!(outbox.purge_sent(Utc::now()).await.unwrap(), 0);
+}
+
+async fn sent_email_keeps_its_status_until_purged(outbox: EmailOutboxType) {
+    let id = enqueue(&outbox, "sent").await;
+    let claimed = claim(&outbox, LEASE).await.unwrap();
+
+    outbox
+        .mark_sent(id, claimed.claim_token, "postmark")
+        .await
+        .unwrap();
+    // an outcome is recorded once per claim
+    assert!(matches!(
+        outbox.mark_sent(id, claimed.claim_token, "postmark").await,
+        Err(EmailOutboxError::ClaimExpired)
+    ));
+
+    let delivery = outbox.delivery(id).await.unwrap().unwrap();
+    assert_eq!(delivery.status, EmailDeliveryStatus::Sent);
+    assert_eq!(delivery.provider.as_deref(), Some("postmark"));
+    assert!(delivery.sent_at.is_some());
+    assert_eq!(outbox.stats().await.unwrap().pending, 0);
+
+    let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+    assert_eq!(outbox.purge_sent(an_hour_ago).await.unwrap(), 0);
+    let in_an_hour = Utc::now() + chrono::Duration::hours(1);
+    assert_eq!(outbox.purge_sent(in_an_hour).await.unwrap(), 1);
+    assert!(outbox.delivery(id).await.unwrap().is_none());
+}
+
+async fn email_is_claimed_again_after_its_lease(outbox: EmailOutboxType) {
+    let id = enqueue(&outbox, "lease").await;
+    let lease = Duration::from_millis(100);
+
+    let first = claim(&outbox, lease).await.unwrap();
+    assert!(claim(&outbox, lease).await.is_none());
+    tokio::time::sleep(lease * 2).await;
+
+    let second = claim(&outbox, lease).await.unwrap();
+    assert_eq!((second.id, second.attempts), (id, 2));
+    assert_ne!(second.claim_token, first.claim_token);
+
+    // the first dispatcher's outcome arrives too late to be recorded
+    assert!(matches!(
+        outbox.mark_sent(id, first.claim_token, "postmark").await,
+        Err(EmailOutboxError::ClaimExpired)
+    ));
+    assert!(matches!(
+        outbox
+            .mark_failed(id, first.claim_token, "timed out", None)
+            .await,
+        Err(EmailOutboxError::ClaimExpired)
+    ));
+    outbox
+        .mark_sent(id, second.claim_token, "smtp")
+        .await
+        .unwrap();
+    let delivery = outbox.delivery(id).await.unwrap().unwrap();
+    assert_eq!(delivery.provider.as_deref(), Some("smtp"));
+}
+
+async fn overdue_email_is_expired_instead_of_claimed(outbox: EmailOutboxType) {
+    let expired = outbox
+        .enqueue(&random_email(), &message("expired"), Some(Utc::now()))
+        .await
+        .unwrap();
+    let later = Utc::now() + chrono::Duration::hours(1);
+    let current = outbox
+        .enqueue(&random_email(), &message("current"), Some(later))
+        .await
+        .unwrap();
+
+    assert_eq!(claim(&outbox, LEASE).await.unwrap().id, current);
+    assert!(claim(&outbox, LEASE).await.is_none());
+
+    assert_eq!(outbox.expire_overdue().await.unwrap(), 1);
+    assert_eq!(outbox.expire_overdue().await.unwrap(), 0);
+    let delivery = outbox.delivery(expired).await.unwrap().unwrap();
+    assert_eq!(delivery.status, EmailDeliveryStatus::Dead);
+    assert_eq!(delivery.attempts, 0);
+    assert_eq!(delivery.last_error.as_deref(), Some(EXPIRED_ERROR));
+    assert_eq!(outbox.stats().await.unwrap().dead, 1);
+}
+
+async fn concurrent_claims_are_disjoint(outbox: EmailOutboxType) {
+    for _ in 0..CONCURRENT_TASKS {
+        enqueue(&outbox, "concurrent").await;
+    }
+
+    let mut tasks = JoinSet::new();
+    for _ in 0..4 {
+        let outbox = outbox.clone();
+        tasks.spawn(async move {
+            let mut claimed = Vec::new();
+            while let Some(email) = claim(&outbox, LEASE).await {
+                claimed.push(email.id);
+            }
+            claimed
+        });
+    }
+    let mut claimed = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        claimed.extend(result.unwrap());
+    }
+
+    assert_eq!(claimed.len(), CONCURRENT_TASKS);
+    assert_eq!(
+        claimed.iter().collect::<HashSet<_>>().len(),
+        CONCURRENT_TASKS
+    );
+}
+
+#[tokio::test]
+async fn in_memory_email_outbox_conforms() {
+    conformance(|| async { Arc::new(InMemoryEmailOutbox::default()) as EmailOutboxType }).await;
+}
+
+#[tokio::test]
+async fn sqlite_email_outbox_conforms() {
+    conformance(|| async {
+        let pool = get_sqlite_pool(&Secret::new("sqlite::memory:".to_owned()), 5)
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations_sqlite")
+            .run(&pool)
+            .await
+            .unwrap();
+        Arc::new(SqliteEmailOutbox::new(pool)) as EmailOutboxType
+    })
+    .await;
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn postgres_email_outbox_conforms() {
+    // the outbox is one table, so every case gets a database of its own
+    let databases = tokio::sync::Mutex::new(Vec::new());
+
+    conformance(|| async {
+        let db = TestDatabase::new().await;
+        let outbox = Arc::new(PostgresEmailOutbox::new(db.pool.clone())) as EmailOutboxType;
+        databases.lock().await.push(db);
+        outbox
+    })
+    .await;
+
+    for db in databases.into_inner() {
+        db.clean_up().await;
+    }
+}
@@ -0,0 +1,40 @@
+// Behaviour every backend of a store trait must share. Each trait has a generic `conformance`
+// suite that takes a constructor for fresh stores, and one test per backend that runs it.
+mod banned_token_store;
+mod email_outbox;
+mod two_fa_code_store;
+mod user_store;
+
+use std::{future::Future, time::Duration};
+
+#[cfg(feature = "db-tests")]
+use auth_service::utils::keyed_hash::KeyedHasher;
+#[cfg(feature = "db-tests")]
+use secrecy::Secret;
+
+// TTL of stores in the expiry cases, and how long those cases wait for it to pass
+const SHORT_TTL_SECONDS: u64 = 1;
+const PAST_SHORT_TTL: Duration = Duration::from_millis(1500);
+// TTL of stores in every other case
+const LONG_TTL_SECONDS: u64 = 600;
+
+const CONCURRENT_TASKS: usize = 16;
+
+// for the backends that only keep hashes
+#[cfg(feature = "db-tests")]
+fn test_hasher() -> KeyedHasher {
+    KeyedHasher::new(Secret::new("conformance-test-key".to_owned()))
+}
+
+// `CONCURRENT_TASKS` stores from `new_store`, for backends whose stores share external storage
+async fn separate_stores<T, F, Fut>(new_store: F) -> Vec<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let mut stores = Vec::with_capacity(CONCURRENT_TASKS);
+    for _ in 0..CONCURRENT_TASKS {
+        stores.push(new_store().await);
+    }
+    stores
+}
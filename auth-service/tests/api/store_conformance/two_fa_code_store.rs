@@ -0,0 +1,505 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+#[cfg(feature = "db-tests")]
+use auth_service::services::data_stores::{
+    postgres_two_fa_code_store::PostgresTwoFACodeStore,
+    redis_two_fa_code_store::RedisTwoFACodeStore,
+};
+use auth_service::{
+    app_state::TwoFACodeStoreType,
+    domain::{
+        data_stores::{LoginAttempt, LoginAttemptId, TwoFACode, TwoFACodeStoreError},
+        Email,
+    },
+    services::data_stores::hashmap_two_fa_code_store::HashmapTwoFACodeStore,
+};
+use chrono::{SubsecRound, Utc};
+use secrecy::Secret;
+use tokio::{sync::RwLock, task::JoinSet};
+
+#[cfg(feature = "db-tests")]
+use super::{separate_stores, test_hasher};
+use super::{CONCURRENT_TASKS, LONG_TTL_SECONDS, PAST_SHORT_TTL, SHORT_TTL_SECONDS};
+use crate::helpers::get_random_email;
+#[cfg(feature = "db-tests")]
+use crate::helpers::{test_redis_connection, TestDatabase};
+
+// Login attempts carry their own expiry. `new_store()` must return a store that shares nothing
+// with earlier ones, apart from codes of other login attempts. `new_handles()` must return
+// `CONCURRENT_TASKS` separate stores over the same storage, like the instances of a deployment.
+async fn conformance<F, Fut, H, HFut>(new_store: F, new_handles: H)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = TwoFACodeStoreType>,
+    H: Fn() -> HFut,
+    HFut: Future<Output = Vec<TwoFACodeStoreType>>,
+{
+    unknown_login_attempt_is_not_found(new_store().await).await;
+    added_attempt_is_returned(new_store().await).await;
+    only_the_added_code_is_valid(new_store().await).await;
+    code_is_only_accepted_once(new_store().await).await;
+    new_code_replaces_previous_one(new_store().await).await;
+    resent_code_keeps_the_expiry(new_store().await).await;
+    wrong_codes_are_limited(new_store().await).await;
+    attempts_of_the_same_user_are_kept_apart(new_store().await).await;
+    removed_code_is_not_found(new_store().await).await;
+    code_expires(new_store().await).await;
+    concurrent_codes_are_not_mixed_up(new_handles().await).await;
+}
+
+// enough wrong codes that no case but the one about them runs out
+const MAX_FAILED_ATTEMPTS: u32 = 100;
+
+fn random_email() -> Email {
+    Email::parse(Secret::new(get_random_email())).unwrap()
+}
+
+fn code(code: &str) -> TwoFACode {
+    TwoFACode::parse(Secret::new(code.to_owned())).unwrap()
+}
+
+// whole seconds, which every backend stores exactly
+fn login_attempt(email: Email, ttl_seconds: u64) -> LoginAttempt {
+    let sent_at = Utc::now().trunc_subsecs(0);
+    LoginAttempt {
+        sent_at,
+        expires_at: sent_at + chrono::Duration::seconds(ttl_seconds as i64),
+        ..LoginAttempt::new(email, Duration::ZERO)
+    }
+}
+
+async fn unknown_login_attempt_is_not_found(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let login_attempt_id = LoginAttemptId::default();
+
+    assert_eq!(
+        store.get_attempt(&login_attempt_id).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+    assert_eq!(
+        store
+            .consume_code(
+                &login_attempt_id,
+                &TwoFACode::default(),
+                MAX_FAILED_ATTEMPTS
+            )
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+    assert_eq!(
+        store
+            .resend_code(&login_attempt_id, TwoFACode::default(), Utc::now())
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+}
+
+async fn added_attempt_is_returned(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let login_attempt_id = LoginAttemptId::default();
+    let attempt = login_attempt(random_email(), LONG_TTL_SECONDS);
+
+    store
+        .add_code(
+            login_attempt_id.clone(),
+            attempt.clone(),
+            TwoFACode::default(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(store.get_attempt(&login_attempt_id).await.unwrap(), attempt);
+}
+
+async fn only_the_added_code_is_valid(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let login_attempt_id = LoginAttemptId::default();
+    let attempt = login_attempt(random_email(), LONG_TTL_SECONDS);
+
+    store
+        .add_code(login_attempt_id.clone(), attempt.clone(), code("123456"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        store
+            .consume_code(&login_attempt_id, &code("654321"), MAX_FAILED_ATTEMPTS)
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::IncorrectCode
+    );
+    let consumed = store
+        .consume_code(&login_attempt_id, &code("123456"), MAX_FAILED_ATTEMPTS)
+        .await
+        .unwrap();
+    assert_eq!(consumed.email, attempt.email);
+    assert_eq!(consumed.failed_attempts, 1);
+}
+
+async fn code_is_only_accepted_once(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let login_attempt_id = LoginAttemptId::default();
+    let attempt = login_attempt(random_email(), LONG_TTL_SECONDS);
+
+    store
+        .add_code(login_attempt_id.clone(), attempt.clone(), code("123456"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        store
+            .consume_code(&login_attempt_id, &code("123456"), MAX_FAILED_ATTEMPTS)
+            .await
+            .unwrap(),
+        attempt
+    );
+    assert_eq!(
+        store
+            .consume_code(&login_attempt_id, &code("123456"), MAX_FAILED_ATTEMPTS)
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+    assert_eq!(
+        store.get_attempt(&login_attempt_id).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+}
+
+async fn new_code_replaces_previous_one(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let email = random_email();
+    let login_attempt_id = LoginAttemptId::default();
+    let attempt = login_attempt(email.clone(), LONG_TTL_SECONDS);
+
+    store
+        .add_code(
+            login_attempt_id.clone(),
+            login_attempt(email, LONG_TTL_SECONDS),
+            code("123456"),
+        )
+        .await
+        .unwrap();
+    store
+        .add_code(login_attempt_id.clone(), attempt.clone(), code("654321"))
+        .await
+        .unwrap();
+
+    assert_eq!(store.get_attempt(&login_attempt_id).await.unwrap(), attempt);
+    assert_eq!(
+        store
+            .consume_code(&login_attempt_id, &code("123456"), MAX_FAILED_ATTEMPTS)
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::IncorrectCode
+    );
+    assert!(store
+        .consume_code(&login_attempt_id, &code("654321"), MAX_FAILED_ATTEMPTS)
+        .await
+        .is_ok());
+}
+
+async fn resent_code_keeps_the_expiry(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let login_attempt_id = LoginAttemptId::default();
+    let attempt = login_attempt(random_email(), LONG_TTL_SECONDS);
+    let resent_at = attempt.sent_at + chrono::Duration::seconds(30);
+
+    store
+        .add_code(login_attempt_id.clone(), attempt.clone(), code("123456"))
+        .await
+        .unwrap();
+    let resent = store
+        .resend_code(&login_attempt_id, code("654321"), resent_at)
+        .await
+        .unwrap();
+
+    let expected = LoginAttempt {
+        sent_at: resent_at,
+        resends: 1,
+        ..attempt
+    };
+    assert_eq!(resent, expected);
+    assert_eq!(
+        store.get_attempt(&login_attempt_id).await.unwrap(),
+        expected
+    );
+    assert_eq!(
+        store
+            .consume_code(&login_attempt_id, &code("123456"), MAX_FAILED_ATTEMPTS)
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::IncorrectCode
+    );
+    assert!(store
+        .consume_code(&login_attempt_id, &code("654321"), MAX_FAILED_ATTEMPTS)
+        .await
+        .is_ok());
+}
+
+async fn wrong_codes_are_limited(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let login_attempt_id = LoginAttemptId::default();
+
+    store
+        .add_code(
+            login_attempt_id.clone(),
+            login_attempt(random_email(), LONG_TTL_SECONDS),
+            code("123456"),
+        )
+        .await
+        .unwrap();
+
+    for failed_attempts in 1..=2 {
+        assert_eq!(
+            store
+                .consume_code(&login_attempt_id, &code("654321"), 3)
+                .await
+                .unwrap_err(),
+            TwoFACodeStoreError::IncorrectCode
+        );
+        assert_eq!(
+            store
+                .get_attempt(&login_attempt_id)
+                .await
+                .unwrap()
+                .failed_attempts,
+            failed_attempts
+        );
+    }
+    assert_eq!(
+        store
+            .consume_code(&login_attempt_id, &code("654321"), 3)
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::IncorrectCode
+    );
+
+    // the last wrong code dropped the attempt, so not even the right one works now
+    assert_eq!(
+        store
+            .consume_code(&login_attempt_id, &code("123456"), 3)
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+}
+
+async fn attempts_of_the_same_user_are_kept_apart(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let email = random_email();
+    let first = (
+        LoginAttemptId::default(),
+        login_attempt(email.clone(), LONG_TTL_SECONDS),
+        code("111111"),
+    );
+    let second = (
+        LoginAttemptId::default(),
+        login_attempt(email, LONG_TTL_SECONDS),
+        code("222222"),
+    );
+
+    for (login_attempt_id, attempt, code) in [&first, &second] {
+        store
+            .add_code(login_attempt_id.clone(), attempt.clone(), code.clone())
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(store.get_attempt(&first.0).await.unwrap(), first.1);
+    assert_eq!(store.get_attempt(&second.0).await.unwrap(), second.1);
+    assert_eq!(
+        store
+            .consume_code(&first.0, &second.2, MAX_FAILED_ATTEMPTS)
+            .await
+            .unwrap_err(),
+        TwoFACodeStoreError::IncorrectCode
+    );
+    store.remove_code(&first.0).await.unwrap();
+    assert_eq!(
+        store
+            .consume_code(&second.0, &second.2, MAX_FAILED_ATTEMPTS)
+            .await,
+        Ok(second.1)
+    );
+}
+
+async fn removed_code_is_not_found(store: TwoFACodeStoreType) {
+    let mut store = store.write().await;
+    let login_attempt_id = LoginAttemptId::default();
+
+    store
+        .add_code(
+            login_attempt_id.clone(),
+            login_attempt(random_email(), LONG_TTL_SECONDS),
+            TwoFACode::default(),
+        )
+        .await
+        .unwrap();
+    store.remove_code(&login_attempt_id).await.unwrap();
+
+    assert_eq!(
+        store.get_attempt(&login_attempt_id).await.unwrap_err(),
+        TwoFACodeStoreError::LoginAttemptIdNotFound
+    );
+    // removing is idempotent, so a retried verification does not fail
+    store.remove_code(&login_attempt_id).await.unwrap();
+}
+
+async fn code_expires(store: TwoFACodeStoreType) {
+    let login_attempt_id = LoginAttemptId::default();
+    let resent_id = LoginAttemptId::default();
+    let code = TwoFACode::default();
+    {
+        let mut store = store.write().await;
+        for login_attempt_id in [&login_attempt_id, &resent_id] {
+            store
+                .add_code(
+                    login_attempt_id.clone(),
+                    login_attempt(random_email(), SHORT_TTL_SECONDS),
+                    code.clone(),
+                )
+                .await
+                .unwrap();
+        }
+        // resending doesn't move the deadline
+        store
+            .resend_code(&resent_id, code.clone(), Utc::now())
+            .await
+            .unwrap();
+    }
+
+    tokio::time::sleep(PAST_SHORT_TTL).await;
+
+    let mut store = store.write().await;
+    for login_attempt_id in [&login_attempt_id, &resent_id] {
+        assert_eq!(
+            store.get_attempt(login_attempt_id).await.unwrap_err(),
+            TwoFACodeStoreError::LoginAttemptIdNotFound
+        );
+        assert_eq!(
+            store
+                .consume_code(login_attempt_id, &code, MAX_FAILED_ATTEMPTS)
+                .await
+                .unwrap_err(),
+            TwoFACodeStoreError::LoginAttemptIdNotFound
+        );
+    }
+}
+
+async fn concurrent_codes_are_not_mixed_up(stores: Vec<TwoFACodeStoreType>) {
+    let shared_email = random_email();
+    let attempts: Vec<(LoginAttemptId, LoginAttempt, TwoFACode)> = (0..CONCURRENT_TASKS)
+        .map(|i| {
+            // half of the logins are by the same user
+            let email = if i % 2 == 0 {
+                shared_email.clone()
+            } else {
+                random_email()
+            };
+            (
+                LoginAttemptId::default(),
+                login_attempt(email, LONG_TTL_SECONDS),
+                TwoFACode::default(),
+            )
+        })
+        .collect();
+    let shared_id = LoginAttemptId::default();
+
+    let mut tasks = JoinSet::new();
+    for (store, (login_attempt_id, attempt, code)) in stores.iter().cloned().zip(attempts.clone()) {
+        let shared_id = shared_id.clone();
+        tasks.spawn(async move {
+            let mut store = store.write().await;
+            store
+                .add_code(login_attempt_id, attempt.clone(), code.clone())
+                .await?;
+            store.add_code(shared_id, attempt, code).await
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result.unwrap().unwrap();
+    }
+
+    let mut store = stores[0].write().await;
+    for (login_attempt_id, expected, code) in &attempts {
+        assert_eq!(
+            &store.get_attempt(login_attempt_id).await.unwrap(),
+            expected
+        );
+        assert_eq!(
+            &store
+                .consume_code(login_attempt_id, code, MAX_FAILED_ATTEMPTS)
+                .await
+                .unwrap(),
+            expected
+        );
+    }
+    // the last writer wins, but the email and code always come from the same login;
+    // logins by the same user within a second have equal attempts, so any of them may match
+    let shared = store.get_attempt(&shared_id).await.unwrap();
+    let mut matches = 0;
+    for (_, attempt, code) in &attempts {
+        if attempt.email == shared.email
+            && attempt.sent_at == shared.sent_at
+            && store
+                .consume_code(&shared_id, code, MAX_FAILED_ATTEMPTS)
+                .await
+                .is_ok()
+        {
+            matches += 1;
+        }
+    }
+    assert_eq!(
+        matches, 1,
+        "Shared code is not the one of the shared attempt"
+    );
+}
+
+#[tokio::test]
+async fn hashmap_two_fa_code_store_conforms() {
+    let new_store =
+        || async { Arc::new(RwLock::new(HashmapTwoFACodeStore::default())) as TwoFACodeStoreType };
+
+    // the map lives in the process, so instances can only share it through one store
+    conformance(new_store, || async {
+        vec![new_store().await; CONCURRENT_TASKS]
+    })
+    .await;
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn redis_two_fa_code_store_conforms() {
+    // each store has its own connection; the shared test Redis keeps attempts of other cases apart
+    let new_store = || async {
+        Arc::new(RwLock::new(RedisTwoFACodeStore::new(
+            test_redis_connection(),
+            test_hasher(),
+        ))) as TwoFACodeStoreType
+    };
+
+    conformance(new_store, || separate_stores(new_store)).await;
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn postgres_two_fa_code_store_conforms() {
+    let db = TestDatabase::new().await;
+
+    let new_store = || {
+        let pool = db.pool.clone();
+        async move {
+            Arc::new(RwLock::new(PostgresTwoFACodeStore::new(
+                pool,
+                test_hasher(),
+            ))) as TwoFACodeStoreType
+        }
+    };
+
+    conformance(new_store, || separate_stores(new_store)).await;
+
+    db.clean_up().await;
+}
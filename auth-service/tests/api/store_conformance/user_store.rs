@@ -0,0 +1,394 @@
+use std::{future::Future, sync::Arc};
+
+#[cfg(feature = "db-tests")]
+use auth_service::services::data_stores::postgres_user_store::PostgresUserStore;
+use auth_service::{
+    app_state::UserStoreType,
+    domain::{
+        data_stores::{PhoneVerificationSends, UserStoreError},
+        password::Password,
+        Email, PhoneNumber, PhoneVerification, TwoFAChannel, User,
+    },
+    get_sqlite_pool,
+    services::data_stores::{
+        hashmap_user_store::HashmapUserStore, sqlite_user_store::SqliteUserStore,
+    },
+    utils::configuration::PasswordHashingSettings,
+};
+use chrono::{Duration, SubsecRound, Utc};
+use secrecy::Secret;
+use tokio::{sync::RwLock, task::JoinSet};
+
+use super::{separate_stores, CONCURRENT_TASKS};
+use crate::helpers::get_random_email;
+#[cfg(feature = "db-tests")]
+use crate::helpers::TestDatabase;
+
+// `new_store()` must return a store that shares nothing with earlier ones,
+// apart from users with other emails. `new_handles()` must return `CONCURRENT_TASKS`
+// separate stores over the same storage, like the instances of a deployment.
+async fn conformance<F, Fut, H, HFut>(new_store: F, new_handles: H)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = UserStoreType>,
+    H: Fn() -> HFut,
+    HFut: Future<Output = Vec<UserStoreType>>,
+{
+    unknown_user_is_not_found(new_store().await).await;
+    added_user_is_returned_with_a_password_hash(new_store().await).await;
+    duplicate_user_is_rejected(new_store().await).await;
+    credentials_are_verified_against_the_hash(new_store().await).await;
+    concurrent_signups_create_one_user(new_handles().await).await;
+    verified_phone_number_replaces_the_pending_verification(new_store().await).await;
+    two_fa_channel_is_stored(new_store().await).await;
+    last_login_is_stored(new_store().await).await;
+    phone_verification_sends_are_counted(new_store().await).await;
+    updates_of_unknown_users_are_rejected(new_store().await).await;
+}
+
+fn random_user(requires_2fa: bool) -> User {
+    User::new(
+        Email::parse(Secret::new(get_random_email())).unwrap(),
+        Password::parse(Secret::new("password123".to_owned())).unwrap(),
+        requires_2fa,
+    )
+}
+
+async fn unknown_user_is_not_found(store: UserStoreType) {
+    let store = store.read().await;
+    let user = random_user(false);
+
+    assert_eq!(
+        store.get_user(&user.email).await.unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+    assert_eq!(
+        store
+            .validate_user(&user.email, &user.password)
+            .await
+            .unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+}
+
+async fn added_user_is_returned_with_a_password_hash(store: UserStoreType) {
+    let mut store = store.write().await;
+    let user = random_user(true);
+
+    store.add_user(user.clone()).await.unwrap();
+    let stored = store.get_user(&user.email).await.unwrap();
+
+    assert_eq!(stored.email, user.email);
+    assert!(stored.requires_2fa);
+    assert_ne!(stored.password, user.password);
+    // codes are emailed until a phone number is verified
+    assert_eq!(stored.phone_number, None);
+    assert_eq!(stored.two_fa_channel, TwoFAChannel::Email);
+    assert_eq!(stored.phone_verification, None);
+    assert_eq!(stored.locale, None);
+
+    let user = random_user(false).with_locale(Some("de".to_owned()));
+    store.add_user(user.clone()).await.unwrap();
+    let stored = store.get_user(&user.email).await.unwrap();
+    assert_eq!(stored.locale.as_deref(), Some("de"));
+}
+
+async fn duplicate_user_is_rejected(store: UserStoreType) {
+    let mut store = store.write().await;
+    let user = random_user(false);
+
+    store.add_user(user.clone()).await.unwrap();
+    let duplicate = User::new(user.email.clone(), user.password.clone(), true);
+
+    assert_eq!(
+        store.add_user(duplicate).await.unwrap_err(),
+        UserStoreError::UserAlreadyExists
+    );
+    // the first signup is kept
+    assert!(!store.get_user(&user.email).await.unwrap().requires_2fa);
+}
+
+async fn credentials_are_verified_against_the_hash(store: UserStoreType) {
+    let mut store = store.write().await;
+    let user = random_user(false);
+    store.add_user(user.clone()).await.unwrap();
+
+    store
+        .validate_user(&user.email, &user.password)
+        .await
+        .unwrap();
+
+    let wrong_password = Password::parse(Secret::new("password456".to_owned())).unwrap();
+    assert_eq!(
+        store
+            .validate_user(&user.email, &wrong_password)
+            .await
+            .unwrap_err(),
+        UserStoreError::InvalidCredentials
+    );
+    // the stored hash is not accepted as a password
+    let stored_hash = store.get_user(&user.email).await.unwrap().password;
+    assert_eq!(
+        store
+            .validate_user(&user.email, &stored_hash)
+            .await
+            .unwrap_err(),
+        UserStoreError::InvalidCredentials
+    );
+}
+
+async fn concurrent_signups_create_one_user(stores: Vec<UserStoreType>) {
+    let user = random_user(false);
+
+    let mut tasks = JoinSet::new();
+    for store in stores {
+        let user = user.clone();
+        tasks.spawn(async move { store.write().await.add_user(user).await });
+    }
+    let mut created = 0;
+    while let Some(result) = tasks.join_next().await {
+        match result.unwrap() {
+            Ok(()) => created += 1,
+            Err(e) => assert_eq!(e, UserStoreError::UserAlreadyExists),
+        }
+    }
+
+    assert_eq!(created, 1);
+}
+
+fn phone_number(s: &str) -> PhoneNumber {
+    PhoneNumber::parse(Secret::new(s.to_owned())).unwrap()
+}
+
+async fn verified_phone_number_replaces_the_pending_verification(store: UserStoreType) {
+    let mut store = store.write().await;
+    let user = random_user(true);
+    store.add_user(user.clone()).await.unwrap();
+
+    let verification = PhoneVerification {
+        phone_number: phone_number("+14155550100"),
+        code_hash: "hash-of-the-first-code".to_owned(),
+        // every backend keeps at least whole seconds
+        expires_at: Utc::now().trunc_subsecs(0),
+        attempts: 2,
+        sent_at: Utc::now().trunc_subsecs(0),
+    };
+    store
+        .set_phone_verification(&user.email, Some(verification.clone()))
+        .await
+        .unwrap();
+    let stored = store.get_user(&user.email).await.unwrap();
+    assert_eq!(stored.phone_verification, Some(verification));
+    assert_eq!(stored.phone_number, None);
+
+    store
+        .set_phone_number(&user.email, &phone_number("+14155550100"))
+        .await
+        .unwrap();
+    let stored = store.get_user(&user.email).await.unwrap();
+    assert_eq!(stored.phone_number, Some(phone_number("+14155550100")));
+    assert_eq!(stored.phone_verification, None);
+
+    // a new number is pending while the verified one is still used
+    let verification = PhoneVerification {
+        phone_number: phone_number("+442071838750"),
+        code_hash: "hash-of-the-second-code".to_owned(),
+        expires_at: Utc::now().trunc_subsecs(0),
+        attempts: 0,
+        sent_at: Utc::now().trunc_subsecs(0),
+    };
+    store
+        .set_phone_verification(&user.email, Some(verification.clone()))
+        .await
+        .unwrap();
+    let stored = store.get_user(&user.email).await.unwrap();
+    assert_eq!(stored.phone_number, Some(phone_number("+14155550100")));
+    assert_eq!(stored.phone_verification, Some(verification));
+
+    store
+        .set_phone_verification(&user.email, None)
+        .await
+        .unwrap();
+    let stored = store.get_user(&user.email).await.unwrap();
+    assert_eq!(stored.phone_verification, None);
+}
+
+async fn two_fa_channel_is_stored(store: UserStoreType) {
+    let mut store = store.write().await;
+    let user = random_user(true);
+    store.add_user(user.clone()).await.unwrap();
+
+    for channel in [TwoFAChannel::Voice, TwoFAChannel::Sms, TwoFAChannel::Email] {
+        store
+            .set_two_fa_channel(&user.email, channel)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_user(&user.email).await.unwrap().two_fa_channel,
+            channel
+        );
+    }
+}
+
+async fn last_login_is_stored(store: UserStoreType) {
+    let mut store = store.write().await;
+    let user = random_user(false);
+    store.add_user(user.clone()).await.unwrap();
+    assert_eq!(
+        store.get_user(&user.email).await.unwrap().last_login_at,
+        None
+    );
+
+    let logged_in_at = Utc::now().trunc_subsecs(0);
+    store.record_login(&user.email, logged_in_at).await.unwrap();
+    assert_eq!(
+        store.get_user(&user.email).await.unwrap().last_login_at,
+        Some(logged_in_at)
+    );
+}
+
+async fn phone_verification_sends_are_counted(store: UserStoreType) {
+    let mut store = store.write().await;
+    let user = random_user(true);
+    let other_user = random_user(true);
+    store.add_user(user.clone()).await.unwrap();
+    store.add_user(other_user.clone()).await.unwrap();
+    // random, so sends of earlier runs against the same database are not counted
+    let number = phone_number(&format!("+1415555{:04}", rand::random::<u16>() % 10_000));
+    let other_number = phone_number(&format!("+4420718{:04}", rand::random::<u16>() % 10_000));
+    let now = Utc::now();
+
+    for (email, phone_number, sent_at) in [
+        (&user.email, &number, now - Duration::hours(2)),
+        (&user.email, &other_number, now - Duration::minutes(1)),
+        (&other_user.email, &number, now),
+    ] {
+        store
+            .record_phone_verification_sent(email, phone_number, sent_at)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(
+        store
+            .phone_verifications_sent_since(&user.email, &number, now - Duration::days(1))
+            .await
+            .unwrap(),
+        PhoneVerificationSends {
+            by_user: 2,
+            to_number: 2
+        }
+    );
+    assert_eq!(
+        store
+            .phone_verifications_sent_since(&user.email, &number, now - Duration::hours(1))
+            .await
+            .unwrap(),
+        PhoneVerificationSends {
+            by_user: 1,
+            to_number: 1
+        }
+    );
+    assert_eq!(
+        store
+            .record_phone_verification_sent(&random_user(true).email, &number, now)
+            .await
+            .unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+}
+
+async fn updates_of_unknown_users_are_rejected(store: UserStoreType) {
+    let mut store = store.write().await;
+    let user = random_user(true);
+
+    assert_eq!(
+        store
+            .set_phone_verification(&user.email, None)
+            .await
+            .unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+    assert_eq!(
+        store
+            .set_phone_number(&user.email, &phone_number("+14155550100"))
+            .await
+            .unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+    assert_eq!(
+        store
+            .set_two_fa_channel(&user.email, TwoFAChannel::Sms)
+            .await
+            .unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+    assert_eq!(
+        store
+            .record_login(&user.email, Utc::now())
+            .await
+            .unwrap_err(),
+        UserStoreError::UserNotFound
+    );
+}
+
+#[tokio::test]
+async fn hashmap_user_store_conforms() {
+    let new_store =
+        || async { Arc::new(RwLock::new(HashmapUserStore::default())) as UserStoreType };
+
+    // the map lives in the process, so instances can only share it through one store
+    conformance(new_store, || async move {
+        vec![new_store().await; CONCURRENT_TASKS]
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn sqlite_user_store_conforms() {
+    let new_pool = || async {
+        let pool = get_sqlite_pool(&Secret::new("sqlite::memory:".to_owned()), 5)
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations_sqlite")
+            .run(&pool)
+            .await
+            .unwrap();
+        pool
+    };
+    let new_store = |pool| async {
+        Arc::new(RwLock::new(SqliteUserStore::new(
+            pool,
+            PasswordHashingSettings::default(),
+        ))) as UserStoreType
+    };
+
+    conformance(
+        || async { new_store(new_pool().await).await },
+        || async {
+            let pool = new_pool().await;
+            separate_stores(|| new_store(pool.clone())).await
+        },
+    )
+    .await;
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn postgres_user_store_conforms() {
+    let db = TestDatabase::new().await;
+
+    let new_store = || {
+        let pool = db.pool.clone();
+        async move {
+            Arc::new(RwLock::new(PostgresUserStore::new(
+                pool,
+                PasswordHashingSettings::default(),
+            ))) as UserStoreType
+        }
+    };
+
+    conformance(new_store, || separate_stores(new_store)).await;
+
+    db.clean_up().await;
+}
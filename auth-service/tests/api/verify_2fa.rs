@@ -28,15 +28,18 @@ async fn should_return_200_if_correct_code() {
     let login_response = app.post_login(&login_body).await;
     assert_eq!(login_response.status().as_u16(), 206);
 
-    let (attempt_id, code) = {
-        let two_fa_code_store = app.two_fa_code_store.read().await;
-        two_fa_code_store.get_code(&email).await.unwrap()
-    };
+    let login_attempt_id = login_response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    let code = app
+        .two_fa_code_sent_to(email.as_ref().expose_secret())
+        .await;
 
     let verify_two_fa_body = serde_json::json!({
-        "email": email.as_ref().expose_secret(),
-        "loginAttemptId": attempt_id.as_ref().expose_secret(),
-        "2FACode": code.as_ref().expose_secret()
+        "loginAttemptId": login_attempt_id,
+        "2FACode": code
     });
 
     let verify_two_fa_response = app.post_verify_2fa(&verify_two_fa_body).await;
@@ -59,21 +62,18 @@ async fn should_return_400_if_invalid_input() {
 
     let inputs = [
         serde_json::json!({
-            "email": "test",
             "loginAttemptId": "test",
             "2FACode": "test"
         }),
-        // Missing loginAttemptId field
+        // Not a login attempt id
         serde_json::json!({
-            "email": "test",
             "loginAttemptId": "123123",
-            "2FACode": "123456",
+            "2FACode": "123456"
         }),
-        // Missing 2FACode field
+        // Not a 2FA code
         serde_json::json!({
-            "email": "test",
-            "loginAttemptId": "123123",
-            "2FACode": "123456"
+            "loginAttemptId": uuid::Uuid::new_v4().to_string(),
+            "2FACode": "12345"
         }),
     ];
 
@@ -114,7 +114,6 @@ async fn should_return_401_if_incorrect_credentials() {
         .login_attempt_id;
 
     let verify_two_fa_body = serde_json::json!({
-        "email": random_email,
         "loginAttemptId": login_attempt_id,
         "2FACode": "123123"
     });
@@ -126,6 +125,44 @@ async fn should_return_401_if_incorrect_credentials() {
     app.clean_up().await
 }
 
+#[tokio::test]
+async fn should_drop_the_login_attempt_after_too_many_wrong_codes() {
+    let mut app =
+        TestApp::with_settings(|settings| settings.auth.two_fa_max_failed_attempts = 2).await;
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "pass1234"
+    });
+    let login_response = app.post_login(&login_body).await;
+    assert_eq!(login_response.status().as_u16(), 206);
+    let login_attempt_id = login_response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    let code = app.two_fa_code_sent_to(&random_email).await;
+    let wrong_code = if code == "123123" { "321321" } else { "123123" };
+
+    for two_fa_code in [wrong_code, wrong_code, &code] {
+        let verify_two_fa_body = serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": two_fa_code
+        });
+        let verify_two_fa_response = app.post_verify_2fa(&verify_two_fa_body).await;
+        assert_eq!(verify_two_fa_response.status().as_u16(), 401);
+    }
+
+    app.clean_up().await
+}
+
 #[tokio::test]
 async fn should_return_401_if_same_code_twice() {
     let mut app = TestApp::new().await;
@@ -148,15 +185,18 @@ async fn should_return_401_if_same_code_twice() {
     let login_response = app.post_login(&login_body).await;
     assert_eq!(login_response.status().as_u16(), 206);
 
-    let (attempt_id, code) = {
-        let two_fa_code_store = app.two_fa_code_store.read().await;
-        two_fa_code_store.get_code(&email).await.unwrap()
-    };
+    let login_attempt_id = login_response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    let code = app
+        .two_fa_code_sent_to(email.as_ref().expose_secret())
+        .await;
 
     let verify_two_fa_body = serde_json::json!({
-        "email": email.as_ref().expose_secret(),
-        "loginAttemptId": attempt_id.as_ref().expose_secret(),
-        "2FACode": code.as_ref().expose_secret()
+        "loginAttemptId": login_attempt_id,
+        "2FACode": code
     });
 
     let verify_two_fa_response = app.post_verify_2fa(&verify_two_fa_body).await;
@@ -204,7 +244,6 @@ async fn should_return_401_if_old_code() {
         .login_attempt_id;
 
     let verify_two_fa_body = serde_json::json!({
-        "email": random_email,
         "loginAttemptId": login_attempt_id,
         "2FACode": "123123"
     });
@@ -218,6 +257,59 @@ async fn should_return_401_if_old_code() {
     app.clean_up().await
 }
 
+#[tokio::test]
+async fn should_verify_each_pending_login_of_a_user() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    let signup_response = app.post_signup(&signup_body).await;
+    assert_eq!(signup_response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "pass1234"
+    });
+    // e.g. two browser tabs
+    let mut login_attempt_ids = Vec::new();
+    for _ in 0..2 {
+        let login_response = app.post_login(&login_body).await;
+        assert_eq!(login_response.status().as_u16(), 206);
+        login_attempt_ids.push(
+            login_response
+                .json::<TwoFactorAuthResponse>()
+                .await
+                .expect("Could not deserialize response body to TwoFactorAuthResponse")
+                .login_attempt_id,
+        );
+    }
+    let codes = app.two_fa_codes_sent_to(&random_email, 2).await;
+
+    // a code only verifies its own login
+    if codes[0] != codes[1] {
+        let verify_two_fa_body = serde_json::json!({
+            "loginAttemptId": login_attempt_ids[0],
+            "2FACode": codes[1]
+        });
+        let response = app.post_verify_2fa(&verify_two_fa_body).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    for (login_attempt_id, code) in login_attempt_ids.iter().zip(&codes).rev() {
+        let verify_two_fa_body = serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        });
+        let response = app.post_verify_2fa(&verify_two_fa_body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    app.clean_up().await
+}
+
 #[tokio::test]
 async fn should_return_422_if_malformed_input() {
     let mut app = TestApp::new().await;
@@ -231,7 +323,6 @@ async fn should_return_422_if_malformed_input() {
             "loginAttemptId": "123123"
         }),
         serde_json::json!({
-            "email": "test@email.com",
             "loginAttemptId": "123123",
             "2FACode": true
         }),
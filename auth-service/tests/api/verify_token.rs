@@ -55,6 +55,55 @@ async fn should_return_401_if_invalid_token() {
     app.clean_up().await
 }
 
+#[tokio::test]
+async fn should_return_401_if_signed_with_another_secret() {
+    let mut app = TestApp::new().await;
+    // each test app is configured with its own JWT secret
+    let mut other_app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(
+        other_app.post_signup(&signup_body).await.status().as_u16(),
+        201
+    );
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "pass1234"
+    });
+    let login_response = other_app.post_login(&login_body).await;
+    assert_eq!(login_response.status().as_u16(), 200);
+
+    let cookie = login_response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+
+    let verify_body = serde_json::json!({
+        "token": cookie.value()
+    });
+    assert_eq!(
+        other_app
+            .post_verify_token(&verify_body)
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+    assert_eq!(
+        app.post_verify_token(&verify_body).await.status().as_u16(),
+        401
+    );
+
+    app.clean_up().await;
+    other_app.clean_up().await
+}
+
 #[tokio::test]
 async fn should_return_401_if_banned_token() {
     let mut app = TestApp::new().await;
@@ -0,0 +1,365 @@
+use auth_service::{
+    domain::webhook::WebhookEventType,
+    routes::TwoFactorAuthResponse,
+    services::webhooks::{
+        dispatcher::WebhookDispatcher, signature::WEBHOOK_SIGNATURE_HEADER,
+        subscriptions::PostgresWebhookSubscriptionStore,
+    },
+    utils::{
+        configuration::{UserStoreBackend, WebhookSettings},
+        secret_cipher::SecretCipher,
+    },
+};
+use secrecy::{ExposeSecret, Secret};
+use wiremock::{
+    matchers::{body_partial_json, header_exists, method},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+// Signup events are queued by `PostgresUserStore` only
+async fn postgres_users_app() -> TestApp {
+    TestApp::with_settings(|settings| settings.stores.users = UserStoreBackend::Postgres).await
+}
+
+fn cipher() -> SecretCipher {
+    SecretCipher::new(&[7; 32])
+}
+
+fn dispatcher(app: &TestApp, settings: &WebhookSettings) -> WebhookDispatcher {
+    WebhookDispatcher::new(
+        app.db_pool().clone(),
+        reqwest::Client::new(),
+        cipher(),
+        settings,
+    )
+}
+
+async fn subscribe(app: &TestApp, url: &str) {
+    subscribe_to(app, url, &[WebhookEventType::UserSignedUp]).await
+}
+
+async fn subscribe_to(app: &TestApp, url: &str, event_types: &[WebhookEventType]) {
+    PostgresWebhookSubscriptionStore::new(app.db_pool().clone(), cipher())
+        .add_subscription(url, Secret::new("whsec_test".to_owned()), event_types)
+        .await
+        .expect("Failed to add webhook subscription");
+}
+
+async fn signup(app: &TestApp, email: &str) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+}
+
+// Event types and payloads in the outbox, oldest first
+async fn queued_events(app: &TestApp) -> Vec<(String, serde_json::Value)> {
+    sqlx::query_as::<_, (String, sqlx::types::Json<serde_json::Value>)>(
+        "SELECT event_type, payload FROM webhook_outbox ORDER BY created_at",
+    )
+    .fetch_all(app.db_pool())
+    .await
+    .expect("Failed to read webhook outbox")
+    .into_iter()
+    .map(|(event_type, payload)| (event_type, payload.0))
+    .collect()
+}
+
+async fn delivery_status(app: &TestApp) -> (String, i32) {
+    sqlx::query_as("SELECT status, attempts FROM webhook_outbox")
+        .fetch_one(app.db_pool())
+        .await
+        .expect("Failed to read webhook outbox")
+}
+
+#[tokio::test]
+async fn should_deliver_signed_signup_event() {
+    let mut app = postgres_users_app().await;
+    let receiver = MockServer::start().await;
+    let email = get_random_email();
+
+    Mock::given(method("POST"))
+        .and(header_exists(WEBHOOK_SIGNATURE_HEADER))
+        .and(body_partial_json(serde_json::json!({
+            "type": "user.signed_up",
+            "data": { "email": email }
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+
+    subscribe(&app, &receiver.uri()).await;
+    signup(&app, &email).await;
+
+    let dispatcher = dispatcher(&app, &WebhookSettings::default());
+    assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+    assert_eq!(delivery_status(&app).await, ("delivered".to_owned(), 1));
+
+    // nothing left to deliver
+    assert_eq!(dispatcher.dispatch_due().await.unwrap(), 0);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_not_queue_event_if_signup_fails() {
+    let mut app = postgres_users_app().await;
+    let receiver = MockServer::start().await;
+    let email = get_random_email();
+
+    subscribe(&app, &receiver.uri()).await;
+    signup(&app, &email).await;
+
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 409);
+
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM webhook_outbox")
+        .fetch_one(app.db_pool())
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_dead_letter_after_max_attempts() {
+    let mut app = postgres_users_app().await;
+    let receiver = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&receiver)
+        .await;
+
+    subscribe(&app, &receiver.uri()).await;
+    signup(&app, &get_random_email()).await;
+
+    let settings = WebhookSettings {
+        max_attempts: 2,
+        base_delay_ms: 0,
+        ..Default::default()
+    };
+    let dispatcher = dispatcher(&app, &settings);
+
+    assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+    assert_eq!(delivery_status(&app).await, ("pending".to_owned(), 1));
+
+    assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+    assert_eq!(delivery_status(&app).await, ("dead".to_owned(), 2));
+
+    assert_eq!(dispatcher.dispatch_due().await.unwrap(), 0);
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_store_secrets_encrypted() {
+    let mut app = postgres_users_app().await;
+    let receiver = MockServer::start().await;
+
+    subscribe(&app, &receiver.uri()).await;
+
+    let (stored,): (String,) = sqlx::query_as("SELECT secret FROM webhook_subscriptions")
+        .fetch_one(app.db_pool())
+        .await
+        .expect("Failed to read webhook subscriptions");
+    assert!(!stored.contains("whsec_test"));
+
+    let subscriptions = PostgresWebhookSubscriptionStore::new(app.db_pool().clone(), cipher())
+        .list_subscriptions()
+        .await
+        .expect("Failed to list webhook subscriptions");
+    assert_eq!(subscriptions[0].secret.expose_secret(), "whsec_test");
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_retry_delivery_whose_lease_expired() {
+    let mut app = postgres_users_app().await;
+    let receiver = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+
+    subscribe(&app, &receiver.uri()).await;
+    signup(&app, &get_random_email()).await;
+
+    // a dispatcher that claimed the delivery and died before recording the outcome
+    sqlx::query(
+        r#"
+            UPDATE webhook_outbox
+            SET attempts = 1, claim_token = gen_random_uuid(),
+                next_attempt_at = now() - interval '1 second'
+        "#,
+    )
+    .execute(app.db_pool())
+    .await
+    .unwrap();
+
+    let dispatcher = dispatcher(&app, &WebhookSettings::default());
+    assert_eq!(dispatcher.dispatch_due().await.unwrap(), 1);
+    assert_eq!(delivery_status(&app).await, ("delivered".to_owned(), 2));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_not_hand_out_claimed_deliveries() {
+    let mut app = postgres_users_app().await;
+    let receiver = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(500)))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+
+    subscribe(&app, &receiver.uri()).await;
+    signup(&app, &get_random_email()).await;
+
+    let first = dispatcher(&app, &WebhookSettings::default());
+    let second = dispatcher(&app, &WebhookSettings::default());
+    let (first, second) = tokio::join!(first.dispatch_due(), async {
+        // the first dispatcher is sending, so its claim is committed
+        while receiver
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .is_empty()
+        {
+            tokio::task::yield_now().await;
+        }
+        second.dispatch_due().await
+    });
+
+    assert_eq!(first.unwrap(), 1);
+    assert_eq!(second.unwrap(), 0);
+    assert_eq!(delivery_status(&app).await, ("delivered".to_owned(), 1));
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_queue_account_changes_and_logins() {
+    let mut app = postgres_users_app().await;
+    let receiver = MockServer::start().await;
+    let email = get_random_email();
+
+    subscribe_to(
+        &app,
+        &receiver.uri(),
+        &[
+            WebhookEventType::UserLoggedIn,
+            WebhookEventType::UserPhoneNumberVerified,
+            WebhookEventType::UserTwoFAChannelChanged,
+        ],
+    )
+    .await;
+    signup(&app, &email).await;
+
+    // rejected logins change nothing, so they queue nothing
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": "wrong1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(queued_events(&app).await.is_empty());
+
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": "pass1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_phone_number(
+            &serde_json::json!({ "phoneNumber": "+14155550100", "password": "pass1234" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let code = app.code_sent_to_phone("+14155550100");
+    let response = app
+        .post_verify_phone_number(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_2fa_channel(&serde_json::json!({ "channel": "sms", "password": "pass1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let events = queued_events(&app).await;
+    let types: Vec<_> = events
+        .iter()
+        .map(|(event_type, _)| event_type.as_str())
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "user.logged_in",
+            "user.phone_number_verified",
+            "user.two_fa_channel_changed"
+        ]
+    );
+    assert_eq!(events[0].1["data"], serde_json::json!({ "email": email }));
+    assert_eq!(
+        events[2].1["data"],
+        serde_json::json!({ "email": email, "twoFAChannel": "sms" })
+    );
+
+    app.clean_up().await
+}
+
+#[tokio::test]
+async fn should_queue_2fa_login_once_the_code_is_accepted() {
+    let mut app = postgres_users_app().await;
+    let receiver = MockServer::start().await;
+    let email = get_random_email();
+
+    subscribe_to(&app, &receiver.uri(), &[WebhookEventType::UserLoggedIn]).await;
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "pass1234",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": "pass1234" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    // the password alone doesn't log the user in
+    assert!(queued_events(&app).await.is_empty());
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "loginAttemptId": login_attempt_id,
+            "2FACode": app.two_fa_code_sent_to(&email).await,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let events = queued_events(&app).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "user.logged_in");
+
+    app.clean_up().await
+}
@@ -83,12 +83,15 @@ resource "aws_lb_target_group" "rustyauth_tg" {
   protocol = "HTTP"
   vpc_id   = "vpc-84d821e2"
 
+  # readiness turns 503 as soon as shutdown starts (see shutdown.unready_delay_ms) or a
+  # dependency is down, so instances leave the rotation within interval * unhealthy_threshold
   health_check {
-    interval            = 30
-    path                = "/"
+    interval            = 5
+    path                = "/health/ready"
     protocol            = "HTTP"
-    timeout             = 5
-    healthy_threshold   = 5
+    matcher             = "200"
+    timeout             = 3
+    healthy_threshold   = 2
     unhealthy_threshold = 2
   }
 }